@@ -0,0 +1,14 @@
+// Grammar files straight from libFuzzer through loading, validation,
+// compiling and generation (perturb::check_bytes())
+
+#![no_main]
+
+use std::time::Duration;
+
+use libfuzzer_sys::fuzz_target;
+use maybe_fastest_fuzzer::perturb;
+
+fuzz_target!(|data: &[u8]| {
+    let outcome = perturb::check_bytes(data.to_vec(), 4, 1, Duration::from_secs(10));
+    assert!(!outcome.is_bug(), "{:?}", outcome);
+});
@@ -0,0 +1,18 @@
+// Grammars perturb::random_grammar() builds with the input of libFuzzer as
+// its random source, so mutations of the input are mutations of the
+// grammar rather than of its json
+
+#![no_main]
+
+use std::time::Duration;
+
+use libfuzzer_sys::fuzz_target;
+use maybe_fastest_fuzzer::perturb;
+
+fuzz_target!(|data: &[u8]| {
+    let mut words = data.chunks(4).map(|x| x.iter().fold(0, |acc, &b| acc << 8 | b as usize));
+    let grammar = perturb::random_grammar(&mut || words.next().unwrap_or(0));
+    let data = serde_json::to_vec(&perturb::to_json(&grammar)).unwrap();
+    let outcome = perturb::check_bytes(data, 4, 1, Duration::from_secs(10));
+    assert!(!outcome.is_bug(), "{:?}", outcome);
+});
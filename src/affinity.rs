@@ -0,0 +1,49 @@
+// Pinning worker threads to CPU cores
+//
+// Affinity is inherited across fork/exec, so a pinned worker also keeps
+// the targets it spawns on its core. That avoids the scheduler bouncing
+// forkserver style workloads between cores and keeps benchmarks stable.
+
+use std::io;
+
+// Cores this process is allowed to run on, in ascending order
+#[cfg(target_os = "linux")]
+pub fn allowed_cores() -> io::Result<Vec<usize>> {
+    // SAFETY: cpu_set_t is plain data, the kernel fills it in
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        if libc::sched_getaffinity(0, std::mem::size_of_val(&set), &mut set)
+                != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((0..libc::CPU_SETSIZE as usize)
+            .filter(|&core| libc::CPU_ISSET(core, &set)).collect())
+    }
+}
+
+// Restrict the calling thread (and everything it spawns) to one core
+#[cfg(target_os = "linux")]
+pub fn pin_current_thread(core: usize) -> io::Result<()> {
+    // SAFETY: see allowed_cores
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(core, &mut set);
+        if libc::sched_setaffinity(0, std::mem::size_of_val(&set), &set)
+                != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn allowed_cores() -> io::Result<Vec<usize>> {
+    Err(io::Error::new(io::ErrorKind::Unsupported,
+        "core binding is only supported on Linux"))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pin_current_thread(_core: usize) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported,
+        "core binding is only supported on Linux"))
+}
@@ -0,0 +1,131 @@
+// Experimental: many derivations in lock-step
+//
+// GrammarRust::generate() runs one derivation at a time, every step waits
+// on the fragment lookup of the step before. A Batch runs a number of
+// lanes, each an independent generator with its own state, and advances
+// all of them one step per round, so the lookups of different lanes
+// overlap. Lane state is kept as structure of arrays. Terminals are not
+// copied while deriving, a lane records the terminal fragments it took and
+// the bytes go out in one pass at the end, into a buffer allocated once
+// at the final size.
+//
+// Output is the same as generate(): lane i produces exactly what
+// generate() produces from a GeneratorState seeded like the lane, call
+// after call. Grammars that corrupt terminals copy them as they are
+// taken, corruption draws random numbers in between the choices.
+//
+// Only with the batch feature. Choice sampling stays on the CPU, there is
+// no GPU offload.
+
+use crate::grammar::{Fragment, FragmentId, GeneratorState, GrammarRust};
+use crate::tree::{Tree, TreeStack};
+
+pub struct Batch<'a> {
+    gram: &'a GrammarRust,
+
+    // one entry per lane
+    states: Vec<GeneratorState>,
+    stacks: Vec<Vec<FragmentId>>,
+    nodes: Vec<usize>,
+    bytes: Vec<usize>,
+    terminals: Vec<Vec<FragmentId>>,
+
+    // lanes whose derivation is still going
+    active: Vec<usize>,
+}
+
+impl<'a> Batch<'a> {
+    // One lane per seed
+    pub fn new(gram: &'a GrammarRust, seeds: &[usize]) -> Self {
+        let lanes = seeds.len();
+        Batch {
+            gram,
+            states: seeds.iter().map(|&x| GeneratorState::new(x)).collect(),
+            stacks: vec![Vec::new(); lanes],
+            nodes: vec![0; lanes],
+            bytes: vec![0; lanes],
+            terminals: vec![Vec::new(); lanes],
+            active: Vec::with_capacity(lanes),
+        }
+    }
+
+    pub fn lanes(&self) -> usize {
+        self.states.len()
+    }
+
+    // One test case per lane into out, which has a buffer per lane. The
+    // buffers are cleared first
+    pub fn generate(&mut self, out: &mut [Vec<u8>]) {
+        assert_eq!(out.len(), self.lanes(), "one output buffer per lane");
+        let gram = self.gram;
+        let corrupting = gram.is_corrupting();
+
+        // shared rules need the tree, lanes go one after the other like
+        // generate() does then
+        if gram.has_shared() {
+            let (mut stack, mut tree) = (TreeStack::default(), Tree::default());
+            for (state, buf) in self.states.iter_mut().zip(out) {
+                buf.clear();
+                gram.generate_full_tree(state, &mut stack, &mut tree);
+                tree.serialize(gram, buf);
+            }
+            return;
+        }
+
+        self.active.clear();
+        for (lane, buf) in out.iter_mut().enumerate() {
+            self.stacks[lane].clear();
+            self.stacks[lane].push(gram.start());
+            self.nodes[lane] = 0;
+            self.bytes[lane] = 0;
+            self.terminals[lane].clear();
+            gram.start_derivation(&mut self.states[lane]);
+            buf.clear();
+            self.active.push(lane);
+        }
+
+        // one step of every running lane per round, same steps as generate()
+        while !self.active.is_empty() {
+            let Batch { states, stacks, nodes, bytes, terminals, active, .. } = self;
+            active.retain(|&lane| {
+                let Some(cur) = stacks[lane].pop() else {
+                    return false;
+                };
+                nodes[lane] += 1;
+                match gram.lookup_fragment(cur) {
+                    Fragment::NonTerminal(options) => {
+                        match gram.choose(&mut states[lane], cur, options, nodes[lane], None) {
+                            Some(sel) => stacks[lane].push(sel),
+                            None => return false,
+                        }
+                    }
+                    Fragment::Expression(expr) => stacks[lane].extend(expr.iter().rev()),
+                    Fragment::Terminal(value) => {
+                        if corrupting {
+                            gram.emit(&mut states[lane], cur, value, &mut out[lane]);
+                        } else {
+                            terminals[lane].push(cur);
+                        }
+                        bytes[lane] += value.len();
+                        if bytes[lane] > gram.output_limit(&states[lane]) {
+                            return false;
+                        }
+                    }
+                }
+                true
+            });
+        }
+
+        if corrupting {
+            return;
+        }
+        for (lane, buf) in out.iter_mut().enumerate() {
+            buf.reserve_exact(self.bytes[lane]);
+            for &id in &self.terminals[lane] {
+                if let Fragment::Terminal(value) = gram.lookup_fragment(id) {
+                    buf.extend_from_slice(value);
+                }
+            }
+        }
+    }
+}
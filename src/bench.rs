@@ -0,0 +1,143 @@
+// Comparing generation speed and validity with other generators
+//
+// The bench-compare command generates the same number of samples from a
+// grammar with this crate and with each reference generator (Grammarinator,
+// fzero, anything with a command line) and reports samples and bytes per
+// second, relative to ours, and the share of samples a validator (the
+// target, a reference parser) accepts, see selftest::accepted().
+//
+// A reference is a command line that writes samples as files into a
+// directory: @@ in it becomes the directory, ## the number of samples, e.g.
+//
+//   grammarinator=grammarinator-generate JsonGenerator.JsonGenerator -n ## -o @@/%d.json
+//
+// Its time is the wall clock time of the whole command, process start up
+// and file writes included, ours is generation in memory. A reference whose
+// program is not installed is skipped.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "batch")]
+use crate::batch::Batch;
+use crate::executor::Executor;
+use crate::grammar::GrammarRust;
+#[cfg(feature = "batch")]
+use crate::rng::SplitSeed;
+use crate::selftest;
+
+// One generator's samples and how long they took
+#[derive(Clone, Debug, Default)]
+pub struct Run {
+    pub name: String,
+    pub samples: Vec<Vec<u8>>,
+    pub time: Duration,
+    // samples the validator accepted, None without one
+    pub valid: Option<usize>,
+}
+
+impl Run {
+    pub fn bytes(&self) -> usize {
+        self.samples.iter().map(|x| x.len()).sum()
+    }
+
+    pub fn samples_per_sec(&self) -> f64 {
+        self.samples.len() as f64 / self.time.as_secs_f64().max(1e-9)
+    }
+
+    pub fn bytes_per_sec(&self) -> f64 {
+        self.bytes() as f64 / self.time.as_secs_f64().max(1e-9)
+    }
+
+    // Share of samples accepted, percent
+    pub fn validity(&self) -> Option<f64> {
+        self.valid.map(|x| x as f64 * 100. / self.samples.len().max(1) as f64)
+    }
+}
+
+// Generate samples from seed with this crate
+pub fn own(gram: &GrammarRust, seed: usize, samples: usize) -> Run {
+    let mut cases = gram.iter_testcases(seed);
+    let mut out = Vec::with_capacity(samples);
+    let start = Instant::now();
+    for _ in 0..samples {
+        out.push(cases.next_ref().to_vec());
+    }
+    Run {
+        name: env!("CARGO_PKG_NAME").to_string(),
+        samples: out,
+        time: start.elapsed(),
+        valid: None,
+    }
+}
+
+// Lanes of the batched generator
+#[cfg(feature = "batch")]
+const LANES: usize = 64;
+
+// Generate samples from seed with the batched generator, see batch.rs
+#[cfg(feature = "batch")]
+pub fn batched(gram: &GrammarRust, seed: usize, samples: usize) -> Run {
+    let split = SplitSeed::new(seed as u64);
+    let seeds = (0..LANES).map(|x| split.stream(x)).collect::<Vec<_>>();
+    let mut batch = Batch::new(gram, &seeds);
+    let mut bufs = vec![Vec::new(); LANES];
+    let mut out = Vec::with_capacity(samples);
+    let start = Instant::now();
+    while out.len() < samples {
+        batch.generate(&mut bufs);
+        out.extend(bufs.iter().take(samples - out.len()).cloned());
+    }
+    Run {
+        name: format!("{} (batch)", env!("CARGO_PKG_NAME")),
+        samples: out,
+        time: start.elapsed(),
+        valid: None,
+    }
+}
+
+// Generate samples with the reference command line argv, see the top of the
+// file. None if its program is not installed
+pub fn reference(name: &str, argv: &[String], samples: usize, dir: &Path)
+        -> io::Result<Option<Run>> {
+    let _ = fs::remove_dir_all(dir);
+    fs::create_dir_all(dir)?;
+    let args = argv.iter().map(|x| x.replace("@@", &dir.to_string_lossy())
+        .replace("##", &samples.to_string())).collect::<Vec<_>>();
+
+    let start = Instant::now();
+    let status = match Command::new(&args[0]).args(&args[1..])
+            .stdin(Stdio::null()).stdout(Stdio::null()).status() {
+        Ok(status) => status,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(io::Error::new(e.kind(),
+            format!("running {} failed: {}", args[0], e))),
+    };
+    let time = start.elapsed();
+    if !status.success() {
+        return Err(io::Error::other(format!("{} failed: {}", args[0], status)));
+    }
+
+    let mut paths = fs::read_dir(dir)?.map(|x| x.map(|x| x.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    paths.sort();
+    let out = paths.iter().filter(|x| x.is_file()).take(samples)
+        .map(fs::read).collect::<io::Result<Vec<_>>>()?;
+    fs::remove_dir_all(dir)?;
+    Ok(Some(Run { name: name.to_string(), samples: out, time, valid: None }))
+}
+
+// Run every sample through validator and count the accepted ones
+pub fn check(run: &mut Run, validator: &mut dyn Executor) -> io::Result<()> {
+    let mut valid = 0;
+    for sample in &run.samples {
+        if selftest::accepted(&validator.run(sample)?) {
+            valid += 1;
+        }
+    }
+    run.valid = Some(valid);
+    Ok(())
+}
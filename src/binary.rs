@@ -0,0 +1,233 @@
+// Terminals for binary formats
+//
+// Headers and packets are made of integers of a fixed width and byte
+// order, and bitfields, which are tedious to write as byte strings (json
+// strings cannot even hold most bytes). A symbol of the form <kind:value>
+// stands for the encoded bytes:
+//
+//   "<u16le:0x7f45>"        constant, u8 i8 u16le u16be i16le ... i64be
+//   "<u32be:1|2|0x10>"      one of the listed constants
+//   "<u64le:*>"             any value, every byte uniform
+//   "<bits:4=4,4=*,8=0>"    bitfields packed most significant bit first,
+//                           each width=constant or width=*, whole bytes
+//
+// Constants become terminals when the grammar is compiled. The other forms
+// are rules, named like the symbol, which expand() adds to the grammar
+// when loading it: an enumeration has one alternative per constant, a
+// random integer is a sequence of <u8:*>, a bitfield one constant or
+// enumerated byte after the other. A rule of the grammar with the same
+// name takes precedence.
+
+use std::collections::HashMap;
+
+use crate::grammar::Grammar;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Int {
+    bytes: usize,
+    signed: bool,
+    big_endian: bool,
+}
+
+impl Int {
+    fn parse(kind: &str) -> Option<Int> {
+        let (signed, rest) = match kind.as_bytes().first()? {
+            b'u' => (false, &kind[1..]),
+            b'i' => (true, &kind[1..]),
+            _ => return None,
+        };
+        let (bits, big_endian) = match rest {
+            "8" => ("8", false),
+            _ if rest.ends_with("le") => (&rest[..rest.len() - 2], false),
+            _ if rest.ends_with("be") => (&rest[..rest.len() - 2], true),
+            _ => return None,
+        };
+        let bytes = match bits {
+            "8" if rest == "8" => 1,
+            "16" => 2,
+            "32" => 4,
+            "64" => 8,
+            _ => return None,
+        };
+        Some(Int { bytes, signed, big_endian })
+    }
+
+    // Parse a constant and check it fits
+    fn value(&self, text: &str) -> Result<u64, String> {
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, text),
+        };
+        let magnitude = match digits.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => digits.parse(),
+        }.map_err(|_| format!("{:?} is not a number", text))?;
+
+        let bits = self.bytes as u32 * 8;
+        let fits = match (self.signed, negative) {
+            (false, true) => magnitude == 0,
+            (false, false) => bits == 64 || magnitude < 1 << bits,
+            (true, false) => magnitude < 1 << (bits - 1),
+            (true, true) => magnitude <= 1 << (bits - 1),
+        };
+        if !fits {
+            return Err(format!("{} does not fit {} bytes{}", text, self.bytes,
+                if self.signed { " signed" } else { "" }));
+        }
+        Ok(if negative { magnitude.wrapping_neg() } else { magnitude })
+    }
+
+    fn encode(&self, value: u64) -> Vec<u8> {
+        let bytes = value.to_le_bytes();
+        let mut out = bytes[..self.bytes].to_vec();
+        if self.big_endian {
+            out.reverse();
+        }
+        out
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Spec {
+    // integer, None for any value
+    Int(Int, Option<Vec<u64>>),
+    // bitfields, (width, None for any value)
+    Bits(Vec<(u32, Option<u64>)>),
+}
+
+// The spec of a symbol: None if it is not one, an error if it looks like
+// one but is malformed
+fn parse(symbol: &str) -> Option<Result<Spec, String>> {
+    let inner = symbol.strip_prefix('<')?.strip_suffix('>')?;
+    let (kind, values) = inner.split_once(':')?;
+    let error = |what: String| format!("{}: {}", symbol, what);
+    if kind == "bits" {
+        return Some(parse_bits(values).map_err(error));
+    }
+    let int = Int::parse(kind)?;
+    if values == "*" {
+        return Some(Ok(Spec::Int(int, None)));
+    }
+    Some(values.split('|').map(|x| int.value(x.trim()))
+        .collect::<Result<Vec<_>, _>>()
+        .map(|values| Spec::Int(int, Some(values)))
+        .map_err(error))
+}
+
+fn parse_bits(fields: &str) -> Result<Spec, String> {
+    let fields = fields.split(',').map(|field| {
+        let (width, value) = field.split_once('=')
+            .ok_or_else(|| format!("field {:?} is not width=value", field))?;
+        let width: u32 = width.trim().parse().ok().filter(|x| (1..=64).contains(x))
+            .ok_or_else(|| format!("field width {:?} is not 1 to 64", width))?;
+        let value = match value.trim() {
+            "*" => None,
+            value => Some(Int { bytes: 8, signed: false, big_endian: false }
+                .value(value)?).filter(|&x| width == 64 || x < 1 << width)
+                .map(Some)
+                .ok_or_else(|| format!("{} does not fit {} bits", value, width))?,
+        };
+        Ok((width, value))
+    }).collect::<Result<Vec<_>, String>>()?;
+    if fields.iter().map(|x| x.0).sum::<u32>() % 8 != 0 {
+        return Err("fields do not add up to whole bytes".to_string());
+    }
+    Ok(Spec::Bits(fields))
+}
+
+impl Spec {
+    // Bytes of a spec with a single value
+    fn constant(&self) -> Option<Vec<u8>> {
+        match self {
+            Spec::Int(int, Some(values)) if values.len() == 1 =>
+                Some(int.encode(values[0])),
+            Spec::Bits(_) => {
+                let (masks, values) = self.bit_bytes();
+                masks.iter().all(|&x| x == 0xff).then_some(values)
+            }
+            _ => None,
+        }
+    }
+
+    // Fixed bits of every byte of a bitfield and their values
+    fn bit_bytes(&self) -> (Vec<u8>, Vec<u8>) {
+        let Spec::Bits(fields) = self else {
+            unreachable!("only bitfields have bit bytes");
+        };
+        let (mut masks, mut values) = (Vec::new(), Vec::new());
+        let mut pos = 0;
+        for &(width, value) in fields {
+            for bit in (0..width).rev() {
+                if pos % 8 == 0 {
+                    masks.push(0);
+                    values.push(0);
+                }
+                let shift = 7 - pos % 8;
+                if let Some(value) = value {
+                    *masks.last_mut().unwrap() |= 1 << shift;
+                    *values.last_mut().unwrap() |= ((value >> bit) as u8 & 1) << shift;
+                }
+                pos += 1;
+            }
+        }
+        (masks, values)
+    }
+
+    // Alternatives of the rule standing for a spec with more than one value
+    fn alternatives(&self, kind: &str) -> Vec<Vec<String>> {
+        match self {
+            Spec::Int(int, Some(values)) => values.iter().map(|&x| vec![match int.signed {
+                true => format!("<{}:{}>", kind, x as i64),
+                false => format!("<{}:{:#x}>", kind, x),
+            }]).collect(),
+            Spec::Int(int, None) if int.bytes == 1 => (0..=255)
+                .map(|x| vec![format!("<u8:{}>", x)]).collect(),
+            Spec::Int(int, None) => vec![vec!["<u8:*>".to_string(); int.bytes]],
+            Spec::Bits(_) => {
+                let (masks, values) = self.bit_bytes();
+                vec![masks.iter().zip(values).map(|(&mask, value)| match mask {
+                    0xff => format!("<u8:{}>", value),
+                    0 => "<u8:*>".to_string(),
+                    _ => format!("<u8:{}>", (0..=255u8).filter(|x| x & mask == value)
+                        .map(|x| x.to_string()).collect::<Vec<_>>().join("|")),
+                }).collect()]
+            }
+        }
+    }
+}
+
+// Bytes of a constant binary terminal, None for anything else
+pub fn constant(symbol: &str) -> Option<Vec<u8>> {
+    parse(symbol)?.ok()?.constant()
+}
+
+// Whether a symbol is of the form <kind:value>, well formed or not
+pub fn is_symbol(symbol: &str) -> bool {
+    parse(symbol).is_some()
+}
+
+// Add the rules for the binary terminals of the grammar that are not
+// constants, see the top of the file. Errors name malformed ones
+pub fn expand(grammar: &mut Grammar) -> Result<(), String> {
+    let mut pending = grammar.0.values().flatten().flatten().cloned()
+        .collect::<Vec<_>>();
+    let mut added: HashMap<String, Vec<Vec<String>>> = HashMap::new();
+    while let Some(symbol) = pending.pop() {
+        if grammar.0.contains_key(&symbol) || added.contains_key(&symbol) {
+            continue;
+        }
+        let spec = match parse(&symbol) {
+            Some(spec) => spec?,
+            None => continue,
+        };
+        if spec.constant().is_some() {
+            continue;
+        }
+        let kind = symbol[1..].split(':').next().unwrap();
+        let alternatives = spec.alternatives(kind);
+        pending.extend(alternatives.iter().flatten().cloned());
+        added.insert(symbol, alternatives);
+    }
+    grammar.0.extend(added);
+    Ok(())
+}
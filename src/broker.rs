@@ -0,0 +1,228 @@
+// Distributed fuzzing through a central broker
+//
+// Clients push the inputs that found new coverage locally (and crashes) to
+// the broker and pull everything other clients found since their last sync.
+// The broker keeps a grow-only set of entries keyed by content hash, so
+// merging is just a union: the order in which clients sync never matters
+// and the same input found twice is stored once.
+//
+// Wire format, all integers little endian, one request per connection:
+//   request:  cursor u64, count u32, count * entry
+//   response: new cursor u64, count u32, count * entry (corpus only)
+//   entry:    kind u8, len u32, len bytes
+
+use std::collections::HashSet;
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::error;
+use crate::hash::hash64;
+
+// Refuse entries bigger than this, protects the broker from garbage
+const MAX_ENTRY_SIZE: usize = 64 << 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum EntryKind {
+    // input that increased coverage somewhere
+    Corpus,
+    // input that crashed the target somewhere
+    Crash,
+}
+
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub kind: EntryKind,
+    pub data: Vec<u8>,
+}
+
+fn read_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(r: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn write_entries(w: &mut impl Write, cursor: u64, entries: &[&Entry])
+        -> io::Result<()> {
+    w.write_all(&cursor.to_le_bytes())?;
+    w.write_all(&(entries.len() as u32).to_le_bytes())?;
+    for entry in entries {
+        w.write_all(&[entry.kind as u8])?;
+        w.write_all(&(entry.data.len() as u32).to_le_bytes())?;
+        w.write_all(&entry.data)?;
+    }
+    w.flush()
+}
+
+fn read_entries(r: &mut impl Read) -> io::Result<(u64, Vec<Entry>)> {
+    let cursor = read_u64(r)?;
+    let count = read_u32(r)?;
+
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let mut kind = [0u8];
+        r.read_exact(&mut kind)?;
+        let kind = match kind[0] {
+            0 => EntryKind::Corpus,
+            1 => EntryKind::Crash,
+            _ => return Err(io::Error::new(ErrorKind::InvalidData,
+                "bad entry kind")),
+        };
+
+        let len = read_u32(r)? as usize;
+        if len > MAX_ENTRY_SIZE {
+            return Err(io::Error::new(ErrorKind::InvalidData,
+                "entry too large"));
+        }
+        let mut data = vec![0u8; len];
+        r.read_exact(&mut data)?;
+        entries.push(Entry { kind, data });
+    }
+    Ok((cursor, entries))
+}
+
+// Merged state of all clients
+struct BrokerState {
+    // corpus entries in arrival order, cursors index into this
+    corpus: Vec<Entry>,
+
+    // (kind, content hash) of everything stored
+    seen: HashSet<(EntryKind, u64)>,
+
+    // where entries are persisted
+    dir: PathBuf,
+    crashes: usize,
+}
+
+impl BrokerState {
+    // Add an entry unless we have it already
+    fn merge(&mut self, entry: Entry) -> io::Result<()> {
+        let hash = hash64(&entry.data);
+        if !self.seen.insert((entry.kind, hash)) {
+            return Ok(());
+        }
+
+        match entry.kind {
+            EntryKind::Corpus => {
+                let path = self.dir.join("queue").join(
+                    format!("id-{:06}-{:016x}", self.corpus.len(), hash));
+                std::fs::write(path, &entry.data)?;
+                self.corpus.push(entry);
+            }
+            EntryKind::Crash => {
+                self.crashes += 1;
+                let path = self.dir.join("crashes").join(
+                    format!("crash-{:06}-{:016x}", self.crashes, hash));
+                std::fs::write(path, &entry.data)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn handle_client(stream: TcpStream, state: &Mutex<BrokerState>)
+        -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(60)))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let (cursor, entries) = read_entries(&mut reader)?;
+
+    let mut state = state.lock().unwrap();
+    for entry in entries {
+        state.merge(entry)?;
+    }
+
+    // everything the client has not seen yet, including its own pushes
+    // (the client filters those)
+    let start = (cursor as usize).min(state.corpus.len());
+    let new = state.corpus[start..].iter().collect::<Vec<_>>();
+    write_entries(&mut BufWriter::new(stream), state.corpus.len() as u64,
+        &new)
+}
+
+// Serve clients forever, persisting the merged corpus below dir
+pub fn run_broker(addr: &str, dir: PathBuf) -> io::Result<()> {
+    std::fs::create_dir_all(dir.join("queue"))?;
+    std::fs::create_dir_all(dir.join("crashes"))?;
+
+    let state = Arc::new(Mutex::new(BrokerState {
+        corpus: Vec::new(),
+        seen: HashSet::new(),
+        dir,
+        crashes: 0,
+    }));
+
+    let listener = TcpListener::bind(addr)?;
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let state = state.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = handle_client(stream, &state) {
+                error!("sync", "broker: sync with client failed: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+// Client side of the sync, owned by whoever drives the periodic sync
+pub struct BrokerClient {
+    addr: String,
+
+    // position in the broker corpus we are synced up to
+    cursor: u64,
+
+    // entries found locally since the last sync
+    outbox: Vec<Entry>,
+
+    // hashes of corpus entries we already have
+    known: HashSet<u64>,
+}
+
+impl BrokerClient {
+    pub fn new(addr: &str) -> Self {
+        BrokerClient {
+            addr: addr.to_string(),
+            cursor: 0,
+            outbox: Vec::new(),
+            known: HashSet::new(),
+        }
+    }
+
+    // Remember a local finding for the next sync
+    pub fn push(&mut self, kind: EntryKind, data: &[u8]) {
+        if kind == EntryKind::Corpus && !self.known.insert(hash64(data)) {
+            return;
+        }
+        self.outbox.push(Entry { kind, data: data.to_vec() });
+    }
+
+    // Exchange findings with the broker, returns the corpus entries other
+    // clients found. On failure the outbox is kept for the next attempt
+    pub fn sync(&mut self) -> io::Result<Vec<Vec<u8>>> {
+        let addr = self.addr.to_socket_addrs()?.next().ok_or_else(||
+            io::Error::new(ErrorKind::InvalidInput, "address did not resolve"))?;
+        let stream = TcpStream::connect_timeout(&addr, Duration::from_secs(5))?;
+        stream.set_read_timeout(Some(Duration::from_secs(60)))?;
+
+        let outbox = self.outbox.iter().collect::<Vec<_>>();
+        write_entries(&mut BufWriter::new(stream.try_clone()?), self.cursor,
+            &outbox)?;
+        let (cursor, entries) = read_entries(&mut BufReader::new(stream))?;
+
+        self.outbox.clear();
+        self.cursor = cursor;
+        Ok(entries.into_iter()
+            .filter(|x| self.known.insert(hash64(&x.data)))
+            .map(|x| x.data).collect())
+    }
+}
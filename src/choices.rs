@@ -0,0 +1,107 @@
+// Choice sequences
+//
+// A derivation is fully described by the alternative taken at every
+// non-terminal with more than one, in preorder. Stored as LEB128 varints
+// that is a few bytes per test case, and it only depends on the order of
+// the alternatives in the grammar, not on the RNG or on fragment ids, so it
+// replays the same input on any machine and across versions.
+//
+// Replay is forgiving so the byte string can be mutated freely: an index
+// past the end wraps around, and once the choices run out every remaining
+// non-terminal takes its cheapest way out.
+
+use crate::grammar::{Fragment, FragmentId, GeneratorState, GrammarRust};
+use crate::tree::{Tree, TreeStack};
+
+// Append value as LEB128
+fn push_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+// Read a LEB128 value, None once the input is used up
+fn read_varint(input: &mut &[u8]) -> Option<usize> {
+    let mut value = 0usize;
+    let mut shift = 0;
+    loop {
+        let (&byte, rest) = input.split_first()?;
+        *input = rest;
+        if shift < usize::BITS {
+            value |= ((byte & 0x7f) as usize) << shift;
+        }
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+}
+
+impl Tree {
+    // Append the choice sequence of the derivation to out
+    pub fn choices(&self, grammar: &GrammarRust, out: &mut Vec<u8>) {
+        // the option taken always directly follows the non-terminal
+        for pair in self.nodes.windows(2) {
+            if let Fragment::NonTerminal(options) = grammar.lookup_fragment(pair[0].fragment) {
+                if options.len() > 1 {
+                    let index = options.iter()
+                        .position(|&x| x == pair[1].fragment)
+                        .expect("tree does not match the grammar");
+                    push_varint(out, index);
+                }
+            }
+        }
+    }
+}
+
+impl GrammarRust {
+    // Derive the tree a choice sequence describes, rooted at from
+    pub fn replay_choices(&self, from: FragmentId, mut choices: &[u8],
+            stack: &mut TreeStack, tree: &mut Tree) {
+        self.derive_tree(from, stack, tree, |cur, options, _, _| {
+            if options.len() == 1 {
+                return Some(options[0]);
+            }
+            match read_varint(&mut choices) {
+                Some(index) => Some(options[index % options.len()]),
+                None => self.cheapest(cur),
+            }
+        });
+    }
+
+    // Derive the full test case a choice sequence describes
+    pub fn replay_full_choices(&self, choices: &[u8],
+            stack: &mut TreeStack, tree: &mut Tree) {
+        self.replay_choices(self.start(), choices, stack, tree);
+    }
+}
+
+impl GrammarRust {
+    // Mutate a choice sequence in place: change, insert or delete a few
+    // choices. Every byte string replays to a valid derivation, so this is
+    // structure aware without touching a tree
+    pub fn mutate_choices(&self, state: &mut GeneratorState, choices: &mut Vec<u8>) {
+        for _ in 0..1 + state.rand() % 4 {
+            let len = choices.len();
+            match state.rand() % 4 {
+                // different alternative somewhere
+                0 | 1 if len > 0 => choices[state.rand() % len] = state.rand() as u8 & 0x7f,
+                // drop a run of choices, the tail shifts to other places
+                2 if len > 0 => {
+                    let start = state.rand() % len;
+                    let end = start + 1 + state.rand() % (len - start).min(16);
+                    choices.drain(start..end);
+                }
+                // extra choices, appended ones grow the derivation
+                _ => {
+                    let pos = state.rand() % (len + 1);
+                    let count = 1 + state.rand() % 8;
+                    choices.splice(pos..pos,
+                        (0..count).map(|_| state.rand() as u8 & 0x7f));
+                }
+            }
+        }
+    }
+}
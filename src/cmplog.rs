@@ -0,0 +1,200 @@
+// Input-to-state substitution with a comparison logging build (RedQueen)
+//
+// A second build of the target, instrumented with AFL++ CMPLOG, records
+// the operands of its comparisons (integer compares and strcmp/memcmp
+// style calls) in a shared memory map while it runs an input. Operands
+// that differ are a guard the input failed: when one side shows up as the
+// exact bytes of a node of the derivation (a keyword, a number, a magic
+// value), the bytes of that node are replaced by the other side. Integers
+// are looked for in decimal, hex and raw little/big endian form.
+//
+// The map layout follows include/cmplog.h of AFL++ 4.x.
+
+use std::collections::HashSet;
+use std::io;
+
+#[cfg(unix)]
+use crate::coverage::ShmCoverageMap;
+#[cfg(unix)]
+use crate::executor::{Executor, ProcessExecutor};
+
+// Comparison sites and entries logged per site
+const CMP_MAP_W: usize = 65536;
+const CMP_MAP_H: usize = 32;
+const CMP_MAP_RTN_H: usize = CMP_MAP_H / 2;
+
+// Header per site, operand entries of 72 bytes each
+const HEADER_SIZE: usize = 2;
+const ENTRY_SIZE: usize = 72;
+#[cfg(unix)]
+const MAP_SIZE: usize = CMP_MAP_W * HEADER_SIZE + CMP_MAP_W * CMP_MAP_H * ENTRY_SIZE;
+
+// Header type of call (routine) operands, integer compares are 0
+const CMP_TYPE_RTN: u16 = 1;
+
+// Patched inputs derived from one seed at most
+pub const MAX_PATCHES: usize = 256;
+
+// Operands of a comparison that did not hold
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Comparison {
+    // integer compare of size bytes
+    Int { size: usize, left: u64, right: u64 },
+    // strcmp, memcmp, ...
+    Bytes { left: Vec<u8>, right: Vec<u8> },
+}
+
+impl Comparison {
+    // Forms an operand can take in the input, each with what to put
+    // there instead to satisfy the comparison
+    fn substitutions(&self, out: &mut Vec<(Vec<u8>, Vec<u8>)>) {
+        match self {
+            &Comparison::Int { size, left, right } => {
+                for (from, to) in [(left, right), (right, left)] {
+                    for (from, to) in encodings(size, from).zip(encodings(size, to)) {
+                        out.push((from, to));
+                    }
+                }
+            }
+            Comparison::Bytes { left, right } => {
+                out.push((left.clone(), right.clone()));
+                out.push((right.clone(), left.clone()));
+            }
+        }
+    }
+}
+
+// Textual and binary forms of an integer of size bytes, in a fixed order
+fn encodings(size: usize, value: u64) -> impl Iterator<Item = Vec<u8>> {
+    let bits = size * 8;
+    let value = if bits >= 64 { value } else { value & ((1 << bits) - 1) };
+    let signed = if bits >= 64 || value >> (bits - 1) == 0 {
+        value as i64
+    } else {
+        (value | !((1 << bits) - 1)) as i64
+    };
+    [
+        value.to_string().into_bytes(),
+        signed.to_string().into_bytes(),
+        format!("{:x}", value).into_bytes(),
+        format!("0x{:x}", value).into_bytes(),
+        value.to_le_bytes()[..size].to_vec(),
+        value.to_be_bytes()[8 - size..].to_vec(),
+    ].into_iter()
+}
+
+// Parse the failed comparisons out of a map
+fn parse(map: &[u8], out: &mut Vec<Comparison>) {
+    let (headers, log) = map.split_at(CMP_MAP_W * HEADER_SIZE);
+    let u64_at = |x: &[u8], off: usize|
+        u64::from_le_bytes(x[off..off + 8].try_into().unwrap());
+
+    for (site, header) in headers.chunks_exact(HEADER_SIZE).enumerate() {
+        // hits:6 shape:5 type:1 attribute:4, lowest bits first
+        let header = u16::from_le_bytes([header[0], header[1]]);
+        let hits = (header & 0x3f) as usize;
+        if hits == 0 {
+            continue;
+        }
+        let shape = ((header >> 6) & 0x1f) as usize;
+        let rtn = (header >> 11) & 1 == CMP_TYPE_RTN;
+
+        let entries = &log[site * CMP_MAP_H * ENTRY_SIZE..][..CMP_MAP_H * ENTRY_SIZE];
+        let max = if rtn { CMP_MAP_RTN_H } else { CMP_MAP_H };
+        for entry in entries.chunks_exact(ENTRY_SIZE).take(hits.min(max)) {
+            let comparison = if rtn {
+                let (left_len, right_len) = (entry[64].min(32) as usize,
+                    entry[65].min(32) as usize);
+                Comparison::Bytes {
+                    left: entry[..left_len].to_vec(),
+                    right: entry[32..32 + right_len].to_vec(),
+                }
+            } else {
+                Comparison::Int {
+                    size: (shape + 1).min(8),
+                    left: u64_at(entry, 0),
+                    right: u64_at(entry, 32),
+                }
+            };
+            let failed = match &comparison {
+                Comparison::Int { left, right, .. } => left != right,
+                Comparison::Bytes { left, right } => !left.is_empty()
+                    && !right.is_empty() && left != right,
+            };
+            if failed && !out.contains(&comparison) {
+                out.push(comparison);
+            }
+        }
+    }
+}
+
+// Runs inputs through the comparison logging build of the target
+#[cfg(unix)]
+pub struct CmpLog {
+    inner: ProcessExecutor,
+    map: ShmCoverageMap,
+}
+
+#[cfg(unix)]
+impl CmpLog {
+    // inner runs the cmplog build, set up like the fuzzed target
+    pub fn new(mut inner: ProcessExecutor) -> io::Result<Self> {
+        let map = ShmCoverageMap::new(MAP_SIZE)?;
+        inner.env("__AFL_CMPLOG_SHM_ID", &map.id().to_string());
+        Ok(CmpLog { inner, map })
+    }
+
+    // Failed comparisons of one run of input
+    pub fn comparisons(&mut self, input: &[u8]) -> io::Result<Vec<Comparison>> {
+        // entries are only read as far as their header says
+        self.map.as_mut_slice()[..CMP_MAP_W * HEADER_SIZE].fill(0);
+        self.inner.run(input)?;
+        let mut comparisons = Vec::new();
+        parse(self.map.as_slice(), &mut comparisons);
+        Ok(comparisons)
+    }
+}
+
+// CMPLOG needs shared memory, elsewhere there is no way to get one
+#[cfg(not(unix))]
+pub enum CmpLog {}
+
+#[cfg(not(unix))]
+impl CmpLog {
+    pub fn comparisons(&mut self, _input: &[u8]) -> io::Result<Vec<Comparison>> {
+        match *self {}
+    }
+}
+
+// Inputs with one node of the derivation replaced to satisfy a failed
+// comparison. input is the serialized derivation, spans the byte range of
+// every node (see Tree::serialize_spans()). At most MAX_PATCHES
+pub fn patches(comparisons: &[Comparison], input: &[u8],
+        spans: &[(usize, usize)]) -> Vec<Vec<u8>> {
+    let mut substitutions = Vec::new();
+    for comparison in comparisons {
+        comparison.substitutions(&mut substitutions);
+    }
+
+    let mut seen = HashSet::new();
+    let mut patches = Vec::new();
+    for &(start, end) in spans {
+        let text = &input[start..end];
+        if text.is_empty() {
+            continue;
+        }
+        for (from, to) in &substitutions {
+            if from.as_slice() != text || !seen.insert((start, end, to)) {
+                continue;
+            }
+            let mut patched = input[..start].to_vec();
+            patched.extend_from_slice(to);
+            patched.extend_from_slice(&input[end..]);
+            patches.push(patched);
+            if patches.len() == MAX_PATCHES {
+                return patches;
+            }
+        }
+    }
+    patches
+}
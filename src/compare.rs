@@ -0,0 +1,259 @@
+// A/B comparison of campaigns
+//
+// Whether a strategy or mutator mix is better only shows over repeated
+// runs: fuzzing is noisy enough that one run each says little. The
+// compare-runs command takes the runs of a baseline and of a candidate
+// (instance directories with stats.csv and campaign_report.json, sync
+// directories standing for all their instances, or stats.csv files) and
+// compares them metric by metric: medians, the Vargha-Delaney A12 effect
+// size (the chance a candidate run beats a baseline run, 0.5 for no
+// difference) and the two sided p-value of the Mann-Whitney U test. The
+// usual advice applies, 10 or more runs a side for the p-values to mean
+// something.
+//
+// Coverage over time is compared at fractions of the shortest run, so all
+// runs are looked at after the same time, and as the mean coverage up to
+// it (the area under the curve over time), which rewards finding early.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::dashboard::Sample;
+use crate::plot::{self, CSV_NAME};
+
+const REPORT_NAME: &str = "campaign_report.json";
+
+// Fractions of the common duration coverage is compared at
+const CHECKPOINTS: [f64; 3] = [0.25, 0.5, 1.0];
+
+// p-values below this are marked
+const SIGNIFICANT: f64 = 0.05;
+
+pub struct Run {
+    pub name: String,
+    // stats over time, empty without stats.csv
+    pub samples: Vec<Sample>,
+    // campaign_report.json, if the run finished
+    pub report: Option<serde_json::Value>,
+}
+
+impl Run {
+    fn report_number(&self, key: &str) -> Option<f64> {
+        self.report.as_ref()?.get(key)?.as_f64()
+    }
+
+    fn duration(&self) -> Option<f64> {
+        self.samples.last().map(|x| x.time).or_else(|| self.report_number("run_time"))
+    }
+
+    // Last sample at or before time
+    fn at(&self, time: f64) -> Option<&Sample> {
+        self.samples.iter().take_while(|x| x.time <= time).last()
+    }
+
+    // Mean edges over 0..time, the coverage curve a step function
+    fn mean_edges(&self, time: f64) -> Option<f64> {
+        if self.samples.is_empty() || time <= 0. {
+            return None;
+        }
+        let mut area = 0.;
+        for pair in self.samples.windows(2) {
+            let (from, to) = (pair[0].time.min(time), pair[1].time.min(time));
+            area += pair[0].edges as f64 * (to - from);
+        }
+        let last = self.samples.last().unwrap();
+        area += last.edges as f64 * (time - last.time).max(0.);
+        Some(area / time)
+    }
+}
+
+fn read_run(dir: &Path) -> io::Result<Option<Run>> {
+    let csv = dir.join(CSV_NAME);
+    let report = dir.join(REPORT_NAME);
+    if !csv.is_file() && !report.is_file() {
+        return Ok(None);
+    }
+    let samples = match csv.is_file() {
+        true => plot::read_csv(&csv)?,
+        false => Vec::new(),
+    };
+    let report = match report.is_file() {
+        true => Some(serde_json::from_slice(&fs::read(&report)?).map_err(|e|
+            io::Error::new(io::ErrorKind::InvalidData,
+                format!("{}: {}", report.display(), e)))?),
+        false => None,
+    };
+    Ok(Some(Run { name: dir.display().to_string(), samples, report }))
+}
+
+// The runs a path stands for, see the top of the file
+pub fn load_runs(path: &Path) -> io::Result<Vec<Run>> {
+    if path.is_file() {
+        let samples = plot::read_csv(path)?;
+        return Ok(vec![Run { name: path.display().to_string(), samples, report: None }]);
+    }
+    if let Some(run) = read_run(path)? {
+        return Ok(vec![run]);
+    }
+    let mut dirs = fs::read_dir(path)?.filter_map(|x| x.ok()).map(|x| x.path())
+        .filter(|x| x.is_dir()).collect::<Vec<_>>();
+    dirs.sort();
+    let runs = dirs.iter().filter_map(|x| read_run(x).transpose())
+        .collect::<io::Result<Vec<_>>>()?;
+    if runs.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!(
+            "{}: no {} or {} in it or its subdirectories", path.display(), CSV_NAME,
+            REPORT_NAME)));
+    }
+    Ok(runs)
+}
+
+// Vargha-Delaney A12 of b over a and the two sided Mann-Whitney p-value
+// (normal approximation with tie correction), None with fewer than two
+// values a side
+pub fn mann_whitney(a: &[f64], b: &[f64]) -> Option<(f64, f64)> {
+    if a.len() < 2 || b.len() < 2 {
+        return None;
+    }
+    let (n1, n2) = (a.len() as f64, b.len() as f64);
+
+    // ranks of the pooled values, ties get the mean of their ranks
+    let mut pooled = a.iter().map(|&x| (x, false)).chain(b.iter().map(|&x| (x, true)))
+        .collect::<Vec<_>>();
+    pooled.sort_by(|x, y| x.0.total_cmp(&y.0));
+    let (mut rank_b, mut ties) = (0., 0.);
+    let mut start = 0;
+    while start < pooled.len() {
+        let end = start + pooled[start..].iter()
+            .take_while(|x| x.0 == pooled[start].0).count();
+        let rank = (start + end + 1) as f64 / 2.;
+        rank_b += rank * pooled[start..end].iter().filter(|x| x.1).count() as f64;
+        let t = (end - start) as f64;
+        ties += t * t * t - t;
+        start = end;
+    }
+    let u = rank_b - n2 * (n2 + 1.) / 2.;
+    let a12 = u / (n1 * n2);
+
+    let n = n1 + n2;
+    let variance = n1 * n2 / 12. * ((n + 1.) - ties / (n * (n - 1.)));
+    if variance <= 0. {
+        // every value the same
+        return Some((a12, 1.));
+    }
+    // continuity correction
+    let z = ((u - n1 * n2 / 2.).abs() - 0.5).max(0.) / variance.sqrt();
+    Some((a12, erfc(z / std::f64::consts::SQRT_2)))
+}
+
+// Complementary error function, Numerical Recipes' erfcc (error below 1.2e-7)
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1. / (1. + 0.5 * z);
+    let r = t * (-z * z - 1.26551223 + t * (1.00002368 + t * (0.37409196
+        + t * (0.09678418 + t * (-0.18628806 + t * (0.27886807 + t * (-1.13520398
+        + t * (1.48851587 + t * (-0.82215223 + t * 0.17087277))))))))).exp();
+    if x >= 0. { r } else { 2. - r }
+}
+
+fn median(values: &[f64]) -> f64 {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    match sorted.len() {
+        0 => f64::NAN,
+        n if n % 2 == 1 => sorted[n / 2],
+        n => (sorted[n / 2 - 1] + sorted[n / 2]) / 2.,
+    }
+}
+
+// One compared metric
+pub struct Row {
+    pub metric: String,
+    pub baseline: Vec<f64>,
+    pub candidate: Vec<f64>,
+}
+
+// The metrics every run has a value of
+pub fn compare(baseline: &[Run], candidate: &[Run]) -> Vec<Row> {
+    let runs = || baseline.iter().chain(candidate);
+    let mut rows = Vec::new();
+    let mut row = |metric: String, value: &dyn Fn(&Run) -> Option<f64>| {
+        let a = baseline.iter().map(value).collect::<Option<Vec<_>>>();
+        let b = candidate.iter().map(value).collect::<Option<Vec<_>>>();
+        if let (Some(baseline), Some(candidate)) = (a, b) {
+            rows.push(Row { metric, baseline, candidate });
+        }
+    };
+
+    // coverage over time, up to the shortest run
+    let common = runs().filter(|x| !x.samples.is_empty())
+        .map(|x| x.samples.last().unwrap().time).reduce(f64::min);
+    if let Some(common) = common.filter(|&x| x > 0.) {
+        for fraction in CHECKPOINTS {
+            let time = common * fraction;
+            row(format!("edges at {}s", number(time)),
+                &|run| run.at(time).map(|x| x.edges as f64));
+        }
+        row(format!("mean edges over {}s", number(common)), &|run| run.mean_edges(common));
+    }
+
+    // totals at the end, each run as long as it went
+    row("edges".to_string(), &|run| run.samples.last().map(|x| x.edges as f64)
+        .or_else(|| run.report_number("edges")));
+    row("execs/s".to_string(), &|run| {
+        let execs = run.samples.last().map(|x| x.execs as f64)
+            .or_else(|| run.report_number("execs"))?;
+        Some(execs / run.duration().filter(|&x| x > 0.)?)
+    });
+    row("crashes".to_string(), &|run| run.samples.last().map(|x| x.crashes as f64)
+        .or_else(|| run.report_number("crashes")));
+    row("saved crashes".to_string(), &|run| run.report_number("saved_crashes"));
+    row("bugs".to_string(), &|run|
+        Some(run.report.as_ref()?.get("bugs")?.as_array()?.len() as f64));
+    row("timeouts".to_string(), &|run| run.samples.last().map(|x| x.timeouts as f64)
+        .or_else(|| run.report_number("timeouts")));
+    // runs without a crash never got one, as late as it gets
+    if runs().any(|x| x.samples.iter().any(|x| x.crashes > 0)) {
+        row("first crash (s)".to_string(), &|run| (!run.samples.is_empty()).then(||
+            run.samples.iter().find(|x| x.crashes > 0).map_or(f64::INFINITY, |x| x.time)));
+    }
+    rows
+}
+
+fn number(value: f64) -> String {
+    match value {
+        x if x.is_infinite() => "never".to_string(),
+        x if x.abs() >= 100. || x.fract() == 0. => format!("{:.0}", x),
+        x => format!("{:.2}", x),
+    }
+}
+
+// The comparison as a table, one metric per line
+pub fn write_table(baseline: &[Run], candidate: &[Run], rows: &[Row],
+        out: &mut impl Write) -> io::Result<()> {
+    writeln!(out, "baseline: {} run(s), candidate: {} run(s)", baseline.len(),
+        candidate.len())?;
+    writeln!(out, "{:<24} {:>12} {:>12} {:>9} {:>6} {:>8}", "metric", "baseline",
+        "candidate", "change", "A12", "p")?;
+    for row in rows {
+        let (a, b) = (median(&row.baseline), median(&row.candidate));
+        let change = match a != 0. && a.is_finite() && b.is_finite() {
+            true => format!("{:+.1}%", (b - a) / a.abs() * 100.),
+            false => "-".to_string(),
+        };
+        let (a12, p) = match mann_whitney(&row.baseline, &row.candidate) {
+            Some((a12, p)) => (format!("{:.2}", a12),
+                format!("{:.3}{}", p, if p < SIGNIFICANT { "*" } else { "" })),
+            None => ("-".to_string(), "-".to_string()),
+        };
+        writeln!(out, "{:<24} {:>12} {:>12} {:>9} {:>6} {:>8}", row.metric, number(a),
+            number(b), change, a12, p)?;
+    }
+    if baseline.len() < 2 || candidate.len() < 2 {
+        writeln!(out, "A12 and p need two runs a side or more")?;
+    } else {
+        writeln!(out, "A12 above 0.5: the candidate tends higher; * p < {}", SIGNIFICANT)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,87 @@
+// zstd compressed corpus entries
+//
+// Verbose formats (XML, JSON) compress very well, and corpora of millions
+// of them take a lot of disk otherwise. With compression on, saved entries
+// are zstd frames named <entry>.zst, and every directory gets an .index
+// listing each entry with its original and stored size. Whoever reads an
+// entry goes through read_entry(), which recognizes frames by their magic
+// number and decompresses them, so compressed and plain entries (and
+// instances) mix freely.
+//
+// Compression runs the zstd tool ($ZSTD, or zstd in $PATH). Entries are
+// only saved when something new turns up, starting a process for each is
+// cheap enough.
+
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+// First bytes of every zstd frame
+const MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+// File name suffix of compressed entries
+pub const SUFFIX: &str = ".zst";
+
+// Level used for entries, fast and still small
+const LEVEL: u32 = 3;
+
+fn zstd() -> OsString {
+    std::env::var_os("ZSTD").unwrap_or_else(|| "zstd".into())
+}
+
+// Pipe data through the zstd tool with args
+fn pipe(args: &[&str], data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut child = Command::new(zstd()).args(args)
+        .stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::inherit())
+        .spawn()
+        .map_err(|e| io::Error::new(e.kind(), format!("running zstd failed: {}", e)))?;
+
+    // feed stdin from another thread, zstd writes while it reads
+    let mut stdin = child.stdin.take().unwrap();
+    let mut out = Vec::new();
+    std::thread::scope(|s| -> io::Result<()> {
+        let writer = s.spawn(move || stdin.write_all(data));
+        child.stdout.take().unwrap().read_to_end(&mut out)?;
+        writer.join().expect("zstd writer panicked")
+    })?;
+    if !child.wait()?.success() {
+        return Err(io::Error::other("zstd failed"));
+    }
+    Ok(out)
+}
+
+pub fn compress(data: &[u8]) -> io::Result<Vec<u8>> {
+    pipe(&["-q", "-c", &format!("-{}", LEVEL)], data)
+}
+
+pub fn decompress(data: &[u8]) -> io::Result<Vec<u8>> {
+    pipe(&["-q", "-d", "-c"], data)
+}
+
+pub fn is_compressed(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+// Contents of a corpus entry, decompressed if it is a zstd frame
+pub fn read_entry(path: &Path) -> io::Result<Vec<u8>> {
+    let data = fs::read(path)?;
+    if is_compressed(&data) {
+        decompress(&data)
+    } else {
+        Ok(data)
+    }
+}
+
+// Note an entry in the .index of its directory: name, original size and
+// stored size, tab separated. An entry rewritten later (trim) gets another
+// line, the last one counts
+pub fn index(entry: &Path, size: usize, stored: usize) -> io::Result<()> {
+    let (Some(dir), Some(name)) = (entry.parent(), entry.file_name()) else {
+        return Err(io::Error::from(io::ErrorKind::InvalidInput));
+    };
+    let mut file = fs::OpenOptions::new().create(true).append(true)
+        .open(dir.join(".index"))?;
+    writeln!(file, "{}\t{}\t{}", name.to_string_lossy(), size, stored)
+}
@@ -0,0 +1,291 @@
+// Campaign configuration files
+//
+// The flat subset of TOML a campaign definition needs: `key = value` lines
+// with strings (basic and literal), integers, floats, booleans and arrays
+// of those (which may span lines), plus # comments. Of the tables only
+// arrays of tables are supported: every [[name]] header starts a table,
+// its keys up to the next header, appended to the array under name.
+//
+// A config file stands for command line flags, see to_args(). Keys are the
+// long flag names without the dashes. A flag that may be given more than
+// once takes an array, with a flag per element, since keys are unique.
+
+use std::fmt;
+use std::io;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Array(Vec<Value>),
+    Table(Vec<(String, Value)>),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::String(x) => write!(f, "{}", x),
+            Value::Integer(x) => write!(f, "{}", x),
+            Value::Float(x) => write!(f, "{}", x),
+            Value::Boolean(x) => write!(f, "{}", x),
+            Value::Array(x) => write!(f, "{}", x.iter().map(|x| x.to_string())
+                .collect::<Vec<_>>().join(" ")),
+            Value::Table(x) => write!(f, "{}", x.iter()
+                .map(|(key, value)| format!("{}={}", key, value))
+                .collect::<Vec<_>>().join(",")),
+        }
+    }
+}
+
+struct Parser<'a> {
+    text: &'a [u8],
+    pos: usize,
+    line: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, what: &str) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData,
+            format!("config line {}: {}", self.line, what))
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.get(self.pos).copied()
+    }
+
+    fn bump(&mut self) -> Option<u8> {
+        let byte = self.peek()?;
+        self.pos += 1;
+        if byte == b'\n' {
+            self.line += 1;
+        }
+        Some(byte)
+    }
+
+    // Skip spaces and tabs, with newlines and comments as well if asked to
+    fn skip(&mut self, newlines: bool) {
+        while let Some(byte) = self.peek() {
+            match byte {
+                b' ' | b'\t' | b'\r' => {
+                    self.bump();
+                }
+                b'\n' if newlines => {
+                    self.bump();
+                }
+                b'#' => while self.peek().is_some_and(|x| x != b'\n') {
+                    self.bump();
+                },
+                _ => break,
+            }
+        }
+    }
+
+    fn key(&mut self) -> io::Result<String> {
+        let start = self.pos;
+        while self.peek().is_some_and(|x|
+                x.is_ascii_alphanumeric() || x == b'-' || x == b'_') {
+            self.bump();
+        }
+        if start == self.pos {
+            return Err(self.error("expected a key"));
+        }
+        Ok(String::from_utf8_lossy(&self.text[start..self.pos]).into_owned())
+    }
+
+    fn string(&mut self, quote: u8) -> io::Result<Value> {
+        self.bump();
+        let mut out = Vec::new();
+        loop {
+            match self.bump() {
+                None | Some(b'\n') => return Err(self.error("unterminated string")),
+                Some(x) if x == quote => break,
+                // literal strings have no escapes
+                Some(b'\\') if quote == b'"' => {
+                    let escaped = match self.bump() {
+                        Some(b'n') => b'\n',
+                        Some(b't') => b'\t',
+                        Some(b'r') => b'\r',
+                        Some(b'"') => b'"',
+                        Some(b'\\') => b'\\',
+                        _ => return Err(self.error("unsupported escape")),
+                    };
+                    out.push(escaped);
+                }
+                Some(x) => out.push(x),
+            }
+        }
+        String::from_utf8(out).map(Value::String)
+            .map_err(|_| self.error("string is not valid UTF-8"))
+    }
+
+    fn value(&mut self) -> io::Result<Value> {
+        match self.peek() {
+            Some(quote @ (b'"' | b'\'')) => self.string(quote),
+            Some(b'[') => {
+                self.bump();
+                let mut items = Vec::new();
+                loop {
+                    self.skip(true);
+                    if self.peek() == Some(b']') {
+                        self.bump();
+                        return Ok(Value::Array(items));
+                    }
+                    items.push(self.value()?);
+                    self.skip(true);
+                    match self.peek() {
+                        Some(b',') => {
+                            self.bump();
+                        }
+                        Some(b']') => {}
+                        _ => return Err(self.error("expected ',' or ']'")),
+                    }
+                }
+            }
+            _ => {
+                let start = self.pos;
+                while self.peek().is_some_and(|x| !b" \t\r\n#,]".contains(&x)) {
+                    self.bump();
+                }
+                let word = std::str::from_utf8(&self.text[start..self.pos])
+                    .unwrap_or("").replace('_', "");
+                match word.as_str() {
+                    "true" => Ok(Value::Boolean(true)),
+                    "false" => Ok(Value::Boolean(false)),
+                    _ => word.parse().map(Value::Integer)
+                        .or_else(|_| word.parse().map(Value::Float))
+                        .map_err(|_| self.error("expected a value")),
+                }
+            }
+        }
+    }
+}
+
+// Parse a configuration file into its key/value pairs, in file order. An
+// array of tables is an array of Value::Table under its name
+pub fn parse(text: &str) -> io::Result<Vec<(String, Value)>> {
+    let mut parser = Parser { text: text.as_bytes(), pos: 0, line: 1 };
+    let mut ret: Vec<(String, Value)> = Vec::new();
+    // the array and the table in it keys go to, after a [[name]] header
+    let mut table: Option<(usize, usize)> = None;
+
+    loop {
+        parser.skip(true);
+        match parser.peek() {
+            None => return Ok(ret),
+            Some(b'[') => {
+                parser.bump();
+                if parser.bump() != Some(b'[') {
+                    return Err(parser.error("only arrays of tables are supported"));
+                }
+                parser.skip(false);
+                let name = parser.key()?;
+                parser.skip(false);
+                if parser.bump() != Some(b']') || parser.bump() != Some(b']') {
+                    return Err(parser.error("expected ']]'"));
+                }
+                let idx = match ret.iter().position(|(x, _)| *x == name) {
+                    Some(idx) => idx,
+                    None => {
+                        ret.push((name.clone(), Value::Array(Vec::new())));
+                        ret.len() - 1
+                    }
+                };
+                let tables = match &mut ret[idx].1 {
+                    Value::Array(x) if x.iter().all(|x| matches!(x, Value::Table(_))) => x,
+                    _ => return Err(parser.error(&format!("{} is not an array of tables",
+                        name))),
+                };
+                tables.push(Value::Table(Vec::new()));
+                table = Some((idx, tables.len() - 1));
+                parser.skip(false);
+                if parser.peek().is_some_and(|x| x != b'\n') {
+                    return Err(parser.error("expected end of line"));
+                }
+                continue;
+            }
+            _ => {}
+        }
+
+        let key = parser.key()?;
+        parser.skip(false);
+        if parser.bump() != Some(b'=') {
+            return Err(parser.error("expected '='"));
+        }
+        parser.skip(false);
+        let value = parser.value()?;
+
+        let keys = match table {
+            None => &mut ret,
+            Some((array, idx)) => match &mut ret[array].1 {
+                Value::Array(tables) => match &mut tables[idx] {
+                    Value::Table(keys) => keys,
+                    _ => unreachable!("tables are only pushed as tables"),
+                },
+                _ => unreachable!("arrays of tables stay arrays"),
+            },
+        };
+        if keys.iter().any(|(x, _)| *x == key) {
+            return Err(parser.error(&format!("duplicate key {}", key)));
+        }
+        keys.push((key, value));
+
+        // nothing but a comment may follow on the line
+        parser.skip(false);
+        if parser.peek().is_some_and(|x| x != b'\n') {
+            return Err(parser.error("expected end of line"));
+        }
+    }
+}
+
+// Flags that may be given more than once
+const REPEATABLE: &[&str] = &["--include", "--template", "--worker", "--import-dir",
+    "--import-corpus", "--position", "--output", "--reference", "--feedback", "--oracle",
+    "--input", "--baseline", "--candidate"];
+
+// Turn a config file into the equivalent command line flags plus the
+// target command line. Keys are the long flag names without the dashes,
+// except for grammar, output (-o), outputs (--output), main (-M),
+// secondary (-S) and target. Every element of an array is a flag of its
+// own for repeatable flags and arrays of tables (--worker), other arrays
+// are one value with the elements separated by spaces (--filter)
+pub fn to_args(text: &str) -> io::Result<(Vec<String>, Vec<String>)> {
+    let mut args = Vec::new();
+    let mut target = Vec::new();
+
+    for (key, value) in parse(text)? {
+        let flag = match key.as_str() {
+            "grammar" => {
+                args.push(value.to_string());
+                continue;
+            }
+            "target" => {
+                let Value::Array(items) = value else {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData,
+                        "target must be an array"));
+                };
+                target = items.iter().map(|x| x.to_string()).collect();
+                continue;
+            }
+            "output" => String::from("-o"),
+            "outputs" => String::from("--output"),
+            "main" => String::from("-M"),
+            "secondary" => String::from("-S"),
+            _ => format!("--{}", key),
+        };
+
+        match value {
+            Value::Boolean(true) => args.push(flag),
+            Value::Boolean(false) => {}
+            Value::Array(items) if REPEATABLE.contains(&flag.as_str())
+                    || items.iter().all(|x| matches!(x, Value::Table(_))) => {
+                for item in items {
+                    args.extend([flag.clone(), item.to_string()]);
+                }
+            }
+            value => args.extend([flag, value.to_string()]),
+        }
+    }
+    Ok((args, target))
+}
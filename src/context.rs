@@ -0,0 +1,69 @@
+// Everything a worker generates with
+//
+// Generation needs the RNG and RareBoost counters (GeneratorState), a
+// stack of fragments to visit, an output buffer and for trees a stack and
+// a scratch tree. A GenerationContext holds all of them, created once per
+// worker and reset between iterations: the buffers keep their capacity,
+// so once they have grown to the largest test case so far, generating
+// allocates nothing.
+
+use crate::derivation_cache::DerivationCache;
+use crate::grammar::{FragmentId, GeneratorState, GrammarRust};
+use crate::tree::{Tree, TreeStack};
+
+#[derive(Clone, Debug)]
+pub struct GenerationContext {
+    pub state: GeneratorState,
+
+    // fragments still to visit in generate()
+    pub stack: Vec<FragmentId>,
+
+    // output of generate_bytes()
+    pub buf: Vec<u8>,
+
+    // scratch space of tree derivations
+    pub tree_stack: TreeStack,
+    pub tree: Tree,
+
+    // expansions generate() may copy instead of deriving, see
+    // derivation_cache.rs
+    pub cache: Option<DerivationCache>,
+}
+
+impl GenerationContext {
+    pub fn new(seed: usize) -> Self {
+        GenerationContext {
+            state: GeneratorState::new(seed),
+            stack: Vec::new(),
+            buf: Vec::new(),
+            tree_stack: TreeStack::default(),
+            tree: Tree::default(),
+            cache: None,
+        }
+    }
+
+    // Empty the buffers for the next iteration, keeping their capacity.
+    // The state carries on
+    pub fn reset(&mut self) {
+        self.stack.clear();
+        self.buf.clear();
+        self.tree.nodes.clear();
+    }
+
+    // Generate a test case into the context's buffer and borrow it, valid
+    // until the next call
+    pub fn generate_bytes(&mut self, gram: &GrammarRust) -> &[u8] {
+        self.reset();
+        let mut buf = std::mem::take(&mut self.buf);
+        gram.generate(self, &mut buf);
+        self.buf = buf;
+        &self.buf
+    }
+
+    // Derive a tree for a complete test case into the context's scratch
+    // tree and borrow it, valid until the next call
+    pub fn generate_tree(&mut self, gram: &GrammarRust) -> &Tree {
+        gram.generate_full_tree(&mut self.state, &mut self.tree_stack, &mut self.tree);
+        &self.tree
+    }
+}
@@ -0,0 +1,238 @@
+// Corpus of derivation trees with an AFLFast style power schedule
+//
+// Seeds are visited round robin, each visit gets an energy: the number of
+// mutated children derived from it. Energy grows exponentially with the
+// number of times a seed was picked and shrinks with how often its path
+// was exercised overall (FAST schedule), so seeds on rare paths get the
+// bulk of the work. Fast and freshly found seeds get an extra boost.
+//
+// Over a long campaign the corpus piles up entries whose coverage later
+// ones have as well, and the round robin spends ever more of its time on
+// them. With culling on, every entry keeps its coverage signature (the
+// edges it hit with bucketed hit counts), and when the corpus grows past
+// the limit it is cut down to a subset with the same signatures, the way
+// afl-cmin does it: for every edge and hit count, rarest first, the
+// smallest and fastest entry having it stays unless one staying already
+// has it. Entries without coverage stay. The queue directory keeps the
+// files of culled entries. A corpus whose cut down size is close to the
+// limit grows by a quarter of the limit before the next pass.
+
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use crate::coverage::bucket;
+use crate::info;
+use crate::tree::Tree;
+
+// Energy of an average seed on its first visit
+const BASE_ENERGY: f64 = 16.0;
+
+// Energy limit for a single visit
+const MAX_ENERGY: u32 = 1024;
+
+pub struct CorpusEntry {
+    pub tree: Tree,
+
+    // serialized size in bytes
+    pub len: usize,
+
+    pub exec_time: Duration,
+
+    // hash of the coverage this entry exercises
+    pub path: u64,
+
+    // times the schedule picked this entry
+    pub fuzzed: u32,
+
+    // file name in the queue, entries kept for other reasons have none
+    pub name: Option<String>,
+
+    // coverage signature with culling on (see signature()), empty
+    // otherwise
+    pub signature: Vec<u32>,
+}
+
+#[derive(Default)]
+pub struct Corpus {
+    entries: Vec<CorpusEntry>,
+
+    // executions that exercised each path
+    path_hits: HashMap<u64, u64>,
+
+    // next entry to visit
+    cursor: usize,
+
+    // for the average execution time
+    total_exec_time: Duration,
+
+    // cull when the corpus grows past the limit, past cull_at after the
+    // first pass
+    cull: Option<usize>,
+    cull_at: usize,
+
+    // entries culled so far
+    culled: u64,
+}
+
+// Identify the path an execution took: which edges were hit, with
+// bucketed hit counts
+pub fn path_hash(map: &[u8]) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    for (idx, &count) in map.iter().enumerate() {
+        if count != 0 {
+            hash ^= ((idx as u64) << 8) | bucket(count) as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    hash
+}
+
+// Coverage signature of an execution for culling: every edge hit, with its
+// bucketed hit count in the low byte
+pub fn signature(map: &[u8]) -> Vec<u32> {
+    map.iter().enumerate().filter(|x| *x.1 != 0)
+        .map(|(idx, &count)| ((idx as u32) << 8) | bucket(count) as u32)
+        .collect()
+}
+
+impl Corpus {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, idx: usize) -> &CorpusEntry {
+        &self.entries[idx]
+    }
+
+    pub fn add(&mut self, entry: CorpusEntry) {
+        self.total_exec_time += entry.exec_time;
+        *self.path_hits.entry(entry.path).or_insert(0) += 1;
+        self.entries.push(entry);
+        if self.cull.is_some() && self.entries.len() > self.cull_at {
+            self.cull();
+        }
+    }
+
+    // Cull past limit entries, see the top of the file
+    pub fn set_cull(&mut self, limit: usize) {
+        self.cull = Some(limit);
+        self.cull_at = limit;
+    }
+
+    // Whether entries need a signature
+    pub fn culls(&self) -> bool {
+        self.cull.is_some()
+    }
+
+    pub fn culled(&self) -> u64 {
+        self.culled
+    }
+
+    // Cut the corpus down to a subset with the same signatures
+    fn cull(&mut self) {
+        // smaller and faster is better, like afl-fuzz favored entries
+        let cost = |entry: &CorpusEntry| entry.len.max(1) as u128
+            * entry.exec_time.as_nanos().max(1);
+        let mut best = HashMap::<u32, (usize, usize)>::new();
+        for (idx, entry) in self.entries.iter().enumerate() {
+            for &tuple in &entry.signature {
+                let (holders, best) = best.entry(tuple).or_insert((0, idx));
+                *holders += 1;
+                if cost(entry) < cost(&self.entries[*best]) {
+                    *best = idx;
+                }
+            }
+        }
+        let mut tuples = best.iter().map(|(&tuple, &(holders, _))| (holders, tuple))
+            .collect::<Vec<_>>();
+        tuples.sort_unstable();
+
+        let mut keep = self.entries.iter().map(|x| x.signature.is_empty())
+            .collect::<Vec<_>>();
+        let mut covered = HashSet::new();
+        for (_, tuple) in tuples {
+            if covered.contains(&tuple) {
+                continue;
+            }
+            let idx = best[&tuple].1;
+            keep[idx] = true;
+            covered.extend(self.entries[idx].signature.iter().copied());
+        }
+
+        let before = self.entries.len();
+        self.cursor = keep[..self.cursor.min(before)].iter().filter(|&&x| x).count();
+        let mut keep = keep.into_iter();
+        self.entries.retain(|_| keep.next().unwrap_or(true));
+        self.total_exec_time = self.entries.iter().map(|x| x.exec_time).sum();
+        let paths = self.entries.iter().map(|x| x.path).collect::<HashSet<_>>();
+        self.path_hits.retain(|path, _| paths.contains(path));
+
+        let culled = before - self.entries.len();
+        let limit = self.cull.unwrap_or(0);
+        self.cull_at = limit.max(self.entries.len() + limit / 4);
+        self.culled += culled as u64;
+        info!("corpus", "culled {} of {} entries, {} edge hit counts still covered",
+            culled, before, covered.len());
+    }
+
+    // Count an execution of a path, called for every execution
+    pub fn record_path(&mut self, path: u64) {
+        if let Some(hits) = self.path_hits.get_mut(&path) {
+            *hits += 1;
+        }
+    }
+
+    // Pick the next seed and how many children to derive from it
+    pub fn schedule(&mut self) -> Option<(usize, u32)> {
+        if self.entries.is_empty() {
+            return None;
+        }
+
+        let idx = self.cursor % self.entries.len();
+        self.cursor = idx + 1;
+
+        let energy = self.energy(idx);
+        self.entries[idx].fuzzed += 1;
+        Some((idx, energy))
+    }
+
+    fn energy(&self, idx: usize) -> u32 {
+        let entry = &self.entries[idx];
+
+        // faster than average seeds are cheaper to work on (like afl-fuzz
+        // calculate_score)
+        let avg = self.total_exec_time.as_secs_f64() / self.entries.len() as f64;
+        let time = entry.exec_time.as_secs_f64();
+        let speed = if time * 0.1 > avg {
+            0.1
+        } else if time * 0.25 > avg {
+            0.25
+        } else if time * 0.5 > avg {
+            0.5
+        } else if time * 0.75 > avg {
+            0.75
+        } else if time * 4.0 < avg {
+            3.0
+        } else if time * 3.0 < avg {
+            2.0
+        } else if time * 2.0 < avg {
+            1.5
+        } else {
+            1.0
+        };
+
+        // never fuzzed yet, give new finds a head start
+        let fresh = if entry.fuzzed == 0 { 2.0 } else { 1.0 };
+
+        // FAST: 2^s(i) / f(i)
+        let hits = self.path_hits.get(&entry.path).copied().unwrap_or(1).max(1);
+        let rarity = (1u64 << entry.fuzzed.min(20)) as f64 / hits as f64;
+
+        (BASE_ENERGY * speed * fresh * rarity).clamp(1.0, MAX_ENERGY as f64)
+            as u32
+    }
+}
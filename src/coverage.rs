@@ -0,0 +1,137 @@
+// Edge coverage maps and the novelty check built on top of them
+//
+// Backends that can observe the target (QEMU, ...) fill an AFL style
+// byte map of edge hit counters, the feedback compares it against
+// everything seen so far to decide whether an input found something new
+
+#[cfg(unix)]
+use std::io;
+
+// Size of the edge map, matches the AFL default so existing
+// instrumentation can write into it unchanged
+pub const MAP_SIZE: usize = 1 << 16;
+
+// Coverage map living in SysV shared memory, handed to the target through
+// the __AFL_SHM_ID environment variable
+#[cfg(unix)]
+pub struct ShmCoverageMap {
+    id: i32,
+    ptr: *mut u8,
+    size: usize,
+}
+
+#[cfg(unix)]
+impl ShmCoverageMap {
+    pub fn new(size: usize) -> io::Result<Self> {
+        // SAFETY: plain syscalls, the result is checked before use
+        unsafe {
+            let id = libc::shmget(libc::IPC_PRIVATE, size,
+                libc::IPC_CREAT | libc::IPC_EXCL | 0o600);
+            if id < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let ptr = libc::shmat(id, std::ptr::null(), 0);
+            if ptr as isize == -1 {
+                let err = io::Error::last_os_error();
+                libc::shmctl(id, libc::IPC_RMID, std::ptr::null_mut());
+                return Err(err);
+            }
+
+            Ok(ShmCoverageMap { id, ptr: ptr as *mut u8, size })
+        }
+    }
+
+    // Identifier targets attach to
+    pub fn id(&self) -> i32 {
+        self.id
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        // SAFETY: mapping is size bytes long and lives as long as self
+        unsafe { std::slice::from_raw_parts(self.ptr, self.size) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        // SAFETY: see as_slice
+        unsafe { std::slice::from_raw_parts_mut(self.ptr, self.size) }
+    }
+
+    // Reset all counters before the next execution
+    pub fn clear(&mut self) {
+        self.as_mut_slice().fill(0);
+    }
+}
+
+#[cfg(unix)]
+impl Drop for ShmCoverageMap {
+    fn drop(&mut self) {
+        // SAFETY: detaching our own mapping and removing our own segment
+        unsafe {
+            libc::shmdt(self.ptr as *const libc::c_void);
+            libc::shmctl(self.id, libc::IPC_RMID, std::ptr::null_mut());
+        }
+    }
+}
+
+// Bucket raw hit counts the way AFL does, so loop iteration counts only
+// matter when they change by an order of magnitude
+#[inline]
+pub fn bucket(count: u8) -> u8 {
+    match count {
+        0 => 0,
+        1 => 1,
+        2 => 2,
+        3 => 4,
+        4..=7 => 8,
+        8..=15 => 16,
+        16..=31 => 32,
+        32..=127 => 64,
+        128..=255 => 128,
+    }
+}
+
+// Remembers every (edge, bucket) pair seen during the campaign
+pub struct CoverageFeedback {
+    // bits still unseen for every edge, starts out all ones
+    virgin: Vec<u8>,
+
+    // number of edges hit at least once
+    edges: usize,
+}
+
+impl CoverageFeedback {
+    pub fn new(size: usize) -> Self {
+        CoverageFeedback {
+            virgin: vec![0xff; size],
+            edges: 0,
+        }
+    }
+
+    // Merge the map of an execution, true if it hit a new edge or a new
+    // hit count bucket of a known edge
+    pub fn is_interesting(&mut self, map: &[u8]) -> bool {
+        let mut new = false;
+
+        for (virgin, &count) in self.virgin.iter_mut().zip(map) {
+            if count == 0 {
+                continue;
+            }
+
+            let bits = bucket(count);
+            if *virgin & bits != 0 {
+                if *virgin == 0xff {
+                    self.edges += 1;
+                }
+                *virgin &= !bits;
+                new = true;
+            }
+        }
+        new
+    }
+
+    // Number of distinct edges covered so far
+    pub fn edges(&self) -> usize {
+        self.edges
+    }
+}
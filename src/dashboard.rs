@@ -0,0 +1,236 @@
+// Web dashboard
+//
+// A tiny http server for headless campaigns. "/" is a single static page
+// polling "/stats" (all samples taken so far, once a second from the stats
+// loop in main) and drawing them, "/entries" lists the newest queue, crash
+// and hang entries and "/entry?dir=..&name=.." returns one of them.
+//
+// Plain std sockets, one connection at a time, which is plenty for a
+// handful of people looking at a campaign.
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::compress;
+
+// Entries shown per directory in the browser
+const RECENT_ENTRIES: usize = 50;
+
+// Campaign numbers at one point in time
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Sample {
+    // seconds since the start of the campaign
+    pub time: f64,
+    pub execs: u64,
+    pub execs_per_sec: f64,
+    pub edges: usize,
+    pub corpus: u64,
+    pub crashes: u64,
+    pub timeouts: u64,
+}
+
+pub struct Dashboard {
+    listener: TcpListener,
+
+    // instance directory of the campaign, for the entry browser
+    dir: PathBuf,
+
+    history: Mutex<Vec<Sample>>,
+}
+
+impl Dashboard {
+    pub fn new(addr: impl ToSocketAddrs, dir: &Path) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        // polled so serve() notices when the campaign is over
+        listener.set_nonblocking(true)?;
+        Ok(Dashboard {
+            listener,
+            dir: dir.to_path_buf(),
+            history: Mutex::new(Vec::new()),
+        })
+    }
+
+    pub fn record(&self, sample: Sample) {
+        self.history.lock().unwrap().push(sample);
+    }
+
+    // Answer requests until stop is set
+    pub fn serve(&self, stop: &AtomicBool) -> io::Result<()> {
+        while !stop.load(Ordering::Relaxed) {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    // a client going away is not our problem
+                    let _ = self.handle(stream);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    std::thread::sleep(Duration::from_millis(100));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    fn handle(&self, stream: TcpStream) -> io::Result<()> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        let mut reader = BufReader::new(&stream);
+
+        // "GET /path?query HTTP/1.1", headers are of no interest
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let target = line.split_whitespace().nth(1).unwrap_or("/").to_string();
+        while line.trim_end() != "" {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                break;
+            }
+        }
+
+        let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+        let (status, kind, body) = match path {
+            "/" => ("200 OK", "text/html", PAGE.as_bytes().to_vec()),
+            "/stats" => ("200 OK", "application/json",
+                serde_json::to_vec(&*self.history.lock().unwrap())?),
+            "/entries" => ("200 OK", "application/json",
+                serde_json::to_vec(&self.entries())?),
+            "/entry" => match self.entry(query) {
+                Some(data) => ("200 OK", "application/octet-stream", data),
+                None => ("404 Not Found", "text/plain", b"not found".to_vec()),
+            },
+            _ => ("404 Not Found", "text/plain", b"not found".to_vec()),
+        };
+
+        let mut stream = &stream;
+        write!(stream, "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+            Cache-Control: no-store\r\nConnection: close\r\n\r\n",
+            status, kind, body.len())?;
+        stream.write_all(&body)
+    }
+
+    // Newest entries of queue and crashes, [(dir, [names])]
+    fn entries(&self) -> Vec<(&'static str, Vec<String>)> {
+        ["crashes", "hangs", "queue"].into_iter().map(|sub| {
+            let mut names = fs::read_dir(self.dir.join(sub)).into_iter().flatten()
+                .filter_map(|x| x.ok())
+                .map(|x| x.file_name().to_string_lossy().into_owned())
+                .filter(|x| x.starts_with("id:"))
+                .collect::<Vec<_>>();
+            names.sort_unstable_by(|a, b| b.cmp(a));
+            names.truncate(RECENT_ENTRIES);
+            (sub, names)
+        }).collect()
+    }
+
+    // Contents of the entry named in the query, never outside our dirs
+    fn entry(&self, query: &str) -> Option<Vec<u8>> {
+        let mut dir = None;
+        let mut name = None;
+        for pair in query.split('&') {
+            match pair.split_once('=') {
+                Some(("dir", x)) => dir = Some(x),
+                Some(("name", x)) => name = Some(percent_decode(x)),
+                _ => {}
+            }
+        }
+        let dir = dir.filter(|x| ["crashes", "hangs", "queue"].contains(x))?;
+        let name = name.filter(|x| x.starts_with("id:") && !x.contains('/'))?;
+        compress::read_entry(&self.dir.join(dir).join(name)).ok()
+    }
+}
+
+// Undo the %xx escapes of encodeURIComponent
+fn percent_decode(value: &str) -> String {
+    let mut out = Vec::new();
+    let mut bytes = value.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte == b'%' {
+            let hex = [bytes.next().unwrap_or(b'0'), bytes.next().unwrap_or(b'0')];
+            out.push(std::str::from_utf8(&hex).ok()
+                .and_then(|x| u8::from_str_radix(x, 16).ok()).unwrap_or(b'?'));
+        } else {
+            out.push(byte);
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+const PAGE: &str = r#"<!DOCTYPE html>
+<html><head><meta charset="utf-8"><title>maybe_fastest_fuzzer</title>
+<style>
+body { font-family: monospace; margin: 1em; background: #fafafa; }
+.charts { display: grid; grid-template-columns: repeat(2, 1fr); gap: 1em; }
+canvas { width: 100%; height: 180px; background: #fff; border: 1px solid #ccc; }
+.browser { display: grid; grid-template-columns: 30em 1fr; gap: 1em; margin-top: 1em; }
+ul { list-style: none; padding: 0; margin: 0 0 1em 0; max-height: 20em; overflow: auto; }
+li { cursor: pointer; white-space: nowrap; } li:hover { background: #eee; }
+pre { background: #fff; border: 1px solid #ccc; padding: .5em; white-space: pre-wrap;
+      word-break: break-all; min-height: 10em; }
+</style></head><body>
+<h2>maybe_fastest_fuzzer</h2>
+<div id="summary"></div>
+<div class="charts">
+<div>edges<canvas id="edges"></canvas></div>
+<div>execs per sec<canvas id="execs_per_sec"></canvas></div>
+<div>corpus<canvas id="corpus"></canvas></div>
+<div>crashes<canvas id="crashes"></canvas></div>
+</div>
+<div class="browser"><div id="entries"></div><pre id="entry"></pre></div>
+<script>
+function plot(id, samples) {
+  const c = document.getElementById(id), ctx = c.getContext('2d');
+  c.width = c.clientWidth; c.height = c.clientHeight;
+  ctx.clearRect(0, 0, c.width, c.height);
+  if (samples.length < 2) return;
+  const t1 = samples[samples.length - 1].time;
+  const max = Math.max(1, ...samples.map(s => s[id]));
+  ctx.fillText(max.toFixed(0), 2, 10);
+  ctx.beginPath();
+  samples.forEach((s, i) => {
+    const x = s.time / t1 * (c.width - 1), y = c.height - 1 - s[id] / max * (c.height - 12);
+    i ? ctx.lineTo(x, y) : ctx.moveTo(x, y);
+  });
+  ctx.stroke();
+}
+function show(dir, name) {
+  fetch('/entry?dir=' + dir + '&name=' + encodeURIComponent(name))
+    .then(r => r.arrayBuffer()).then(b => {
+      const bytes = new Uint8Array(b);
+      let s = '';
+      bytes.forEach(x => s += (x >= 32 && x < 127) || x == 10 ? String.fromCharCode(x)
+        : '\\x' + x.toString(16).padStart(2, '0'));
+      document.getElementById('entry').textContent = name + '\n\n' + s;
+    });
+}
+function update() {
+  fetch('/stats').then(r => r.json()).then(samples => {
+    ['edges', 'execs_per_sec', 'corpus', 'crashes'].forEach(id => plot(id, samples));
+    const s = samples[samples.length - 1];
+    if (s) document.getElementById('summary').textContent =
+      `run time ${s.time.toFixed(0)}s | execs ${s.execs} | ${s.execs_per_sec.toFixed(0)}/s | ` +
+      `edges ${s.edges} | corpus ${s.corpus} | crashes ${s.crashes} | timeouts ${s.timeouts}`;
+  });
+  fetch('/entries').then(r => r.json()).then(dirs => {
+    const root = document.getElementById('entries');
+    root.innerHTML = '';
+    dirs.forEach(([dir, names]) => {
+      const h = document.createElement('b'); h.textContent = dir; root.appendChild(h);
+      const ul = document.createElement('ul');
+      names.forEach(n => {
+        const li = document.createElement('li'); li.textContent = n;
+        li.onclick = () => show(dir, n); ul.appendChild(li);
+      });
+      root.appendChild(ul);
+    });
+  });
+}
+update(); setInterval(update, 2000);
+</script></body></html>
+"#;
@@ -0,0 +1,93 @@
+// Skipping inputs the target already ran
+//
+// Small grammars produce the same test case over and over, and running it
+// again tells nothing new. Every input is hashed before it is executed.
+// A bloom filter answers "certainly new" without taking a lock, a set of
+// the exact hashes settles the rest. The set stops growing at MAX_EXACT
+// hashes, past that the filter alone decides and an input is skipped with
+// the filter's false positive rate.
+
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::hash::hash64;
+
+// Bits of the filter, 16 MiB
+const BLOOM_BITS: usize = 1 << 27;
+
+// Bit positions per hash
+const BLOOM_HASHES: u64 = 4;
+
+// Shards of the exact set, to keep workers apart
+const SHARDS: usize = 16;
+
+// Hashes kept exactly, 64 MiB or so
+pub const MAX_EXACT: usize = 1 << 22;
+
+pub struct Dedup {
+    bloom: Vec<AtomicU64>,
+    exact: Vec<Mutex<HashSet<u64>>>,
+    exact_len: AtomicU64,
+
+    // inputs checked and duplicates found among them
+    pub checked: AtomicU64,
+    pub duplicates: AtomicU64,
+}
+
+impl Default for Dedup {
+    fn default() -> Self {
+        Dedup {
+            bloom: (0..BLOOM_BITS / 64).map(|_| AtomicU64::new(0)).collect(),
+            exact: (0..SHARDS).map(|_| Mutex::new(HashSet::new())).collect(),
+            exact_len: AtomicU64::new(0),
+            checked: AtomicU64::new(0),
+            duplicates: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Dedup {
+    // Remember input, true if it was seen before
+    pub fn seen(&self, input: &[u8]) -> bool {
+        self.checked.fetch_add(1, Ordering::Relaxed);
+        let hash = hash64(input);
+
+        // double hashing, the upper half steps through the filter
+        let step = (hash >> 32) | 1;
+        let mut in_bloom = true;
+        for ii in 0..BLOOM_HASHES {
+            let bit = hash.wrapping_add(ii.wrapping_mul(step)) as usize % BLOOM_BITS;
+            let mask = 1 << (bit % 64);
+            let old = self.bloom[bit / 64].fetch_or(mask, Ordering::Relaxed);
+            in_bloom &= old & mask != 0;
+        }
+
+        let full = self.exact_len.load(Ordering::Relaxed) >= MAX_EXACT as u64;
+        let duplicate = if !in_bloom {
+            if !full {
+                self.exact[hash as usize % SHARDS].lock().unwrap().insert(hash);
+                self.exact_len.fetch_add(1, Ordering::Relaxed);
+            }
+            false
+        } else if full {
+            true
+        } else {
+            let new = self.exact[hash as usize % SHARDS].lock().unwrap().insert(hash);
+            if new {
+                self.exact_len.fetch_add(1, Ordering::Relaxed);
+            }
+            !new
+        };
+        if duplicate {
+            self.duplicates.fetch_add(1, Ordering::Relaxed);
+        }
+        duplicate
+    }
+
+    // Share of checked inputs that were duplicates, percent
+    pub fn rate(&self) -> f64 {
+        self.duplicates.load(Ordering::Relaxed) as f64 * 100.
+            / self.checked.load(Ordering::Relaxed).max(1) as f64
+    }
+}
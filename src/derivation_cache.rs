@@ -0,0 +1,187 @@
+// Cached expansions of small rules
+//
+// Identifiers, numbers, keywords: much of a derivation is spent walking
+// small rules that end up as a few bytes. With a derivation cache,
+// generate() records what small rules derived (rules with a handful of
+// rules below them, MAX_RULES at most, so not the ones the structure of a
+// test case hangs off), and the next time such a rule comes up copies
+// one of its recorded expansions with probability reuse instead of
+// walking the fragments again. Every rule keeps up to entries expansions,
+// a newly walked one replaces a random one, so what the cache holds keeps
+// moving with the derivations. A rule that derives more than MAX_LEN bytes
+// once is not small after all and drops out of the cache: recording only
+// its short expansions would make copies of it shorter than derivations.
+// A copy counts the nodes its expansion took against the node budget.
+//
+// Reuse trades diversity for throughput: at reuse 0.9 nine in ten
+// expansions of a cached rule repeat one of the last few it derived.
+// Only generate() uses the cache (generation without feedback, --emit-*,
+// throughput measurements), trees are always derived in full.
+
+use std::collections::HashSet;
+
+use crate::grammar::{Fragment, FragmentId, GeneratorState, GrammarRust};
+use crate::mmap::Buffer;
+
+// Expansions a rule keeps unless the policy says otherwise
+pub const DEFAULT_ENTRIES: usize = 16;
+
+// Rules reaching more rules (themselves included) are not cached
+const MAX_RULES: usize = 8;
+
+// Rules deriving longer expansions are not cached
+const MAX_LEN: usize = 64;
+
+// Marks the end of an expansion being recorded on the stack of generate()
+const END: FragmentId = FragmentId(u32::MAX);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CachePolicy {
+    // probability of copying a recorded expansion instead of walking
+    pub reuse: f64,
+    // expansions kept per rule
+    pub entries: usize,
+}
+
+// Expansions of a rule with the nodes they took
+type Recorded = Vec<(Vec<u8>, usize)>;
+
+#[derive(Clone, Debug)]
+pub struct DerivationCache {
+    policy: CachePolicy,
+
+    // recorded expansions by fragment index, None for fragments that are
+    // not cached
+    slots: Vec<Option<Recorded>>,
+
+    // expansions being recorded: the rule, where its output starts and
+    // the nodes expanded before it
+    open: Vec<(FragmentId, usize, usize)>,
+}
+
+// Whether a rule reaches at most MAX_RULES rules
+fn is_small(gram: &GrammarRust, rules: &HashSet<FragmentId>, rule: FragmentId) -> bool {
+    let mut seen = HashSet::from([rule]);
+    let mut pending = vec![rule];
+    let mut reached = 0;
+    while let Some(cur) = pending.pop() {
+        if rules.contains(&cur) {
+            reached += 1;
+            if reached > MAX_RULES {
+                return false;
+            }
+        }
+        let children = match gram.lookup_fragment(cur) {
+            Fragment::NonTerminal(children) | Fragment::Expression(children) => children,
+            Fragment::Terminal(_) => continue,
+        };
+        pending.extend(children.iter().filter(|&&x| seen.insert(x)));
+    }
+    true
+}
+
+// Uniform in [0, 1)
+fn unit(state: &mut GeneratorState) -> f64 {
+    (state.rand() as u64 >> 11) as f64 / (1u64 << 53) as f64
+}
+
+impl DerivationCache {
+    // An empty cache for the small rules of a grammar. The start rule and
+    // shared rules (see Annotation::shared) are never cached
+    pub fn new(gram: &GrammarRust, policy: CachePolicy) -> Self {
+        let rules = gram.rules().map(|x| x.1).collect::<HashSet<_>>();
+        let mut slots = Vec::new();
+        for &id in &rules {
+            if id == gram.start() || gram.is_shared(id) || !is_small(gram, &rules, id) {
+                continue;
+            }
+            if slots.len() <= id.index() {
+                slots.resize(id.index() + 1, None);
+            }
+            slots[id.index()] = Some(Vec::new());
+        }
+        DerivationCache { policy, slots, open: Vec::new() }
+    }
+
+    // Keep a walked expansion of rule
+    fn record(&mut self, state: &mut GeneratorState, rule: FragmentId, value: &[u8],
+            nodes: usize) {
+        let Some(slot) = self.slots.get_mut(rule.index()) else {
+            return;
+        };
+        if value.len() > MAX_LEN {
+            *slot = None;
+            return;
+        }
+        let Some(recorded) = slot else {
+            return;
+        };
+        if recorded.len() < self.policy.entries {
+            recorded.push((value.to_vec(), nodes));
+        } else if !recorded.is_empty() {
+            let slot = &mut recorded[state.rand() % self.policy.entries];
+            slot.0.clear();
+            slot.0.extend_from_slice(value);
+            slot.1 = nodes;
+        }
+    }
+}
+
+impl GrammarRust {
+    // GrammarRust::generate() with a derivation cache
+    pub fn generate_cached<B: Buffer + ?Sized>(&self, state: &mut GeneratorState,
+            stack: &mut Vec<FragmentId>, cache: &mut DerivationCache, buf: &mut B) {
+        stack.clear();
+        stack.push(self.start());
+        cache.open.clear();
+        self.start_derivation(state);
+
+        let mut nodes = 0usize;
+        let max_output = self.output_limit(state);
+
+        while let Some(cur) = stack.pop() {
+            if cur == END {
+                let (rule, from, before) = cache.open.pop().expect("unbalanced expansion");
+                let value = &buf.as_mut_slice()[from..];
+                cache.record(state, rule, value, nodes - before);
+                continue;
+            }
+            nodes += 1;
+
+            match self.lookup_fragment(cur) {
+                Fragment::NonTerminal(options) => {
+                    if let Some(Some(recorded)) = cache.slots.get(cur.index()) {
+                        if !recorded.is_empty() && unit(state) < cache.policy.reuse {
+                            let (value, taken) = &recorded[state.rand() % recorded.len()];
+                            buf.extend_from_slice(value);
+                            nodes += taken - 1;
+                            if buf.len() > max_output {
+                                break;
+                            }
+                            continue;
+                        }
+                        cache.open.push((cur, buf.len(), nodes - 1));
+                        stack.push(END);
+                    }
+                    let Some(sel) = self.choose(state, cur, options, nodes, None) else {
+                        break;
+                    };
+                    stack.push(sel);
+                }
+                Fragment::Expression(expr) => {
+                    expr.iter().rev().for_each(|x| stack.push(*x));
+                }
+                Fragment::Terminal(value) => {
+                    if self.is_corrupting() {
+                        self.emit(state, cur, value, buf);
+                    } else {
+                        buf.extend_from_slice(value);
+                    }
+                    if buf.len() > max_output {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,71 @@
+// Checking that a seed means the same inputs everywhere
+//
+// Reproducing a finding from a seed only works as long as generation is a
+// function of grammar and seed alone. Hash map iteration order (rules come
+// out of hash maps), the width of usize (the RNG state) or a change to the
+// generator can quietly break that. A manifest records
+// the hash of every input generated from a seed; verify-determinism
+// regenerates them, on another machine or after an upgrade, and reports
+// the first one that differs. Every run also compiles the grammar twice,
+// each time with differently seeded hash maps, and compares the two.
+
+use serde::{Deserialize, Serialize};
+
+use crate::grammar::GrammarRust;
+use crate::hash::hash64;
+use crate::rng::SplitSeed;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub seed: u64,
+    // fingerprint of the grammar, hex
+    pub grammar: String,
+    // where it was recorded, for the report
+    pub platform: String,
+    // hash of every input, hex
+    pub inputs: Vec<String>,
+}
+
+impl Manifest {
+    // Hashes of count inputs generated from seed
+    pub fn generate(gram: &GrammarRust, seed: u64, count: usize) -> Self {
+        let mut cases = gram.iter_testcases(SplitSeed::new(seed).stream(0));
+        Manifest {
+            seed,
+            grammar: format!("{:016x}", gram.fingerprint()),
+            platform: platform(),
+            inputs: (0..count).map(|_| format!("{:016x}", hash64(cases.next_ref())))
+                .collect(),
+        }
+    }
+
+    // What differs from an expected manifest, nothing if all matches
+    pub fn compare(&self, expected: &Manifest) -> Vec<String> {
+        let mut differences = Vec::new();
+        if self.seed != expected.seed {
+            differences.push(format!("seed {} instead of {}", self.seed, expected.seed));
+        }
+        if self.grammar != expected.grammar {
+            differences.push(format!("grammar {} instead of {}", self.grammar,
+                expected.grammar));
+        }
+        if self.inputs.len() != expected.inputs.len() {
+            differences.push(format!("{} inputs instead of {}", self.inputs.len(),
+                expected.inputs.len()));
+        }
+        let mut differing = self.inputs.iter().zip(&expected.inputs).enumerate()
+            .filter(|(_, (a, b))| a != b).map(|x| x.0);
+        if let Some(first) = differing.next() {
+            differences.push(format!("{} inputs differ, the first is input {} \
+                (recorded on {}, this is {})", differing.count() + 1, first,
+                expected.platform, self.platform));
+        }
+        differences
+    }
+}
+
+// Architecture, OS and pointer width
+pub fn platform() -> String {
+    format!("{}-{} {}-bit", std::env::consts::ARCH, std::env::consts::OS,
+        usize::BITS)
+}
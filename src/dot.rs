@@ -0,0 +1,94 @@
+// Graphviz export of a compiled grammar
+//
+// One node per rule, alternative and terminal. References to other rules
+// are drawn as edges straight to the rule, edges that close a cycle (the
+// recursion generation and the recursion mutation feed on) are red.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crate::grammar::{Fragment, FragmentId, GrammarRust};
+
+// Rules referenced by the alternatives of a rule
+fn references(grammar: &GrammarRust, rule: FragmentId) -> Vec<FragmentId> {
+    grammar.lookup_fragment_nonterm(rule).iter()
+        .flat_map(|&expr| match grammar.lookup_fragment(expr) {
+            Fragment::Expression(x) => x,
+            _ => &[],
+        })
+        .filter_map(|&x| match grammar.lookup_fragment(x) {
+            Fragment::NonTerminal(target) => Some(target[0]),
+            _ => None,
+        })
+        .collect()
+}
+
+// Quote a label for dot
+fn label(value: &[u8]) -> String {
+    format!("{:?}", String::from_utf8_lossy(value))
+}
+
+// Write the grammar as a dot digraph
+pub fn write_dot(grammar: &GrammarRust, out: &mut impl Write) -> io::Result<()> {
+    let rules = grammar.rules().collect::<Vec<_>>();
+    let edges = rules.iter()
+        .map(|&(_, id)| (id, references(grammar, id)))
+        .collect::<HashMap<_, _>>();
+
+    let rule_index = rules.iter().enumerate().map(|(ii, &(_, id))| (id, ii))
+        .collect::<HashMap<_, _>>();
+
+    // rules reachable from every rule, an edge a -> b is recursive if a is
+    // reachable from b again
+    let reach = rules.iter().map(|&(_, from)| {
+        let mut seen = vec![false; rules.len()];
+        let mut stack = edges[&from].clone();
+        while let Some(cur) = stack.pop() {
+            if !std::mem::replace(&mut seen[rule_index[&cur]], true) {
+                stack.extend_from_slice(&edges[&cur]);
+            }
+        }
+        (from, seen)
+    }).collect::<HashMap<_, _>>();
+
+    writeln!(out, "digraph grammar {{")?;
+    writeln!(out, "  rankdir=LR;")?;
+    writeln!(out, "  node [fontname=monospace];")?;
+
+    for &(name, id) in &rules {
+        let shape = if id == grammar.start() { "doubleoctagon" } else { "ellipse" };
+        writeln!(out, "  f{} [label={}, shape={}];", id.0,
+            label(name.as_bytes()), shape)?;
+
+        for (alt, &expr) in grammar.lookup_fragment_nonterm(id).iter().enumerate() {
+            let empty = matches!(grammar.lookup_fragment(expr),
+                Fragment::Expression(x) if x.is_empty());
+            writeln!(out, "  f{} [label=\"{}\", shape=circle, fontsize=8, width=0.2];",
+                expr.0, if empty { String::from("ε") } else { alt.to_string() })?;
+            writeln!(out, "  f{} -> f{};", id.0, expr.0)?;
+
+            let Fragment::Expression(symbols) = grammar.lookup_fragment(expr) else {
+                continue;
+            };
+            for (pos, &symbol) in symbols.iter().enumerate() {
+                match grammar.lookup_fragment(symbol) {
+                    Fragment::NonTerminal(target) => {
+                        let target = target[0];
+                        let recursive = reach[&target][rule_index[&id]];
+                        writeln!(out, "  f{} -> f{} [label=\"{}\"{}];", expr.0,
+                            target.0, pos,
+                            if recursive { ", color=red, fontcolor=red" } else { "" })?;
+                    }
+                    Fragment::Terminal(value) => {
+                        writeln!(out, "  f{} [label={}, shape=box];", symbol.0,
+                            label(value))?;
+                        writeln!(out, "  f{} -> f{} [label=\"{}\"];", expr.0,
+                            symbol.0, pos)?;
+                    }
+                    Fragment::Expression(_) => {}
+                }
+            }
+        }
+    }
+    writeln!(out, "}}")
+}
@@ -0,0 +1,470 @@
+// Derivation trees of inputs the grammar did not derive
+//
+// An input from elsewhere (another fuzzer's corpus, a collection of
+// samples) only gets mutated structurally once it has a derivation tree.
+// GrammarRust::parse() finds one with an Earley parser over the compiled
+// fragments: a non-terminal derives one of its options, an expression its
+// fragments in order, a terminal its bytes. Every item of the chart keeps
+// the item it advanced from and what it advanced over, the first way an
+// item came about is the one kept, which gives one parse of an ambiguous
+// input. The parse becomes choices, the choices a tree through
+// derive_tree(), and the tree has to serialize to the input again: shared
+// rules, which repeat bytes instead of deriving them, and the output limit
+// can make a parse the tree does not reproduce.
+//
+// Predictions look one byte ahead: a fragment is only predicted where it
+// derives nothing or where its derivations can start with the byte there.
+// Right recursion (lists, strings) would make every completion complete
+// the whole chain of items above it again, Leo's optimization completes
+// only the topmost item of such a chain and leaves the ones in between to
+// be found again when the parse becomes choices.
+//
+// Charts grow with the input and the ambiguity of the grammar: about 20
+// items per byte for grammar.json without the quote among the string
+// characters, quadratic in the number of strings with it, since every
+// string may go on to the end. A parse giving up past MAX_ITEMS items
+// counts as too large.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::grammar::{Fragment, FragmentId, GrammarRust};
+use crate::tree::{Tree, TreeStack};
+
+// Items of a chart before the parse gives up, 100 MB or so
+pub const MAX_ITEMS: usize = 1 << 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    // no derivation goes past this byte
+    NoParse(usize),
+    // the chart outgrew MAX_ITEMS
+    TooLarge,
+    // the tree of the parse derives something else
+    Mismatch,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::NoParse(pos) => write!(f, "no parse past byte {}", pos),
+            ParseError::TooLarge => write!(f, "more than {} parser items", MAX_ITEMS),
+            ParseError::Mismatch => f.write_str("the parse derives a different input"),
+        }
+    }
+}
+
+// Set and index of an item
+type Pos = (u32, u32);
+
+// What an item advanced over
+#[derive(Clone, Copy, Debug)]
+enum Child {
+    Terminal,
+    // a fragment deriving nothing, without an item of its own
+    Empty(FragmentId),
+    // a completed item
+    Item(Pos),
+    // a completed item at the bottom of a chain of completions, see
+    // Chart::leo()
+    Leo(Pos),
+}
+
+// A fragment with the symbols of one of its right hand sides before the
+// dot, started at origin
+#[derive(Clone, Copy, Debug)]
+struct Item {
+    lhs: FragmentId,
+    // option of a non-terminal, 0 for expressions
+    alt: u32,
+    dot: u32,
+    origin: u32,
+    // the item at dot - 1, None at dot 0 and for items made at dot 1
+    // directly, and what the dot moved over
+    prev: Option<Pos>,
+    child: Option<Child>,
+}
+
+type Key = (FragmentId, u32, u32, u32);
+
+#[derive(Default)]
+struct Set {
+    items: Vec<Item>,
+    index: HashMap<Key, u32>,
+    // items waiting for a fragment to complete, by fragment
+    waiting: HashMap<FragmentId, Vec<u32>>,
+}
+
+struct Chart<'a> {
+    gram: &'a GrammarRust,
+    input: &'a [u8],
+    sets: Vec<Set>,
+    items: usize,
+    // fragments deriving nothing, with the option that does for
+    // non-terminals
+    empty: HashMap<FragmentId, u32>,
+    // bytes the derivations of every fragment can start with
+    first: HashMap<FragmentId, Bytes>,
+    // topmost items of chains of completions, see leo()
+    leo: HashMap<(u32, FragmentId), Option<Pos>>,
+}
+
+// A set of bytes
+type Bytes = [u64; 4];
+
+fn contains(bytes: &Bytes, byte: u8) -> bool {
+    bytes[byte as usize / 64] & 1 << (byte % 64) != 0
+}
+
+impl<'a> Chart<'a> {
+    // The right hand side of an item
+    fn rhs(&self, item: &Item) -> &'a [FragmentId] {
+        match self.gram.lookup_fragment(item.lhs) {
+            Fragment::NonTerminal(options) => &options[item.alt as usize..][..1],
+            Fragment::Expression(children) => children,
+            Fragment::Terminal(_) => &[],
+        }
+    }
+
+    // Add an item to a set unless it is there already
+    fn add(&mut self, set: usize, item: Item) {
+        let key = (item.lhs, item.alt, item.dot, item.origin);
+        let next = self.rhs(&item).get(item.dot as usize).copied();
+        let set = &mut self.sets[set];
+        if set.index.contains_key(&key) {
+            return;
+        }
+        let idx = set.items.len() as u32;
+        set.index.insert(key, idx);
+        set.items.push(item);
+        if let Some(next) = next {
+            set.waiting.entry(next).or_default().push(idx);
+        }
+        self.items += 1;
+    }
+
+    fn item(&self, (set, idx): Pos) -> Item {
+        self.sets[set as usize].items[idx as usize]
+    }
+
+    // Item (set, idx) with the dot moved over child into set to
+    fn advance(&mut self, set: usize, idx: usize, child: Child, to: usize) {
+        let item = self.sets[set].items[idx];
+        self.add(to, Item {
+            dot: item.dot + 1,
+            prev: Some((set as u32, idx as u32)),
+            child: Some(child),
+            ..item
+        });
+    }
+
+    // The only item of a set waiting for fragment, if fragment is the last
+    // thing it waits for
+    fn penultimate(&self, set: usize, fragment: FragmentId) -> Option<Pos> {
+        let &[idx] = self.sets[set].waiting.get(&fragment)?.as_slice() else {
+            return None;
+        };
+        let item = &self.sets[set].items[idx as usize];
+        (item.dot as usize + 1 == self.rhs(item).len()).then_some((set as u32, idx))
+    }
+
+    // A fragment started at set completing leaves no choice while the
+    // only item waiting for it is waiting for it last: that one completes,
+    // then the only one waiting for it, and so on. The topmost of these
+    // items, if there is one. Sets before the one being processed do not
+    // change any more, so chains are found once
+    fn leo(&mut self, set: usize, fragment: FragmentId) -> Option<Pos> {
+        let mut key = (set as u32, fragment);
+        let mut path: Vec<((u32, FragmentId), Pos)> = Vec::new();
+        let mut top = loop {
+            if let Some(&known) = self.leo.get(&key) {
+                break known;
+            }
+            // a cycle of rules deriving each other at the same place
+            if path.iter().any(|x| x.0 == key) {
+                break None;
+            }
+            let Some(pos) = self.penultimate(key.0 as usize, key.1) else {
+                break None;
+            };
+            path.push((key, pos));
+            let item = self.item(pos);
+            key = (item.origin, item.lhs);
+        };
+        for (key, pos) in path.into_iter().rev() {
+            top = top.or(Some(pos));
+            self.leo.insert(key, top);
+        }
+        top
+    }
+
+    // Process item idx of set pos
+    fn step(&mut self, pos: usize, idx: usize) {
+        let item = self.sets[pos].items[idx];
+        let Some(&next) = self.rhs(&item).get(item.dot as usize) else {
+            // complete: advance everything that waited for it, or only the
+            // top of a chain
+            let origin = item.origin as usize;
+            if origin < pos {
+                if let Some((set, top)) = self.leo(origin, item.lhs) {
+                    let child = Child::Leo((pos as u32, idx as u32));
+                    self.advance(set as usize, top as usize, child, pos);
+                    return;
+                }
+            }
+            let waiting = self.sets[origin].waiting.get(&item.lhs).cloned()
+                .unwrap_or_default();
+            for parent in waiting {
+                self.advance(origin, parent as usize, Child::Item((pos as u32, idx as u32)),
+                    pos);
+            }
+            return;
+        };
+        if self.empty.contains_key(&next) {
+            self.advance(pos, idx, Child::Empty(next), pos);
+        }
+        match self.gram.lookup_fragment(next) {
+            Fragment::Terminal(value) => {
+                if !value.is_empty() && self.input[pos..].starts_with(value) {
+                    self.advance(pos, idx, Child::Terminal, pos + value.len());
+                }
+            }
+            _ => self.predict(pos, next),
+        }
+    }
+
+    // Whether fragment may derive what follows pos
+    fn may_start(&self, fragment: FragmentId, pos: usize) -> bool {
+        self.empty.contains_key(&fragment) || self.input.get(pos).is_some_and(|&x|
+            self.first.get(&fragment).is_some_and(|first| contains(first, x)))
+    }
+
+    // Items of the right hand sides of a non-terminal or expression
+    // starting at pos
+    fn predict(&mut self, pos: usize, fragment: FragmentId) {
+        if !self.may_start(fragment, pos) {
+            return;
+        }
+        match self.gram.lookup_fragment(fragment) {
+            Fragment::Terminal(_) => {}
+            Fragment::NonTerminal(options) => {
+                for (alt, &option) in options.iter().enumerate() {
+                    if !self.may_start(option, pos) {
+                        continue;
+                    }
+                    let item = Item { lhs: fragment, alt: alt as u32, dot: 0,
+                        origin: pos as u32, prev: None, child: None };
+                    // terminal options are scanned right away, no item at
+                    // dot 0 waits for them
+                    match self.gram.lookup_fragment(option) {
+                        Fragment::Terminal(value) if !value.is_empty() => {
+                            if self.input[pos..].starts_with(value) {
+                                self.add(pos + value.len(), Item { dot: 1,
+                                    child: Some(Child::Terminal), ..item });
+                            }
+                        }
+                        _ => self.add(pos, item),
+                    }
+                }
+            }
+            Fragment::Expression(_) => self.add(pos, Item { lhs: fragment, alt: 0, dot: 0,
+                origin: pos as u32, prev: None, child: None }),
+        }
+    }
+
+    // The choices of the derivation of a completed item, in preorder
+    fn choices(&self, set: usize, idx: usize) -> Vec<u32> {
+        enum Task {
+            // an item completed, or with the dot moved over one more
+            // completed child
+            Item(Pos, Option<Box<Task>>),
+            Empty(FragmentId),
+        }
+        let mut choices = Vec::new();
+        let mut tasks = vec![Task::Item((set as u32, idx as u32), None)];
+        let mut children = Vec::new();
+        while let Some(task) = tasks.pop() {
+            match task {
+                Task::Item(pos, last) => {
+                    let item = self.item(pos);
+                    if let Fragment::NonTerminal(_) = self.gram.lookup_fragment(item.lhs) {
+                        choices.push(item.alt);
+                    }
+                    tasks.extend(last.map(|x| *x));
+                    // what the dot moved over, last first, with the item
+                    // before the one moving over it
+                    children.clear();
+                    let mut cur = Some(item);
+                    while let Some(item) = cur {
+                        children.extend(item.child.map(|x| (x, item.prev)));
+                        cur = item.prev.map(|pos| self.item(pos));
+                    }
+                    for &(child, prev) in &children {
+                        match child {
+                            Child::Terminal => {}
+                            Child::Empty(fragment) => tasks.push(Task::Empty(fragment)),
+                            Child::Item(pos) => tasks.push(Task::Item(pos, None)),
+                            Child::Leo(bottom) => {
+                                // the completions of the chain between the
+                                // item at the bottom and the top, which is
+                                // the item moving over it
+                                let mut task = Task::Item(bottom, None);
+                                let mut cur = self.item(bottom);
+                                while let Some(pos) = self.penultimate(cur.origin as usize,
+                                        cur.lhs).filter(|&x| Some(x) != prev) {
+                                    task = Task::Item(pos, Some(Box::new(task)));
+                                    cur = self.item(pos);
+                                }
+                                tasks.push(task);
+                            }
+                        }
+                    }
+                }
+                Task::Empty(fragment) => match self.gram.lookup_fragment(fragment) {
+                    Fragment::NonTerminal(options) => {
+                        let alt = self.empty[&fragment];
+                        choices.push(alt);
+                        tasks.push(Task::Empty(options[alt as usize]));
+                    }
+                    Fragment::Expression(children) => {
+                        tasks.extend(children.iter().rev().map(|&x| Task::Empty(x)));
+                    }
+                    Fragment::Terminal(_) => {}
+                },
+            }
+        }
+        choices
+    }
+}
+
+impl GrammarRust {
+    // The fragments a derivation of the start rule can visit
+    fn reachable(&self) -> Vec<FragmentId> {
+        let mut fragments = vec![self.start()];
+        let mut seen = HashSet::from([self.start()]);
+        let mut ii = 0;
+        while ii < fragments.len() {
+            if let Fragment::NonTerminal(children) | Fragment::Expression(children)
+                    = self.lookup_fragment(fragments[ii]) {
+                fragments.extend(children.iter().filter(|&&x| seen.insert(x)));
+            }
+            ii += 1;
+        }
+        fragments
+    }
+
+    // Fragments deriving nothing, with an option that does for
+    // non-terminals. An option goes in only once it derives nothing
+    // itself, so following them always ends
+    fn empty_fragments(&self, fragments: &[FragmentId]) -> HashMap<FragmentId, u32> {
+        let mut empty = HashMap::new();
+        loop {
+            let before = empty.len();
+            for &fragment in fragments {
+                if empty.contains_key(&fragment) {
+                    continue;
+                }
+                let alt = match self.lookup_fragment(fragment) {
+                    Fragment::Terminal(value) => value.is_empty().then_some(0),
+                    Fragment::Expression(children) => children.iter()
+                        .all(|x| empty.contains_key(x)).then_some(0),
+                    Fragment::NonTerminal(options) => options.iter()
+                        .position(|x| empty.contains_key(x)).map(|x| x as u32),
+                };
+                if let Some(alt) = alt {
+                    empty.insert(fragment, alt);
+                }
+            }
+            if empty.len() == before {
+                return empty;
+            }
+        }
+    }
+
+    // The bytes the derivations of every fragment can start with
+    fn first_bytes(&self, fragments: &[FragmentId], empty: &HashMap<FragmentId, u32>)
+            -> HashMap<FragmentId, Bytes> {
+        let mut first = fragments.iter().map(|&x| (x, Bytes::default()))
+            .collect::<HashMap<_, _>>();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &fragment in fragments {
+                let mut bytes = first[&fragment];
+                match self.lookup_fragment(fragment) {
+                    Fragment::Terminal(value) => if let Some(&x) = value.first() {
+                        bytes[x as usize / 64] |= 1 << (x % 64);
+                    },
+                    Fragment::NonTerminal(children) | Fragment::Expression(children) => {
+                        let expression = matches!(self.lookup_fragment(fragment),
+                            Fragment::Expression(_));
+                        for child in children {
+                            for (word, x) in bytes.iter_mut().zip(first[child]) {
+                                *word |= x;
+                            }
+                            // an expression starts with its first child that
+                            // derives something
+                            if expression && !empty.contains_key(child) {
+                                break;
+                            }
+                        }
+                    }
+                }
+                if bytes != first[&fragment] {
+                    first.insert(fragment, bytes);
+                    changed = true;
+                }
+            }
+        }
+        first
+    }
+
+    // A derivation tree of a complete test case deriving input
+    pub fn parse(&self, input: &[u8]) -> Result<Tree, ParseError> {
+        let mut chart = Chart {
+            gram: self,
+            input,
+            sets: (0..=input.len()).map(|_| Set::default()).collect(),
+            items: 0,
+            empty: HashMap::new(),
+            first: HashMap::new(),
+            leo: HashMap::new(),
+        };
+        let fragments = self.reachable();
+        chart.empty = self.empty_fragments(&fragments);
+        chart.first = self.first_bytes(&fragments, &chart.empty);
+
+        let start = self.start();
+        chart.predict(0, start);
+        let mut furthest = 0;
+        for pos in 0..=input.len() {
+            let mut idx = 0;
+            while idx < chart.sets[pos].items.len() {
+                chart.step(pos, idx);
+                idx += 1;
+                if chart.items > MAX_ITEMS {
+                    return Err(ParseError::TooLarge);
+                }
+            }
+            if !chart.sets[pos].items.is_empty() {
+                furthest = pos;
+            }
+        }
+
+        let set = &chart.sets[input.len()];
+        let Some(idx) = set.items.iter().position(|x| x.lhs == start && x.origin == 0
+                && chart.rhs(x).len() == x.dot as usize) else {
+            return Err(ParseError::NoParse(furthest));
+        };
+        let mut choices = chart.choices(input.len(), idx).into_iter();
+        let mut tree = Tree::default();
+        self.derive_tree(start, &mut TreeStack::default(), &mut tree,
+            |_, options, _, _| choices.next().and_then(|x| options.get(x as usize)).copied());
+        let mut bytes = Vec::new();
+        tree.serialize(self, &mut bytes);
+        match bytes == input {
+            true => Ok(tree),
+            false => Err(ParseError::Mismatch),
+        }
+    }
+}
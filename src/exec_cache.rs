@@ -0,0 +1,124 @@
+// Results of inputs the target already ran
+//
+// Deduplication skips an input that ran before, and everything the
+// campaign would have learned from it along: the power schedule does not
+// see its path exercised again, a different derivation of the same bytes
+// does not add its production pairs. With an execution cache the result of
+// every input run (exit status, oracle verdict, coverage) is kept under
+// the input's hash, and an input found there goes through the book keeping
+// with the cached result instead of through the target. Only the book
+// keeping of the input itself: path counts, production pairs, rule stats.
+// Crashes, hangs, limits and the feedbacks saw the result when the input
+// ran, a cached crash is not counted again and does not stop the campaign,
+// and cached results are not executions.
+//
+// Coverage is kept sparse, as the edges hit and their counts. Every shard
+// keeps its share of the entries and drops the oldest one for a new one.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::executor::ExecResult;
+use crate::hash::hash64;
+
+// Shards of the cache, to keep workers apart
+const SHARDS: usize = 16;
+
+// Entries kept unless --exec-cache says otherwise
+pub const DEFAULT_ENTRIES: usize = 1 << 16;
+
+#[derive(Clone, Debug)]
+struct Cached {
+    result: ExecResult,
+    // oracle that flagged the input and what it saw
+    violation: Option<(String, String)>,
+    // size of the coverage map and the edges hit in it
+    coverage: Option<(usize, Vec<(u32, u8)>)>,
+}
+
+#[derive(Default)]
+struct Shard {
+    entries: HashMap<u64, Cached>,
+    // hashes by age, oldest first
+    order: VecDeque<u64>,
+}
+
+pub struct ExecCache {
+    shards: Vec<Mutex<Shard>>,
+    per_shard: usize,
+
+    // inputs looked up and the ones found
+    pub checked: AtomicU64,
+    pub hits: AtomicU64,
+}
+
+// A cached result, its coverage is in the map lookup() was given
+pub struct Hit {
+    pub result: ExecResult,
+    pub violation: Option<(String, String)>,
+    pub coverage: bool,
+}
+
+impl ExecCache {
+    pub fn new(entries: usize) -> Self {
+        ExecCache {
+            shards: (0..SHARDS).map(|_| Mutex::new(Shard::default())).collect(),
+            per_shard: entries.div_ceil(SHARDS).max(1),
+            checked: AtomicU64::new(0),
+            hits: AtomicU64::new(0),
+        }
+    }
+
+    // The key of an input
+    pub fn key(input: &[u8]) -> u64 {
+        hash64(input)
+    }
+
+    // The result of the input with key, its coverage written to map
+    pub fn lookup(&self, key: u64, map: &mut Vec<u8>) -> Option<Hit> {
+        self.checked.fetch_add(1, Ordering::Relaxed);
+        let shard = self.shards[key as usize % SHARDS].lock().unwrap();
+        let cached = shard.entries.get(&key)?;
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        if let Some((len, edges)) = &cached.coverage {
+            map.clear();
+            map.resize(*len, 0);
+            for &(idx, count) in edges {
+                map[idx as usize] = count;
+            }
+        }
+        Some(Hit {
+            result: cached.result.clone(),
+            violation: cached.violation.clone(),
+            coverage: cached.coverage.is_some(),
+        })
+    }
+
+    // Keep the result of the input with key
+    pub fn insert(&self, key: u64, result: &ExecResult, violation: Option<&(String, String)>,
+            coverage: Option<&[u8]>) {
+        let cached = Cached {
+            result: result.clone(),
+            violation: violation.cloned(),
+            coverage: coverage.map(|map| (map.len(), map.iter().enumerate()
+                .filter(|x| *x.1 != 0).map(|(idx, &count)| (idx as u32, count))
+                .collect())),
+        };
+        let mut shard = self.shards[key as usize % SHARDS].lock().unwrap();
+        if shard.entries.insert(key, cached).is_some() {
+            return;
+        }
+        shard.order.push_back(key);
+        if shard.order.len() > self.per_shard {
+            let oldest = shard.order.pop_front().expect("the shard is full");
+            shard.entries.remove(&oldest);
+        }
+    }
+
+    // Share of inputs looked up that were found, percent
+    pub fn rate(&self) -> f64 {
+        self.hits.load(Ordering::Relaxed) as f64 * 100.
+            / self.checked.load(Ordering::Relaxed).max(1) as f64
+    }
+}
@@ -0,0 +1,195 @@
+// In-memory harness for target libraries
+//
+// A C/C++ library target built as a shared object with an entry point
+//
+//   int fuzz_one(const uint8_t *data, size_t size);
+//
+// is loaded into the fuzzer with dlopen() and called directly, no process
+// per input. By default the calls happen in-process, which is about as fast
+// as a persistent loop gets, but a crash or hang of the library is one of
+// the fuzzer too: in-process runs cannot be interrupted, and a crashing
+// input takes the campaign down. With fork on, every call runs in a child
+// forked off the fuzzer with the library loaded already: crashes and hangs
+// are contained and reported like those of a process target, for the cost
+// of a fork instead of an exec.
+//
+// Libraries built with AFL++ instrumentation (afl-clang-fast -shared)
+// record edge coverage through __afl_area_ptr, which is pointed at our map
+// before every call. Without it there is no coverage.
+//
+// Every worker of the fuzzer gets the same copy of the library (dlopen()
+// loads a library once per process), so the library's globals, coverage
+// pointer included, are shared: in-process calls take turns, forked ones
+// only while forking.
+
+use std::ffi::{c_void, CStr, CString};
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use super::{ExecResult, Executor, ExitKind};
+use crate::coverage::{ShmCoverageMap, MAP_SIZE};
+
+type FuzzOne = unsafe extern "C" fn(*const u8, usize) -> i32;
+
+// The library's globals are the process's, see the top of the file
+static TURN: Mutex<()> = Mutex::new(());
+
+fn dl_error(what: &str) -> io::Error {
+    // SAFETY: dlerror returns null or a C string valid until the next call
+    let reason = unsafe {
+        let err = libc::dlerror();
+        match err.is_null() {
+            true => "unknown error".to_string(),
+            false => CStr::from_ptr(err).to_string_lossy().into_owned(),
+        }
+    };
+    io::Error::other(format!("{}: {}", what, reason))
+}
+
+pub struct DlopenExecutor {
+    handle: *mut c_void,
+    fuzz_one: FuzzOne,
+
+    // the instrumentation's coverage pointer in the library, and the map
+    // it is pointed at (shared memory, so forked children write to it)
+    area_ptr: Option<*mut *mut u8>,
+    map: ShmCoverageMap,
+
+    // run every call in a forked child, killed after timeout
+    fork: bool,
+    timeout: Duration,
+}
+
+impl DlopenExecutor {
+    pub fn new(library: &Path, symbol: &str, fork: bool, timeout: Duration)
+            -> io::Result<Self> {
+        let path = CString::new(library.as_os_str().as_encoded_bytes())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "NUL in library path"))?;
+        let name = CString::new(symbol)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "NUL in symbol"))?;
+        // SAFETY: plain C strings, the results are checked before use. A
+        // library is trusted with the fuzzer's process like a target
+        // command line is trusted with a shell
+        unsafe {
+            let handle = libc::dlopen(path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL);
+            if handle.is_null() {
+                return Err(dl_error(&library.display().to_string()));
+            }
+            let entry = libc::dlsym(handle, name.as_ptr());
+            if entry.is_null() {
+                let err = dl_error(symbol);
+                libc::dlclose(handle);
+                return Err(err);
+            }
+            let area_ptr = libc::dlsym(handle, c"__afl_area_ptr".as_ptr());
+            // edges the instrumentation numbers past our map would be
+            // written past its end
+            let final_loc = libc::dlsym(handle, c"__afl_final_loc".as_ptr());
+            if !final_loc.is_null() && *(final_loc as *const u32) as usize > MAP_SIZE {
+                libc::dlclose(handle);
+                return Err(io::Error::other(format!(
+                    "{}: instrumented for {} edges, the map has {}", library.display(),
+                    *(final_loc as *const u32), MAP_SIZE)));
+            }
+            Ok(DlopenExecutor {
+                handle,
+                fuzz_one: std::mem::transmute::<*mut c_void, FuzzOne>(entry),
+                area_ptr: (!area_ptr.is_null()).then_some(area_ptr as *mut *mut u8),
+                map: ShmCoverageMap::new(MAP_SIZE)?,
+                fork,
+                timeout,
+            })
+        }
+    }
+
+    // Point the instrumentation at our map
+    fn attach(&mut self) {
+        if let Some(area_ptr) = self.area_ptr {
+            // SAFETY: the symbol is the library's coverage pointer, our
+            // map is as large as its instrumentation needs (see new())
+            unsafe { *area_ptr = self.map.as_mut_slice().as_mut_ptr() };
+        }
+    }
+
+    fn run_forked(&mut self, input: &[u8]) -> io::Result<ExecResult> {
+        let start = Instant::now();
+        let pid = {
+            let _turn = TURN.lock().unwrap_or_else(|e| e.into_inner());
+            self.attach();
+            // SAFETY: the child only calls into the library and exits
+            match unsafe { libc::fork() } {
+                0 => unsafe {
+                    (self.fuzz_one)(input.as_ptr(), input.len());
+                    libc::_exit(0);
+                },
+                pid if pid < 0 => return Err(io::Error::last_os_error()),
+                pid => pid,
+            }
+        };
+
+        let mut result = ExecResult::default();
+        loop {
+            let mut status = 0;
+            // SAFETY: waiting for our own child
+            let ret = unsafe { libc::waitpid(pid, &mut status, libc::WNOHANG) };
+            if ret < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if ret > 0 {
+                if libc::WIFSIGNALED(status) {
+                    result.signal = Some(libc::WTERMSIG(status));
+                    result.exit = ExitKind::Crash;
+                } else {
+                    result.code = Some(libc::WEXITSTATUS(status));
+                }
+                break;
+            }
+            if start.elapsed() > self.timeout {
+                // SAFETY: killing and reaping our own child
+                unsafe {
+                    libc::kill(pid, libc::SIGKILL);
+                    libc::waitpid(pid, &mut status, 0);
+                }
+                result.exit = ExitKind::Timeout;
+                break;
+            }
+            std::thread::sleep(Duration::from_micros(50));
+        }
+        result.exec_time = start.elapsed();
+        Ok(result)
+    }
+}
+
+impl Executor for DlopenExecutor {
+    fn run(&mut self, input: &[u8]) -> io::Result<ExecResult> {
+        self.map.clear();
+        if self.fork {
+            return self.run_forked(input);
+        }
+        let _turn = TURN.lock().unwrap_or_else(|e| e.into_inner());
+        self.attach();
+        let start = Instant::now();
+        // SAFETY: the entry point has the signature of the top of the file
+        let code = unsafe { (self.fuzz_one)(input.as_ptr(), input.len()) };
+        Ok(ExecResult {
+            code: Some(code),
+            exec_time: start.elapsed(),
+            ..ExecResult::default()
+        })
+    }
+
+    fn coverage(&self) -> Option<&[u8]> {
+        self.area_ptr.map(|_| self.map.as_slice())
+    }
+}
+
+impl Drop for DlopenExecutor {
+    fn drop(&mut self) {
+        // SAFETY: the handle is ours, dlopen() counts references
+        unsafe {
+            libc::dlclose(self.handle);
+        }
+    }
+}
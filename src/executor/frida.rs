@@ -0,0 +1,92 @@
+// Binary-only coverage through frida-gum (AFL++ frida_mode)
+//
+// The target is started with afl-frida-trace.so preloaded, which rewrites
+// basic blocks on the fly and records edge coverage into the shared memory
+// map named by __AFL_SHM_ID. Works on binaries and platforms where QEMU
+// user-mode is not an option.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::{find_afl_file, ExecResult, Executor, ProcessExecutor};
+use crate::coverage::{ShmCoverageMap, MAP_SIZE};
+
+// Name of the frida_mode runtime library
+#[cfg(target_os = "macos")]
+const FRIDA_LIB: &str = "afl-frida-trace.dylib";
+#[cfg(not(target_os = "macos"))]
+const FRIDA_LIB: &str = "afl-frida-trace.so";
+
+// Variable used by the dynamic loader to preload the runtime
+#[cfg(target_os = "macos")]
+const PRELOAD_VAR: &str = "DYLD_INSERT_LIBRARIES";
+#[cfg(not(target_os = "macos"))]
+const PRELOAD_VAR: &str = "LD_PRELOAD";
+
+// Persistent loop setup, see AFL++ frida_mode/README.md
+#[derive(Clone, Debug, Default)]
+pub struct FridaPersistent {
+    // address of the function to loop over (hex, like AFL_FRIDA_PERSISTENT_ADDR)
+    pub addr: String,
+
+    // iterations before the target gets restarted
+    pub count: Option<u32>,
+
+    // shared library with afl_persistent_hook() writing the input into the
+    // arguments of the looped function (inline hook)
+    pub hook: Option<PathBuf>,
+}
+
+pub struct FridaExecutor {
+    inner: ProcessExecutor,
+    map: ShmCoverageMap,
+}
+
+impl FridaExecutor {
+    pub fn new(mut inner: ProcessExecutor, lib: PathBuf,
+            persistent: Option<FridaPersistent>) -> io::Result<Self> {
+        let map = ShmCoverageMap::new(MAP_SIZE)?;
+
+        inner.env(PRELOAD_VAR, &lib.to_string_lossy());
+        inner.env("__AFL_SHM_ID", &map.id().to_string());
+        inner.env("AFL_MAP_SIZE", &MAP_SIZE.to_string());
+
+        if let Some(persistent) = persistent {
+            inner.env("AFL_FRIDA_PERSISTENT_ADDR", &persistent.addr);
+            if let Some(count) = persistent.count {
+                inner.env("AFL_FRIDA_PERSISTENT_CNT", &count.to_string());
+            }
+            if let Some(hook) = persistent.hook {
+                inner.env("AFL_FRIDA_PERSISTENT_HOOK",
+                    &hook.to_string_lossy());
+            }
+        }
+
+        Ok(FridaExecutor { inner, map })
+    }
+
+    // Locate the frida_mode runtime library
+    pub fn find_frida() -> Option<PathBuf> {
+        find_afl_file(FRIDA_LIB)
+    }
+}
+
+impl Executor for FridaExecutor {
+    fn run(&mut self, input: &[u8]) -> io::Result<ExecResult> {
+        self.map.clear();
+        self.inner.run(input)
+    }
+
+    fn in_place_file(&self) -> Option<&Path> {
+        self.inner.in_place_file()
+    }
+
+    fn run_in_place(&mut self, input: &[u8]) -> io::Result<ExecResult> {
+        self.map.clear();
+        self.inner.run_in_place(input)
+    }
+
+    fn coverage(&self) -> Option<&[u8]> {
+        Some(self.map.as_slice())
+    }
+}
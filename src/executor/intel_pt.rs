@@ -0,0 +1,441 @@
+// Coverage from Intel Processor Trace, no target instrumentation needed
+//
+// Every execution gets its own perf event of the intel_pt PMU, enabled on
+// exec of the target. The raw trace in the AUX area is walked packet by
+// packet: TIP packets (indirect branch targets) and TNT bits (conditional
+// branch outcomes) are hashed into the regular edge map. That is not a full
+// instruction flow reconstruction, but it needs no disassembly of the
+// target and distinguishes control flow well enough for feedback.
+
+use std::ffi::CString;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use super::{input_file_path, ExecResult, Executor, ExitKind};
+use crate::coverage::MAP_SIZE;
+
+// PMU type of intel_pt is assigned dynamically by the kernel
+const PT_TYPE_PATH: &str = "/sys/bus/event_source/devices/intel_pt/type";
+
+// config:13 enables branch tracing (see intel_pt/format/branch)
+const PT_CONFIG_BRANCH_EN: u64 = 1 << 13;
+
+// perf_event_attr flag bits
+const ATTR_DISABLED: u64 = 1 << 0;
+const ATTR_EXCLUDE_KERNEL: u64 = 1 << 5;
+const ATTR_EXCLUDE_HV: u64 = 1 << 6;
+const ATTR_ENABLE_ON_EXEC: u64 = 1 << 12;
+
+const PERF_FLAG_FD_CLOEXEC: libc::c_ulong = 1 << 3;
+
+// Offsets into struct perf_event_mmap_page
+const AUX_HEAD: usize = 1056;
+const AUX_TAIL: usize = 1064;
+const AUX_OFFSET: usize = 1072;
+const AUX_SIZE: usize = 1080;
+
+// Size of the trace buffer, power of two pages
+const DEFAULT_AUX_SIZE: usize = 4 << 20;
+
+// struct perf_event_attr, PERF_ATTR_SIZE_VER7 layout
+#[repr(C)]
+#[derive(Default)]
+struct PerfEventAttr {
+    type_: u32,
+    size: u32,
+    config: u64,
+    sample_period: u64,
+    sample_type: u64,
+    read_format: u64,
+    flags: u64,
+    wakeup_events: u32,
+    bp_type: u32,
+    config1: u64,
+    config2: u64,
+    branch_sample_type: u64,
+    sample_regs_user: u64,
+    sample_stack_user: u32,
+    clockid: i32,
+    sample_regs_intr: u64,
+    aux_watermark: u32,
+    sample_max_stack: u16,
+    reserved_2: u16,
+    aux_sample_size: u32,
+    reserved_3: u32,
+    sig_data: u64,
+}
+
+pub struct IntelPtExecutor {
+    argv: Vec<String>,
+
+    // input is written here, the target gets it as stdin and via @@
+    input_file: PathBuf,
+
+    timeout: Duration,
+
+    // dynamic PMU type of intel_pt
+    pt_type: u32,
+
+    // bytes of trace buffer per execution
+    aux_size: usize,
+
+    map: Vec<u8>,
+}
+
+impl IntelPtExecutor {
+    pub fn new(argv: Vec<String>, timeout: Duration) -> io::Result<Self> {
+        let pt_type = std::fs::read_to_string(PT_TYPE_PATH)
+            .map_err(|_| io::Error::new(io::ErrorKind::Unsupported,
+                "intel_pt PMU not available on this machine"))?
+            .trim().parse().map_err(|_| io::Error::new(
+                io::ErrorKind::InvalidData, "bad intel_pt PMU type"))?;
+
+        Ok(IntelPtExecutor {
+            argv,
+            input_file: input_file_path(),
+            timeout,
+            pt_type,
+            aux_size: DEFAULT_AUX_SIZE,
+            map: vec![0; MAP_SIZE],
+        })
+    }
+
+    // Fork the target, stopped until the trace is set up
+    // Returns the pid and the write end of the "go" pipe
+    fn spawn(&self) -> io::Result<(libc::pid_t, libc::c_int)> {
+        // everything the child needs is prepared before forking
+        let path = CString::new(self.input_file.as_os_str().as_encoded_bytes())
+            .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        let args = self.argv.iter().map(|x| if x == "@@" {
+            path.clone()
+        } else {
+            CString::new(x.as_bytes()).unwrap_or_default()
+        }).collect::<Vec<_>>();
+        let mut argv = args.iter().map(|x| x.as_ptr()).collect::<Vec<_>>();
+        argv.push(std::ptr::null());
+        let devnull = c"/dev/null";
+
+        // SAFETY: between fork and exec the child only calls async signal
+        // safe functions on memory prepared above
+        unsafe {
+            let mut go = [0 as libc::c_int; 2];
+            if libc::pipe2(go.as_mut_ptr(), libc::O_CLOEXEC) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let pid = libc::fork();
+            if pid < 0 {
+                let err = io::Error::last_os_error();
+                libc::close(go[0]);
+                libc::close(go[1]);
+                return Err(err);
+            }
+
+            if pid == 0 {
+                // wait for the tracer
+                let mut byte = 0u8;
+                libc::read(go[0], &mut byte as *mut u8 as *mut libc::c_void, 1);
+
+                let input = libc::open(path.as_ptr(), libc::O_RDONLY);
+                let null = libc::open(devnull.as_ptr(), libc::O_RDWR);
+                libc::dup2(input, 0);
+                libc::dup2(null, 1);
+                libc::dup2(null, 2);
+                libc::execvp(argv[0], argv.as_ptr());
+                libc::_exit(127);
+            }
+
+            libc::close(go[0]);
+            Ok((pid, go[1]))
+        }
+    }
+
+    // Attach a trace to the (still waiting) child and map its buffers
+    fn open_trace(&self, pid: libc::pid_t)
+            -> io::Result<(libc::c_int, *mut u8, usize, *mut u8)> {
+        let attr = PerfEventAttr {
+            type_: self.pt_type,
+            size: std::mem::size_of::<PerfEventAttr>() as u32,
+            config: PT_CONFIG_BRANCH_EN,
+            flags: ATTR_DISABLED | ATTR_EXCLUDE_KERNEL | ATTR_EXCLUDE_HV |
+                ATTR_ENABLE_ON_EXEC,
+            ..Default::default()
+        };
+
+        // SAFETY: attr outlives the call, mappings are checked and handed
+        // to the caller which unmaps them
+        unsafe {
+            let fd = libc::syscall(libc::SYS_perf_event_open,
+                &attr as *const PerfEventAttr, pid, -1, -1,
+                PERF_FLAG_FD_CLOEXEC) as libc::c_int;
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            // header page plus one data page (1 + 2^0 pages)
+            let page = libc::sysconf(libc::_SC_PAGESIZE) as usize;
+            let base_len = page * 2;
+            let base = libc::mmap(std::ptr::null_mut(), base_len,
+                libc::PROT_READ | libc::PROT_WRITE, libc::MAP_SHARED, fd, 0);
+            if base == libc::MAP_FAILED {
+                let err = io::Error::last_os_error();
+                libc::close(fd);
+                return Err(err);
+            }
+            let base = base as *mut u8;
+
+            // tell the kernel where the AUX area goes
+            (base.add(AUX_OFFSET) as *mut u64).write_volatile(base_len as u64);
+            (base.add(AUX_SIZE) as *mut u64).write_volatile(self.aux_size as u64);
+
+            let aux = libc::mmap(std::ptr::null_mut(), self.aux_size,
+                libc::PROT_READ | libc::PROT_WRITE, libc::MAP_SHARED, fd,
+                base_len as libc::off_t);
+            if aux == libc::MAP_FAILED {
+                let err = io::Error::last_os_error();
+                libc::munmap(base as *mut libc::c_void, base_len);
+                libc::close(fd);
+                return Err(err);
+            }
+
+            Ok((fd, base, base_len, aux as *mut u8))
+        }
+    }
+
+    // Wait for the target, killing it when it runs out of time
+    // Returns how it ended and the signal that killed it
+    fn wait(&self, pid: libc::pid_t, start: Instant)
+            -> (ExitKind, Option<i32>) {
+        let mut status = 0;
+        loop {
+            // SAFETY: waiting on our own child
+            let ret = unsafe { libc::waitpid(pid, &mut status, libc::WNOHANG) };
+            if ret == pid {
+                return if libc::WIFSIGNALED(status) {
+                    (ExitKind::Crash, Some(libc::WTERMSIG(status)))
+                } else {
+                    (ExitKind::Ok, None)
+                };
+            }
+            if ret < 0 {
+                return (ExitKind::Ok, None);
+            }
+
+            if start.elapsed() > self.timeout {
+                // SAFETY: see above
+                unsafe {
+                    libc::kill(pid, libc::SIGKILL);
+                    libc::waitpid(pid, &mut status, 0);
+                }
+                return (ExitKind::Timeout, None);
+            }
+            std::thread::sleep(Duration::from_micros(100));
+        }
+    }
+}
+
+impl Executor for IntelPtExecutor {
+    fn run(&mut self, input: &[u8]) -> io::Result<ExecResult> {
+        std::fs::write(&self.input_file, input)?;
+        self.map.fill(0);
+
+        let (pid, go) = self.spawn()?;
+        let trace = self.open_trace(pid);
+
+        let start = Instant::now();
+        // SAFETY: releasing the child, closing our end of the pipe
+        unsafe {
+            if trace.is_err() {
+                libc::kill(pid, libc::SIGKILL);
+            }
+            libc::write(go, b"g".as_ptr() as *const libc::c_void, 1);
+            libc::close(go);
+        }
+
+        let (exit, signal) = self.wait(pid, start);
+        let (fd, base, base_len, aux) = trace?;
+
+        // SAFETY: the kernel is done writing, head/tail live in the header
+        // page and the trace in the AUX mapping of aux_size bytes
+        unsafe {
+            let head = (base.add(AUX_HEAD) as *const u64).read_volatile() as usize;
+            let tail = (base.add(AUX_TAIL) as *const u64).read_volatile() as usize;
+            let aux = std::slice::from_raw_parts(aux, self.aux_size);
+
+            // the buffer is a ring, unroll it when it wrapped
+            let len = (head - tail).min(self.aux_size);
+            let begin = (head - len) % self.aux_size;
+            if begin + len <= self.aux_size {
+                decode(&aux[begin..begin + len], &mut self.map);
+            } else {
+                let mut trace = aux[begin..].to_vec();
+                trace.extend_from_slice(&aux[..begin + len - self.aux_size]);
+                decode(&trace, &mut self.map);
+            }
+
+            libc::munmap(aux.as_ptr() as *mut libc::c_void, self.aux_size);
+            libc::munmap(base as *mut libc::c_void, base_len);
+            libc::close(fd);
+        }
+
+        Ok(ExecResult {
+            exit,
+            signal,
+            exec_time: start.elapsed(),
+            ..Default::default()
+        })
+    }
+
+    fn coverage(&self) -> Option<&[u8]> {
+        Some(&self.map)
+    }
+}
+
+impl Drop for IntelPtExecutor {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.input_file);
+    }
+}
+
+// Mix a value into the running location hash
+#[inline]
+fn mix(loc: u64, value: u64) -> u64 {
+    (loc.rotate_left(5) ^ value).wrapping_mul(0x9e3779b97f4a7c15)
+}
+
+// Walk raw PT packets, hashing branch outcomes into the edge map
+// Unknown or truncated packets resynchronize at the next PSB
+pub fn decode(trace: &[u8], map: &mut [u8]) {
+    const PSB: [u8; 16] = [0x02, 0x82, 0x02, 0x82, 0x02, 0x82, 0x02, 0x82,
+                           0x02, 0x82, 0x02, 0x82, 0x02, 0x82, 0x02, 0x82];
+
+    let mut last_ip = 0u64;
+    let mut loc = 0u64;
+
+    let hit = |loc: u64, map: &mut [u8]| {
+        let idx = (loc >> 32) as usize % map.len();
+        map[idx] = map[idx].wrapping_add(1);
+    };
+
+    // no trace is trustworthy before the first PSB
+    let resync = |from: usize| trace[from..].windows(PSB.len())
+        .position(|x| x == PSB).map(|x| from + x);
+    let Some(mut pos) = resync(0) else {
+        return;
+    };
+
+    while pos < trace.len() {
+        let byte = trace[pos];
+
+        // length of this packet, None if unknown
+        let len = match byte {
+            // PAD
+            0x00 => Some(1),
+            0x02 => match trace.get(pos + 1) {
+                Some(0x82) => {
+                    // PSB, IP compression restarts from scratch
+                    last_ip = 0;
+                    Some(PSB.len())
+                }
+                Some(0x23) | Some(0xf3) | Some(0x83) | Some(0x62) => Some(2),
+                Some(0x03) | Some(0x22) => Some(4),
+                Some(0x73) | Some(0xc8) | Some(0xa2) => Some(7),
+                Some(0x43) => Some(8),
+                Some(0xc2) => Some(10),
+                Some(0xc3) => Some(11),
+                Some(0xa3) => {
+                    // long TNT: 47 bits of payload behind a stop bit
+                    trace.get(pos + 2..pos + 8).map(|x| {
+                        let mut bits = 0u64;
+                        for (ii, b) in x.iter().enumerate() {
+                            bits |= (*b as u64) << (ii * 8);
+                        }
+                        if bits != 0 {
+                            let stop = 63 - bits.leading_zeros();
+                            for bit in (0..stop).rev() {
+                                loc = mix(loc, 1 + ((bits >> bit) & 1));
+                                hit(loc, map);
+                            }
+                        }
+                        8
+                    })
+                }
+                Some(x) if x & 0x1f == 0x12 => {
+                    // PTWRITE, 4 or 8 byte payload
+                    Some(if x & 0x20 == 0 { 6 } else { 10 })
+                }
+                _ => None,
+            },
+            // TSC
+            0x19 => Some(8),
+            // MTC
+            0x59 => Some(2),
+            // MODE
+            0x99 => Some(2),
+            // CYC, extended by following bytes while their low bit is set
+            x if x & 3 == 3 => {
+                let mut len = 1;
+                if x & 4 != 0 {
+                    while trace.get(pos + len).is_some_and(|x| x & 1 == 1) {
+                        len += 1;
+                    }
+                    len += 1;
+                }
+                Some(len)
+            }
+            // short TNT: up to 6 bits behind a stop bit
+            x if x & 1 == 0 => {
+                let stop = 7 - x.leading_zeros();
+                for bit in (1..stop).rev() {
+                    loc = mix(loc, 1 + ((x as u64 >> bit) & 1));
+                    hit(loc, map);
+                }
+                Some(1)
+            }
+            // TIP, TIP.PGE, TIP.PGD, FUP
+            x if matches!(x & 0x1f, 0x0d | 0x11 | 0x01 | 0x1d) => {
+                let ip_bytes = match x >> 5 {
+                    0 => 0,
+                    1 => 2,
+                    2 => 4,
+                    3 | 4 => 6,
+                    6 => 8,
+                    _ => usize::MAX,
+                };
+                trace.get(pos + 1..pos.saturating_add(1).saturating_add(ip_bytes))
+                    .map(|payload| {
+                        if !payload.is_empty() {
+                            let mut ip = 0u64;
+                            for (ii, b) in payload.iter().enumerate() {
+                                ip |= (*b as u64) << (ii * 8);
+                            }
+                            let bits = payload.len() * 8;
+                            last_ip = match x >> 5 {
+                                // sign extend bit 47
+                                3 => ((ip << 16) as i64 >> 16) as u64,
+                                6 => ip,
+                                _ => (last_ip & !((1u64 << bits) - 1)) | ip,
+                            };
+
+                            // FUP marks asynchronous events, not branches
+                            if x & 0x1f != 0x1d {
+                                loc = mix(loc, last_ip);
+                                hit(loc, map);
+                            }
+                        }
+                        1 + payload.len()
+                    })
+            }
+            _ => None,
+        };
+
+        pos = match len {
+            Some(len) => pos + len,
+            None => match resync(pos + 1) {
+                Some(next) => next,
+                None => break,
+            },
+        };
+    }
+}
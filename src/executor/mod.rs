@@ -0,0 +1,147 @@
+// Running generated test cases against a target
+//
+// Every backend implements Executor, the fuzz loop only ever sees the
+// ExecResult so new ways of talking to a target slot in without touching it
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use crate::sanitizer::Report;
+use crate::workspace;
+
+#[cfg(unix)]
+pub mod dlopen;
+#[cfg(unix)]
+pub mod frida;
+#[cfg(target_os = "linux")]
+pub mod intel_pt;
+pub mod network;
+#[cfg(unix)]
+pub mod process;
+#[cfg(unix)]
+pub mod qemu;
+#[cfg(unix)]
+pub mod sandbox;
+pub mod session;
+#[cfg(windows)]
+pub mod windows;
+
+#[cfg(unix)]
+pub use dlopen::DlopenExecutor;
+#[cfg(unix)]
+pub use frida::{FridaExecutor, FridaPersistent};
+#[cfg(target_os = "linux")]
+pub use intel_pt::IntelPtExecutor;
+pub use network::NetworkExecutor;
+#[cfg(unix)]
+pub use process::{Limits, ProcessExecutor};
+#[cfg(unix)]
+pub use qemu::QemuExecutor;
+// the fuzz loop does not care which platform spawns the target
+#[cfg(windows)]
+pub use windows::WindowsExecutor as ProcessExecutor;
+
+// How a single execution ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+pub enum ExitKind {
+    // target handled the input and is still alive
+    #[default]
+    Ok,
+    // target died (signal, lost connection and failed liveness probe, ...)
+    Crash,
+    // target did not finish (or answer) in time
+    Timeout,
+    // target ran into one of the resource limits it was started with
+    Limit(Resource),
+}
+
+// Resource limits a target can run into, see process::Limits
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Resource {
+    Memory,
+    CpuTime,
+    FileSize,
+}
+
+impl Resource {
+    pub const ALL: [Resource; 3] = [Resource::Memory, Resource::CpuTime,
+        Resource::FileSize];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Resource::Memory => "memory",
+            Resource::CpuTime => "cpu time",
+            Resource::FileSize => "file size",
+        }
+    }
+}
+
+// Everything a backend learned from running one input
+#[derive(Clone, Debug, Default)]
+pub struct ExecResult {
+    pub exit: ExitKind,
+
+    // signal that killed the target, if it died of one
+    pub signal: Option<i32>,
+
+    // exit code, if the target exited by itself
+    pub code: Option<i32>,
+
+    // whatever the target sent back (stdout, network response)
+    pub output: Vec<u8>,
+
+    // what the target printed to stderr, for backends capturing it
+    pub stderr: Vec<u8>,
+
+    // wall clock time of the execution
+    pub exec_time: Duration,
+
+    // sanitizer report the target printed, for backends capturing stderr
+    pub sanitizer: Option<Report>,
+}
+
+pub trait Executor {
+    // Deliver one input to the target and report what happened
+    // Errors are reserved for problems of the fuzzer itself (cannot spawn
+    // the target, ...), misbehaving targets are reported via ExitKind
+    fn run(&mut self, input: &[u8]) -> std::io::Result<ExecResult>;
+
+    // File the target reads its input from as is, for callers that write
+    // inputs there themselves (see mmap.rs) and run them with run_in_place()
+    fn in_place_file(&self) -> Option<&Path> {
+        None
+    }
+
+    // Run the input that already is in in_place_file()
+    fn run_in_place(&mut self, input: &[u8]) -> std::io::Result<ExecResult> {
+        self.run(input)
+    }
+
+    // Edge coverage map of the last run, for backends that can observe
+    // the target
+    fn coverage(&self) -> Option<&[u8]> {
+        None
+    }
+}
+
+// Find a file shipped with AFL++ (emulator, runtime library): $AFL_PATH
+// first, then $PATH, then the default install locations
+#[cfg(unix)]
+pub fn find_afl_file(name: &str) -> Option<PathBuf> {
+    let dirs = std::env::var_os("AFL_PATH").into_iter()
+        .chain(std::env::var_os("PATH"))
+        .flat_map(|x| std::env::split_paths(&x).collect::<Vec<_>>())
+        .chain(["/usr/local/lib/afl", "/usr/lib/afl"].map(PathBuf::from));
+
+    dirs.map(|dir| dir.join(name)).find(|x| x.is_file())
+}
+
+// Unique temp file for handing inputs to a target, several executors can
+// live in one process (one per worker)
+pub fn input_file_path() -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+    workspace::temp_dir().join(format!(".cur_input_{}_{}",
+        std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed)))
+}
@@ -0,0 +1,262 @@
+// Network service runner
+//
+// Connects to a TCP service, sends the payload and optionally waits for a
+// response. Connection resets and refused connections are double checked
+// with a liveness probe, and when the service turns out to be dead it is
+// reported as a crash and restarted. Uses plain blocking sockets with
+// timeouts, one connection per test case. With a session script the test
+// case is a sequence of messages sent on that connection, see session.rs.
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+use super::session::{self, Script, Step};
+use super::{ExecResult, Executor, ExitKind};
+use crate::{debug, info};
+
+// How long to wait for a (re)started service to accept connections
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+// How long a session waits for every reply without --net-timeout
+const SESSION_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub struct NetworkExecutor {
+    addr: SocketAddr,
+
+    // timeout for establishing the connection
+    connect_timeout: Duration,
+
+    // wait this long for a response, None means fire and forget
+    response_timeout: Option<Duration>,
+
+    // shell command exiting with 0 while the service is healthy
+    probe: Option<String>,
+
+    // command line used to (re)start the service, if we own it
+    server_cmd: Option<Vec<String>>,
+    server: Option<Child>,
+
+    // inputs are sessions of several messages
+    script: Option<Script>,
+}
+
+impl NetworkExecutor {
+    pub fn new(addr: &str) -> io::Result<Self> {
+        let addr = addr.to_socket_addrs()?.next().ok_or_else(||
+            io::Error::new(ErrorKind::InvalidInput, "address did not resolve"))?;
+
+        Ok(NetworkExecutor {
+            addr,
+            connect_timeout: Duration::from_secs(1),
+            response_timeout: None,
+            probe: None,
+            server_cmd: None,
+            server: None,
+            script: None,
+        })
+    }
+
+    // Send inputs as scripted sessions, see session.rs
+    pub fn session(mut self, script: Script) -> Self {
+        self.script = Some(script);
+        self
+    }
+
+    // Wait for a response after sending the payload
+    pub fn response_timeout(mut self, timeout: Duration) -> Self {
+        self.response_timeout = Some(timeout);
+        self
+    }
+
+    // Shell command used to decide whether the service survived
+    pub fn probe(mut self, cmd: &str) -> Self {
+        self.probe = Some(cmd.to_string());
+        self
+    }
+
+    // Launch (and relaunch after crashes) the service ourselves
+    pub fn server(mut self, cmd: Vec<String>) -> io::Result<Self> {
+        self.server_cmd = Some(cmd);
+        self.restart()?;
+        Ok(self)
+    }
+
+    // Did the service we spawned exit?
+    fn server_exited(&mut self) -> bool {
+        match &mut self.server {
+            Some(server) => !matches!(server.try_wait(), Ok(None)),
+            None => false,
+        }
+    }
+
+    // Is the service still up?
+    fn alive(&mut self) -> bool {
+        // a service we spawned that exited is dead no matter what
+        if self.server_exited() {
+            return false;
+        }
+
+        match &self.probe {
+            Some(probe) => Command::new("sh").arg("-c").arg(probe)
+                .stdin(Stdio::null()).stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status().map(|x| x.success()).unwrap_or(false),
+            None => TcpStream::connect_timeout(
+                &self.addr, self.connect_timeout).is_ok(),
+        }
+    }
+
+    // Kill whatever is left of the service and start a fresh one
+    fn restart(&mut self) -> io::Result<()> {
+        let Some(cmd) = &self.server_cmd else {
+            return Ok(());
+        };
+
+        if let Some(mut old) = self.server.take() {
+            info!("executor", "restarting {}", cmd[0]);
+            let _ = old.kill();
+            let _ = old.wait();
+        }
+
+        self.server = Some(Command::new(&cmd[0]).args(&cmd[1..])
+            .stdin(Stdio::null()).stdout(Stdio::null())
+            .stderr(Stdio::null()).spawn()?);
+
+        // wait until the service listens again
+        let start = Instant::now();
+        while start.elapsed() < STARTUP_TIMEOUT {
+            if TcpStream::connect_timeout(&self.addr, self.connect_timeout)
+                    .is_ok() {
+                return Ok(());
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        Err(io::Error::new(ErrorKind::TimedOut,
+            "service did not come up after restart"))
+    }
+
+    // Send the input and collect the response, any error means the
+    // connection went away under us
+    fn exchange(&self, input: &[u8], response: &mut Vec<u8>)
+            -> io::Result<ExitKind> {
+        let mut stream = TcpStream::connect_timeout(
+            &self.addr, self.connect_timeout)?;
+        stream.set_nodelay(true)?;
+        if let Some(script) = &self.script {
+            return self.converse(&mut stream, script, input, response);
+        }
+        stream.write_all(input)?;
+
+        let Some(timeout) = self.response_timeout else {
+            return Ok(ExitKind::Ok);
+        };
+        read_reply(&mut stream, timeout, response, |_| true)
+    }
+
+    // Send the messages of a session, reading and checking the reply to
+    // each. A reply that does not match ends the session early
+    fn converse(&self, stream: &mut TcpStream, script: &Script, input: &[u8],
+            response: &mut Vec<u8>) -> io::Result<ExitKind> {
+        let timeout = self.response_timeout.unwrap_or(SESSION_TIMEOUT);
+        let mut vars = Vec::new();
+        // read the reply to a step, whether the session goes on
+        let mut reply = |stream: &mut TcpStream, step: Option<&Step>,
+                vars: &mut Vec<(String, Vec<u8>)>| -> io::Result<Option<ExitKind>> {
+            let start = response.len();
+            let exit = read_reply(stream, timeout, response,
+                |reply| step.is_none_or(|x| x.satisfied(reply)))?;
+            let reply = &response[start..];
+            match step {
+                _ if exit != ExitKind::Ok => Ok(Some(exit)),
+                Some(step) if !step.satisfied(reply) => {
+                    debug!("executor", "unexpected reply {:?}",
+                        String::from_utf8_lossy(reply));
+                    Ok(Some(ExitKind::Ok))
+                }
+                Some(step) => {
+                    step.bind(reply, vars);
+                    Ok(None)
+                }
+                None => Ok(None),
+            }
+        };
+
+        if let Some(banner) = &script.banner {
+            if let Some(exit) = reply(stream, Some(banner), &mut vars)? {
+                return Ok(exit);
+            }
+        }
+        for (ii, message) in script.messages(input).into_iter().enumerate() {
+            stream.write_all(&session::substitute(message, &vars))?;
+            if let Some(exit) = reply(stream, script.steps.get(ii), &mut vars)? {
+                return Ok(exit);
+            }
+        }
+        Ok(ExitKind::Ok)
+    }
+}
+
+// Read a reply into response until done() says it is complete (then only
+// what is already on its way) or timeout passes without data
+fn read_reply(stream: &mut TcpStream, timeout: Duration, response: &mut Vec<u8>,
+        done: impl Fn(&[u8]) -> bool) -> io::Result<ExitKind> {
+    let start = response.len();
+    stream.set_read_timeout(Some(timeout))?;
+    let mut chunk = [0u8; 4096];
+    loop {
+        match stream.read(&mut chunk) {
+            // hung up without answering, treat like a reset
+            Ok(0) if response.len() == start => return Err(
+                io::Error::from(ErrorKind::ConnectionAborted)),
+            Ok(0) => return Ok(ExitKind::Ok),
+            Ok(len) => {
+                response.extend_from_slice(&chunk[..len]);
+                // got an answer, no need to wait for the server to
+                // close the connection
+                if done(&response[start..]) {
+                    stream.set_read_timeout(
+                        Some(Duration::from_millis(1)))?;
+                }
+            }
+            Err(e) if matches!(e.kind(),
+                    ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                return Ok(if response.len() == start {
+                    ExitKind::Timeout
+                } else {
+                    ExitKind::Ok
+                });
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+impl Executor for NetworkExecutor {
+    fn run(&mut self, input: &[u8]) -> io::Result<ExecResult> {
+        let start = Instant::now();
+        let mut result = ExecResult::default();
+
+        result.exit = match self.exchange(input, &mut result.output) {
+            // also catch a spawned service dying after it answered
+            Ok(exit) => if self.server_exited() { ExitKind::Crash } else { exit },
+            Err(_) => if self.alive() { ExitKind::Ok } else { ExitKind::Crash },
+        };
+        result.exec_time = start.elapsed();
+
+        if result.exit == ExitKind::Crash {
+            self.restart()?;
+        }
+        Ok(result)
+    }
+}
+
+impl Drop for NetworkExecutor {
+    fn drop(&mut self) {
+        if let Some(mut server) = self.server.take() {
+            let _ = server.kill();
+            let _ = server.wait();
+        }
+    }
+}
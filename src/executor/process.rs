@@ -0,0 +1,447 @@
+// Plain fork/exec runner
+//
+// Spawns the target for every input. The input goes to stdin, or into a
+// file when the command line contains @@ (replaced by the file path, like
+// afl-fuzz does), or into shared memory for targets built with AFL++
+// __AFL_FUZZ_TESTCASE_BUF: the segment named by __AFL_SHM_FUZZ_ID holds a
+// u32 length followed by the input.
+//
+// Targets can be started under rlimits. Running into the CPU or file size
+// limit kills the target with SIGXCPU/SIGXFSZ. Hitting the memory limit
+// just makes allocations fail, so a target that died after using at least
+// half of its limit counts as out of memory. Running out of file
+// descriptors is whatever the target makes of EMFILE.
+//
+// With sanitizer capture on, stderr goes to a file that is parsed after
+// every execution that printed something. A sanitizer report makes the
+// execution a crash even when the target exited normally afterwards
+// (ASAN_OPTIONS without abort_on_error=1).
+//
+// Capturing stdout (and stderr without sanitizers) works the same way, for
+// feedbacks and oracles judging what the target printed (see feedback.rs
+// and oracle.rs).
+//
+// With fuzzed positions, the leading parts of an input go into command
+// line arguments, environment variables and the files of named outputs
+// instead, see positions.rs.
+
+use std::ffi::{OsStr, OsString};
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use super::sandbox::Sandbox;
+use super::{input_file_path, ExecResult, Executor, ExitKind, Resource};
+use crate::coverage::ShmCoverageMap;
+use crate::debug;
+use crate::positions::{self, Position};
+use crate::sanitizer;
+
+// Input delivery through shared memory, the region is a plain SysV segment
+// like the coverage map
+struct ShmInput {
+    shm: ShmCoverageMap,
+
+    // largest input that fits, longer ones get truncated like afl-fuzz
+    // does with inputs above MAX_FILE
+    max_len: usize,
+}
+
+// rlimits for the target, None leaves the limit alone
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Limits {
+    // address space, bytes
+    pub memory: Option<u64>,
+    // seconds
+    pub cpu_time: Option<u64>,
+    // largest file the target may write, bytes
+    pub file_size: Option<u64>,
+    pub open_files: Option<u64>,
+}
+
+impl Limits {
+    fn is_empty(&self) -> bool {
+        self.memory.is_none() && self.cpu_time.is_none()
+            && self.file_size.is_none() && self.open_files.is_none()
+    }
+
+    // Apply the limits to the current process, between fork and exec
+    fn apply(&self) -> io::Result<()> {
+        // at the hard CPU limit the kernel sends SIGKILL instead of
+        // SIGXCPU, give it a second of slack
+        let limits = [
+            (libc::RLIMIT_AS, self.memory, 0),
+            (libc::RLIMIT_CPU, self.cpu_time, 1),
+            (libc::RLIMIT_FSIZE, self.file_size, 0),
+            (libc::RLIMIT_NOFILE, self.open_files, 0),
+        ];
+        for (resource, value, slack) in limits {
+            let Some(value) = value else {
+                continue;
+            };
+            let limit = libc::rlimit {
+                rlim_cur: value as libc::rlim_t,
+                rlim_max: (value + slack) as libc::rlim_t,
+            };
+            // SAFETY: plain syscall on a local struct
+            if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct ProcessExecutor {
+    argv: Vec<String>,
+
+    // arguments of a wrapper in front of the target command line
+    wrapped: usize,
+
+    // argv, environment and files taken from the input, and the directory
+    // the files of named outputs go to
+    positions: Vec<Position>,
+    output_dir: Option<PathBuf>,
+
+    // file the input is written to when the target reads it via @@
+    input_file: Option<PathBuf>,
+
+    // extra environment for the target
+    env: Vec<(String, String)>,
+
+    // kill the target after this long
+    timeout: Duration,
+
+    shm_input: Option<ShmInput>,
+    sandbox: Option<Sandbox>,
+    limits: Limits,
+
+    // stderr of the target, when looking for sanitizer reports or oracles
+    // look at it
+    stderr_file: Option<PathBuf>,
+    sanitizer: bool,
+
+    // stdout of the target, when feedbacks look at it
+    stdout_file: Option<PathBuf>,
+}
+
+impl ProcessExecutor {
+    pub fn new(argv: Vec<String>, timeout: Duration) -> Self {
+        let input_file = argv.iter().any(|x| x == "@@")
+            .then(input_file_path);
+
+        ProcessExecutor {
+            argv,
+            wrapped: 0,
+            positions: Vec::new(),
+            output_dir: None,
+            input_file,
+            env: Vec::new(),
+            timeout,
+            shm_input: None,
+            sandbox: None,
+            limits: Limits::default(),
+            stderr_file: None,
+            sanitizer: false,
+            stdout_file: None,
+        }
+    }
+
+    // Deliver inputs through shared memory instead of stdin. The
+    // segment is sized for max_len bytes of input, the target learns the
+    // limit from AFL_INPUT_LEN_MAX and can refuse to start (or truncate)
+    // if it cannot take that much
+    pub fn shm_input(&mut self, max_len: usize) -> io::Result<()> {
+        let shm = ShmCoverageMap::new(4 + max_len)?;
+        self.env("__AFL_SHM_FUZZ_ID", &shm.id().to_string());
+        self.env("AFL_INPUT_LEN_MAX", &max_len.to_string());
+        self.shm_input = Some(ShmInput { shm, max_len });
+        Ok(())
+    }
+
+    // File the input is handed over in, for @@ command lines
+    pub fn input_file(&self) -> Option<&Path> {
+        self.input_file.as_deref()
+    }
+
+    // Set an environment variable for every execution
+    pub fn env(&mut self, key: &str, value: &str) {
+        self.env.push((key.to_string(), value.to_string()));
+    }
+
+    // Run the target confined, see sandbox.rs
+    pub fn sandbox(&mut self) -> io::Result<()> {
+        self.sandbox = Some(Sandbox::new()?);
+        Ok(())
+    }
+
+    // Capture stderr of the target and parse sanitizer reports out of it
+    pub fn capture_sanitizer(&mut self) {
+        self.capture_stderr();
+        self.sanitizer = true;
+    }
+
+    // Capture stderr of the target into ExecResult::stderr
+    pub fn capture_stderr(&mut self) {
+        self.stderr_file.get_or_insert_with(||
+            input_file_path().with_extension("stderr"));
+    }
+
+    // Capture stdout of the target into ExecResult::output
+    pub fn capture_output(&mut self) {
+        self.stdout_file = Some(input_file_path().with_extension("stdout"));
+    }
+
+    // Start the target under rlimits
+    pub fn limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    // Take argv entries (indices into the target command line),
+    // environment variables and files from the input, see positions.rs.
+    // The files go into a directory of their own, a failure to create it
+    // comes up with the first execution
+    pub fn positions(&mut self, positions: Vec<Position>) {
+        if positions.iter().any(|x| matches!(x, Position::File(_))) {
+            let dir = input_file_path().with_extension("files");
+            let _ = std::fs::create_dir_all(&dir);
+            self.output_dir = Some(dir);
+        }
+        self.positions = positions;
+    }
+
+    // Split an input into the values of the fuzzed positions and what goes
+    // to stdin/file
+    pub fn split<'a>(&self, input: &'a [u8]) -> (Vec<&'a [u8]>, &'a [u8]) {
+        positions::split(input, self.positions.len())
+    }
+
+    // Prepend a wrapper (emulator, tracer, ...) to the target command line
+    pub fn wrap(&mut self, wrapper: &[String]) {
+        self.argv.splice(0..0, wrapper.iter().cloned());
+        self.wrapped += wrapper.len();
+    }
+
+    // Actual command line, with @@ and @@<name> resolved and the values of
+    // the fuzzed positions filled in
+    fn command(&self, values: &[&[u8]]) -> io::Result<Command> {
+        let value = |position: &Position| self.positions.iter()
+            .position(|x| x == position)
+            .map(|idx| OsStr::from_bytes(values[idx]).to_os_string());
+        let file = |arg: &str| arg.strip_prefix("@@")
+            .filter(|name| self.positions.contains(&Position::File(name.to_string())))
+            .zip(self.output_dir.as_ref())
+            .map(|(name, dir)| dir.join(name).into_os_string());
+        let mut args = self.argv.iter().enumerate().map(|(ii, x)| {
+            let fuzzed = ii.checked_sub(self.wrapped)
+                .and_then(|index| value(&Position::Arg(index)))
+                .or_else(|| file(x));
+            match (fuzzed, &self.input_file) {
+                (Some(fuzzed), _) => fuzzed,
+                (None, Some(path)) if x == "@@" => path.clone().into_os_string(),
+                _ => OsString::from(x),
+            }
+        });
+
+        let mut cmd = Command::new(args.next().expect("empty command line"));
+        cmd.args(args)
+            .envs(self.env.iter().map(|(k, v)| (k, v)))
+            .envs(self.positions.iter().zip(values).filter_map(|(position, value)|
+                match position {
+                    Position::Env(name) => Some((name, OsStr::from_bytes(value))),
+                    Position::Arg(_) | Position::File(_) => None,
+                }))
+            .stdout(match &self.stdout_file {
+                Some(path) => std::fs::File::create(path)?.into(),
+                None => Stdio::null(),
+            })
+            .stderr(match &self.stderr_file {
+                Some(path) => std::fs::File::create(path)?.into(),
+                None => Stdio::null(),
+            })
+            .stdin(if self.input_file.is_some() || self.shm_input.is_some() {
+                Stdio::null()
+            } else {
+                Stdio::piped()
+            });
+        if let Some(sandbox) = &self.sandbox {
+            sandbox.apply(&mut cmd);
+        }
+        if !self.limits.is_empty() {
+            let limits = self.limits;
+            // SAFETY: setrlimit is async signal safe
+            unsafe {
+                cmd.pre_exec(move || limits.apply());
+            }
+        }
+        Ok(cmd)
+    }
+}
+
+impl ProcessExecutor {
+    // How the target ended, telling limits apart from real crashes
+    fn classify(&self, status: &std::process::ExitStatus,
+            usage: &libc::rusage) -> ExitKind {
+        match status.signal() {
+            Some(libc::SIGXCPU) if self.limits.cpu_time.is_some() =>
+                return ExitKind::Limit(Resource::CpuTime),
+            Some(libc::SIGXFSZ) if self.limits.file_size.is_some() =>
+                return ExitKind::Limit(Resource::FileSize),
+            _ => {}
+        }
+
+        // ru_maxrss is in kilobytes, bytes on macOS
+        #[cfg(target_os = "macos")]
+        let peak = usage.ru_maxrss as u64;
+        #[cfg(not(target_os = "macos"))]
+        let peak = usage.ru_maxrss as u64 * 1024;
+        if let Some(memory) = self.limits.memory {
+            if !status.success() && peak >= memory / 2 {
+                return ExitKind::Limit(Resource::Memory);
+            }
+        }
+
+        if status.signal().is_some() {
+            ExitKind::Crash
+        } else {
+            ExitKind::Ok
+        }
+    }
+}
+
+impl Executor for ProcessExecutor {
+    fn run(&mut self, input: &[u8]) -> io::Result<ExecResult> {
+        if let Some(path) = &self.input_file {
+            std::fs::write(path, self.split(input).1)?;
+        }
+        self.launch(input)
+    }
+
+    // Only with the whole input in the file, fuzzed positions take theirs
+    // from the front of it
+    fn in_place_file(&self) -> Option<&Path> {
+        self.input_file.as_deref().filter(|_| self.positions.is_empty())
+    }
+
+    fn run_in_place(&mut self, input: &[u8]) -> io::Result<ExecResult> {
+        self.launch(input)
+    }
+}
+
+impl ProcessExecutor {
+    // Start the target on input, the input file is written already
+    fn launch(&mut self, input: &[u8]) -> io::Result<ExecResult> {
+        let (values, input) = self.split(input);
+        if let Some(dir) = &self.output_dir {
+            for (position, value) in self.positions.iter().zip(&values) {
+                if let Position::File(name) = position {
+                    std::fs::write(dir.join(name), value)?;
+                }
+            }
+        }
+        if let Some(ShmInput { shm, max_len }) = &mut self.shm_input {
+            if input.len() > *max_len {
+                debug!("executor", "input of {} bytes truncated to {} for shared memory",
+                    input.len(), max_len);
+            }
+            let len = input.len().min(*max_len);
+            let region = shm.as_mut_slice();
+            region[..4].copy_from_slice(&(len as u32).to_le_bytes());
+            region[4..4 + len].copy_from_slice(&input[..len]);
+        }
+
+        let start = Instant::now();
+        let mut child = self.command(&values)?.spawn()?;
+
+        // stdin is fed as the target reads it, one that never does still
+        // runs into the timeout
+        let mut stdin = child.stdin.take();
+        if let Some(pipe) = &stdin {
+            let fd = pipe.as_raw_fd();
+            // SAFETY: fcntl on a pipe we own
+            unsafe {
+                let flags = libc::fcntl(fd, libc::F_GETFL);
+                libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK);
+            }
+        }
+        let mut written = 0;
+
+        let mut result = ExecResult::default();
+        loop {
+            if let Some(pipe) = &mut stdin {
+                match pipe.write(&input[written..]) {
+                    Ok(len) => written += len,
+                    Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock
+                        | io::ErrorKind::Interrupted) => {}
+                    // target may exit without reading everything
+                    Err(_) => written = input.len(),
+                }
+                if written == input.len() {
+                    stdin = None;
+                }
+            }
+
+            // wait4 instead of try_wait, for the peak memory of the target
+            let mut status = 0;
+            // SAFETY: zeroed rusage is valid, wait4 only writes to locals
+            let mut usage = unsafe { std::mem::zeroed::<libc::rusage>() };
+            let pid = unsafe { libc::wait4(child.id() as i32, &mut status,
+                libc::WNOHANG, &mut usage) };
+            if pid < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if pid > 0 {
+                let status = std::process::ExitStatus::from_raw(status);
+                result.signal = status.signal();
+                result.code = status.code();
+                result.exit = self.classify(&status, &usage);
+                break;
+            }
+
+            if start.elapsed() > self.timeout {
+                drop(stdin.take());
+                let _ = child.kill();
+                child.wait()?;
+                result.exit = ExitKind::Timeout;
+                break;
+            }
+            std::thread::sleep(Duration::from_micros(100));
+        }
+        result.exec_time = start.elapsed();
+
+        if let Some(path) = &self.stderr_file {
+            if result.exit != ExitKind::Timeout
+                    && std::fs::metadata(path)?.len() > 0 {
+                result.stderr = std::fs::read(path)?;
+                if self.sanitizer {
+                    result.sanitizer = sanitizer::parse(&result.stderr);
+                }
+                if result.sanitizer.is_some() {
+                    result.exit = ExitKind::Crash;
+                }
+            }
+        }
+        if let Some(path) = &self.stdout_file {
+            result.output = std::fs::read(path)?;
+        }
+        if let Some(sandbox) = &self.sandbox {
+            sandbox.cleanup()?;
+        }
+        Ok(result)
+    }
+}
+
+impl Drop for ProcessExecutor {
+    fn drop(&mut self) {
+        for path in [&self.input_file, &self.stderr_file, &self.stdout_file].into_iter().flatten() {
+            let _ = std::fs::remove_file(path);
+        }
+        if let Some(dir) = &self.output_dir {
+            let _ = std::fs::remove_dir_all(dir);
+        }
+    }
+}
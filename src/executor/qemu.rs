@@ -0,0 +1,54 @@
+// Binary-only coverage through QEMU user-mode emulation
+//
+// Runs the target under afl-qemu-trace (AFL++ qemuafl), which records edge
+// coverage of the emulated code into the shared memory map named by
+// __AFL_SHM_ID. No forkserver, one emulator start per input.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::{find_afl_file, ExecResult, Executor, ProcessExecutor};
+use crate::coverage::{ShmCoverageMap, MAP_SIZE};
+
+pub struct QemuExecutor {
+    inner: ProcessExecutor,
+    map: ShmCoverageMap,
+}
+
+impl QemuExecutor {
+    pub fn new(mut inner: ProcessExecutor, qemu: PathBuf) -> io::Result<Self> {
+        let map = ShmCoverageMap::new(MAP_SIZE)?;
+
+        inner.wrap(&[qemu.to_string_lossy().into_owned()]);
+        inner.env("__AFL_SHM_ID", &map.id().to_string());
+        // AFL++ sizes its instrumentation to the map we provide
+        inner.env("AFL_MAP_SIZE", &MAP_SIZE.to_string());
+
+        Ok(QemuExecutor { inner, map })
+    }
+
+    // Locate afl-qemu-trace
+    pub fn find_qemu() -> Option<PathBuf> {
+        find_afl_file("afl-qemu-trace")
+    }
+}
+
+impl Executor for QemuExecutor {
+    fn run(&mut self, input: &[u8]) -> io::Result<ExecResult> {
+        self.map.clear();
+        self.inner.run(input)
+    }
+
+    fn in_place_file(&self) -> Option<&Path> {
+        self.inner.in_place_file()
+    }
+
+    fn run_in_place(&mut self, input: &[u8]) -> io::Result<ExecResult> {
+        self.map.clear();
+        self.inner.run_in_place(input)
+    }
+
+    fn coverage(&self) -> Option<&[u8]> {
+        Some(self.map.as_slice())
+    }
+}
@@ -0,0 +1,142 @@
+// Keeping targets from touching the host
+//
+// Every execution runs in a private scratch directory that is emptied after
+// each run, so targets that drop files do not fill the disk or see leftovers
+// of earlier inputs. On Linux the target also loses the network: it gets a
+// fresh network namespace when we are allowed to create one (root), and a
+// seccomp filter failing IPv4/IPv6 sockets with EACCES in any case.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::workspace;
+
+pub struct Sandbox {
+    // working directory of the target
+    dir: PathBuf,
+}
+
+impl Sandbox {
+    pub fn new() -> io::Result<Self> {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+        let dir = workspace::temp_dir().join(format!(".sandbox_{}_{}",
+            std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed)));
+        fs::create_dir_all(&dir)?;
+        Ok(Sandbox { dir })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // Confine a command about to be spawned
+    pub fn apply(&self, cmd: &mut Command) {
+        cmd.current_dir(&self.dir);
+
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::unix::process::CommandExt;
+
+            // SAFETY: runs between fork and exec, only async signal safe
+            // syscalls on memory set up before the fork
+            unsafe {
+                cmd.pre_exec(|| {
+                    // best effort, unprivileged users cannot do this
+                    libc::unshare(libc::CLONE_NEWNET);
+                    deny_network()
+                });
+            }
+        }
+    }
+
+    // Remove whatever the last execution left behind
+    pub fn cleanup(&self) -> io::Result<()> {
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            // targets may chmod their files, do not let that stop us
+            if entry.file_type()?.is_dir() {
+                fs::remove_dir_all(entry.path())?;
+            } else {
+                fs::remove_file(entry.path())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+// AUDIT_ARCH_* of the architecture we are built for, the filter only knows
+// the syscall numbers of that one
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+const AUDIT_ARCH: u32 = 0xc000_003e;
+#[cfg(all(target_os = "linux", target_arch = "aarch64"))]
+const AUDIT_ARCH: u32 = 0xc000_00b7;
+
+// Install a seccomp filter making socket(AF_INET | AF_INET6, ...) fail,
+// unix sockets keep working. Syscalls of other ABIs (ia32 emulation, x32)
+// are killed as they could get around the filter
+#[cfg(all(target_os = "linux", any(target_arch = "x86_64",
+    target_arch = "aarch64")))]
+fn deny_network() -> io::Result<()> {
+    use libc::{sock_filter, sock_fprog, BPF_ABS, BPF_JEQ, BPF_JGE, BPF_JMP, BPF_K,
+        BPF_LD, BPF_RET, BPF_W};
+
+    const fn stmt(code: u32, k: u32) -> sock_filter {
+        sock_filter { code: code as u16, jt: 0, jf: 0, k }
+    }
+    const fn jump(code: u32, k: u32, jt: u8, jf: u8) -> sock_filter {
+        sock_filter { code: code as u16, jt, jf, k }
+    }
+
+    // offsets into struct seccomp_data
+    const NR: u32 = 0;
+    const ARCH: u32 = 4;
+    const ARG0: u32 = 16;
+    const KILL: u32 = 0; // SECCOMP_RET_KILL_THREAD
+    const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+    let filter = [
+        stmt(BPF_LD | BPF_W | BPF_ABS, ARCH),
+        jump(BPF_JMP | BPF_JEQ | BPF_K, AUDIT_ARCH, 1, 0),
+        stmt(BPF_RET | BPF_K, KILL),
+        stmt(BPF_LD | BPF_W | BPF_ABS, NR),
+        jump(BPF_JMP | BPF_JGE | BPF_K, X32_SYSCALL_BIT, 0, 1),
+        stmt(BPF_RET | BPF_K, KILL),
+        jump(BPF_JMP | BPF_JEQ | BPF_K, libc::SYS_socket as u32, 0, 4),
+        stmt(BPF_LD | BPF_W | BPF_ABS, ARG0),
+        jump(BPF_JMP | BPF_JEQ | BPF_K, libc::AF_INET as u32, 1, 0),
+        jump(BPF_JMP | BPF_JEQ | BPF_K, libc::AF_INET6 as u32, 0, 1),
+        stmt(BPF_RET | BPF_K, libc::SECCOMP_RET_ERRNO | libc::EACCES as u32),
+        stmt(BPF_RET | BPF_K, libc::SECCOMP_RET_ALLOW),
+    ];
+    let prog = sock_fprog {
+        len: filter.len() as u16,
+        filter: filter.as_ptr() as *mut sock_filter,
+    };
+
+    // SAFETY: prog points at the filter, which outlives the call
+    unsafe {
+        if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0
+            || libc::prctl(libc::PR_SET_SECCOMP, libc::SECCOMP_MODE_FILTER,
+                &prog as *const sock_fprog) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+// Nothing to filter with, the network namespace has to do
+#[cfg(all(target_os = "linux", not(any(target_arch = "x86_64",
+    target_arch = "aarch64"))))]
+fn deny_network() -> io::Result<()> {
+    Ok(())
+}
@@ -0,0 +1,157 @@
+// Scripted sessions with stateful network services
+//
+// Most protocols only get interesting after a handshake: log in, take the
+// session id the server hands out, use it in every later request. With a
+// session script (--net-session) an input is a sequence of messages, split
+// after every occurrence of the script's separator (which stays part of the
+// message), sent one after the other on one connection.
+// The grammar produces the whole sequence, so saved inputs replay the
+// whole session. After every message the reply is read and checked
+// against the step of the same index:
+//
+//   {
+//     "separator": "\n",
+//     "banner": {"expect": {"prefix": "220"}},
+//     "steps": [
+//       {"expect": {"prefix": "+OK"},
+//        "capture": {"sid": {"after": "SID=", "until": "\r\n"}}},
+//       {"expect": {"contains": "DONE"}}
+//     ]
+//   }
+//
+// banner is what the server says right after connecting. expect is a byte
+// prefix or a substring of the reply, a reply that does not match ends the
+// session there. capture binds variables to parts of a reply, ${name} in
+// any later message is replaced by the bytes bound to name. Messages past
+// the last step are sent without checking their replies.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Script {
+    #[serde(default = "default_separator")]
+    pub separator: String,
+    #[serde(default)]
+    pub banner: Option<Step>,
+    #[serde(default)]
+    pub steps: Vec<Step>,
+}
+
+fn default_separator() -> String {
+    "\n".to_string()
+}
+
+// What a reply has to look like and what to take from it
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Step {
+    #[serde(default)]
+    pub expect: Option<Expect>,
+    #[serde(default)]
+    pub capture: BTreeMap<String, Capture>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Expect {
+    Prefix(String),
+    Contains(String),
+}
+
+// The bytes after the first occurrence of after, up to until (or the end
+// of the reply)
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Capture {
+    pub after: String,
+    #[serde(default)]
+    pub until: Option<String>,
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    match needle.len() {
+        0 => Some(0),
+        len => haystack.windows(len).position(|x| x == needle),
+    }
+}
+
+impl Capture {
+    fn extract<'a>(&self, reply: &'a [u8]) -> Option<&'a [u8]> {
+        let start = find(reply, self.after.as_bytes())? + self.after.len();
+        let rest = &reply[start..];
+        match &self.until {
+            Some(until) => Some(&rest[..find(rest, until.as_bytes())?]),
+            None => Some(rest),
+        }
+    }
+}
+
+impl Step {
+    // Whether the reply is complete: it matches and has every capture
+    pub fn satisfied(&self, reply: &[u8]) -> bool {
+        let matches = match &self.expect {
+            None => true,
+            Some(Expect::Prefix(prefix)) => reply.starts_with(prefix.as_bytes()),
+            Some(Expect::Contains(part)) => find(reply, part.as_bytes()).is_some(),
+        };
+        matches && self.capture.values().all(|x| x.extract(reply).is_some())
+    }
+
+    // Bind the captures of a reply
+    pub fn bind(&self, reply: &[u8], vars: &mut Vec<(String, Vec<u8>)>) {
+        for (name, capture) in &self.capture {
+            let Some(value) = capture.extract(reply) else {
+                continue;
+            };
+            vars.retain(|x| x.0 != *name);
+            vars.push((name.clone(), value.to_vec()));
+        }
+    }
+}
+
+impl Script {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        serde_json::from_slice(&std::fs::read(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e)))
+    }
+
+    // The messages of an input, each ending in the separator but the last
+    pub fn messages<'a>(&'a self, input: &'a [u8]) -> Vec<&'a [u8]> {
+        let separator = self.separator.as_bytes();
+        if separator.is_empty() {
+            return vec![input];
+        }
+        let mut messages = Vec::new();
+        let mut rest = input;
+        while let Some(pos) = find(rest, separator) {
+            let (message, tail) = rest.split_at(pos + separator.len());
+            messages.push(message);
+            rest = tail;
+        }
+        if !rest.is_empty() {
+            messages.push(rest);
+        }
+        messages
+    }
+}
+
+// message with every ${name} of a bound variable replaced by its value
+pub fn substitute(message: &[u8], vars: &[(String, Vec<u8>)]) -> Vec<u8> {
+    let mut out = message.to_vec();
+    for (name, value) in vars {
+        let pattern = format!("${{{}}}", name);
+        let mut from = 0;
+        while let Some(pos) = find(&out[from..], pattern.as_bytes()) {
+            let at = from + pos;
+            out.splice(at..at + pattern.len(), value.iter().copied());
+            from = at + value.len();
+        }
+    }
+    out
+}
@@ -0,0 +1,271 @@
+// Windows process runner
+//
+// Same job as the unix ProcessExecutor: spawn the target per input, with
+// the input on stdin or in a file substituted for @@. Every target runs in
+// its own job object, which enforces the memory limit, suppresses the WER
+// crash dialog and lets a timeout take down the whole process tree. Crashes
+// are recognized by the NTSTATUS exception code the process exits with.
+
+use std::ffi::c_void;
+use std::io::{self, Write};
+use std::os::windows::io::AsRawHandle;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+use super::{input_file_path, ExecResult, Executor, ExitKind};
+
+type Handle = *mut c_void;
+
+// JOBOBJECTINFOCLASS JobObjectExtendedLimitInformation
+const JOB_OBJECT_EXTENDED_LIMIT_INFORMATION: i32 = 9;
+
+const JOB_OBJECT_LIMIT_PROCESS_MEMORY: u32 = 0x0000_0100;
+const JOB_OBJECT_LIMIT_DIE_ON_UNHANDLED_EXCEPTION: u32 = 0x0000_0400;
+const JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE: u32 = 0x0000_2000;
+
+// Exit codes which are really unhandled exceptions
+const EXCEPTION_CODES: &[u32] = &[
+    0x8000_0003, // EXCEPTION_BREAKPOINT
+    0xc000_0005, // EXCEPTION_ACCESS_VIOLATION
+    0xc000_001d, // EXCEPTION_ILLEGAL_INSTRUCTION
+    0xc000_0094, // EXCEPTION_INT_DIVIDE_BY_ZERO
+    0xc000_00fd, // EXCEPTION_STACK_OVERFLOW
+    0xc000_0374, // STATUS_HEAP_CORRUPTION
+    0xc000_0409, // STATUS_STACK_BUFFER_OVERRUN (__fastfail)
+    0xc000_0420, // STATUS_ASSERTION_FAILURE
+];
+
+#[repr(C)]
+#[derive(Default)]
+struct JobObjectBasicLimitInformation {
+    per_process_user_time_limit: i64,
+    per_job_user_time_limit: i64,
+    limit_flags: u32,
+    minimum_working_set_size: usize,
+    maximum_working_set_size: usize,
+    active_process_limit: u32,
+    affinity: usize,
+    priority_class: u32,
+    scheduling_class: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct IoCounters {
+    read_operation_count: u64,
+    write_operation_count: u64,
+    other_operation_count: u64,
+    read_transfer_count: u64,
+    write_transfer_count: u64,
+    other_transfer_count: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct JobObjectExtendedLimitInformation {
+    basic_limit_information: JobObjectBasicLimitInformation,
+    io_info: IoCounters,
+    process_memory_limit: usize,
+    job_memory_limit: usize,
+    peak_process_memory_used: usize,
+    peak_job_memory_used: usize,
+}
+
+#[link(name = "kernel32")]
+extern "system" {
+    fn CreateJobObjectW(attributes: *mut c_void, name: *const u16) -> Handle;
+    fn SetInformationJobObject(job: Handle, class: i32, info: *mut c_void,
+        len: u32) -> i32;
+    fn AssignProcessToJobObject(job: Handle, process: Handle) -> i32;
+    fn TerminateJobObject(job: Handle, exit_code: u32) -> i32;
+    fn CloseHandle(handle: Handle) -> i32;
+}
+
+// Owned job object handle, closing it kills everything still inside
+struct Job(Handle);
+
+impl Job {
+    fn new(memory_limit: Option<usize>) -> io::Result<Self> {
+        // SAFETY: anonymous job, the limit struct outlives the call
+        unsafe {
+            let job = CreateJobObjectW(std::ptr::null_mut(), std::ptr::null());
+            if job.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            let job = Job(job);
+
+            let mut info = JobObjectExtendedLimitInformation::default();
+            info.basic_limit_information.limit_flags =
+                JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE |
+                JOB_OBJECT_LIMIT_DIE_ON_UNHANDLED_EXCEPTION;
+            if let Some(limit) = memory_limit {
+                info.basic_limit_information.limit_flags |=
+                    JOB_OBJECT_LIMIT_PROCESS_MEMORY;
+                info.process_memory_limit = limit;
+            }
+
+            if SetInformationJobObject(job.0,
+                    JOB_OBJECT_EXTENDED_LIMIT_INFORMATION,
+                    &mut info as *mut _ as *mut c_void,
+                    std::mem::size_of_val(&info) as u32) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(job)
+        }
+    }
+}
+
+impl Drop for Job {
+    fn drop(&mut self) {
+        // SAFETY: we own the handle
+        unsafe {
+            CloseHandle(self.0);
+        }
+    }
+}
+
+pub struct WindowsExecutor {
+    argv: Vec<String>,
+
+    // file the input is written to when the target reads it via @@
+    input_file: Option<PathBuf>,
+
+    // extra environment for the target
+    env: Vec<(String, String)>,
+
+    // kill the target after this long
+    timeout: Duration,
+
+    // per process commit limit in bytes
+    memory_limit: Option<usize>,
+}
+
+impl WindowsExecutor {
+    pub fn new(argv: Vec<String>, timeout: Duration) -> Self {
+        let input_file = argv.iter().any(|x| x == "@@")
+            .then(input_file_path);
+
+        WindowsExecutor {
+            argv,
+            input_file,
+            env: Vec::new(),
+            timeout,
+            memory_limit: None,
+        }
+    }
+
+    // Set an environment variable for every execution
+    pub fn env(&mut self, key: &str, value: &str) {
+        self.env.push((key.to_string(), value.to_string()));
+    }
+
+    // Limit the memory every target process may commit
+    pub fn memory_limit(&mut self, bytes: usize) {
+        self.memory_limit = Some(bytes);
+    }
+
+    fn command(&self) -> Command {
+        let mut args = self.argv.iter().map(|x| match &self.input_file {
+            Some(path) if x == "@@" => path.to_string_lossy().into_owned(),
+            _ => x.clone(),
+        });
+
+        let mut cmd = Command::new(args.next().expect("empty command line"));
+        cmd.args(args)
+            .envs(self.env.iter().map(|(k, v)| (k, v)))
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .stdin(if self.input_file.is_some() {
+                Stdio::null()
+            } else {
+                Stdio::piped()
+            });
+        cmd
+    }
+}
+
+impl Executor for WindowsExecutor {
+    fn run(&mut self, input: &[u8]) -> io::Result<ExecResult> {
+        if let Some(path) = &self.input_file {
+            std::fs::write(path, input)?;
+        }
+
+        let job = Job::new(self.memory_limit)?;
+        let start = Instant::now();
+        let mut child = self.command().spawn()?;
+
+        // SAFETY: both handles are valid for the duration of the call
+        if unsafe { AssignProcessToJobObject(job.0,
+                child.as_raw_handle() as Handle) } == 0 {
+            let err = io::Error::last_os_error();
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(err);
+        }
+
+        // stdin is fed from a thread, a target that never reads it still
+        // runs into the timeout. Taking down the job closes the pipe, which
+        // ends the writer
+        let stdin = child.stdin.take();
+        let mut result = std::thread::scope(|s| {
+            if let Some(mut stdin) = stdin {
+                // target may exit without reading everything
+                s.spawn(move || {
+                    let _ = stdin.write_all(input);
+                });
+            }
+            let result = self.wait(&mut child, &job, start);
+            // what is left of the process tree would hold the pipe open,
+            // closing the job would take it down anyway
+            // SAFETY: we own the job handle
+            unsafe {
+                TerminateJobObject(job.0, 1);
+            }
+            result
+        })?;
+        result.exec_time = start.elapsed();
+        Ok(result)
+    }
+}
+
+impl WindowsExecutor {
+    // Wait for the target to exit or the timeout to pass
+    fn wait(&self, child: &mut Child, job: &Job, start: Instant) -> io::Result<ExecResult> {
+        let mut result = ExecResult::default();
+        loop {
+            if let Some(status) = child.try_wait()? {
+                result.code = status.code();
+                let code = status.code().unwrap_or(0) as u32;
+                result.exit = if EXCEPTION_CODES.contains(&code) ||
+                        code & 0xf000_0000 == 0xc000_0000 {
+                    ExitKind::Crash
+                } else {
+                    ExitKind::Ok
+                };
+                break;
+            }
+
+            if start.elapsed() > self.timeout {
+                // take down the target and anything it spawned
+                // SAFETY: we own the job handle
+                unsafe {
+                    TerminateJobObject(job.0, 1);
+                }
+                child.wait()?;
+                result.exit = ExitKind::Timeout;
+                break;
+            }
+            std::thread::sleep(Duration::from_micros(100));
+        }
+        Ok(result)
+    }
+}
+
+impl Drop for WindowsExecutor {
+    fn drop(&mut self) {
+        if let Some(path) = &self.input_file {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
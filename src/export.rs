@@ -0,0 +1,113 @@
+// Test cases as source code literals
+//
+// Interesting samples are most useful as regression tests of the target,
+// this writes them in a form that can be pasted straight into a C, Rust or
+// Python test file.
+
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    // unsigned char array plus a length constant
+    C,
+    // &[u8] byte string
+    Rust,
+    // bytes literal
+    Python,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Option<Format> {
+        match name {
+            "c" => Some(Format::C),
+            "rust" => Some(Format::Rust),
+            "python" => Some(Format::Python),
+            _ => None,
+        }
+    }
+}
+
+// Byte string contents, the escapes Rust and Python agree on
+fn escape(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len());
+    for &byte in data {
+        match byte {
+            b'\n' => out.push_str("\\n"),
+            b'\r' => out.push_str("\\r"),
+            b'\t' => out.push_str("\\t"),
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            0x20..=0x7e => out.push(byte as char),
+            _ => write!(out, "\\x{:02x}", byte).unwrap(),
+        }
+    }
+    out
+}
+
+// Bytes of a string written with the escapes above, plus \0 and \xNN in
+// any case, for byte sequences given on the command line
+pub fn unescape(text: &str) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(text.len());
+    let mut bytes = text.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte != b'\\' {
+            out.push(byte);
+            continue;
+        }
+        match bytes.next() {
+            Some(b'n') => out.push(b'\n'),
+            Some(b'r') => out.push(b'\r'),
+            Some(b't') => out.push(b'\t'),
+            Some(b'0') => out.push(0),
+            Some(b'"') => out.push(b'"'),
+            Some(b'\\') => out.push(b'\\'),
+            Some(b'x') => {
+                let digits = [bytes.next(), bytes.next()];
+                let value = match digits {
+                    [Some(hi), Some(lo)] => std::str::from_utf8(&[hi, lo]).ok()
+                        .and_then(|x| u8::from_str_radix(x, 16).ok()),
+                    _ => None,
+                };
+                out.push(value.ok_or("\\x takes two hex digits")?);
+            }
+            Some(other) => return Err(format!("unknown escape \\{}", other as char)),
+            None => return Err("trailing backslash".to_string()),
+        }
+    }
+    Ok(out)
+}
+
+// Write data as a literal named name, comment says where it came from
+pub fn write_literal(format: Format, name: &str, comment: Option<&str>,
+        data: &[u8], out: &mut impl Write) -> io::Result<()> {
+    match format {
+        Format::C => {
+            if let Some(comment) = comment {
+                writeln!(out, "/* {} */", comment.replace("*/", "* /"))?;
+            }
+            writeln!(out, "static const unsigned char {}[] = {{", name)?;
+            for line in data.chunks(12) {
+                let bytes = line.iter().map(|x| format!("0x{:02x}", x))
+                    .collect::<Vec<_>>();
+                writeln!(out, "    {},", bytes.join(", "))?;
+            }
+            writeln!(out, "}};")?;
+            writeln!(out, "static const size_t {}_len = {};", name, data.len())?;
+        }
+        Format::Rust => {
+            if let Some(comment) = comment {
+                writeln!(out, "// {}", comment)?;
+            }
+            writeln!(out, "const {}: &[u8] = b\"{}\";", name.to_uppercase(),
+                escape(data))?;
+        }
+        Format::Python => {
+            if let Some(comment) = comment {
+                writeln!(out, "# {}", comment)?;
+            }
+            writeln!(out, "{} = b\"{}\"", name, escape(data))?;
+        }
+    }
+    writeln!(out)
+}
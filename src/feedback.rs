@@ -0,0 +1,306 @@
+// Pluggable notion of an interesting input
+//
+// An Observer picks one aspect out of an execution (its coverage map, what
+// the target printed, how it ended, how long it took), a Feedback decides
+// on it whether the input deserves a place in the corpus. Edge coverage
+// and production pairs always count, feedbacks add their own reasons on
+// top: an input is admitted when any of them finds it interesting. Users
+// plug their own in by implementing Feedback and adding it to
+// fuzzer::Shared::feedbacks.
+
+use std::collections::HashSet;
+use std::time::Duration;
+
+use crate::coverage::{CoverageFeedback, MAP_SIZE};
+use crate::executor::ExecResult;
+use crate::hash::hash64;
+
+// One execution, as the fuzz loop saw it
+pub struct Observation<'a> {
+    pub input: &'a [u8],
+    pub result: &'a ExecResult,
+
+    // edge map, for backends that can observe the target
+    pub coverage: Option<&'a [u8]>,
+}
+
+pub trait Observer: Send {
+    type Value: ?Sized;
+
+    // The observed aspect, None if the backend does not provide it
+    fn observe<'a>(&self, obs: &Observation<'a>) -> Option<&'a Self::Value>;
+}
+
+pub trait Feedback: Send {
+    // Reason given when the feedback admits an input
+    fn name(&self) -> &str;
+
+    // Judge an execution and remember it, so the same thing is only
+    // interesting once
+    fn is_interesting(&mut self, obs: &Observation) -> bool;
+}
+
+// Edge coverage map of the execution
+pub struct CoverageMap;
+
+impl Observer for CoverageMap {
+    type Value = [u8];
+
+    fn observe<'a>(&self, obs: &Observation<'a>) -> Option<&'a [u8]> {
+        obs.coverage
+    }
+}
+
+// What the target printed or answered, see ProcessExecutor::capture_output()
+pub struct Output;
+
+impl Observer for Output {
+    type Value = [u8];
+
+    fn observe<'a>(&self, obs: &Observation<'a>) -> Option<&'a [u8]> {
+        Some(&obs.result.output)
+    }
+}
+
+// How the target ended
+pub struct ExitStatus;
+
+impl Observer for ExitStatus {
+    type Value = ExecResult;
+
+    fn observe<'a>(&self, obs: &Observation<'a>) -> Option<&'a ExecResult> {
+        Some(obs.result)
+    }
+}
+
+// How long the execution took
+pub struct ResponseTime;
+
+impl Observer for ResponseTime {
+    type Value = Duration;
+
+    fn observe<'a>(&self, obs: &Observation<'a>) -> Option<&'a Duration> {
+        Some(&obs.result.exec_time)
+    }
+}
+
+// New (edge, hit count bucket) pairs in an AFL style map
+pub struct MapNovelty<O> {
+    observer: O,
+    seen: CoverageFeedback,
+}
+
+impl<O: Observer<Value = [u8]>> MapNovelty<O> {
+    pub fn new(observer: O) -> Self {
+        MapNovelty { observer, seen: CoverageFeedback::new(MAP_SIZE) }
+    }
+}
+
+impl<O: Observer<Value = [u8]>> Feedback for MapNovelty<O> {
+    fn name(&self) -> &str {
+        "map"
+    }
+
+    fn is_interesting(&mut self, obs: &Observation) -> bool {
+        self.observer.observe(obs)
+            .is_some_and(|map| self.seen.is_interesting(map))
+    }
+}
+
+// Output matching a pattern, once per distinct matched text
+pub struct OutputMatches<O> {
+    observer: O,
+    pattern: Pattern,
+    seen: HashSet<u64>,
+}
+
+impl<O: Observer<Value = [u8]>> OutputMatches<O> {
+    pub fn new(observer: O, pattern: Pattern) -> Self {
+        OutputMatches { observer, pattern, seen: HashSet::new() }
+    }
+}
+
+impl<O: Observer<Value = [u8]>> Feedback for OutputMatches<O> {
+    fn name(&self) -> &str {
+        "output"
+    }
+
+    fn is_interesting(&mut self, obs: &Observation) -> bool {
+        self.observer.observe(obs)
+            .and_then(|output| self.pattern.find(output))
+            .is_some_and(|text| self.seen.insert(hash64(text)))
+    }
+}
+
+// First execution of every exit class: how it ended, with which exit code
+// or signal
+pub struct ExitClass<O> {
+    observer: O,
+    seen: HashSet<String>,
+}
+
+impl<O: Observer<Value = ExecResult>> ExitClass<O> {
+    pub fn new(observer: O) -> Self {
+        ExitClass { observer, seen: HashSet::new() }
+    }
+}
+
+impl<O: Observer<Value = ExecResult>> Feedback for ExitClass<O> {
+    fn name(&self) -> &str {
+        "exit-status"
+    }
+
+    fn is_interesting(&mut self, obs: &Observation) -> bool {
+        self.observer.observe(obs).is_some_and(|result| self.seen.insert(
+            format!("{:?}/{:?}/{:?}", result.exit, result.code, result.signal)))
+    }
+}
+
+// Slowest execution so far, once it takes at least threshold
+pub struct SlowExecution<O> {
+    observer: O,
+    threshold: Duration,
+    slowest: Duration,
+}
+
+impl<O: Observer<Value = Duration>> SlowExecution<O> {
+    pub fn new(observer: O, threshold: Duration) -> Self {
+        SlowExecution { observer, threshold, slowest: Duration::ZERO }
+    }
+}
+
+impl<O: Observer<Value = Duration>> Feedback for SlowExecution<O> {
+    fn name(&self) -> &str {
+        "response-time"
+    }
+
+    fn is_interesting(&mut self, obs: &Observation) -> bool {
+        match self.observer.observe(obs) {
+            Some(&time) if time >= self.threshold && time > self.slowest => {
+                self.slowest = time;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+// Built-in feedbacks as given on the command line: output:<pattern>,
+// exit-status or response-time:<ms>
+#[derive(Clone, Debug)]
+pub enum FeedbackSpec {
+    Output(Pattern),
+    ExitStatus,
+    ResponseTime(Duration),
+}
+
+impl FeedbackSpec {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (kind, arg) = spec.split_once(':').unwrap_or((spec, ""));
+        match (kind, arg) {
+            ("output", pattern) if !pattern.is_empty() =>
+                Pattern::parse(pattern).map(FeedbackSpec::Output),
+            ("exit-status", "") => Ok(FeedbackSpec::ExitStatus),
+            ("response-time", ms) => ms.parse().map(|ms|
+                    FeedbackSpec::ResponseTime(Duration::from_millis(ms)))
+                .map_err(|_| format!("{}: expected response-time:<ms>", spec)),
+            _ => Err(format!("{}: unknown feedback", spec)),
+        }
+    }
+
+    pub fn build(&self) -> Box<dyn Feedback> {
+        match self {
+            FeedbackSpec::Output(pattern) =>
+                Box::new(OutputMatches::new(Output, pattern.clone())),
+            FeedbackSpec::ExitStatus => Box::new(ExitClass::new(ExitStatus)),
+            FeedbackSpec::ResponseTime(threshold) =>
+                Box::new(SlowExecution::new(ResponseTime, *threshold)),
+        }
+    }
+
+    // Whether the target's output has to be captured for it
+    pub fn needs_output(&self) -> bool {
+        matches!(self, FeedbackSpec::Output(_))
+    }
+}
+
+// Small regular expression subset for OutputMatches: literal bytes, '.',
+// the repetitions '*', '+' and '?', '^' and '$' anchors and '\' escapes
+#[derive(Clone, Debug)]
+pub struct Pattern {
+    items: Vec<Item>,
+    start: bool,
+    end: bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Item {
+    // None matches any byte
+    byte: Option<u8>,
+    min: usize,
+    max: usize,
+}
+
+impl Pattern {
+    pub fn parse(pattern: &str) -> Result<Self, String> {
+        let mut bytes = pattern.as_bytes();
+        let start = bytes.first() == Some(&b'^');
+        if start {
+            bytes = &bytes[1..];
+        }
+        let end = bytes.last() == Some(&b'$')
+            && !bytes.ends_with(b"\\$");
+        if end {
+            bytes = &bytes[..bytes.len() - 1];
+        }
+
+        let mut items = Vec::<Item>::new();
+        let mut iter = bytes.iter();
+        while let Some(&byte) = iter.next() {
+            let (min, max) = match byte {
+                b'*' => (0, usize::MAX),
+                b'+' => (1, usize::MAX),
+                b'?' => (0, 1),
+                _ => {
+                    let byte = match byte {
+                        b'.' => None,
+                        b'\\' => Some(*iter.next().ok_or_else(||
+                            format!("{}: trailing '\\'", pattern))?),
+                        _ => Some(byte),
+                    };
+                    items.push(Item { byte, min: 1, max: 1 });
+                    continue;
+                }
+            };
+            match items.last_mut() {
+                Some(item) if item.min == 1 && item.max == 1 => {
+                    item.min = min;
+                    item.max = max;
+                }
+                _ => return Err(format!("{}: '{}' repeats nothing", pattern,
+                    byte as char)),
+            }
+        }
+        Ok(Pattern { items, start, end })
+    }
+
+    // Leftmost match in text
+    pub fn find<'a>(&self, text: &'a [u8]) -> Option<&'a [u8]> {
+        let starts = if self.start { 0..=0 } else { 0..=text.len() };
+        starts.into_iter().find_map(|begin| {
+            self.match_here(&self.items, &text[begin..])
+                .map(|len| &text[begin..begin + len])
+        })
+    }
+
+    // Length of the longest match of items at the start of text
+    fn match_here(&self, items: &[Item], text: &[u8]) -> Option<usize> {
+        let Some((item, rest)) = items.split_first() else {
+            return (!self.end || text.is_empty()).then_some(0);
+        };
+        let matches = |x: &u8| item.byte.is_none_or(|byte| byte == *x);
+        let most = text.iter().take(item.max).take_while(|x| matches(x)).count();
+        (item.min..=most).rev().find_map(|count|
+            self.match_here(rest, &text[count..]).map(|len| count + len))
+    }
+}
@@ -0,0 +1,191 @@
+// Findings of a campaign for bug trackers and CI dashboards
+//
+// The findings command reads the crashes and hangs of an instance
+// directory with their metadata sidecars (see output::Metadata) and writes
+// them as SARIF 2.1.0, what code scanning dashboards import, or as plain
+// json:
+//
+//   {"generator": "maybe_fastest_fuzzer-0.1.0", "instance": "out/main",
+//    "findings": [{"kind": "crash", "path": "crashes/id:000000,sig:11,...",
+//      "bucket": "heap-buffer-overflow in parse_value", "signal": 11,
+//      "sanitizer": "AddressSanitizer: heap-buffer-overflow in ...",
+//      "seed": 7, "op": "splice", "execs": 12345, "time": 1700000000,
+//      "grammar_hash": "1a2b3c4d5e6f7a8b", "replay": {...}}]}
+//
+// kind is crash, hang or oracle (a crash an oracle flagged, see oracle.rs),
+// path is relative to the instance directory. Everything else is left out
+// when the sidecar does not say: entries without a sidecar, crashes
+// without a sanitizer report, entries nobody replayed (see replay.rs).
+//
+// In SARIF every bucket is a rule and every entry a result pointing at its
+// reproducer, with the fields above as its properties.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::hash::hash64;
+use crate::output::GENERATOR;
+use crate::replay;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Crash,
+    Hang,
+    Oracle,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Record {
+    pub kind: Kind,
+    pub path: String,
+    pub bucket: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signal: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sanitizer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oracle: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub op: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub execs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub grammar_hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replay: Option<Value>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Findings {
+    pub generator: String,
+    pub instance: String,
+    pub findings: Vec<Record>,
+}
+
+fn sidecar(entry: &Path) -> io::Result<Value> {
+    let (Some(dir), Some(name)) = (entry.parent(), entry.file_name()) else {
+        return Ok(Value::Null);
+    };
+    let mut name = name.to_os_string();
+    name.push(".json");
+    let path = dir.join(".meta").join(name);
+    match fs::read(&path) {
+        Ok(bytes) => serde_json::from_slice(&bytes).map_err(|e|
+            io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Value::Null),
+        Err(e) => Err(e),
+    }
+}
+
+// The crashes and hangs of an instance directory, in the order they were
+// saved
+pub fn collect(instance: &Path) -> io::Result<Findings> {
+    let mut findings = Vec::new();
+    for (sub, kind) in [("crashes", Kind::Crash), ("hangs", Kind::Hang)] {
+        let dir = instance.join(sub);
+        if !dir.is_dir() {
+            continue;
+        }
+        for entry in replay::entries(&dir)? {
+            let meta = sidecar(&entry)?;
+            let text = |key: &str| meta.get(key).and_then(Value::as_str).map(str::to_string);
+            let number = |key: &str| meta.get(key).and_then(Value::as_u64);
+            let oracle = text("oracle");
+            let kind = match oracle {
+                Some(_) => Kind::Oracle,
+                None => kind,
+            };
+            let bucket = text("bucket").unwrap_or_else(|| match kind {
+                Kind::Hang => "hang".to_string(),
+                _ => "crash".to_string(),
+            });
+            findings.push(Record {
+                kind,
+                path: format!("{}/{}", sub, entry.file_name().unwrap().to_string_lossy()),
+                bucket,
+                signal: meta.get("signal").and_then(Value::as_i64),
+                sanitizer: text("sanitizer"),
+                oracle,
+                seed: number("seed"),
+                op: text("op"),
+                execs: number("execs"),
+                time: number("time"),
+                grammar_hash: text("grammar_hash"),
+                replay: meta.get("replay").cloned(),
+            });
+        }
+    }
+    Ok(Findings {
+        generator: GENERATOR.to_string(),
+        instance: instance.display().to_string(),
+        findings,
+    })
+}
+
+// The findings as a SARIF 2.1.0 log
+pub fn to_sarif(findings: &Findings, instance: &Path) -> Value {
+    let mut buckets: Vec<&str> = Vec::new();
+    for record in &findings.findings {
+        if !buckets.contains(&record.bucket.as_str()) {
+            buckets.push(&record.bucket);
+        }
+    }
+    let rules = buckets.iter().map(|bucket| json!({
+        "id": bucket,
+        "shortDescription": {"text": bucket},
+    })).collect::<Vec<_>>();
+
+    let results = findings.findings.iter().map(|record| {
+        let what = match record.kind {
+            Kind::Crash => "crash",
+            Kind::Hang => "hang",
+            Kind::Oracle => "oracle finding",
+        };
+        let detail = record.sanitizer.clone().or_else(|| record.oracle.as_ref()
+            .map(|x| format!("{} oracle: {}", x, record.bucket)))
+            .unwrap_or_else(|| record.bucket.clone());
+        json!({
+            "ruleId": record.bucket,
+            "ruleIndex": buckets.iter().position(|x| *x == record.bucket).unwrap(),
+            "level": if record.kind == Kind::Hang { "warning" } else { "error" },
+            "message": {"text": format!("{} ({}), reproducer {}", what, detail, record.path)},
+            "locations": [{"physicalLocation": {"artifactLocation": {
+                "uri": record.path,
+                "uriBaseId": "INSTANCE",
+            }}}],
+            "partialFingerprints": {
+                "bucket/v1": format!("{:016x}", hash64(record.bucket.as_bytes())),
+            },
+            "properties": record,
+        })
+    }).collect::<Vec<_>>();
+
+    // absolute with a trailing slash, as SARIF wants base URIs
+    let base = fs::canonicalize(instance).unwrap_or_else(|_| instance.to_path_buf());
+    let mut base = base.display().to_string();
+    if !base.ends_with('/') {
+        base.push('/');
+    }
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {"driver": {
+                "name": env!("CARGO_PKG_NAME"),
+                "version": env!("CARGO_PKG_VERSION"),
+                "rules": rules,
+            }},
+            "originalUriBaseIds": {"INSTANCE": {"uri": format!("file://{}", base)}},
+            "results": results,
+        }],
+    })
+}
@@ -0,0 +1,855 @@
+// The fuzz loop run by every worker thread
+//
+// Workers own their compiled grammar and target, everything they learn
+// (coverage, corpus, counters) goes through Shared. With a coverage
+// capable target, inputs are derived as trees and the ones finding new
+// coverage form a corpus that the power schedule mutates. Without coverage
+// every input is a fresh generation.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Instant;
+
+use crate::broker::EntryKind;
+use crate::cmplog::{self, CmpLog};
+use crate::symcc::SymCc;
+use crate::context::GenerationContext;
+use crate::corpus::{path_hash, signature, Corpus, CorpusEntry};
+use crate::coverage::{CoverageFeedback, MAP_SIZE};
+use crate::dedup::Dedup;
+use crate::derivation_cache::{CachePolicy, DerivationCache};
+use crate::exec_cache::ExecCache;
+use crate::executor::{Executor, ExitKind};
+use crate::feedback::{Feedback, Observation};
+use crate::grammar::{GeneratorState, GrammarRust, Strategy};
+use crate::havoc::repair_utf8;
+use crate::histogram::Histograms;
+use crate::import::Seed;
+use crate::length::LengthControl;
+use crate::markov::Markov;
+use crate::mmap::{Buffer, Output};
+use crate::mutator::{MutationContext, Scheduler, Scratch, TestCase};
+use crate::output::{AflOutputDir, Finding, Metadata, GENERATOR};
+use crate::oracle::Oracle;
+use crate::pairs::PairCoverage;
+use crate::record::{Decision, Mode, Pick, Session};
+use crate::roles::Role;
+use crate::rule_stats::RuleStats;
+use crate::selftest;
+use crate::temperature::Schedule;
+use crate::throttle::Throttle;
+use crate::tree::Tree;
+use crate::affinity;
+use crate::{debug, info};
+
+// Derivations generated per fresh input in pairwise mode
+const PAIRWISE_CANDIDATES: usize = 4;
+
+// Rounds between snapshots of the shared Markov model
+const MARKOV_REFRESH: u64 = 256;
+
+// Counters shared by all workers
+#[derive(Default)]
+pub struct Stats {
+    pub execs: AtomicU64,
+    pub crashes: AtomicU64,
+    pub timeouts: AtomicU64,
+
+    // executions that ran into a resource limit, by Resource
+    pub limits: [AtomicU64; 3],
+
+    // synced inputs that were new to us
+    pub imported: AtomicU64,
+
+    // inputs run through the validity filter, and the ones it rejected
+    pub filter_checked: AtomicU64,
+    pub filter_rejected: AtomicU64,
+}
+
+impl Stats {
+    // Share of inputs the validity filter rejected, percent
+    pub fn filter_rate(&self) -> f64 {
+        self.filter_rejected.load(Ordering::Relaxed) as f64 * 100.
+            / self.filter_checked.load(Ordering::Relaxed).max(1) as f64
+    }
+}
+
+// State shared by all workers of a campaign
+pub struct Shared {
+    pub feedback: Mutex<CoverageFeedback>,
+
+    // coverage of crashing/hanging inputs, only new paths get saved
+    pub crash_feedback: Mutex<CoverageFeedback>,
+    pub hang_feedback: Mutex<CoverageFeedback>,
+
+    // derivation trees that found new coverage
+    pub corpus: Mutex<Corpus>,
+
+    // production pairs generated so far, see pairs.rs
+    pub pairs: Mutex<PairCoverage>,
+
+    // further reasons to keep an input besides coverage, see feedback.rs
+    pub feedbacks: Vec<Mutex<Box<dyn Feedback>>>,
+
+    // alternative weights learned from the corpus, for Strategy::Markov
+    pub markov: Mutex<Arc<Markov>>,
+
+    // inputs executed so far, to skip repeats. None runs everything
+    pub dedup: Option<Dedup>,
+
+    // results of inputs executed so far, see exec_cache.rs
+    pub exec_cache: Option<ExecCache>,
+
+    // crashes with a sanitizer report, by bug type and faulting function,
+    // and oracle findings, by oracle and what it saw
+    pub bugs: Mutex<BTreeMap<String, BTreeMap<String, u64>>>,
+
+    pub output: AflOutputDir,
+    pub stats: Stats,
+    pub stop: AtomicBool,
+
+    // findings waiting to be synced to the broker
+    pub outbox: Mutex<Vec<(EntryKind, Vec<u8>)>>,
+
+    // inputs other fuzzers found, to be run through our target
+    pub inbox: Mutex<Vec<Vec<u8>>>,
+
+    // seed files to run before fuzzing, last first, see import.rs
+    pub seeds: Mutex<Vec<Seed>>,
+
+    // execution rate cap and duty cycle, see throttle.rs
+    pub throttle: Throttle,
+
+    // depth, size and node count of executed inputs, see histogram.rs
+    pub histograms: Option<Histograms>,
+
+    // what every rule does for the campaign, see rule_stats.rs
+    pub rule_stats: Option<RuleStats>,
+}
+
+impl Shared {
+    pub fn new(output: AflOutputDir) -> Self {
+        Shared {
+            feedback: Mutex::new(CoverageFeedback::new(MAP_SIZE)),
+            crash_feedback: Mutex::new(CoverageFeedback::new(MAP_SIZE)),
+            hang_feedback: Mutex::new(CoverageFeedback::new(MAP_SIZE)),
+            corpus: Mutex::new(Corpus::default()),
+            pairs: Mutex::new(PairCoverage::default()),
+            feedbacks: Vec::new(),
+            markov: Mutex::new(Arc::default()),
+            dedup: None,
+            exec_cache: None,
+            bugs: Mutex::new(BTreeMap::new()),
+            output,
+            stats: Stats::default(),
+            stop: AtomicBool::new(false),
+            outbox: Mutex::new(Vec::new()),
+            inbox: Mutex::new(Vec::new()),
+            seeds: Mutex::new(Vec::new()),
+            throttle: Throttle::default(),
+            histograms: None,
+            rule_stats: None,
+        }
+    }
+}
+
+// Per worker settings
+#[derive(Clone, Debug)]
+pub struct WorkerConfig {
+    // RNG seed of this worker
+    pub seed: usize,
+
+    // pin to this core
+    pub core: Option<usize>,
+
+    // queue findings for the broker
+    pub syncing: bool,
+
+    // keep every input valid UTF-8, havoc output gets repaired
+    pub utf8: bool,
+
+    // also keep inputs with new pairs of productions, and prefer fresh
+    // derivations with many of them
+    pub pairwise: bool,
+
+    // end the campaign after this many executions (of all workers), or
+    // at the first crash
+    pub max_execs: Option<u64>,
+    pub stop_on_crash: bool,
+
+    // generation temperature over the campaign, see temperature.rs
+    pub temperature: Option<Schedule>,
+
+    // record the worker's decisions to this log, or replay them from it,
+    // see record.rs
+    pub record: Option<(Mode, PathBuf)>,
+
+    // generate into the target's input file mapped to memory, see mmap.rs
+    pub mmap: bool,
+
+    // fixed bytes around every generated input (file magic, handshakes),
+    // outside the grammar. Saved entries have them, their choice sequence
+    // sidecars only derive what is in between
+    pub prefix: Vec<u8>,
+    pub suffix: Vec<u8>,
+
+    // move the output size limit with what the target takes, see length.rs
+    pub adaptive_size: bool,
+
+    // copy cached expansions of small rules into fresh test cases without
+    // feedback, see derivation_cache.rs
+    pub derivation_cache: Option<CachePolicy>,
+
+    // what the worker works on, see roles.rs
+    pub role: Role,
+}
+
+// Other builds of the target some stages run new seeds through
+#[derive(Default)]
+pub struct TargetBuilds {
+    // comparison logging, see cmplog.rs
+    pub cmplog: Option<CmpLog>,
+    // concolic execution, see symcc.rs
+    pub symcc: Option<SymCc>,
+    // fast validity check (a validator, not a build of the target) an
+    // input has to pass before the target runs it, see selftest.rs
+    pub filter: Option<Box<dyn Executor>>,
+}
+
+struct Worker<'a> {
+    // compiled once and shared by all workers, the RNG and the generation
+    // buffers are ours
+    gram: &'a GrammarRust,
+    gen: GenerationContext,
+    mutators: &'a Scheduler,
+    executor: Box<dyn Executor>,
+    builds: TargetBuilds,
+    // bugs besides crashes, see oracle.rs
+    oracles: Vec<Box<dyn Oracle>>,
+    shared: &'a Shared,
+    config: &'a WorkerConfig,
+
+    // scratch space reused across iterations
+    input: TestCase,
+    // the corpus entry being fuzzed, serialized once for all its rounds
+    base: TestCase,
+    // previous input, for havoc splicing, and what the target runs. Mapped
+    // from the target's input file with --mmap-output, see mmap.rs
+    last: Output,
+    scratch: Scratch,
+    // stacked mutators of the last round, see Scheduler::report()
+    applied: Vec<usize>,
+    alternatives: Vec<u32>,
+    // coverage of a cached result
+    cached_map: Vec<u8>,
+
+    // for metadata sidecars: GrammarRust::fingerprint() and the queue
+    // entry the current input was mutated from
+    grammar_hash: u64,
+    parent: Option<String>,
+
+    session: Option<Session>,
+
+    // the output size limit, with --adaptive-size and coverage
+    length: Option<LengthControl>,
+}
+
+impl Worker<'_> {
+    // Run the mutators on the input: all of them on corpus inputs, only
+    // the finishers on fresh ones. See mutator.rs
+    fn mutate(&mut self, op: &mut String, fresh: bool) -> io::Result<()> {
+        let mut ctx = MutationContext {
+            gram: self.gram,
+            state: &mut self.gen.state,
+            scratch: &mut self.scratch,
+            splice: &self.last,
+            note: String::new(),
+            session: self.session.as_mut(),
+        };
+        op.clear();
+        if fresh {
+            self.mutators.finish(&mut ctx, &mut self.input, op);
+        } else {
+            self.mutators.mutate(&mut ctx, &mut self.input, op, &mut self.applied)?;
+        }
+        Ok(())
+    }
+
+    // Run the input and book keep the outcome, op is where it came from
+    // ("sync" for inputs of other fuzzers, "import" for seed files). True
+    // if the input found something new: a queue entry, new pairs, a unique
+    // crash or hang
+    fn execute(&mut self, op: &str) -> io::Result<bool> {
+        let Shared { feedback, crash_feedback, hang_feedback, corpus, pairs,
+            feedbacks, bugs, output, stats, outbox, .. } = self.shared;
+        let imported = op == "sync" || op == "import";
+        // a derivation tree never has prefix and suffix
+        let framed = !imported || self.input.tree().is_some();
+
+        // generated inputs already are, see GrammarRust::non_utf8_terminal()
+        if self.config.utf8 && self.input.tree().is_none() {
+            repair_utf8(self.input.bytes_mut(self.gram));
+        }
+        // a mapped output takes a fresh serialization directly, no need
+        // for the heap copy. Synced inputs come framed already
+        self.last.clear();
+        if framed {
+            self.last.extend_from_slice(&self.config.prefix);
+        }
+        match self.input.tree().filter(|_| self.last.is_mapped()
+                && !self.input.is_serialized()) {
+            Some(tree) => tree.serialize(self.gram, &mut self.last),
+            None => self.last.extend_from_slice(self.input.bytes(self.gram)),
+        }
+        if framed {
+            self.last.extend_from_slice(&self.config.suffix);
+        }
+        self.last.finish()?;
+        let tree = self.input.tree();
+        let input: &[u8] = &self.last;
+
+        // an input that ran before gets the result it got then, see
+        // exec_cache.rs
+        let cache = self.shared.exec_cache.as_ref().filter(|_| !imported);
+        let key = cache.map(|_| ExecCache::key(input));
+        let hit = match (cache, key) {
+            (Some(cache), Some(key)) => cache.lookup(key, &mut self.cached_map),
+            _ => None,
+        };
+
+        // the same input twice tells nothing new, synced ones were only
+        // run by the other fuzzer
+        let fresh = !imported && hit.is_none();
+        if let Some(dedup) = self.shared.dedup.as_ref().filter(|_| fresh) {
+            if dedup.seen(input) {
+                return Ok(false);
+            }
+        }
+        // a loose grammar produces lots of inputs the target rejects right
+        // away, the filter keeps them from taking its (slow) time
+        if let Some(filter) = self.builds.filter.as_mut().filter(|_| fresh) {
+            stats.filter_checked.fetch_add(1, Ordering::Relaxed);
+            if !selftest::accepted(&filter.run(input)?) {
+                stats.filter_rejected.fetch_add(1, Ordering::Relaxed);
+                return Ok(false);
+            }
+        }
+        if let Some(histograms) = self.shared.histograms.as_ref().filter(|_| !imported) {
+            histograms.record(self.gram, tree, input.len());
+        }
+        let cached = hit.is_some();
+        let (result, violation, map, execs) = match hit {
+            Some(hit) => {
+                let map = hit.coverage.then_some(self.cached_map.as_slice());
+                (hit.result, hit.violation, map, stats.execs.load(Ordering::Relaxed))
+            }
+            None => {
+                self.shared.throttle.wait(input.len(), &self.shared.stop);
+                if self.shared.stop.load(Ordering::Relaxed) {
+                    return Ok(false);
+                }
+                let mut result = if self.last.is_mapped() {
+                    self.executor.run_in_place(input)?
+                } else {
+                    self.executor.run(input)?
+                };
+
+                // ctrl-c reaches the target as well, that is not a crash
+                if self.shared.stop.load(Ordering::Relaxed) {
+                    return Ok(false);
+                }
+
+                // a target that ended normally can still have done
+                // something wrong
+                let mut violation = None;
+                if result.exit == ExitKind::Ok {
+                    let observation = Observation { input, result: &result,
+                        coverage: self.executor.coverage() };
+                    for oracle in &mut self.oracles {
+                        if let Some(what) = oracle.check(&observation)? {
+                            violation = Some((oracle.name().to_string(), what));
+                            break;
+                        }
+                    }
+                    if violation.is_some() {
+                        result.exit = ExitKind::Crash;
+                    }
+                }
+                let map = self.executor.coverage();
+                if let (Some(cache), Some(key)) = (cache, key) {
+                    cache.insert(key, &result, violation.as_ref(), map);
+                }
+                let execs = stats.execs.fetch_add(1, Ordering::Relaxed) + 1;
+                (result, violation, map, execs)
+            }
+        };
+        if !cached && (self.config.max_execs.is_some_and(|x| execs >= x)
+                || (self.config.stop_on_crash && result.exit == ExitKind::Crash)) {
+            self.shared.stop.store(true, Ordering::Relaxed);
+        }
+        let mut found = false;
+
+        // reproducer of the input next to it if it came from the grammar,
+        // and where it came from
+        let gram = &self.gram;
+        let (config, parent) = (self.config, &self.parent);
+        let grammar_hash = self.grammar_hash;
+        let save_sidecars = |path: &Path, finding: Finding| -> io::Result<()> {
+            let choices = match tree {
+                Some(tree) => {
+                    let mut choices = Vec::new();
+                    tree.choices(gram, &mut choices);
+                    output.save_choices(path, &choices)?;
+                    Some(choices.iter().map(|x| format!("{:02x}", x)).collect())
+                }
+                None => None,
+            };
+            output.save_meta(path, &Metadata {
+                generator: GENERATOR,
+                grammar_hash: format!("{:016x}", grammar_hash),
+                seed: config.seed as u64,
+                op: op.to_string(),
+                choices,
+                parent: parent.clone(),
+                execs,
+                finding,
+            })
+        };
+
+        // without coverage every crash/hang counts as unique
+        let unique = |feedback: &Mutex<CoverageFeedback>|
+            map.is_none_or(|map| feedback.lock().unwrap().is_interesting(map));
+
+        // a cached crash, hang or limit was counted when the input ran
+        match result.exit {
+            _ if cached => {}
+            ExitKind::Ok => {}
+            ExitKind::Timeout => {
+                stats.timeouts.fetch_add(1, Ordering::Relaxed);
+                if unique(hang_feedback) {
+                    let entry = output.save_hang(input, execs, op)?;
+                    info!("feedback", "new hang {}", entry.display());
+                    save_sidecars(&entry, Finding::default())?;
+                    found = true;
+                }
+            }
+            ExitKind::Limit(resource) => {
+                stats.limits[resource as usize].fetch_add(1, Ordering::Relaxed);
+                debug!("feedback", "target hit the {} limit", resource.name());
+            }
+            ExitKind::Crash => {
+                stats.crashes.fetch_add(1, Ordering::Relaxed);
+
+                // a sanitizer report names the bug, one crash per bug is
+                // enough no matter the coverage. Oracle findings go by
+                // oracle and what it saw
+                let bug = match (&result.sanitizer, &violation) {
+                    (Some(report), _) => Some((report.bug_type.clone(),
+                        report.function.clone().unwrap_or_else(|| "?".into()))),
+                    (None, Some((oracle, what))) => Some((format!("oracle {}", oracle),
+                        what.clone())),
+                    (None, None) => None,
+                };
+                let bucket = match &bug {
+                    Some((bug_type, function)) => Some(format!("{} in {}", bug_type, function)),
+                    None => result.signal.map(|x| format!("signal {}", x)),
+                };
+                let new = match bug {
+                    Some((bug_type, function)) => {
+                        let mut bugs = bugs.lock().unwrap();
+                        let hits = bugs.entry(bug_type).or_default()
+                            .entry(function).or_insert(0);
+                        *hits += 1;
+                        *hits == 1
+                    }
+                    None => unique(crash_feedback),
+                };
+                if new {
+                    let entry = output.save_crash(input, result.signal, execs,
+                        op)?;
+                    match (&result.sanitizer, &violation) {
+                        (None, Some((oracle, what))) => info!("feedback",
+                            "new finding {} ({} oracle: {})", entry.display(),
+                            oracle, what),
+                        (Some(report), _) => info!("feedback", "new crash {} ({}{})",
+                            entry.display(), report.key(),
+                            if report.allocation.is_empty() {
+                                String::new()
+                            } else {
+                                format!(", allocated in {}",
+                                    report.allocation.join(" < "))
+                            }),
+                        (None, None) => info!("feedback", "new crash {}",
+                            entry.display()),
+                    }
+                    save_sidecars(&entry, Finding {
+                        signal: result.signal,
+                        bucket,
+                        sanitizer: result.sanitizer.as_ref().map(|x| x.summary()),
+                        oracle: violation.as_ref().map(|x| x.0.clone()),
+                    })?;
+                    found = true;
+                    if self.config.syncing {
+                        outbox.lock().unwrap().push(
+                            (EntryKind::Crash, input.to_vec()));
+                    }
+                }
+            }
+        }
+
+        // combinations of productions the campaign has not produced yet
+        let new_pairs = match tree {
+            Some(tree) if self.config.pairwise => pairs.lock().unwrap()
+                .add(gram, tree, &mut self.alternatives),
+            _ => 0,
+        };
+
+        // without coverage all inputs share one path
+        let path = map.map_or(0, path_hash);
+        if map.is_some() {
+            corpus.lock().unwrap().record_path(path);
+        }
+
+        // keep inputs that reached new code or new pairs, or that one of
+        // the feedbacks likes. All of them get to see every execution, and
+        // saw the cached ones already
+        let new_coverage = !cached && map.is_some_and(|map|
+            feedback.lock().unwrap().is_interesting(map));
+        if new_pairs > 0 {
+            debug!("feedback", "{} new production pairs", new_pairs);
+        }
+        let observation = Observation { input, result: &result, coverage: map };
+        let mut keep = new_coverage;
+        for feedback in feedbacks.iter().filter(|_| !cached) {
+            let mut feedback = feedback.lock().unwrap();
+            if feedback.is_interesting(&observation) {
+                debug!("feedback", "{} feedback keeps the input", feedback.name());
+                keep = true;
+            }
+        }
+        let mut name = None;
+        if keep {
+            let entry = output.save_queue(input, execs, op)?;
+            debug!("feedback", "new queue entry {}", entry.display());
+            save_sidecars(&entry, Finding::default())?;
+            name = entry.file_name().map(|x| x.to_string_lossy().into_owned());
+
+            if imported {
+                stats.imported.fetch_add(1, Ordering::Relaxed);
+            } else if self.config.syncing {
+                outbox.lock().unwrap().push((EntryKind::Corpus, input.to_vec()));
+            }
+        }
+        if let Some(rule_stats) = self.shared.rule_stats.as_ref().filter(|_| !imported) {
+            if let Some(tree) = tree {
+                rule_stats.record(gram, tree, keep || found);
+            }
+        }
+        if let Some(tree) = tree.filter(|_| keep
+                && self.gram.strategy() == Strategy::Markov) {
+            Arc::make_mut(&mut self.shared.markov.lock().unwrap()).learn(gram, tree);
+        }
+        if let Some(tree) = tree.filter(|_| keep || new_pairs > 0) {
+            let mut corpus = corpus.lock().unwrap();
+            let signature = match map.filter(|_| corpus.culls()) {
+                Some(map) => signature(map),
+                None => Vec::new(),
+            };
+            corpus.add(CorpusEntry {
+                tree: tree.clone(),
+                len: input.len(),
+                exec_time: result.exec_time,
+                path,
+                fuzzed: 0,
+                name,
+                signature,
+            });
+        }
+        if let Some(length) = self.length.as_mut().filter(|_| !cached) {
+            let len = input.len().saturating_sub(
+                if framed { self.config.prefix.len() + self.config.suffix.len() } else { 0 });
+            if let Some(limit) = length.observe(len, &result, new_coverage) {
+                info!("length", "output size limit now {} bytes", limit);
+                self.gen.state.set_max_output(limit);
+            }
+        }
+        Ok(found || keep || new_pairs > 0)
+    }
+
+    // Satisfy the comparisons a new seed fails, one patched input per
+    // operand found in the derivation. See cmplog.rs
+    fn cmplog_stage(&mut self, seed: &Tree) -> io::Result<()> {
+        let Some(cmplog) = &mut self.builds.cmplog else {
+            return Ok(());
+        };
+        let (mut input, mut spans) = (Vec::new(), Vec::new());
+        seed.serialize_spans(self.gram, &mut input, &mut spans);
+        // the build sees what the target does, patches go to execute()
+        // which frames them again
+        let framed = [&self.config.prefix, &input[..], &self.config.suffix].concat();
+        let comparisons = cmplog.comparisons(&framed)?;
+        let patches = cmplog::patches(&comparisons, &input, &spans);
+        debug!("cmplog", "{} failed comparisons, {} patched inputs",
+            comparisons.len(), patches.len());
+
+        for patched in patches {
+            if self.shared.stop.load(Ordering::Relaxed) {
+                break;
+            }
+            *self.input.reset_bytes() = patched;
+            self.execute("cmplog")?;
+        }
+        Ok(())
+    }
+
+    // Run the inputs the solver finds for a new seed, see symcc.rs
+    fn symcc_stage(&mut self, seed: &Tree) -> io::Result<()> {
+        let Some(symcc) = &mut self.builds.symcc else {
+            return Ok(());
+        };
+        let mut input = self.config.prefix.clone();
+        seed.serialize(self.gram, &mut input);
+        input.extend_from_slice(&self.config.suffix);
+        let solved = symcc.solve(&input)?;
+        debug!("symcc", "{} solved inputs", solved.len());
+
+        // execute() frames them again, ones that changed the framing cannot
+        // be run
+        let (prefix, suffix) = (&self.config.prefix, &self.config.suffix);
+        let solved = solved.into_iter().filter_map(|x| x.strip_prefix(&prefix[..])
+            .and_then(|x| x.strip_suffix(&suffix[..])).map(<[u8]>::to_vec))
+            .collect::<Vec<_>>();
+        for input in solved {
+            if self.shared.stop.load(Ordering::Relaxed) {
+                break;
+            }
+            *self.input.reset_bytes() = input;
+            self.execute("symcc")?;
+        }
+        Ok(())
+    }
+
+    // Run the seed files, the ones adding coverage join the corpus. All
+    // workers share the work
+    fn import_stage(&mut self) -> io::Result<()> {
+        loop {
+            if self.shared.stop.load(Ordering::Relaxed) {
+                break;
+            }
+            let Some(seed) = self.shared.seeds.lock().unwrap().pop() else {
+                break;
+            };
+            match seed.tree {
+                Some(tree) => *self.input.reset_tree() = tree,
+                None => *self.input.reset_bytes() = seed.data,
+            }
+            self.execute("import")?;
+        }
+        Ok(())
+    }
+
+    fn run(&mut self) -> io::Result<()> {
+        let shared = self.shared;
+        let feedback = self.executor.coverage().is_some();
+        let mut op = String::new();
+        let started = Instant::now();
+        let markov = self.gram.strategy() == Strategy::Markov;
+
+        if self.config.adaptive_size {
+            match feedback {
+                true => self.length = Some(LengthControl::new(self.gram.max_output())),
+                false => info!("length", "no coverage, --adaptive-size keeps the limit"),
+            }
+        }
+        if !feedback && !shared.seeds.lock().unwrap().is_empty() {
+            info!("import", "no coverage, only --feedback can admit seeds");
+        }
+        self.import_stage()?;
+
+        for round in 0u64.. {
+            if shared.stop.load(Ordering::Relaxed) {
+                break;
+            }
+            self.parent = None;
+            // what the corpus taught so far, refreshed now and then
+            if markov && round % MARKOV_REFRESH == 0 {
+                let model = shared.markov.lock().unwrap().clone();
+                self.gen.state.set_markov(model);
+            }
+            if let Some(schedule) = &self.config.temperature {
+                self.gen.state.set_temperature(schedule.at(started.elapsed()));
+            }
+
+            // what to work on, from the log when replaying
+            let (pairwise, role) = (self.config.pairwise, self.config.role);
+            let decision = match &mut self.session {
+                None => schedule(shared, &mut self.gen.state, feedback, pairwise, role),
+                Some(session) => {
+                    let live = || schedule(shared, &mut self.gen.state, feedback, pairwise,
+                        role);
+                    let Some(decision) = session.decide(live)? else {
+                        break;
+                    };
+                    session.state(&mut self.gen.state)?;
+                    decision
+                }
+            };
+
+            match decision {
+                Decision::Sync(input) => {
+                    *self.input.reset_bytes() = input;
+                    self.execute("sync")?;
+                }
+                Decision::Fresh if !feedback && !pairwise => {
+                    self.gram.generate(&mut self.gen, self.input.reset_bytes());
+                    self.mutate(&mut op, true)?;
+                    self.execute(if op.is_empty() { "grammar" } else { &op })?;
+                }
+                Decision::Corpus(Pick { tree: seed, parent, energy, first }) => {
+                    self.parent = parent;
+                    if first {
+                        self.cmplog_stage(&seed)?;
+                        self.symcc_stage(&seed)?;
+                    }
+                    self.base.reset_tree().clone_from(&seed);
+                    self.base.serialize(self.gram);
+                    for _ in 0..energy {
+                        if shared.stop.load(Ordering::Relaxed) {
+                            break;
+                        }
+                        self.input.reset_from(&self.base);
+                        self.mutate(&mut op, false)?;
+                        let found = self.execute(if op.is_empty() { "copy" } else { &op })?;
+                        self.mutators.report(&self.applied, found);
+                    }
+                }
+                Decision::Fresh if pairwise => {
+                    // best of a few derivations by unseen pairs, a replay
+                    // keeps the one the recording did
+                    let wanted = match &mut self.session {
+                        Some(session) if session.replaying() =>
+                            Some(session.index(b'B', 0)?),
+                        _ => None,
+                    };
+                    let tree = self.input.reset_tree();
+                    let GenerationContext { state, tree_stack: stack, .. } = &mut self.gen;
+                    self.gram.generate_full_tree(state, stack, tree);
+                    let pairs = shared.pairs.lock().unwrap();
+                    let mut best = pairs.count_new(self.gram, tree,
+                        &mut self.alternatives);
+                    let mut kept = 0;
+                    for ii in 1..PAIRWISE_CANDIDATES {
+                        let candidate = &mut self.scratch.tree;
+                        self.gram.generate_full_tree(state, stack, candidate);
+                        let new = pairs.count_new(self.gram, candidate,
+                            &mut self.alternatives);
+                        if wanted.map_or(new > best, |x| x == ii) {
+                            best = new;
+                            kept = ii;
+                            std::mem::swap(tree, candidate);
+                        }
+                    }
+                    drop(pairs);
+                    if let Some(session) = self.session.as_mut().filter(|x| !x.replaying()) {
+                        session.index(b'B', kept)?;
+                    }
+                    self.mutate(&mut op, true)?;
+                    self.execute(if op.is_empty() { "pairwise" } else { &op })?;
+                }
+                Decision::Fresh => {
+                    self.gram.generate_full_tree(&mut self.gen.state,
+                        &mut self.gen.tree_stack, self.input.reset_tree());
+                    self.mutate(&mut op, true)?;
+                    self.execute(if op.is_empty() { "grammar" } else { &op })?;
+                }
+            }
+        }
+        if let Some(session) = &mut self.session {
+            session.flush()?;
+        }
+        Ok(())
+    }
+}
+
+// What a round works on: synced inputs first, then mostly the corpus,
+// generating from scratch for the structure it does not have yet. Workers
+// with a role only generate or only mutate, until there is a corpus
+fn schedule(shared: &Shared, state: &mut GeneratorState, feedback: bool,
+        pairwise: bool, role: Role) -> Decision {
+    if let Some(input) = shared.inbox.lock().unwrap().pop() {
+        return Decision::Sync(input);
+    }
+    let fresh = match role {
+        Role::Generate => true,
+        Role::Tree | Role::Havoc => false,
+        Role::Mixed => state.rand().is_multiple_of(4),
+    };
+    if (!feedback && !pairwise) || fresh {
+        return Decision::Fresh;
+    }
+    let mut corpus = shared.corpus.lock().unwrap();
+    match corpus.schedule() {
+        Some((idx, energy)) => {
+            let entry = corpus.get(idx);
+            Decision::Corpus(Pick {
+                tree: entry.tree.clone(),
+                parent: entry.name.clone(),
+                energy: energy as u64,
+                first: entry.fuzzed == 1,
+            })
+        }
+        None => Decision::Fresh,
+    }
+}
+
+// Fuzz until shared.stop is set or something fails, mutators decides how
+// corpus inputs are mutated, see mutator.rs
+pub fn run_worker(gram: &GrammarRust, mutators: &Scheduler, config: &WorkerConfig,
+        executor: Box<dyn Executor>, builds: TargetBuilds,
+        oracles: Vec<Box<dyn Oracle>>, shared: &Shared) -> io::Result<()> {
+    if let Some(core) = config.core {
+        affinity::pin_current_thread(core)?;
+    }
+    let last = match executor.in_place_file().filter(|_| config.mmap) {
+        Some(path) => Output::mapped(path)?,
+        None if config.mmap => return Err(io::Error::new(io::ErrorKind::InvalidInput,
+            "--mmap-output needs a target that reads the whole input from a file (@@)")),
+        None => Output::default(),
+    };
+
+    let mut gen = GenerationContext::new(config.seed);
+    gen.cache = config.derivation_cache.map(|x| DerivationCache::new(gram, x));
+    let ret = Worker {
+        gram,
+        gen,
+        mutators,
+        executor,
+        builds,
+        oracles,
+        shared,
+        config,
+        input: TestCase::default(),
+        base: TestCase::default(),
+        last,
+        scratch: Scratch::default(),
+        applied: Vec::new(),
+        alternatives: Vec::new(),
+        cached_map: Vec::new(),
+        grammar_hash: gram.fingerprint(),
+        parent: None,
+        session: config.record.as_ref()
+            .map(|(mode, path)| Session::open(*mode, path))
+            .transpose()?,
+        length: None,
+    }.run();
+
+    // the recording stopped in the middle of a round, so does the replay
+    match ret {
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof
+                && matches!(config.record, Some((Mode::Replay, _))) => Ok(()),
+        ret => ret,
+    }
+}
@@ -0,0 +1,153 @@
+// Golden corpus for regression suites
+//
+// A fixed set of inputs to check into the target's repository and run in
+// its test suite: small enough to run on every commit, diverse enough to
+// touch every corner of the grammar. The golden command picks --count
+// inputs as a function of grammar and seed alone. It derives CANDIDATES
+// times as many trees and takes them greedily, each time the one adding
+// the most alternatives none of the picked inputs has, then the most new
+// pairs of alternatives (see pairs.rs), then the shortest.
+//
+// Files are named by their position and the hash of their content
+// (0007-1a2b3c4d5e6f7a8b), manifest.json records the grammar fingerprint,
+// the seed and every file, so running it again with the same grammar,
+// seed and count rewrites the directory byte for byte, and a diff of the
+// manifest shows what a grammar change did to the suite.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::grammar::{Fragment, GeneratorState, GrammarRust};
+use crate::hash::hash64;
+use crate::output::GENERATOR;
+use crate::rng::SplitSeed;
+use crate::tree::{Tree, TreeStack};
+
+// Derivations generated per input picked
+const CANDIDATES: usize = 16;
+
+pub const MANIFEST_NAME: &str = "manifest.json";
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GoldenFile {
+    pub name: String,
+    // hash64 of the content, hex
+    pub hash: String,
+    pub len: usize,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GoldenManifest {
+    pub generator: String,
+    // fingerprint of the grammar, hex
+    pub grammar: String,
+    pub seed: u64,
+    // alternatives of rules with a choice, and how many the files use
+    pub alternatives: usize,
+    pub alternatives_covered: usize,
+    pub pairs_covered: usize,
+    pub files: Vec<GoldenFile>,
+}
+
+struct Candidate {
+    data: Vec<u8>,
+    // alternatives picked at rules with more than one, sorted
+    alternatives: Vec<u32>,
+}
+
+fn candidate(gram: &GrammarRust, tree: &Tree) -> Candidate {
+    let mut alternatives = tree.nodes.windows(2).filter(|pair| matches!(
+            gram.lookup_fragment(pair[0].fragment),
+            Fragment::NonTerminal(options) if options.len() > 1))
+        .map(|pair| pair[1].fragment.0).collect::<Vec<_>>();
+    alternatives.sort_unstable();
+    alternatives.dedup();
+    let mut data = Vec::new();
+    tree.serialize(gram, &mut data);
+    Candidate { data, alternatives }
+}
+
+// Pairs of alternatives of a candidate, each alternative with itself too
+fn pairs(alternatives: &[u32]) -> impl Iterator<Item = u64> + '_ {
+    alternatives.iter().enumerate().flat_map(move |(ii, &a)|
+        alternatives[ii..].iter().map(move |&b| (a as u64) << 32 | b as u64))
+}
+
+// The inputs of the golden corpus of a grammar and seed, see the top of
+// the file, with their manifest
+pub fn select(gram: &GrammarRust, seed: u64, count: usize)
+        -> (Vec<Vec<u8>>, GoldenManifest) {
+    let mut state = GeneratorState::new(SplitSeed::new(seed).stream(0));
+    let (mut stack, mut tree) = (TreeStack::default(), Tree::default());
+    let mut seen = HashSet::new();
+    let mut candidates = Vec::new();
+    for _ in 0..count.saturating_mul(CANDIDATES) {
+        gram.generate_full_tree(&mut state, &mut stack, &mut tree);
+        let candidate = candidate(gram, &tree);
+        if seen.insert(candidate.data.clone()) {
+            candidates.push(candidate);
+        }
+    }
+
+    let mut covered = HashSet::new();
+    let mut covered_pairs = HashSet::new();
+    let mut picked = Vec::new();
+    while picked.len() < count && !candidates.is_empty() {
+        // most new alternatives, most new pairs, shortest, first generated
+        let best = (0..candidates.len()).max_by_key(|&ii| {
+            let x = &candidates[ii];
+            let new = x.alternatives.iter().filter(|a| !covered.contains(*a)).count();
+            let new_pairs = pairs(&x.alternatives)
+                .filter(|p| !covered_pairs.contains(p)).count();
+            (new, new_pairs, std::cmp::Reverse(x.data.len()), std::cmp::Reverse(ii))
+        }).unwrap();
+        let best = candidates.remove(best);
+        covered.extend(best.alternatives.iter().copied());
+        covered_pairs.extend(pairs(&best.alternatives));
+        picked.push(best.data);
+    }
+
+    let alternatives = gram.rules().map(|x| gram.lookup_fragment_nonterm(x.1).len())
+        .filter(|&x| x > 1).sum();
+    let files = picked.iter().enumerate().map(|(ii, data)| {
+        let hash = format!("{:016x}", hash64(data));
+        GoldenFile { name: format!("{:04}-{}", ii, hash), hash, len: data.len() }
+    }).collect();
+    let manifest = GoldenManifest {
+        generator: GENERATOR.to_string(),
+        grammar: format!("{:016x}", gram.fingerprint()),
+        seed,
+        alternatives,
+        alternatives_covered: covered.len(),
+        pairs_covered: covered_pairs.len(),
+        files,
+    };
+    (picked, manifest)
+}
+
+// Write the corpus to dir. The files of an earlier manifest there go
+// first, anything else in dir stays
+pub fn write(dir: &Path, inputs: &[Vec<u8>], manifest: &GoldenManifest) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let old = dir.join(MANIFEST_NAME);
+    if let Ok(bytes) = fs::read(&old) {
+        let old: GoldenManifest = serde_json::from_slice(&bytes).map_err(|e|
+            io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", old.display(), e)))?;
+        for file in old.files {
+            // names only, a manifest cannot point outside the directory
+            if !file.name.contains(['/', '\\']) && !file.name.starts_with('.') {
+                let _ = fs::remove_file(dir.join(&file.name));
+            }
+        }
+    }
+    for (file, data) in manifest.files.iter().zip(inputs) {
+        fs::write(dir.join(&file.name), data)?;
+    }
+    let mut json = serde_json::to_vec_pretty(manifest)?;
+    json.push(b'\n');
+    fs::write(dir.join(MANIFEST_NAME), json)
+}
@@ -0,0 +1,1044 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+use serde::{Deserialize, Serialize};
+
+use crate::binary;
+use crate::context::GenerationContext;
+use crate::hash::hash64;
+use crate::markov::Markov;
+use crate::mmap::Buffer;
+
+// Number of expansion steps after which generation only picks the
+// cheapest alternatives to wrap up the current test case
+pub const DEFAULT_NODE_BUDGET: usize = 1 << 16;
+
+// Generation stops emitting terminals once a test case grows beyond this,
+// unless the grammar was given another limit (GrammarRust::set_max_output())
+pub const MAX_OUTPUT_SIZE: usize = 1024 * 1024;
+
+// Json representation of the data struct
+// Map Fragment name : List<List <Fragment Names>>
+// An empty alternative, [] or [""], is epsilon. Rules with annotations
+// have theirs in the second map (see loader::Rule for the json)
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Grammar(pub HashMap<String, Vec<Vec<String>>>,
+    #[serde(default)] pub HashMap<String, Annotation>);
+
+// Optional settings of a rule
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Annotation {
+    // probability of corrupting every terminal of the rule as it is
+    // emitted, overrides the campaign setting (GrammarRust::set_corruption())
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub corrupt: Option<f64>,
+
+    // expansions of the rule per derivation, past them it takes its
+    // cheapest alternative like a derivation out of node budget
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_expansions: Option<u32>,
+
+    // what an expansion of an expensive rule costs the target, in the
+    // units of GrammarRust::set_cost_budget()
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cost: Option<u32>,
+
+    // token class of the rule, the identifier a token stream has for
+    // everything the rule derives (see tokens.rs)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<u32>,
+
+    // alternatives of the rule (as in its definition) only taken when
+    // regenerating a subtree of an existing tree, or only when deriving a
+    // fresh test case
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub mutation_only: Vec<Vec<String>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub generation_only: Vec<Vec<String>>,
+
+    // every expansion of the rule in a test case produces what the first
+    // one did, like a variable bound once (see Tree::serialize())
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub shared: bool,
+}
+
+impl Annotation {
+    // Fill in what self leaves open from another definition of the rule
+    pub fn merge(&mut self, other: Annotation) {
+        self.corrupt = self.corrupt.or(other.corrupt);
+        self.max_expansions = self.max_expansions.or(other.max_expansions);
+        self.cost = self.cost.or(other.cost);
+        self.token = self.token.or(other.token);
+        self.shared |= other.shared;
+        for alternative in other.mutation_only {
+            if !self.mutation_only.contains(&alternative) {
+                self.mutation_only.push(alternative);
+            }
+        }
+        for alternative in other.generation_only {
+            if !self.generation_only.contains(&alternative) {
+                self.generation_only.push(alternative);
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Hash)]
+pub struct FragmentId(pub u32);
+
+impl FragmentId {
+    // Position in the fragment arena
+    #[inline]
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+// A fragment as lookup_fragment() hands it out, borrowing its children or
+// bytes from the arenas of the grammar
+#[derive(Clone, Copy, Debug)]
+pub enum Fragment<'a> {
+    // nonterminal contains a slice of fragments (some might be non-terminal)
+    NonTerminal(&'a [FragmentId]),
+    // Ordered list of fragments
+    Expression(&'a [FragmentId]),
+    // terminal results to bytes
+    Terminal(&'a [u8]),
+}
+
+// Range of one of the arenas of GrammarRust
+#[derive(Clone, Copy, Debug, Default)]
+struct Span {
+    offset: u32,
+    len: u32,
+}
+
+impl Span {
+    #[inline]
+    fn range(self) -> std::ops::Range<usize> {
+        self.offset as usize..(self.offset + self.len) as usize
+    }
+}
+
+// How a fragment is stored: the children of all non-terminals and
+// expressions sit back to back in one arena, the bytes of all terminals in
+// another, so a fragment is a few bytes and the whole grammar a handful of
+// allocations
+#[derive(Clone, Copy, Debug)]
+enum Stored {
+    NonTerminal(Span),
+    Expression(Span),
+    Terminal(Span),
+}
+
+// How generation picks among the alternatives of a rule
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Strategy {
+    // every alternative equally likely
+    #[default]
+    Uniform,
+    // alternatives picked less often so far are more likely, weight
+    // 1 / (1 + times picked), which spreads generation over the whole
+    // grammar instead of letting popular branches dominate
+    RareBoost,
+    // alternatives weighted by how often the corpus took them in the same
+    // context, see markov.rs. Uniform until there is a model
+    Markov,
+}
+
+// How Strategy::Uniform draws among the alternatives of a rule, fixed per
+// rule when compiling. A modulo is slow and slightly favours the first
+// alternatives: power of two counts take a mask of the random bits, other
+// counts Lemire's multiply and shift, with the rare redraw that makes it
+// exact. Weights need no table of their own, an alternative listed twice
+// in a rule is two alternatives
+#[derive(Clone, Copy, Debug, Default)]
+enum Selector {
+    #[default]
+    Single,
+    Mask(u64),
+    Lemire { len: u64, threshold: u64 },
+}
+
+impl Selector {
+    fn new(len: usize) -> Self {
+        let len = len as u64;
+        match len {
+            0 | 1 => Selector::Single,
+            _ if len.is_power_of_two() => Selector::Mask(len - 1),
+            // products whose low half is below this would over-represent
+            // their alternative
+            _ => Selector::Lemire { len, threshold: len.wrapping_neg() % len },
+        }
+    }
+
+    // Index of the alternative to take
+    #[inline]
+    fn select(self, state: &mut GeneratorState) -> usize {
+        match self {
+            Selector::Single => 0,
+            Selector::Mask(mask) => (state.rand() as u64 & mask) as usize,
+            Selector::Lemire { len, threshold } => loop {
+                let product = state.rand() as u64 as u128 * len as u128;
+                if product as u64 >= threshold {
+                    return (product >> 64) as usize;
+                }
+            },
+        }
+    }
+}
+
+// Rust representation: transformed into nested structure
+#[derive(Debug, Default)]
+pub struct GrammarRust {
+    // all types, and the arenas of their children and bytes
+    fragments: Vec<Stored>,
+    children: Vec<FragmentId>,
+    bytes: Vec<u8>,
+
+    // Cached fragment identifier for the start node
+    start: Option<FragmentId>,
+
+    // Mapping of non-terminal names to fragment identifiers
+    name_to_fragment: BTreeMap<String, FragmentId>,
+
+    // Minimum number of nodes needed to fully expand each fragment
+    // (usize::MAX if the fragment can never terminate)
+    min_cost: Vec<usize>,
+
+    // For every non-terminal, the option with the smallest min_cost
+    // used to wrap up a derivation once the node budget is spent
+    cheapest: Vec<Option<FragmentId>>,
+
+    // how every non-terminal picks an alternative uniformly
+    selectors: Vec<Selector>,
+
+    // Maximum number of expansion steps before generate() switches to
+    // minimal completion
+    node_budget: usize,
+
+    // output size at which generation stops emitting terminals
+    max_output: usize,
+
+    strategy: Strategy,
+
+    // corruption probability of the terminals of annotated rules, and of
+    // every terminal as set_corruption() left it
+    corrupt_annotated: HashMap<FragmentId, f64>,
+    corrupt: Vec<f64>,
+    corrupting: bool,
+
+    // max_expansions annotation of every fragment, u32::MAX for none, and
+    // whether any rule has one
+    max_expansions: Vec<u32>,
+    limited: bool,
+
+    // cost annotation of every fragment (0 for none), the least cost a
+    // complete expansion of every fragment takes, and what a derivation
+    // may spend. Budgeted when there is a budget and an annotation
+    cost: Vec<u32>,
+    min_expense: Vec<u64>,
+    cost_budget: Option<u64>,
+    budgeted: bool,
+
+    // alternatives of the rules with mutation_only or generation_only
+    // annotations that fresh derivations ([0]) and regenerated subtrees
+    // ([1]) may take
+    phase_options: HashMap<FragmentId, [Vec<FragmentId>; 2]>,
+
+    // rules with the shared annotation
+    shared: HashSet<FragmentId>,
+}
+
+// The compiled grammar is immutable while generating, threads share one
+const _: fn() = || {
+    fn assert_sync<T: Sync>() {}
+    assert_sync::<GrammarRust>();
+};
+
+// What changes while generating: the RNG and the RareBoost counters. Kept
+// out of GrammarRust so one compiled grammar can serve any number of
+// threads, each with its own state
+#[derive(Clone, Debug, Default)]
+pub struct GeneratorState {
+    // Xorshift seed
+    seed: usize,
+
+    // times every fragment was picked as an alternative (RareBoost),
+    // sized on first use
+    picked: Vec<u32>,
+
+    // skew towards expensive (above 1) or cheap alternatives, see
+    // temperature.rs
+    temperature: f64,
+
+    // learned weights for Strategy::Markov
+    markov: Option<Arc<Markov>>,
+
+    // expansions of every rule in the current derivation, for rules with
+    // a max_expansions annotation
+    expansions: Vec<u32>,
+
+    // cost of the expensive rules expanded in the current derivation, and
+    // scratch space for the alternatives still in budget
+    spent: u64,
+    affordable: Vec<FragmentId>,
+
+    // regenerating a subtree of an existing tree rather than deriving a
+    // fresh test case, for mutation_only and generation_only annotations
+    mutating: bool,
+
+    // output size limit of this state instead of the grammar's, see
+    // length.rs
+    max_output: Option<usize>,
+}
+
+impl GeneratorState {
+    pub fn new(seed: usize) -> Self {
+        GeneratorState { seed, picked: Vec::new(), temperature: 1.0, markov: None,
+            expansions: Vec::new(), spent: 0, affordable: Vec::new(), mutating: false,
+            max_output: None }
+    }
+
+    pub fn set_markov(&mut self, markov: Arc<Markov>) {
+        self.markov = Some(markov);
+    }
+
+    pub fn markov(&self) -> Option<&Arc<Markov>> {
+        self.markov.as_ref()
+    }
+
+    // Whether what gets derived replaces part of an existing tree, see
+    // Annotation::mutation_only
+    pub fn set_mutating(&mut self, mutating: bool) {
+        self.mutating = mutating;
+    }
+
+    // Stop emitting terminals beyond bytes instead of where the grammar
+    // says (GrammarRust::set_max_output())
+    pub fn set_max_output(&mut self, bytes: usize) {
+        self.max_output = Some(bytes);
+    }
+
+    pub fn set_temperature(&mut self, temperature: f64) {
+        self.temperature = temperature;
+    }
+
+    pub fn temperature(&self) -> f64 {
+        self.temperature
+    }
+
+    // Initialize the RNG
+    pub fn seed(&mut self, val: usize) {
+        self.seed = val;
+    }
+
+    // Current RNG state, seed() with it continues from here
+    pub fn rng_state(&self) -> usize {
+        self.seed
+    }
+
+    // get a random value
+    pub fn rand(&mut self) -> usize {
+        let mut seed = self.seed;
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 43;
+
+        self.seed = seed;
+        seed
+    }
+}
+
+// turns json representation into rust data structure
+impl GrammarRust {
+    pub fn new(grammar: &Grammar) -> Self {
+        // create new grammar structure
+        let mut ret = GrammarRust::default();
+
+        // rules by name: fragment ids are handed out in this order, hash map
+        // order would give every build (every map) ids of its own
+        let mut rules = grammar.0.iter().collect::<Vec<_>>();
+        rules.sort_unstable_by_key(|x| x.0);
+
+        // parse the input grammar to create non-term fragment names
+        // (names are unique, duplicates are dealt with by loader::combine())
+        for &(non_term, _) in &rules {
+            // allocate a new empty fragment
+            let fragment_id = ret.allocate_fragment(Fragment::NonTerminal(&[]));
+
+            // add name resolution to the fragment
+            ret.name_to_fragment.insert(non_term.clone(), fragment_id);
+        }
+
+        // every terminal value and every reference to a non-terminal is
+        // allocated once and shared by all expressions using it
+        // (the terminals of rules with their own corruption rate are theirs)
+        let mut terminals: HashMap<(&str, Option<u64>), FragmentId> = HashMap::new();
+        let mut references: HashMap<FragmentId, FragmentId> = HashMap::new();
+
+        // having all non-term names, allocate their term/non-term extensions
+        for &(non_term, fragments) in &rules {
+            // get the non-terminal fragment identifier
+            let fragment_id = ret.name_to_fragment[non_term];
+            let corrupt = grammar.1.get(non_term).and_then(|x| x.corrupt);
+
+            // Expressions
+            let mut expressions = Vec::new();
+
+            // go through all sub-fragments (vectors of fragment names)
+            for js_sub_fragment in fragments {
+                // Options for this sub fragment
+                let mut options = Vec::new();
+
+                for option in js_sub_fragment {
+                    // "" is epsilon just like [], it would only add a node
+                    // that produces nothing
+                    if option.is_empty() {
+                        continue;
+                    }
+
+                    // if option is one of the previously found non-terminals
+                    let fragment_id = if let Some(&non_terminal) =
+                    ret.name_to_fragment.get(option) {
+                        *references.entry(non_terminal).or_insert_with(||
+                            ret.allocate_fragment(
+                                Fragment::NonTerminal(&[non_terminal])))
+                    } else {
+                        // Convert the terminal bytes into a vector
+                        // and create a new fragment containing it
+                        *terminals.entry((option.as_str(), corrupt.map(f64::to_bits)))
+                            .or_insert_with(|| {
+                                let value = binary::constant(option)
+                                    .unwrap_or_else(|| option.as_bytes().to_vec());
+                                let id = ret.allocate_fragment(Fragment::Terminal(&value));
+                                if let Some(corrupt) = corrupt {
+                                    ret.corrupt_annotated.insert(id, corrupt);
+                                }
+                                id
+                            })
+                    };
+                    options.push(fragment_id);
+                }
+                // Allocate a new fragment for all the options
+                // List of Options - Vec<String>
+                expressions.push(
+                    ret.allocate_fragment(Fragment::Expression(&options)));
+            }
+
+            // alternatives restricted to one phase, unless that would
+            // leave the other phase without any
+            if let Some(annotation) = grammar.1.get(non_term) {
+                if annotation.shared {
+                    ret.shared.insert(fragment_id);
+                }
+                let allowed = |excluded: &[Vec<String>]| expressions.iter().zip(fragments)
+                    .filter(|x| !excluded.contains(x.1)).map(|x| *x.0).collect::<Vec<_>>();
+                let fresh = allowed(&annotation.mutation_only);
+                let mutating = allowed(&annotation.generation_only);
+                if (fresh.len() < expressions.len() || mutating.len() < expressions.len())
+                        && !fresh.is_empty() && !mutating.is_empty() {
+                    ret.phase_options.insert(fragment_id, [fresh, mutating]);
+                }
+            }
+
+            // Overwrite the empty definition
+            // expressions - Vec<Vec<String>>
+            let span = ret.store_children(&expressions);
+            ret.fragments[fragment_id.index()] = Stored::NonTerminal(span);
+        }
+
+        // the arenas are final, drop the slack of the growth strategy
+        ret.fragments.shrink_to_fit();
+        ret.children.shrink_to_fit();
+        ret.bytes.shrink_to_fit();
+
+        // Resolve the start node
+        ret.start = Some(ret.name_to_fragment["<start>"]);
+
+        // Figure out the cheapest way to finish every fragment
+        ret.compute_min_costs();
+        ret.selectors = (0..ret.fragments.len() as u32).map(|id|
+            match ret.lookup_fragment(FragmentId(id)) {
+                Fragment::NonTerminal(options) => Selector::new(options.len()),
+                _ => Selector::Single,
+            }).collect();
+        ret.node_budget = DEFAULT_NODE_BUDGET;
+        ret.max_output = MAX_OUTPUT_SIZE;
+        ret.set_corruption(0.0);
+
+        ret.max_expansions = vec![u32::MAX; ret.fragments.len()];
+        for (name, annotation) in &grammar.1 {
+            if let (Some(&id), Some(max)) = (ret.name_to_fragment.get(name),
+                    annotation.max_expansions) {
+                ret.max_expansions[id.index()] = max;
+                ret.limited = true;
+            }
+        }
+        ret.cost = vec![0; ret.fragments.len()];
+        for (name, annotation) in &grammar.1 {
+            if let (Some(&id), Some(cost)) = (ret.name_to_fragment.get(name),
+                    annotation.cost) {
+                ret.cost[id.index()] = cost;
+            }
+        }
+        ret.compute_min_expenses();
+
+        // print!("{:#?}\n", ret);
+        ret
+    }
+
+    // Fixpoint over the fragment graph computing the minimum number of
+    // nodes a full expansion of each fragment takes
+    fn compute_min_costs(&mut self) {
+        let mut cost = vec![usize::MAX; self.fragments.len()];
+
+        loop {
+            let mut changed = false;
+
+            for id in 0..self.fragments.len() {
+                let new_cost = match self.lookup_fragment(FragmentId(id as u32)) {
+                    Fragment::NonTerminal(options) => options.iter()
+                        .map(|x| cost[x.index()]).min().unwrap_or(usize::MAX)
+                        .saturating_add(1),
+                    Fragment::Expression(expr) => expr.iter()
+                        .fold(1usize, |acc, x| acc.saturating_add(cost[x.index()])),
+                    Fragment::Terminal(_) => 1,
+                };
+
+                if new_cost < cost[id] {
+                    cost[id] = new_cost;
+                    changed = true;
+                }
+            }
+
+            if !changed {
+                break;
+            }
+        }
+
+        // remember the cheapest option of every non-terminal
+        let mut cheapest = vec![None; self.fragments.len()];
+        for (id, cheap) in cheapest.iter_mut().enumerate() {
+            if let Fragment::NonTerminal(_) = self.lookup_fragment(FragmentId(id as u32)) {
+                *cheap = self.lookup_fragment_nonterm(FragmentId(id as u32)).iter()
+                    .copied().min_by_key(|x| cost[x.index()]);
+            }
+        }
+
+        self.min_cost = cost;
+        self.cheapest = cheapest;
+    }
+
+    // Same fixpoint as compute_min_costs(), over the cost annotations
+    fn compute_min_expenses(&mut self) {
+        let mut expense = vec![u64::MAX; self.fragments.len()];
+        loop {
+            let mut changed = false;
+            for id in 0..self.fragments.len() {
+                let new_expense = match self.lookup_fragment(FragmentId(id as u32)) {
+                    Fragment::NonTerminal(options) => options.iter()
+                        .map(|x| expense[x.index()]).min().unwrap_or(u64::MAX)
+                        .saturating_add(self.cost[id] as u64),
+                    Fragment::Expression(expr) => expr.iter()
+                        .fold(0u64, |acc, x| acc.saturating_add(expense[x.index()])),
+                    Fragment::Terminal(_) => 0,
+                };
+                if new_expense < expense[id] {
+                    expense[id] = new_expense;
+                    changed = true;
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        self.min_expense = expense;
+    }
+
+    // Limit the number of expansion steps a single generate() call takes
+    // before it falls back to the cheapest alternatives
+    pub fn set_node_budget(&mut self, nodes: usize) {
+        self.node_budget = nodes;
+    }
+
+    // Stop emitting terminals once a test case grows beyond bytes
+    pub fn set_max_output(&mut self, bytes: usize) {
+        self.max_output = bytes;
+    }
+
+    pub fn max_output(&self) -> usize {
+        self.max_output
+    }
+
+    // The limit a derivation with state stops at
+    #[inline]
+    pub fn output_limit(&self, state: &GeneratorState) -> usize {
+        state.max_output.unwrap_or(self.max_output)
+    }
+
+    // Change how alternatives are picked
+    pub fn set_strategy(&mut self, strategy: Strategy) {
+        self.strategy = strategy;
+    }
+
+    pub fn strategy(&self) -> Strategy {
+        self.strategy
+    }
+
+    // Limit what the expensive rules of a derivation cost together. Once
+    // an alternative cannot be completed within what is left, it is only
+    // taken if no other one can. None for no limit
+    pub fn set_cost_budget(&mut self, budget: Option<u64>) {
+        self.cost_budget = budget;
+        self.budgeted = budget.is_some() && self.cost.iter().any(|&x| x > 0);
+    }
+
+    // Cost annotation of a rule, 0 for none
+    #[inline]
+    pub fn cost(&self, id: FragmentId) -> u32 {
+        self.cost[id.index()]
+    }
+
+    // max_expansions annotation of a rule, if it has one
+    pub fn max_expansions(&self, id: FragmentId) -> Option<u32> {
+        Some(self.max_expansions[id.index()]).filter(|&x| x != u32::MAX)
+    }
+
+    // Least total cost of a complete expansion, u64::MAX if it can never
+    // terminate
+    #[inline]
+    pub fn min_expense(&self, id: FragmentId) -> u64 {
+        self.min_expense[id.index()]
+    }
+
+    // Corrupt every emitted terminal with this probability, the rules that
+    // have a corrupt annotation keep theirs. Corruption flips a bit or
+    // replaces a byte of the terminal where generate() copies it into the
+    // output, derivation trees keep the grammar's bytes
+    pub fn set_corruption(&mut self, probability: f64) {
+        self.corrupt = (0..self.fragments.len()).map(|ii| {
+            let id = FragmentId(ii as u32);
+            match self.lookup_fragment(id) {
+                Fragment::Terminal(_) => self.corrupt_annotated.get(&id)
+                    .copied().unwrap_or(probability),
+                _ => 0.0,
+            }
+        }).collect();
+        self.corrupting = self.corrupt.iter().any(|&x| x > 0.0);
+    }
+
+    // Whether any terminal gets corrupted, then emit() draws random numbers
+    pub fn is_corrupting(&self) -> bool {
+        self.corrupting
+    }
+
+    // Append a terminal to buf, corrupted with the probability of cur
+    #[inline]
+    pub fn emit<B: Buffer + ?Sized>(&self, state: &mut GeneratorState, cur: FragmentId,
+            value: &[u8], buf: &mut B) {
+        let start = buf.len();
+        buf.extend_from_slice(value);
+        let probability = self.corrupt[cur.index()];
+        if value.is_empty() || probability <= 0.0
+                || (state.rand() as u64 >> 11) as f64 / (1u64 << 53) as f64 >= probability {
+            return;
+        }
+        let pos = start + state.rand() % value.len();
+        let rand = state.rand();
+        let buf = buf.as_mut_slice();
+        if rand & 1 == 0 {
+            buf[pos] ^= 1 << ((rand >> 1) % 8);
+        } else {
+            buf[pos] = (rand >> 1) as u8;
+        }
+    }
+
+    // Append children to their arena
+    fn store_children(&mut self, children: &[FragmentId]) -> Span {
+        let offset = self.children.len().try_into().expect("too many fragments");
+        self.children.extend_from_slice(children);
+        Span { offset, len: children.len() as u32 }
+    }
+
+    pub fn allocate_fragment(&mut self, fragment: Fragment) -> FragmentId {
+        // get a unique fragment ID
+        let fragment_id = FragmentId(self.fragments.len().try_into()
+            .expect("too many fragments"));
+
+        // store the fragment, copying its children or bytes to the arenas
+        let stored = match fragment {
+            Fragment::NonTerminal(options) => Stored::NonTerminal(self.store_children(options)),
+            Fragment::Expression(expr) => Stored::Expression(self.store_children(expr)),
+            Fragment::Terminal(value) => {
+                let offset = self.bytes.len().try_into().expect("too many terminal bytes");
+                self.bytes.extend_from_slice(value);
+                Stored::Terminal(Span { offset, len: value.len() as u32 })
+            }
+        };
+        self.fragments.push(stored);
+
+        fragment_id
+    }
+
+    #[inline]
+    pub fn lookup_fragment(&self, id: FragmentId) -> Fragment<'_> {
+        match self.fragments[id.index()] {
+            Stored::NonTerminal(span) => Fragment::NonTerminal(&self.children[span.range()]),
+            Stored::Expression(span) => Fragment::Expression(&self.children[span.range()]),
+            Stored::Terminal(span) => Fragment::Terminal(&self.bytes[span.range()]),
+        }
+    }
+
+    #[inline]
+    pub fn lookup_fragment_nonterm(&self, id: FragmentId) -> &[FragmentId] {
+        // Match control flow action (?)
+        if let Stored::NonTerminal(span) = self.fragments[id.index()] {
+            &self.children[span.range()]
+        } else {
+            panic!("Was not a non-terminal!");
+        }
+    }
+
+    // Named non-terminals (the rules of the json grammar), sorted by name
+    pub fn rules(&self) -> impl Iterator<Item = (&str, FragmentId)> {
+        self.name_to_fragment.iter().map(|(name, &id)| (name.as_str(), id))
+    }
+
+    // Stable hash of the compiled grammar: every rule with its alternatives
+    // in order, after epsilon removal and merging of included files.
+    // Fragment ids are an implementation detail and do not go in, so two
+    // grammars share a fingerprint exactly when choice sequences mean the
+    // same thing for both
+    pub fn fingerprint(&self) -> u64 {
+        let names = self.name_to_fragment.iter().map(|(name, &id)| (id, name))
+            .collect::<HashMap<_, _>>();
+        let sorted = self.name_to_fragment.iter().collect::<BTreeMap<_, _>>();
+
+        let mut buf = Vec::new();
+        for (name, &id) in sorted {
+            buf.extend_from_slice(name.as_bytes());
+            buf.push(b'=');
+            for &option in self.lookup_fragment_nonterm(id) {
+                let Fragment::Expression(expr) = self.lookup_fragment(option) else {
+                    unreachable!("alternatives are expressions");
+                };
+                buf.push(b'|');
+                for &symbol in expr {
+                    // length prefixed so no two sequences look the same
+                    let (tag, value) = match self.lookup_fragment(symbol) {
+                        Fragment::Terminal(value) => (b't', value),
+                        Fragment::NonTerminal(target) =>
+                            (b'n', names[&target[0]].as_bytes()),
+                        Fragment::Expression(_) => unreachable!(),
+                    };
+                    buf.push(tag);
+                    buf.extend_from_slice(&(value.len() as u32).to_le_bytes());
+                    buf.extend_from_slice(value);
+                }
+            }
+            buf.push(b';');
+        }
+        hash64(&buf)
+    }
+
+    // Start symbol of the grammar
+    pub fn start(&self) -> FragmentId {
+        self.start.unwrap()
+    }
+
+    // First terminal that is not valid UTF-8, if any. Without one every
+    // generated test case is valid UTF-8 as well
+    pub fn non_utf8_terminal(&self) -> Option<&[u8]> {
+        let ids = (0..self.fragments.len() as u32).map(FragmentId);
+        ids.map(|id| self.lookup_fragment(id)).find_map(|x| match x {
+            Fragment::Terminal(value) if std::str::from_utf8(value).is_err() => Some(value),
+            _ => None,
+        })
+    }
+
+    // Pick the option of a non-terminal to expand, nodes is the number of
+    // fragments expanded so far in this test case, context the alternative
+    // the non-terminal was referenced from (when generating a tree)
+    // None when the budget is spent and the fragment cannot terminate
+    #[inline]
+    pub fn choose(&self, state: &mut GeneratorState, cur: FragmentId,
+            options: &[FragmentId], nodes: usize, context: Option<FragmentId>)
+            -> Option<FragmentId> {
+        let options = match self.phase_options.is_empty() {
+            true => options,
+            false => self.phase_options.get(&cur)
+                .map_or(options, |x| &x[state.mutating as usize]),
+        };
+        if self.limited {
+            if state.expansions.len() < self.fragments.len() {
+                state.expansions.resize(self.fragments.len(), 0);
+            }
+            let count = &mut state.expansions[cur.index()];
+            *count += 1;
+            if *count > self.max_expansions[cur.index()] {
+                return self.cheapest(cur);
+            }
+        }
+        if self.budgeted {
+            state.spent += self.cost[cur.index()] as u64;
+            let left = self.cost_budget.unwrap_or(u64::MAX).saturating_sub(state.spent);
+            if options.iter().any(|x| self.min_expense[x.index()] > left) {
+                let mut affordable = std::mem::take(&mut state.affordable);
+                affordable.clear();
+                affordable.extend(options.iter()
+                    .filter(|x| self.min_expense[x.index()] <= left));
+                let sel = match affordable.is_empty() {
+                    // over budget whatever we do, spend the least
+                    true => options.iter().copied()
+                        .min_by_key(|x| self.min_expense[x.index()]),
+                    false => self.pick(state, cur, &affordable, nodes, context),
+                };
+                state.affordable = affordable;
+                return sel;
+            }
+        }
+        self.pick(state, cur, options, nodes, context)
+    }
+
+    // choose() within the limits of the annotations
+    #[inline]
+    fn pick(&self, state: &mut GeneratorState, cur: FragmentId,
+            options: &[FragmentId], nodes: usize, context: Option<FragmentId>)
+            -> Option<FragmentId> {
+        if nodes <= self.node_budget {
+            if state.temperature != 1.0 {
+                return Some(self.choose_tempered(state, options));
+            }
+            match self.strategy {
+                Strategy::Uniform => Some(options[self.select_uniform(state, cur, options)]),
+                Strategy::RareBoost => Some(self.choose_rare(state, options)),
+                Strategy::Markov => Some(self.choose_markov(state, options, context)),
+            }
+        } else {
+            // out of budget, take the shortest way out
+            self.cheapest(cur)
+        }
+    }
+
+    // Uniform pick among options, the alternatives of cur unless a budget
+    // filtered them
+    #[inline]
+    fn select_uniform(&self, state: &mut GeneratorState, cur: FragmentId,
+            options: &[FragmentId]) -> usize {
+        let selector = match options.len() == self.lookup_fragment_nonterm(cur).len() {
+            true => self.selectors[cur.index()],
+            false => Selector::new(options.len()),
+        };
+        selector.select(state)
+    }
+
+    // Whether the rule has the shared annotation, and whether any has
+    #[inline]
+    pub fn is_shared(&self, id: FragmentId) -> bool {
+        self.shared.contains(&id)
+    }
+
+    pub fn has_shared(&self) -> bool {
+        !self.shared.is_empty()
+    }
+
+    // Alternatives of a non-terminal a fresh derivation (mutating false) or
+    // a regenerated subtree may take, see Annotation::mutation_only
+    pub fn phase_options(&self, cur: FragmentId, mutating: bool) -> &[FragmentId] {
+        match self.phase_options.get(&cur) {
+            Some(phases) => &phases[mutating as usize],
+            None => self.lookup_fragment_nonterm(cur),
+        }
+    }
+
+    // Reset the expansion counts of max_expansions annotations and the
+    // cost spent, every derivation starts from zero
+    #[inline]
+    pub fn start_derivation(&self, state: &mut GeneratorState) {
+        if self.limited {
+            state.expansions.clear();
+            state.expansions.resize(self.fragments.len(), 0);
+        }
+        state.spent = 0;
+    }
+
+    // Weighted pick favouring the alternatives picked least so far
+    fn choose_rare(&self, state: &mut GeneratorState, options: &[FragmentId])
+            -> FragmentId {
+        if options.len() == 1 {
+            return options[0];
+        }
+        const SCALE: usize = 1 << 20;
+
+        if state.picked.len() < self.fragments.len() {
+            state.picked.resize(self.fragments.len(), 0);
+        }
+        let weight = |picked: &[u32], x: &FragmentId|
+            SCALE / (1 + picked[x.index()] as usize);
+        let total: usize = options.iter().map(|x| weight(&state.picked, x)).sum();
+        let mut pick = state.rand() % total;
+        let sel = *options.iter().find(|x| {
+            let w = weight(&state.picked, x);
+            if pick < w {
+                return true;
+            }
+            pick -= w;
+            false
+        }).unwrap();
+
+        // age the counts of the rule so weights keep reflecting the recent
+        // past and never round down to zero
+        state.picked[sel.index()] += 1;
+        if state.picked[sel.index()] >= 1 << 16 {
+            for x in options {
+                state.picked[x.index()] /= 2;
+            }
+        }
+        sel
+    }
+
+    // Weighted pick by how often the corpus took the alternatives in this
+    // context
+    fn choose_markov(&self, state: &mut GeneratorState, options: &[FragmentId],
+            context: Option<FragmentId>) -> FragmentId {
+        let markov = match state.markov.take() {
+            Some(markov) if options.len() > 1 => markov,
+            markov => {
+                state.markov = markov;
+                return options[Selector::new(options.len()).select(state)];
+            }
+        };
+        let total: usize = options.iter().map(|&x| markov.weight(context, x)).sum();
+        let mut pick = state.rand() % total;
+        let sel = *options.iter().find(|&&x| {
+            let w = markov.weight(context, x);
+            if pick < w {
+                return true;
+            }
+            pick -= w;
+            false
+        }).unwrap();
+        state.markov = Some(markov);
+        sel
+    }
+
+    // Weighted pick by the cost of the alternatives raised to the
+    // temperature, times the RareBoost weight when that is on
+    fn choose_tempered(&self, state: &mut GeneratorState, options: &[FragmentId])
+            -> FragmentId {
+        if options.len() == 1 {
+            return options[0];
+        }
+        // alternatives that never terminate count as very expensive
+        const MAX_COST: usize = 1 << 20;
+
+        let rare = self.strategy == Strategy::RareBoost;
+        if rare && state.picked.len() < self.fragments.len() {
+            state.picked.resize(self.fragments.len(), 0);
+        }
+        let exponent = state.temperature - 1.0;
+        let weight = |picked: &[u32], x: &FragmentId| {
+            let cost = self.min_cost(*x).min(MAX_COST) as f64;
+            let rarity = if rare { 1.0 + picked[x.index()] as f64 } else { 1.0 };
+            cost.powf(exponent) / rarity
+        };
+        let total: f64 = options.iter().map(|x| weight(&state.picked, x)).sum();
+        let mut pick = (state.rand() as u64 >> 11) as f64 / (1u64 << 53) as f64 * total;
+        let sel = *options.iter().find(|x| {
+            let w = weight(&state.picked, x);
+            if pick < w {
+                return true;
+            }
+            pick -= w;
+            false
+        }).unwrap_or(options.last().unwrap());
+
+        if rare {
+            state.picked[sel.index()] += 1;
+            if state.picked[sel.index()] >= 1 << 16 {
+                for x in options {
+                    state.picked[x.index()] /= 2;
+                }
+            }
+        }
+        sel
+    }
+
+    // Fewest nodes a complete expansion of the fragment takes, usize::MAX
+    // if it can never terminate
+    #[inline]
+    pub fn min_cost(&self, id: FragmentId) -> usize {
+        self.min_cost[id.index()]
+    }
+
+    // Option of a non-terminal that finishes the derivation with the fewest
+    // nodes, None if it can never terminate
+    #[inline]
+    pub fn cheapest(&self, cur: FragmentId) -> Option<FragmentId> {
+        match self.cheapest[cur.index()] {
+            Some(sel) if self.min_cost[sel.index()] != usize::MAX => Some(sel),
+            _ => None,
+        }
+    }
+
+    // Append a test case to buf, with the state and stack of the context
+    // (see context.rs)
+    pub fn generate<B: Buffer + ?Sized>(&self, ctx: &mut GenerationContext, buf: &mut B) {
+        // shared rules repeat bytes of earlier expansions, which only a
+        // tree keeps track of
+        if self.has_shared() {
+            let GenerationContext { state, tree_stack, tree, .. } = ctx;
+            self.generate_full_tree(state, tree_stack, tree);
+            tree.serialize(self, buf);
+            return;
+        }
+        if let GenerationContext { state, stack, cache: Some(cache), .. } = ctx {
+            return self.generate_cached(state, stack, cache, buf);
+        }
+        let GenerationContext { state, stack, .. } = ctx;
+
+        // get access to the start node
+        let start = self.start.unwrap();
+
+        // start off working on start
+        stack.clear();
+        stack.push(start);
+        self.start_derivation(state);
+
+        // number of fragments expanded so far
+        let mut nodes = 0usize;
+        let max_output = self.output_limit(state);
+
+        while let Some(cur) = stack.pop() {
+            nodes += 1;
+
+            match self.lookup_fragment(cur) {
+                Fragment::NonTerminal(options) => {
+                    let Some(sel) = self.choose(state, cur, options, nodes, None) else {
+                        break;
+                    };
+                    stack.push(sel);
+                    // print!("Non-terminal: {:?}\n", sel);
+                }
+                Fragment::Expression(expr) => {
+                    // we must process all of these in sequence
+                    // take expr slice and append all elements to stack vec
+                    expr.iter().rev().for_each(|x| stack.push(*x));
+                }
+                Fragment::Terminal(value) => {
+                    if self.corrupting {
+                        self.emit(state, cur, value, buf);
+                    } else {
+                        buf.extend_from_slice(value);
+                    }
+                    // print!("TERM\n");
+                    if buf.len() > max_output {
+                        break;
+                    }
+                }
+            }
+        }
+
+    }
+}
@@ -0,0 +1,15 @@
+// Stable content hashing
+//
+// std's hashers are free to change between releases, anything that leaves
+// the process (corpus names, sync protocol) has to use a hash that is the
+// same on every machine and build
+
+// 64-bit FNV-1a
+pub fn hash64(data: &[u8]) -> u64 {
+    let mut hash = 0xcbf29ce484222325u64;
+    for &byte in data {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
@@ -0,0 +1,114 @@
+// Byte level havoc on top of grammar output
+//
+// Stacks a handful of afl style random edits (bit flips, interesting
+// values, arithmetic, block deletion/duplication, splicing in bytes of
+// another input) on a generated test case. The result is usually just
+// slightly off the grammar, which is exactly the input space pure
+// generation never reaches.
+
+use std::borrow::Cow;
+
+// afl-fuzz interesting values
+const INTERESTING_8: &[i8] = &[-128, -1, 0, 1, 16, 32, 64, 100, 127];
+const INTERESTING_16: &[i16] = &[-32768, -129, 128, 255, 256, 512, 1000,
+    1024, 4096, 32767];
+const INTERESTING_32: &[i32] = &[-2147483648, -100663046, -32769, 32768,
+    65535, 65536, 100663045, 2147483647];
+
+// Largest stack of edits applied in one go (power of two)
+const MAX_STACK: usize = 16;
+
+// Maximum distance for arithmetic edits
+const ARITH_MAX: usize = 35;
+
+// Apply a random stack of edits to buf, splice is another input to take
+// bytes from, rand the caller's random source
+pub fn havoc(buf: &mut Vec<u8>, splice: &[u8], rand: &mut impl FnMut() -> usize) {
+    let stack = 1 << (rand() % MAX_STACK.trailing_zeros() as usize + 1);
+
+    for _ in 0..stack {
+        // edits other than insertion need something to work on
+        if buf.is_empty() {
+            buf.push(rand() as u8);
+            continue;
+        }
+
+        let len = buf.len();
+        let pos = rand() % len;
+
+        match rand() % 11 {
+            // flip a single bit
+            0 => buf[pos] ^= 1 << (rand() % 8),
+            // random byte
+            1 => buf[pos] = rand() as u8,
+            // interesting byte
+            2 => buf[pos] = INTERESTING_8[rand() % INTERESTING_8.len()] as u8,
+            // interesting word, random endianness
+            3 if len >= 2 => {
+                let pos = rand() % (len - 1);
+                let val = INTERESTING_16[rand() % INTERESTING_16.len()];
+                let bytes = if rand() & 1 == 0 {
+                    val.to_le_bytes()
+                } else {
+                    val.to_be_bytes()
+                };
+                buf[pos..pos + 2].copy_from_slice(&bytes);
+            }
+            // interesting dword, random endianness
+            4 if len >= 4 => {
+                let pos = rand() % (len - 3);
+                let val = INTERESTING_32[rand() % INTERESTING_32.len()];
+                let bytes = if rand() & 1 == 0 {
+                    val.to_le_bytes()
+                } else {
+                    val.to_be_bytes()
+                };
+                buf[pos..pos + 4].copy_from_slice(&bytes);
+            }
+            // add or subtract a small value
+            5 => {
+                let delta = (rand() % ARITH_MAX + 1) as u8;
+                buf[pos] = if rand() & 1 == 0 {
+                    buf[pos].wrapping_add(delta)
+                } else {
+                    buf[pos].wrapping_sub(delta)
+                };
+            }
+            // delete a block
+            6 | 7 if len > 1 => {
+                let del = rand() % (len - pos).min(len - 1) + 1;
+                buf.drain(pos..pos + del);
+            }
+            // duplicate a block somewhere else
+            8 => {
+                let from = rand() % len;
+                let size = rand() % (len - from) + 1;
+                let block = buf[from..from + size].to_vec();
+                buf.splice(pos..pos, block);
+            }
+            // overwrite with a block of the other input
+            9 if !splice.is_empty() => {
+                let from = rand() % splice.len();
+                let size = (rand() % (splice.len() - from) + 1).min(len - pos);
+                buf[pos..pos + size].copy_from_slice(&splice[from..from + size]);
+            }
+            // insert a block of the other input
+            10 if !splice.is_empty() => {
+                let from = rand() % splice.len();
+                let size = rand() % (splice.len() - from) + 1;
+                buf.splice(pos..pos, splice[from..from + size].iter().copied());
+            }
+            // edit did not fit this buffer, flip a bit instead
+            _ => buf[pos] ^= 1 << (rand() % 8),
+        }
+    }
+}
+
+// Turn buf back into valid UTF-8 after havoc, for targets that reject
+// anything else up front. Broken sequences become U+FFFD, the rest of the
+// edits survive
+pub fn repair_utf8(buf: &mut Vec<u8>) {
+    if let Cow::Owned(fixed) = String::from_utf8_lossy(buf) {
+        *buf = fixed.into_bytes();
+    }
+}
@@ -0,0 +1,140 @@
+// Shape of the generated inputs over a campaign
+//
+// Node budget, max_expansions and recursion weights are guesswork unless
+// one knows what derivations look like. With --histograms every executed
+// input that has a derivation tree adds its depth and node count, and
+// every executed input its size, to a histogram. Buckets are powers of
+// two, recording is a couple of relaxed atomic adds, so all workers share
+// one. The report has percentiles and the non-empty buckets.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::Serialize;
+
+use crate::grammar::GrammarRust;
+use crate::tree::Tree;
+
+// bucket 0 holds 0, bucket i holds 2^(i-1) to 2^i - 1
+const BUCKETS: usize = 65;
+
+#[derive(Debug)]
+pub struct Histogram {
+    buckets: [AtomicU64; BUCKETS],
+    count: AtomicU64,
+    sum: AtomicU64,
+    max: AtomicU64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+            max: AtomicU64::new(0),
+        }
+    }
+}
+
+// Bucket of a value
+fn bucket(value: u64) -> usize {
+    (u64::BITS - value.leading_zeros()) as usize
+}
+
+// Smallest and largest value of a bucket
+fn range(bucket: usize) -> (u64, u64) {
+    match bucket {
+        0 => (0, 0),
+        _ => (1 << (bucket - 1), ((1u128 << bucket) - 1) as u64),
+    }
+}
+
+impl Histogram {
+    pub fn record(&self, value: u64) {
+        self.buckets[bucket(value)].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value, Ordering::Relaxed);
+        self.max.fetch_max(value, Ordering::Relaxed);
+    }
+
+    pub fn summary(&self) -> Summary {
+        let counts = self.buckets.iter().map(|x| x.load(Ordering::Relaxed))
+            .collect::<Vec<_>>();
+        let count = counts.iter().sum::<u64>();
+        let max = self.max.load(Ordering::Relaxed);
+
+        // upper end of the bucket the percentile falls in, at most max
+        let percentile = |p: f64| {
+            let rank = ((count as f64 * p).ceil() as u64).max(1);
+            let mut seen = 0;
+            counts.iter().position(|&x| {
+                seen += x;
+                seen >= rank
+            }).map_or(0, |x| range(x).1.min(max))
+        };
+        Summary {
+            count,
+            mean: self.sum.load(Ordering::Relaxed) as f64 / count.max(1) as f64,
+            p50: percentile(0.5),
+            p90: percentile(0.9),
+            p99: percentile(0.99),
+            max,
+            buckets: counts.iter().enumerate().filter(|x| *x.1 > 0)
+                .map(|(ii, &count)| Bucket { from: range(ii).0, to: range(ii).1, count })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Bucket {
+    pub from: u64,
+    pub to: u64,
+    pub count: u64,
+}
+
+// Percentiles are bucket upper bounds, exact to a factor of two
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Summary {
+    pub count: u64,
+    pub mean: f64,
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub max: u64,
+    pub buckets: Vec<Bucket>,
+}
+
+// The histograms of a campaign
+#[derive(Debug, Default)]
+pub struct Histograms {
+    pub depth: Histogram,
+    pub nodes: Histogram,
+    pub size: Histogram,
+}
+
+impl Histograms {
+    // Record an executed input, and its derivation tree if it has one
+    pub fn record(&self, gram: &GrammarRust, tree: Option<&Tree>, size: usize) {
+        if let Some(tree) = tree {
+            self.depth.record(tree.depth(gram) as u64);
+            self.nodes.record(tree.nodes.len() as u64);
+        }
+        self.size.record(size as u64);
+    }
+
+    pub fn report(&self) -> HistogramReport {
+        HistogramReport {
+            depth: self.depth.summary(),
+            nodes: self.nodes.summary(),
+            size: self.size.summary(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct HistogramReport {
+    pub depth: Summary,
+    pub nodes: Summary,
+    pub size: Summary,
+}
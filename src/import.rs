@@ -0,0 +1,171 @@
+// Seed files to start a campaign from
+//
+// With --import-dir the files of a directory (an old queue, a collection of
+// samples) are run through the target before the workers start fuzzing,
+// and the ones adding coverage are admitted to the corpus like any other
+// find. A seed only gets mutated structurally with a derivation tree: a
+// seed that comes with a choice sequence made with the same grammar
+// (<dir>/.choices/<name>, the layout of a queue) is replayed into one,
+// provided the tree derives exactly the seed. The others are run as they
+// are.
+//
+// --import-corpus warm starts from the corpus of another fuzzer: the queue
+// directories of an AFL++ output directory (<dir>/queue, or
+// <dir>/<instance>/queue for every instance), or a libFuzzer corpus, which
+// is a directory of plain files. Entries the instances share are imported
+// once. Every entry gets parsed with the grammar (see earley.rs), the ones
+// that parse are seeded with their derivation tree, the rest as bytes.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::compress;
+use crate::debug;
+use crate::earley::ParseError;
+use crate::grammar::GrammarRust;
+use crate::hash::hash64;
+use crate::tree::{Tree, TreeStack};
+
+pub struct Seed {
+    // the whole input, prefix and suffix included
+    pub data: Vec<u8>,
+    // derivation of what is between prefix and suffix
+    pub tree: Option<Tree>,
+}
+
+// Grammar fingerprint in the metadata sidecar of a saved choice sequence
+// (<dir>/.choices/<name> has it in <dir>/.meta/<name>.json), if any
+pub fn sidecar_fingerprint(choices: &Path) -> Option<u64> {
+    let name = choices.file_name()?.to_string_lossy();
+    let meta = choices.parent()?.parent()?.join(".meta")
+        .join(format!("{}.json", name));
+    let meta: serde_json::Value = serde_json::from_slice(
+        &fs::read(meta).ok()?).ok()?;
+    u64::from_str_radix(meta.get("grammar_hash")?.as_str()?, 16).ok()
+}
+
+// Derivation tree of a seed from its choice sequence, if it has one that
+// reproduces it
+fn recover_tree(gram: &GrammarRust, dir: &Path, name: &str, data: &[u8],
+        prefix: &[u8], suffix: &[u8]) -> Option<Tree> {
+    let choices = dir.join(".choices").join(name);
+    if sidecar_fingerprint(&choices) != Some(gram.fingerprint()) {
+        return None;
+    }
+    let inner = data.strip_prefix(prefix)?.strip_suffix(suffix)?;
+    let mut tree = Tree::default();
+    gram.replay_full_choices(&fs::read(&choices).ok()?, &mut TreeStack::default(),
+        &mut tree);
+    let mut bytes = Vec::new();
+    tree.serialize(gram, &mut bytes);
+    (bytes == inner).then_some(tree)
+}
+
+// The files of a directory in name order with their contents, hidden
+// files (sidecars, the files of a queue's bookkeeping) left out
+fn read_files(dir: &Path) -> io::Result<Vec<(String, Vec<u8>)>> {
+    let mut names = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|x| x.is_file()))
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| !name.starts_with('.'))
+        .collect::<Vec<_>>();
+    names.sort();
+
+    names.into_iter().map(|name| {
+        let stored = fs::read(dir.join(&name))?;
+        let data = match compress::is_compressed(&stored) {
+            true => compress::decompress(&stored)?,
+            false => stored,
+        };
+        Ok((name, data))
+    }).collect()
+}
+
+// The seeds in a directory in name order
+pub fn read_dir(dir: &Path, gram: &GrammarRust, prefix: &[u8], suffix: &[u8])
+        -> io::Result<Vec<Seed>> {
+    Ok(read_files(dir)?.into_iter().map(|(name, data)| {
+        let tree = recover_tree(gram, dir, &name, &data, prefix, suffix);
+        Seed { data, tree }
+    }).collect())
+}
+
+// What became of the entries of a corpus
+#[derive(Clone, Debug, Default)]
+pub struct Recovery {
+    pub entries: usize,
+    // entries another instance had already
+    pub duplicates: usize,
+    pub parsed: usize,
+    // entries without prefix and suffix, or the grammar does not derive
+    pub no_parse: usize,
+    pub too_large: usize,
+    // parses whose tree derives something else
+    pub mismatch: usize,
+}
+
+impl Recovery {
+    // Share of the entries that got a derivation tree, percent
+    pub fn rate(&self) -> f64 {
+        self.parsed as f64 * 100. / self.entries.max(1) as f64
+    }
+}
+
+// The queue directories of an AFL++ output directory, or the directory
+// itself
+fn corpus_dirs(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    if dir.join("queue").is_dir() {
+        return Ok(vec![dir.join("queue")]);
+    }
+    let mut queues = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path().join("queue"))
+        .filter(|queue| queue.is_dir())
+        .collect::<Vec<_>>();
+    queues.sort();
+    Ok(match queues.is_empty() {
+        true => vec![dir.to_path_buf()],
+        false => queues,
+    })
+}
+
+// The seeds of another fuzzer's corpus, see the top of the file
+pub fn read_corpus(dir: &Path, gram: &GrammarRust, prefix: &[u8], suffix: &[u8])
+        -> io::Result<(Vec<Seed>, Recovery)> {
+    let mut recovery = Recovery::default();
+    let mut seen = HashSet::new();
+    let mut seeds = Vec::new();
+    for dir in corpus_dirs(dir)? {
+        for (name, data) in read_files(&dir)? {
+            if !seen.insert(hash64(&data)) {
+                recovery.duplicates += 1;
+                continue;
+            }
+            recovery.entries += 1;
+            let parsed = match data.strip_prefix(prefix).and_then(|x| x.strip_suffix(suffix)) {
+                Some(inner) => gram.parse(inner),
+                None => Err(ParseError::NoParse(0)),
+            };
+            let tree = match parsed {
+                Ok(tree) => {
+                    recovery.parsed += 1;
+                    Some(tree)
+                }
+                Err(e) => {
+                    match e {
+                        ParseError::NoParse(_) => recovery.no_parse += 1,
+                        ParseError::TooLarge => recovery.too_large += 1,
+                        ParseError::Mismatch => recovery.mismatch += 1,
+                    }
+                    debug!("import", "{}: {}", dir.join(&name).display(), e);
+                    None
+                }
+            };
+            seeds.push(Seed { data, tree });
+        }
+    }
+    Ok((seeds, recovery))
+}
@@ -0,0 +1,128 @@
+// Error injection for negative testing
+//
+// Takes a valid derivation and breaks it in a few deliberate, targeted
+// ways: leave out a piece the grammar requires, repeat one that it allows
+// only once, or corrupt a single terminal. Inputs stay close to valid, so
+// they get past the first checks of the target and exercise its error
+// handling and recovery instead of being rejected outright.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::grammar::{Fragment, GeneratorState, GrammarRust};
+use crate::tree::Tree;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ViolationKind {
+    // a subtree is left out
+    Drop,
+    // a subtree appears twice
+    Duplicate,
+    // a byte of a terminal is replaced
+    Corrupt,
+}
+
+impl ViolationKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            ViolationKind::Drop => "drop",
+            ViolationKind::Duplicate => "dup",
+            ViolationKind::Corrupt => "corrupt",
+        }
+    }
+}
+
+// One violation applied to an input
+#[derive(Clone, Debug)]
+pub struct Violation {
+    pub kind: ViolationKind,
+    // the rule the affected subtree derives from, or the terminal
+    pub what: String,
+    // position of the affected bytes in the original output
+    pub offset: usize,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} at {}", self.kind.name(), self.what, self.offset)
+    }
+}
+
+// Short tag of a set of violations for file names, "drop+corrupt"
+pub fn tag(violations: &[Violation]) -> String {
+    violations.iter().map(|x| x.kind.name()).collect::<Vec<_>>().join("+")
+}
+
+impl GrammarRust {
+    // Serialize tree into buf with up to count violations, the tree itself
+    // is left alone. Returns what was broken, in output order
+    pub fn inject_errors(&self, state: &mut GeneratorState, tree: &Tree,
+            count: usize, buf: &mut Vec<u8>) -> Vec<Violation> {
+        let mut spans = Vec::with_capacity(tree.nodes.len());
+        buf.clear();
+        tree.serialize_spans(self, buf, &mut spans);
+
+        let names = self.rules().map(|(name, id)| (id, name))
+            .collect::<HashMap<_, _>>();
+
+        // candidates that produce something, one violation per byte range
+        let candidates = (0..tree.nodes.len())
+            .filter(|&ii| spans[ii].1 > spans[ii].0)
+            .collect::<Vec<_>>();
+        let mut chosen: Vec<(usize, ViolationKind)> = Vec::new();
+        for _ in 0..count * 4 {
+            if chosen.len() == count || candidates.is_empty() {
+                break;
+            }
+            let idx = candidates[state.rand() % candidates.len()];
+            let fragment = tree.nodes[idx].fragment;
+            let kind = match self.lookup_fragment(fragment) {
+                Fragment::Terminal(_) => match state.rand() % 2 {
+                    0 => ViolationKind::Corrupt,
+                    _ => ViolationKind::Drop,
+                },
+                // whole rules only, not their alternatives
+                _ if names.contains_key(&fragment) => match state.rand() % 2 {
+                    0 => ViolationKind::Duplicate,
+                    _ => ViolationKind::Drop,
+                },
+                _ => continue,
+            };
+            let (start, end) = spans[idx];
+            if chosen.iter().any(|&(x, _)| spans[x].0 < end && start < spans[x].1) {
+                continue;
+            }
+            chosen.push((idx, kind));
+        }
+
+        // back to front so earlier offsets stay valid
+        chosen.sort_by_key(|&(idx, _)| std::cmp::Reverse(spans[idx].0));
+        let mut violations = Vec::new();
+        for (idx, kind) in chosen {
+            let (start, end) = spans[idx];
+            let fragment = tree.nodes[idx].fragment;
+            let what = match names.get(&fragment) {
+                Some(name) => name.to_string(),
+                None => format!("{:?}", String::from_utf8_lossy(&buf[start..end])),
+            };
+            match kind {
+                ViolationKind::Drop => {
+                    buf.drain(start..end);
+                }
+                ViolationKind::Duplicate => {
+                    let copy = buf[start..end].to_vec();
+                    buf.splice(end..end, copy);
+                }
+                ViolationKind::Corrupt => {
+                    let pos = start + state.rand() % (end - start);
+                    // printable and never the byte that was there
+                    let old = buf[pos].wrapping_sub(b' ') as usize % 95;
+                    buf[pos] = b' ' + ((old + 1 + state.rand() % 94) % 95) as u8;
+                }
+            }
+            violations.push(Violation { kind, what, offset: start });
+        }
+        violations.reverse();
+        violations
+    }
+}
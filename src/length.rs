@@ -0,0 +1,104 @@
+// Adaptive output size limit
+//
+// --max-size is a guess: set it too high and the worker spends its time on
+// inputs the target throws out after reading the first kilobyte, too low
+// and it never builds the large inputs some code wants. With
+// --adaptive-size and coverage every worker moves its own limit between
+// MIN_LIMIT and --max-size, looking at the inputs in the upper half of the
+// current limit over windows of WINDOW executions:
+//
+//   shrink (halve) when the target rejects nearly all of them (times out,
+//   hits a resource limit or exits with an error code), or when they find
+//   no coverage while the smaller inputs still do
+//
+//   grow (double) when they find coverage and the target mostly takes them
+//
+// Too few large inputs in a window (they are rare with the limit far above
+// what the grammar tends to derive) leave the limit alone.
+
+use crate::executor::{ExecResult, ExitKind};
+
+// Smallest limit shrinking goes to
+pub const MIN_LIMIT: usize = 64;
+
+// Executions between decisions
+const WINDOW: u64 = 2000;
+
+// Large inputs a window needs for a decision
+const MIN_SAMPLES: u64 = 64;
+
+// Share of rejected large inputs that shrinks the limit, and the most
+// growing allows
+const REJECT_SHRINK: f64 = 0.9;
+const REJECT_GROW: f64 = 0.5;
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Tally {
+    execs: u64,
+    rejected: u64,
+    finds: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct LengthControl {
+    limit: usize,
+    ceiling: usize,
+    execs: u64,
+    // inputs up to half the limit and above
+    small: Tally,
+    large: Tally,
+}
+
+// The target did not take the input
+fn rejected(result: &ExecResult) -> bool {
+    match result.exit {
+        ExitKind::Ok => result.code.is_some_and(|x| x != 0),
+        ExitKind::Timeout | ExitKind::Limit(_) => true,
+        ExitKind::Crash => false,
+    }
+}
+
+impl LengthControl {
+    // Start at the limit the grammar has, which is also the most it grows to
+    pub fn new(ceiling: usize) -> Self {
+        LengthControl { limit: ceiling, ceiling, execs: 0, small: Tally::default(),
+            large: Tally::default() }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    // Account for an execution of an input of len bytes, found if it got
+    // new coverage. The new limit when it changes
+    pub fn observe(&mut self, len: usize, result: &ExecResult, found: bool)
+            -> Option<usize> {
+        let tally = match len > self.limit / 2 {
+            true => &mut self.large,
+            false => &mut self.small,
+        };
+        tally.execs += 1;
+        tally.rejected += rejected(result) as u64;
+        tally.finds += found as u64;
+
+        self.execs += 1;
+        if !self.execs.is_multiple_of(WINDOW) {
+            return None;
+        }
+        let (small, large) = (self.small, self.large);
+        self.small = Tally::default();
+        self.large = Tally::default();
+        if large.execs < MIN_SAMPLES {
+            return None;
+        }
+
+        let rejected = large.rejected as f64 / large.execs as f64;
+        let old = self.limit;
+        if rejected >= REJECT_SHRINK || (large.finds == 0 && small.finds > 0) {
+            self.limit = (self.limit / 2).max(MIN_LIMIT.min(self.ceiling));
+        } else if large.finds > 0 && rejected < REJECT_GROW {
+            self.limit = self.limit.saturating_mul(2).min(self.ceiling);
+        }
+        (self.limit != old).then_some(self.limit)
+    }
+}
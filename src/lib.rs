@@ -0,0 +1,82 @@
+// Grammar based test case generator
+// The binary in main.rs is a thin driver around this library
+
+mod macros;
+
+pub mod affinity;
+#[cfg(feature = "batch")]
+pub mod batch;
+pub mod bench;
+pub mod binary;
+pub mod broker;
+pub mod choices;
+pub mod cmplog;
+pub mod compare;
+pub mod compress;
+pub mod corpus;
+pub mod config;
+pub mod context;
+pub mod coverage;
+pub mod dashboard;
+pub mod dedup;
+pub mod derivation_cache;
+pub mod determinism;
+pub mod dot;
+pub mod earley;
+pub mod exec_cache;
+pub mod executor;
+pub mod export;
+pub mod feedback;
+pub mod findings;
+pub mod fuzzer;
+pub mod golden;
+pub mod grammar;
+pub mod hash;
+pub mod havoc;
+pub mod histogram;
+pub mod import;
+pub mod inject;
+pub mod length;
+pub mod llvm_cov;
+pub mod loader;
+pub mod log;
+pub mod markov;
+pub mod mmap;
+pub mod mutator;
+pub mod numeric;
+pub mod oracle;
+pub mod orchestrator;
+pub mod output;
+pub mod pairs;
+pub mod perturb;
+pub mod plot;
+pub mod positions;
+pub mod profile;
+pub mod record;
+pub mod repl;
+pub mod replay;
+pub mod report;
+pub mod rng;
+pub mod roles;
+pub mod rule_stats;
+pub mod sanitizer;
+pub mod schema;
+pub mod selftest;
+pub mod signals;
+pub mod sink;
+pub mod storage;
+pub mod symcc;
+pub mod temperature;
+pub mod template;
+pub mod testcases;
+pub mod throttle;
+pub mod tokens;
+pub mod traps;
+pub mod tree;
+pub mod trim;
+pub mod unicode;
+pub mod validate;
+pub mod workspace;
+
+pub use grammar::{Fragment, FragmentId, GeneratorState, Grammar, GrammarRust};
+pub use testcases::TestCases;
@@ -0,0 +1,112 @@
+// Source coverage of the target, for a finished campaign
+//
+// The corpus is replayed against a build of the target instrumented for
+// LLVM source based coverage (-fprofile-instr-generate -fcoverage-mapping,
+// or -C instrument-coverage for Rust). Every run writes a raw profile,
+// llvm-profdata merges them and llvm-cov turns the result into an lcov
+// tracefile and an HTML report:
+//
+//   <report dir>/raw/*.profraw
+//               /coverage.profdata
+//               /coverage.lcov
+//               /html/index.html
+//
+// The tools are taken from $LLVM_PROFDATA and $LLVM_COV, or $PATH. They
+// have to be of the LLVM version the target was built with.
+
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+// Where every run of the instrumented target writes its raw profile, set
+// as LLVM_PROFILE_FILE. %p keeps the runs apart, %m the binaries
+pub fn profile_pattern(report_dir: &Path) -> PathBuf {
+    report_dir.join("raw").join("%p-%m.profraw")
+}
+
+// Line and function totals of an lcov tracefile
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Summary {
+    pub lines_found: u64,
+    pub lines_hit: u64,
+    pub functions_found: u64,
+    pub functions_hit: u64,
+}
+
+fn tool(var: &str, name: &str) -> OsString {
+    std::env::var_os(var).unwrap_or_else(|| name.into())
+}
+
+// Run one of the tools, with its stdout going to stdout
+fn run(mut cmd: Command, stdout: Stdio) -> io::Result<()> {
+    let program = cmd.get_program().to_string_lossy().into_owned();
+    let status = cmd.stdout(stdout).status().map_err(|e| io::Error::new(e.kind(),
+        format!("running {} failed: {}", program, e)))?;
+    if !status.success() {
+        return Err(io::Error::other(format!("{} failed ({})", program, status)));
+    }
+    Ok(())
+}
+
+// Merge the raw profiles of the replay and write the lcov tracefile and
+// HTML report for binary (the instrumented target)
+pub fn report(report_dir: &Path, binary: &Path) -> io::Result<Summary> {
+    let mut raw = fs::read_dir(report_dir.join("raw"))?
+        .map(|x| x.map(|x| x.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    raw.retain(|x| x.extension().is_some_and(|x| x == "profraw"));
+    if raw.is_empty() {
+        return Err(io::Error::other(format!(
+            "no raw profiles, is {} built with coverage instrumentation?",
+            binary.display())));
+    }
+
+    // one file per run, too many for a command line
+    let list = report_dir.join("profraw.list");
+    let mut file = io::BufWriter::new(fs::File::create(&list)?);
+    for path in &raw {
+        writeln!(file, "{}", path.display())?;
+    }
+    file.flush()?;
+    drop(file);
+
+    let profdata = report_dir.join("coverage.profdata");
+    let mut merge = Command::new(tool("LLVM_PROFDATA", "llvm-profdata"));
+    merge.args(["merge", "-sparse", "-f"]).arg(&list).arg("-o").arg(&profdata);
+    run(merge, Stdio::inherit())?;
+
+    let lcov = report_dir.join("coverage.lcov");
+    let mut export = Command::new(tool("LLVM_COV", "llvm-cov"));
+    export.args(["export", "-format=lcov"]).arg("-instr-profile").arg(&profdata)
+        .arg(binary);
+    run(export, fs::File::create(&lcov)?.into())?;
+
+    let mut show = Command::new(tool("LLVM_COV", "llvm-cov"));
+    show.args(["show", "-format=html"]).arg("-instr-profile").arg(&profdata)
+        .arg("-output-dir").arg(report_dir.join("html")).arg(binary);
+    run(show, Stdio::inherit())?;
+
+    summarize(&fs::read_to_string(lcov)?)
+}
+
+// Totals of an lcov tracefile, from the LF/LH/FNF/FNH record of every file
+pub fn summarize(lcov: &str) -> io::Result<Summary> {
+    let mut summary = Summary::default();
+    for line in lcov.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let field = match key {
+            "LF" => &mut summary.lines_found,
+            "LH" => &mut summary.lines_hit,
+            "FNF" => &mut summary.functions_found,
+            "FNH" => &mut summary.functions_hit,
+            _ => continue,
+        };
+        *field += value.trim().parse::<u64>().map_err(|_| io::Error::new(
+            io::ErrorKind::InvalidData, format!("bad lcov record {:?}", line)))?;
+    }
+    Ok(summary)
+}
@@ -0,0 +1,245 @@
+// Loading grammar files
+//
+// A grammar can be spread over several json files (a base grammar plus
+// extensions, shared token definitions, ...). Rules defined in more than
+// one place, across files or twice in the same one, are combined according
+// to a DuplicatePolicy instead of one silently replacing the other. Files
+// state the version of the format they are written in, see schema.rs.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use serde::de::{Deserialize, Deserializer, MapAccess, Visitor};
+
+use crate::binary;
+use crate::grammar::{Annotation, Grammar};
+use crate::numeric;
+use crate::schema::{self, Parsing};
+use crate::unicode;
+use crate::warn;
+
+// What to do with a rule that is defined more than once
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    // append the alternatives of all definitions
+    Merge,
+    // merge, but log a warning for every duplicate
+    Warn,
+    // refuse the grammar
+    Error,
+}
+
+// A rule defined more than once under DuplicatePolicy::Error
+#[derive(Clone, Debug)]
+pub struct DuplicateRule {
+    pub name: String,
+    // where the rule was defined first and again
+    pub first: String,
+    pub second: String,
+}
+
+impl fmt::Display for DuplicateRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "rule {} is defined in {} and again in {}", self.name,
+            self.first, self.second)
+    }
+}
+
+impl std::error::Error for DuplicateRule {}
+
+impl From<DuplicateRule> for io::Error {
+    fn from(e: DuplicateRule) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+// A rule definition: its list of alternatives, or an object with the
+// alternatives and annotations of the rule
+//
+//   "<digit>": [["0"], ["1"]]
+//   "<word>": {"alternatives": [["a"], ["<word>", "a"]], "corrupt": 0.01}
+//   "<attrs>": {"alternatives": [[], ["<attr>", "<attrs>"]], "max_expansions": 100}
+//   "<ident>": {"alternatives": [["<letter>", "<ident>"], ["<letter>"]], "token": 3}
+//   "<table>": {"alternatives": [["<row>"], ["<row>", "<table>"]], "cost": 50}
+//   "<expr>": {"alternatives": [["<num>"], ["<expr>", "/", "0"]],
+//       "mutation_only": [["<expr>", "/", "0"]]}
+//   "<ns>": {"alternatives": [["urn:a"], ["urn:", "<ident>"]], "shared": true}
+//
+// mutation_only and generation_only repeat alternatives of the rule
+#[derive(Debug, Default)]
+pub struct Rule {
+    pub alternatives: Vec<Vec<String>>,
+    pub annotation: Annotation,
+    // keys of the object this version does not know, see schema::conform()
+    pub unknown: Vec<String>,
+}
+
+// Keys of a rule object besides alternatives, the fields of Annotation
+const ANNOTATIONS: &[&str] = &["corrupt", "max_expansions", "cost", "token",
+    "mutation_only", "generation_only", "shared"];
+
+impl<'de> Deserialize<'de> for Rule {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        let mut object = match serde_json::Value::deserialize(deserializer)? {
+            serde_json::Value::Object(object) => object,
+            value => return Ok(Rule {
+                alternatives: serde_json::from_value(value).map_err(D::Error::custom)?,
+                annotation: Annotation::default(),
+                unknown: Vec::new(),
+            }),
+        };
+        let alternatives = object.remove("alternatives")
+            .ok_or_else(|| D::Error::missing_field("alternatives"))?;
+        let unknown = object.keys().filter(|x| !ANNOTATIONS.contains(&x.as_str()))
+            .cloned().collect::<Vec<_>>();
+        for key in &unknown {
+            object.remove(key);
+        }
+        let annotation: Annotation = serde_json::from_value(object.into())
+            .map_err(D::Error::custom)?;
+        if annotation.corrupt.is_some_and(|x| !(0.0..=1.0).contains(&x)) {
+            return Err(D::Error::custom("corrupt is a probability, 0 to 1"));
+        }
+        if annotation.max_expansions == Some(0) {
+            return Err(D::Error::custom("max_expansions must be at least 1"));
+        }
+        if annotation.cost == Some(0) {
+            return Err(D::Error::custom("cost must be at least 1"));
+        }
+        let alternatives: Vec<Vec<String>> = serde_json::from_value(alternatives)
+            .map_err(D::Error::custom)?;
+        for (key, listed) in [("mutation_only", &annotation.mutation_only),
+                ("generation_only", &annotation.generation_only)] {
+            if let Some(x) = listed.iter().find(|x| !alternatives.contains(x)) {
+                return Err(D::Error::custom(format!(
+                    "{} lists {:?}, which is not an alternative of the rule", key, x)));
+            }
+            if !alternatives.is_empty() && alternatives.iter().all(|x| listed.contains(x)) {
+                return Err(D::Error::custom(format!(
+                    "{} lists every alternative of the rule", key)));
+            }
+        }
+        if let Some(x) = annotation.mutation_only.iter()
+                .find(|x| annotation.generation_only.contains(x)) {
+            return Err(D::Error::custom(format!(
+                "{:?} is both mutation_only and generation_only", x)));
+        }
+        Ok(Rule { alternatives, annotation, unknown })
+    }
+}
+
+// Rule definitions of one file in file order, duplicates included (a plain
+// map would keep only the last one), and the version of the format the
+// file states
+#[derive(Debug, Default)]
+pub struct Definitions(pub Vec<(String, Rule)>, pub Option<u32>);
+
+impl<'de> Deserialize<'de> for Definitions {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct DefinitionsVisitor;
+
+        impl<'de> Visitor<'de> for DefinitionsVisitor {
+            type Value = Definitions;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a map of rule names to rule definitions")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A)
+                    -> Result<Definitions, A::Error> {
+                use serde::de::Error;
+
+                let mut ret = Definitions::default();
+                while let Some(key) = map.next_key::<String>()? {
+                    if key != schema::KEY {
+                        ret.0.push((key, map.next_value()?));
+                        continue;
+                    }
+                    if ret.1.is_some() {
+                        return Err(A::Error::duplicate_field(schema::KEY));
+                    }
+                    ret.1 = Some(map.next_value()?);
+                }
+                Ok(ret)
+            }
+        }
+
+        deserializer.deserialize_map(DefinitionsVisitor)
+    }
+}
+
+// Combine the definitions of several sources (named for diagnostics) into
+// one grammar
+pub fn combine(sources: impl IntoIterator<Item = (String, Definitions)>,
+        policy: DuplicatePolicy) -> Result<Grammar, DuplicateRule> {
+    let mut rules: HashMap<String, Vec<Vec<String>>> = HashMap::new();
+    let mut annotations: HashMap<String, Annotation> = HashMap::new();
+    let mut origin: HashMap<String, String> = HashMap::new();
+
+    for (source, definitions) in sources {
+        for (name, Rule { alternatives, annotation, .. }) in definitions.0 {
+            if annotation != Annotation::default() {
+                annotations.entry(name.clone()).or_default().merge(annotation);
+            }
+            let Some(existing) = rules.get_mut(&name) else {
+                origin.insert(name.clone(), source.clone());
+                rules.insert(name, alternatives);
+                continue;
+            };
+
+            match policy {
+                DuplicatePolicy::Error => return Err(DuplicateRule {
+                    first: origin[&name].clone(),
+                    second: source,
+                    name,
+                }),
+                DuplicatePolicy::Warn => warn!("grammar",
+                    "rule {} from {} is also defined in {}, merging", name,
+                    source, origin[&name]),
+                DuplicatePolicy::Merge => {}
+            }
+
+            // the same alternative twice would only skew the odds
+            for alternative in alternatives {
+                if !existing.contains(&alternative) {
+                    existing.push(alternative);
+                }
+            }
+        }
+    }
+    Ok(Grammar(rules, annotations))
+}
+
+// The rule definitions of the contents of a grammar file as they are,
+// whatever its version, source names it for errors
+pub fn parse(source: &str, data: &[u8]) -> io::Result<Definitions> {
+    serde_json::from_slice(data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData,
+        format!("{}: {}", source, e)))
+}
+
+// Load and combine grammar files, migrated to the current version of the
+// format as far as parsing allows (see schema::conform())
+pub fn load(paths: &[impl AsRef<Path>], policy: DuplicatePolicy, parsing: Parsing)
+        -> io::Result<Grammar> {
+    let sources = paths.iter().map(|path| {
+        let path = path.as_ref();
+        let source = path.display().to_string();
+        let mut definitions = parse(&source, &std::fs::read(path)?)?;
+        let warnings = schema::conform(&mut definitions, parsing).map_err(|e|
+            io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", source, e)))?;
+        for warning in warnings {
+            warn!("grammar", "{}: {}", source, warning);
+        }
+        Ok((source, definitions))
+    }).collect::<io::Result<Vec<_>>>()?;
+    let mut grammar = combine(sources, policy)?;
+    unicode::expand(&mut grammar)
+        .and_then(|_| numeric::expand(&mut grammar))
+        .and_then(|_| binary::expand(&mut grammar))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(grammar)
+}
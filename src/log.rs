@@ -0,0 +1,108 @@
+// Leveled logging
+//
+// Every message has a level and a target naming the subsystem it comes
+// from (generator, executor, feedback, sync, stats, ...). Messages go to
+// stderr, as plain text or one json object per line for log collectors.
+// The level and format are process wide, set once from the command line.
+//
+//     info!("feedback", "new crash {}", name);
+
+use std::fmt;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    fn name(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+}
+
+// Most verbose level that still gets logged
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+static JSON: AtomicBool = AtomicBool::new(false);
+
+pub fn set_max_level(level: Level) {
+    MAX_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn set_json(json: bool) {
+    JSON.store(json, Ordering::Relaxed);
+}
+
+#[inline]
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= MAX_LEVEL.load(Ordering::Relaxed)
+}
+
+// Write one message, use the macros instead of calling this directly
+pub fn log(level: Level, target: &str, args: fmt::Arguments) {
+    if !enabled(level) {
+        return;
+    }
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs_f64()).unwrap_or(0.);
+
+    // one write per line so threads do not interleave
+    let line = if JSON.load(Ordering::Relaxed) {
+        format!("{}\n", serde_json::json!({
+            "time": now,
+            "level": level.name(),
+            "target": target,
+            "message": args.to_string(),
+        }))
+    } else {
+        format!("[{:.3} {:5} {}] {}\n", now, level.name(), target, args)
+    };
+    let _ = std::io::stderr().lock().write_all(line.as_bytes());
+}
+
+#[macro_export]
+macro_rules! error {
+    ($target:expr, $($arg:tt)+) => {
+        $crate::log::log($crate::log::Level::Error, $target, format_args!($($arg)+))
+    };
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($target:expr, $($arg:tt)+) => {
+        $crate::log::log($crate::log::Level::Warn, $target, format_args!($($arg)+))
+    };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($target:expr, $($arg:tt)+) => {
+        $crate::log::log($crate::log::Level::Info, $target, format_args!($($arg)+))
+    };
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($target:expr, $($arg:tt)+) => {
+        $crate::log::log($crate::log::Level::Debug, $target, format_args!($($arg)+))
+    };
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($target:expr, $($arg:tt)+) => {
+        $crate::log::log($crate::log::Level::Trace, $target, format_args!($($arg)+))
+    };
+}
@@ -0,0 +1,30 @@
+// Inline grammars
+//
+// Same shape as the json format, rules separated by ';' and alternatives by
+// '|', for grammars that live next to the code using them (tests of other
+// crates, small harnesses):
+//
+//     let gram = grammar! {
+//         "<start>" => ["<number>"];
+//         "<number>" => ["<digit>"] | ["<digit>", "<number>"];
+//         "<digit>" => ["0"] | ["1"] | ["2"];
+//     };
+//
+// The macro builds the json representation and compiles it with
+// GrammarRust::new(), so it panics on the same mistakes a json file would.
+
+#[macro_export]
+macro_rules! grammar {
+    ($($name:literal => $([$($symbol:literal),* $(,)?])|+);* $(;)?) => {{
+        let mut rules = ::std::collections::HashMap::new();
+        $(
+            let prev = rules.insert(::std::string::String::from($name),
+                ::std::vec![$(::std::vec![
+                    $(::std::string::String::from($symbol)),*
+                ]),+]);
+            assert!(prev.is_none(), "non-terminal {} defined twice", $name);
+        )*
+        $crate::GrammarRust::new(&$crate::Grammar(rules,
+            ::std::collections::HashMap::new()))
+    }};
+}
@@ -1,218 +1,2240 @@
-use std::cell::Cell;
-use std::collections::{BTreeMap, HashMap};
-use std::time::Instant;
-use serde::{Deserialize, Serialize};
-use rand::Rng;
-
-// Json representation of the data struct
-// Map Fragment name : List<List <Fragment Names>>
-#[derive(Serialize, Deserialize, Debug, Default)]
-struct Grammar(HashMap<String, Vec<Vec<String>>>);
-
-#[derive(Clone, Debug, Copy)]
-struct FragmentId(usize);
-
-#[derive(Clone, Debug)]
-enum Fragment {
-    // nonterminal contains a vector of fragments (some might be non-terminal)
-    NonTerminal(Vec<FragmentId>),
-    // Ordered list of fragments
-    Expression(Vec<FragmentId>),
-    // terminal results to bytes
-    Terminal(Vec<u8>),
-}
-
-// Rust representation: transformed into nested structure
-#[derive(Debug, Default)]
-struct GrammarRust {
-    // all types
-    fragments: Vec<Fragment>,
-
-    // Cached fragment identifier for the start node
-    start: Option<FragmentId>,
-
-    // Mapping of non-terminal names to fragment identifiers
-    name_to_fragment: BTreeMap<String, FragmentId>,
-
-    // Xorshift seed
-    // in cell so that we do not need mutable access
-    // https://doc.rust-lang.org/std/cell/
-    seed: Cell<usize>
-}
-
-// turns json representation into rust data structure
-impl GrammarRust {
-    fn new(grammar: &Grammar) -> Self {
-        // create new grammar structure
-        let mut ret = GrammarRust::default();
-
-        // parse the input grammar to create non-term fragment names
-        for (non_term, _) in grammar.0.iter() {
-            // have not seen the fragment before?
-            assert!(!ret.name_to_fragment.contains_key(non_term),
-                    "Duplicate non-terminal definition, fail");
-
-            // allocate a new empty fragment
-            let fragment_id = ret.allocate_fragment(Fragment::NonTerminal(Vec::new()));
-
-            // add name resolution to the fragment
-            ret.name_to_fragment.insert(non_term.clone(), fragment_id);
-        }
-
-        // having all non-term names, allocate their term/non-term extensions
-        for (non_term, fragments) in grammar.0.iter() {
-            // get the non-terminal fragment identifier
-            let fragment_id = ret.name_to_fragment[non_term];
-
-            // Expressions
-            let mut expressions = Vec::new();
-
-            // go through all sub-fragments (vectors of fragment names)
-            for js_sub_fragment in fragments {
-                // Options for this sub fragment
-                let mut options = Vec::new();
-
-                for option in js_sub_fragment {
-                    // if option is one of the previously found non-terminals
-                    let fragment_id = if let Some(&non_terminal) =
-                    ret.name_to_fragment.get(option) {
-                        ret.allocate_fragment(
-                            Fragment::NonTerminal(vec![non_terminal]))
-                    } else {
-                        // Convert the terminal bytes into a vector
-                        // and create a new fragment containing it
-                        ret.allocate_fragment(
-                            Fragment::Terminal(
-                                option.as_bytes().to_vec()))
-                    };
-                    options.push(fragment_id);
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+use maybe_fastest_fuzzer::{affinity, debug, dot, error, info, warn, GeneratorState, GrammarRust};
+use maybe_fastest_fuzzer::bench;
+use maybe_fastest_fuzzer::broker::{self, BrokerClient};
+use maybe_fastest_fuzzer::cmplog::CmpLog;
+use maybe_fastest_fuzzer::compare;
+use maybe_fastest_fuzzer::compress;
+use maybe_fastest_fuzzer::config;
+use maybe_fastest_fuzzer::corpus::path_hash;
+use maybe_fastest_fuzzer::coverage::MAP_SIZE;
+use maybe_fastest_fuzzer::dashboard::{Dashboard, Sample};
+use maybe_fastest_fuzzer::dedup::Dedup;
+use maybe_fastest_fuzzer::derivation_cache::{self, CachePolicy};
+use maybe_fastest_fuzzer::determinism::Manifest;
+use maybe_fastest_fuzzer::exec_cache::{self, ExecCache};
+use maybe_fastest_fuzzer::feedback::FeedbackSpec;
+use maybe_fastest_fuzzer::findings;
+use maybe_fastest_fuzzer::fuzzer::{self, Shared, TargetBuilds, WorkerConfig};
+use maybe_fastest_fuzzer::export::{self, Format};
+use maybe_fastest_fuzzer::executor::{
+    Executor, NetworkExecutor, ProcessExecutor};
+use maybe_fastest_fuzzer::executor::session::Script;
+#[cfg(unix)]
+use maybe_fastest_fuzzer::executor::{
+    DlopenExecutor, FridaExecutor, FridaPersistent, Limits, QemuExecutor};
+#[cfg(target_os = "linux")]
+use maybe_fastest_fuzzer::executor::IntelPtExecutor;
+use maybe_fastest_fuzzer::golden;
+use maybe_fastest_fuzzer::grammar::{Grammar, Strategy, DEFAULT_NODE_BUDGET, MAX_OUTPUT_SIZE};
+use maybe_fastest_fuzzer::histogram::Histograms;
+use maybe_fastest_fuzzer::import;
+use maybe_fastest_fuzzer::llvm_cov;
+use maybe_fastest_fuzzer::loader::{self, DuplicatePolicy};
+use maybe_fastest_fuzzer::log::{self, Level};
+use maybe_fastest_fuzzer::mutator::Scheduler;
+use maybe_fastest_fuzzer::oracle::{Oracle, OracleSpec};
+use maybe_fastest_fuzzer::orchestrator::Orchestrator;
+use maybe_fastest_fuzzer::output::{self, AflOutputDir, StatsSnapshot};
+use maybe_fastest_fuzzer::perturb::{self, Outcome};
+use maybe_fastest_fuzzer::plot;
+use maybe_fastest_fuzzer::positions::{self, Position};
+use maybe_fastest_fuzzer::profile;
+use maybe_fastest_fuzzer::record::{self, Mode};
+use maybe_fastest_fuzzer::repl::Repl;
+use maybe_fastest_fuzzer::replay;
+use maybe_fastest_fuzzer::report::{CampaignReport, StopReason};
+use maybe_fastest_fuzzer::roles::{self, Role, WorkerGroup};
+use maybe_fastest_fuzzer::rng::SplitSeed;
+use maybe_fastest_fuzzer::rule_stats::RuleStats;
+use maybe_fastest_fuzzer::schema::{self, Parsing};
+use maybe_fastest_fuzzer::selftest;
+use maybe_fastest_fuzzer::signals;
+use maybe_fastest_fuzzer::sink::SinkSpec;
+use maybe_fastest_fuzzer::storage;
+use maybe_fastest_fuzzer::symcc::{self, SymCc};
+use maybe_fastest_fuzzer::temperature::Schedule;
+use maybe_fastest_fuzzer::template;
+use maybe_fastest_fuzzer::throttle::{DutyCycle, Rate, Throttle};
+use maybe_fastest_fuzzer::tokens::{self, Encoding};
+use maybe_fastest_fuzzer::tree::{Tree, TreeStack};
+use maybe_fastest_fuzzer::trim;
+use maybe_fastest_fuzzer::unicode;
+use maybe_fastest_fuzzer::validate::{self, ValidateOptions};
+use maybe_fastest_fuzzer::workspace::Workspace;
+
+// What to do, the first argument picks a subcommand, fuzzing by default
+#[derive(PartialEq)]
+enum Command {
+    Fuzz,
+    // dump the compiled grammar as graphviz
+    Graph,
+    // rebuild a test case from its choice sequence
+    Derive,
+    // check a grammar without fuzzing
+    Validate,
+    // print test cases as source code literals
+    Export,
+    // source coverage of the corpus in a coverage build of the target
+    Cover,
+    // shrink the queue entries of an instance, see trim.rs
+    Trim,
+    // run samples through an external validator, see selftest.rs
+    Selftest,
+    // chart campaign stats, see plot.rs
+    Plot,
+    // A/B statistics of two sets of campaigns, see compare.rs
+    CompareRuns,
+    // compare with other generators, see bench.rs
+    BenchCompare,
+    // expand rules step by step, see repl.rs
+    Repl,
+    // check broken grammars do not crash us, see perturb.rs
+    MutateGrammar,
+    // compare generated inputs with a manifest, see determinism.rs
+    VerifyDeterminism,
+    // regression suite picked from a seed, see golden.rs
+    Golden,
+    // check saved crashes still crash, see replay.rs
+    Replay,
+    // crashes and hangs as SARIF or json, see findings.rs
+    Findings,
+    // migrate a grammar file to the current format, see schema.rs
+    Upgrade,
+}
+
+// Everything configurable from the command line
+struct Options {
+    command: Command,
+    grammar_path: String,
+    // more grammar files combined with the main one
+    includes: Vec<String>,
+    // files with {{<rule>}} holes the test cases are made from, see
+    // template.rs
+    templates: Vec<PathBuf>,
+    duplicates: DuplicatePolicy,
+    // refuse grammar files of other versions of the format, see schema.rs
+    parsing: Parsing,
+    max_nodes: usize,
+    // output size at which generation stops, see GrammarRust::set_max_output()
+    max_size: usize,
+    strategy: Strategy,
+    // probability of corrupting an emitted terminal, see
+    // GrammarRust::set_corruption()
+    corrupt: f64,
+    // generate token identifiers instead of text, see tokens.rs
+    token_stream: Option<Encoding>,
+    // what the expensive rules of a test case may cost together, see
+    // GrammarRust::set_cost_budget()
+    cost_budget: Option<u64>,
+
+    // -1 for --quiet, +1 per --verbose
+    verbosity: i32,
+    // log json lines instead of text
+    log_json: bool,
+
+    // campaign seed, every worker gets its own stream of it
+    seed: Option<u64>,
+
+    // print the derivation of this many test cases instead of fuzzing
+    trace: Option<usize>,
+    // time the parts of generation instead of fuzzing, see profile.rs.
+    // Not in the usage, it is for work on the generator
+    profile_internals: bool,
+
+    // hand test cases to a sink instead of fuzzing, see sink.rs
+    emit: Option<SinkSpec>,
+
+    // afl style sync dir and our instance name in it
+    out_dir: PathBuf,
+    instance: String,
+    // -M instance, -M or -S enable syncing with the other instances
+    main_node: bool,
+    sync_instances: bool,
+
+    // probability of a havoc stage per input
+    havoc: f64,
+
+    // generation temperature, <start>[:<end>] annealed over anneal (the
+    // time limit, or an hour), see temperature.rs
+    temperature: Option<String>,
+    anneal: Option<Duration>,
+
+    // most mutators stacked on one corpus input, and whether to pick them
+    // by recent success
+    mutation_stack: usize,
+    adaptive_mutators: bool,
+    // move the output size limit below --max-size, see length.rs
+    adaptive_size: bool,
+    // copy cached expansions of small rules, see derivation_cache.rs
+    derivation_cache: Option<CachePolicy>,
+
+    // only ever send valid UTF-8 to the target
+    utf8: bool,
+
+    // run inputs the campaign already ran again, see dedup.rs
+    no_dedup: bool,
+
+    // reuse the results of inputs that ran before, entries kept, see
+    // exec_cache.rs
+    exec_cache: Option<usize>,
+
+    // cut the corpus down past this many entries, see corpus.rs
+    cull: Option<usize>,
+
+    // store saved entries zstd compressed, see compress.rs
+    compress: bool,
+
+    // generate into the target's input file mapped to memory, see mmap.rs
+    mmap_output: bool,
+
+    // fixed bytes before and after every generated input
+    prefix: Vec<u8>,
+    suffix: Vec<u8>,
+
+    // seed files run before fuzzing, see import.rs
+    import_dirs: Vec<PathBuf>,
+    // AFL++ or libFuzzer corpora parsed into seeds
+    import_corpora: Vec<PathBuf>,
+
+    // record every worker's decisions to logs in this dir, or replay them,
+    // see record.rs
+    record: Option<(Mode, PathBuf)>,
+
+    // violations per error injected input, and the share of such inputs
+    inject: usize,
+    inject_rate: f64,
+
+    // chase pairs of productions as well as coverage
+    pairwise: bool,
+
+    // exit criteria, a campaign with any of them exits with status 1
+    // if it found a crash
+    max_time: Option<Duration>,
+    max_execs: Option<u64>,
+    stop_on_crash: bool,
+
+    // execution rate cap, share of every duty period to run
+    rate: Option<Rate>,
+    duty: Option<f64>,
+    duty_period: Duration,
+
+    // number of worker threads, each with its own target instance, and
+    // the groups of them with roles of their own (see roles.rs)
+    jobs: usize,
+    workers: Vec<WorkerGroup>,
+
+    // pin every worker (and its targets) to its own core
+    bind_cores: bool,
+
+    // fuzzer processes to run and supervise, see orchestrator.rs, and the
+    // flags to start them with
+    processes: usize,
+    fuzz_args: Vec<String>,
+
+    // serve the web dashboard here
+    dashboard: Option<String>,
+
+    // run as broker listening here instead of fuzzing
+    broker: Option<String>,
+    broker_dir: PathBuf,
+
+    // sync findings with a broker every sync_interval
+    sync_to: Option<String>,
+    sync_interval: Duration,
+
+    // keep the corpus here too, pushing every storage_interval, see
+    // storage.rs
+    storage: Option<String>,
+    storage_endpoint: Option<String>,
+    storage_interval: Duration,
+
+    // network target
+    net_addr: Option<String>,
+    net_timeout: Option<Duration>,
+    net_probe: Option<String>,
+    net_server: Option<Vec<String>>,
+    net_session: Option<PathBuf>,
+
+    // shared library target called in-process or in forked children, see
+    // executor/dlopen.rs
+    dlopen: Option<PathBuf>,
+    dlopen_symbol: String,
+    dlopen_fork: bool,
+
+    // process target
+    target: Vec<String>,
+    timeout: Duration,
+
+    // coverage backends
+    // input delivery through shared memory, with the largest input
+    shm_input: Option<usize>,
+    // run the target confined, see executor/sandbox.rs
+    sandbox: bool,
+    // parse ASAN/UBSAN reports from stderr of the target
+    sanitizer: bool,
+    // parts of the input going to argv/environment/files, with their
+    // grammars, and the named outputs of the grammar (see positions.rs)
+    positions: Vec<(Position, PathBuf)>,
+    outputs: Vec<String>,
+    // comparison logging and concolic builds of the target, see cmplog.rs
+    // and symcc.rs
+    cmplog: Option<String>,
+    symcc: Option<String>,
+    // validity filter inputs have to pass before the target gets them
+    filter: Option<Vec<String>>,
+
+    // record depth, node count and size of executed inputs
+    histograms: bool,
+    // per rule statistics in the campaign report
+    rule_stats: bool,
+    // reasons to keep inputs besides coverage, see feedback.rs
+    feedbacks: Vec<FeedbackSpec>,
+    // bugs besides crashes, see oracle.rs
+    oracles: Vec<OracleSpec>,
+    #[cfg(unix)]
+    limits: Limits,
+    // memory limit of the target's job object, bytes
+    #[cfg(windows)]
+    memory_limit: Option<usize>,
+
+    qemu: bool,
+    frida: bool,
+    frida_persistent: Option<String>,
+    frida_persistent_cnt: Option<u32>,
+    frida_persistent_hook: Option<PathBuf>,
+    intel_pt: bool,
+
+    // graph: render to svg with dot instead of printing dot source
+    svg: bool,
+
+    // derive: choice sequence file to replay
+    choices: Option<PathBuf>,
+
+    // verify-determinism: manifest to compare with, and whether to write
+    // it instead
+    manifest: Option<PathBuf>,
+    update: bool,
+
+    // export: literal syntax, number of samples to generate, or files to
+    // export instead
+    format: Format,
+    count: usize,
+    inputs: Vec<PathBuf>,
+
+    // go on with a grammar other than the one choices / a resumed
+    // campaign were made with
+    ignore_fingerprint: bool,
+
+    // cover: where to put the coverage report, <sync dir>/coverage by
+    // default. plot: where to put the charts, the current directory by
+    // default
+    report_dir: Option<PathBuf>,
+    // plot: PNG instead of SVG
+    png: bool,
+
+    // compare-runs: the runs of either side
+    baseline: Vec<PathBuf>,
+    candidate: Vec<PathBuf>,
+
+    // replay: times every crash runs (the crashes are in inputs)
+    runs: u32,
+    // findings: plain json instead of SARIF
+    json: bool,
+
+    // validate, selftest, bench-compare: samples to generate. validate: the
+    // length they should stay under
+    samples: usize,
+    max_len: usize,
+
+    // selftest: rejected samples to show
+    examples: usize,
+
+    // upgrade: the version of a file that does not state it, and whether
+    // to rewrite the file instead of printing the upgraded grammar
+    from: Option<u32>,
+    in_place: bool,
+
+    // bench-compare: reference generators, name and command line
+    references: Vec<(String, Vec<String>)>,
+}
+
+fn usage() -> ! {
+    eprintln!("usage: maybe_fastest_fuzzer [grammar.json] [--max-nodes <n>] [--seed <n>]
+    [--include <grammar.json>...] [--duplicates merge|warn|error] [--strict-grammar]
+    [--template <file with {{<rule>}} holes>...]
+    [--strategy uniform|rare|markov] [--pairwise] [--corrupt <probability>]
+    [--token-stream u8|u16le|u16be|u32le|u32be|text] [--cost-budget <n>]
+    [--max-time <secs>] [--max-execs <n>] [--stop-on-first-crash]
+    [--rate <execs/s | bytes/s with B suffix, k/M/G scale>]
+    [--duty <fraction> [--duty-period <secs>]]
+    [--trace <count>] [--config <campaign.toml>]
+    [--emit-stdout | --emit-dir <dir> | --emit-socket <host:port>
+     | --emit-shm <max bytes>]
+    [--ignore-fingerprint]
+    [--quiet | -v...] [--log-json]
+    [-o <sync dir>] [-M <main name> | -S <secondary name>]
+    [--jobs <n>] [--bind-cores] [--processes <n>] [--havoc <probability>]
+    [--worker generate|tree|havoc|mixed[,jobs=<n>][,max-nodes=<n>][,havoc=<p>]...]...
+    [--mutation-stack <n>] [--adaptive-mutators]
+    [--temperature <t> | <start>:<end> [--anneal <secs>]]
+    [--utf8] [--no-dedup] [--exec-cache [--exec-cache-entries <n>]] [--cull <entries>]
+    [--compress] [--dashboard <listen addr>]
+    [--max-size <bytes> [--adaptive-size]] [--mmap-output] [--prefix <bytes>] [--suffix <bytes>]
+    [--derivation-cache <reuse probability>[,<entries per rule>]]
+    [--record <dir> | --replay-record <dir>]
+    [--inject <violations> [--inject-rate <probability>]]
+    [--sync-to <host:port> [--sync-interval <secs>]]
+    [--storage <s3://bucket/prefix | gs://bucket/prefix>
+     [--storage-endpoint <url>] [--storage-interval <secs>]]
+    [--net <host:port> [--net-timeout <ms>] [--net-probe <shell cmd>]
+     [--net-server <cmd line>] [--net-session <script.json>]]
+    [--dlopen <library.so> [--dlopen-symbol <name>] [--dlopen-fork]]
+    [--timeout <ms>] [--shm-input [--shm-input-max <bytes>]]
+    [--sandbox] [--sanitizer] [--cmplog <cmplog build of the target>]
+    [--symcc <SymCC build of the target>] [--filter <validator cmd line>]
+    [--histograms] [--rule-stats] [--import-dir <seed dir>]...
+    [--import-corpus <AFL++ output dir | libFuzzer corpus dir>]...
+    [--position arg:<index>=<grammar.json> | env:<name>=<grammar.json>
+     | file:<name>=<grammar.json>]... [--output <name, @@name in cmd line>]...
+    [--limit-mem <MB>] [--limit-cpu <secs>]
+    [--feedback output:<pattern> | exit-status | response-time:<ms>]...
+    [--oracle output:<pattern> | stderr:<pattern> | exit-code:<code>,...
+     | differential:<golden build of the target>]...
+    [--limit-fsize <MB>] [--limit-nofile <n>] [--qemu | --frida [--frida-persistent <addr>
+     [--frida-persistent-cnt <n>] [--frida-persistent-hook <lib>]]
+     | --intel-pt]
+    [-- <target cmd line, @@ for input file>]
+       maybe_fastest_fuzzer graph [grammar.json] [--svg]
+       maybe_fastest_fuzzer derive [grammar.json] --choices <file>
+    [--ignore-fingerprint]
+       maybe_fastest_fuzzer export [grammar.json] [--format c|rust|python]
+    [--count <n> | --input <file>...]
+       maybe_fastest_fuzzer cover [-o <sync dir>] [--report-dir <dir>]
+    [--position ...]... -- <coverage build cmd line>
+       maybe_fastest_fuzzer trim [grammar.json] [-o <sync dir>]
+    [-M <name> | -S <name>] [--ignore-fingerprint] [coverage backend]
+    [--position ...]... -- <target cmd line>
+       maybe_fastest_fuzzer replay <crash file or dir>... [--runs <n>]
+    [--timeout <ms>] [--position ...]... -- <target cmd line>
+       maybe_fastest_fuzzer findings [-o <sync dir>] [-M <name> | -S <name>] [--json]
+       maybe_fastest_fuzzer upgrade [grammar.json] [--from <version>] [--in-place]
+       maybe_fastest_fuzzer validate [grammar.json] [--samples <n>]
+    [--max-len <bytes>] [--cost-budget <n>]
+       maybe_fastest_fuzzer plot [-o <sync dir>] [-M <name> | -S <name>]
+    [--input <stats.csv>...] [--report-dir <dir>] [--png]
+       maybe_fastest_fuzzer compare-runs --baseline <instance dir | sync dir | stats.csv>...
+    --candidate <instance dir | sync dir | stats.csv>...
+       maybe_fastest_fuzzer selftest [grammar.json] [--samples <n>]
+    [--examples <n>] [--timeout <ms>] -- <validator cmd line>
+       maybe_fastest_fuzzer repl [grammar.json] [--seed <n>] [--max-nodes <n>]
+       maybe_fastest_fuzzer mutate-grammar [grammar.json] [--count <mutants>]
+    [--samples <n>] [--timeout <ms>] [-o <dir for failing mutants>]
+       maybe_fastest_fuzzer verify-determinism [grammar.json] --manifest <file>
+    [--seed <n>] [--count <inputs>] [--update]
+       maybe_fastest_fuzzer golden [grammar.json] --seed <n> [--count <inputs>]
+    [-o <dir>]
+       maybe_fastest_fuzzer bench-compare [grammar.json] [--samples <n>]
+    [--reference <name>=<cmd line, @@ output dir, ## count>]...
+    [--timeout <ms>] [-- <validator cmd line>]
+       maybe_fastest_fuzzer --broker <listen addr> [--broker-dir <dir>]");
+    std::process::exit(1);
+}
+
+fn parse_args() -> Options {
+    let mut opts = Options {
+        command: Command::Fuzz,
+        grammar_path: String::from("test.json"),
+        includes: Vec::new(),
+        templates: Vec::new(),
+        duplicates: DuplicatePolicy::Warn,
+        parsing: Parsing::Lenient,
+        max_nodes: DEFAULT_NODE_BUDGET,
+        max_size: MAX_OUTPUT_SIZE,
+        strategy: Strategy::Uniform,
+        corrupt: 0.0,
+        token_stream: None,
+        cost_budget: None,
+        verbosity: 0,
+        log_json: false,
+        seed: None,
+        trace: None,
+        profile_internals: false,
+        emit: None,
+        out_dir: PathBuf::from("output"),
+        instance: String::from("default"),
+        main_node: false,
+        sync_instances: false,
+        havoc: 0.0,
+        temperature: None,
+        anneal: None,
+        mutation_stack: 1,
+        adaptive_mutators: false,
+        adaptive_size: false,
+        derivation_cache: None,
+        utf8: false,
+        no_dedup: false,
+        exec_cache: None,
+        cull: None,
+        compress: false,
+        mmap_output: false,
+        prefix: Vec::new(),
+        suffix: Vec::new(),
+        import_dirs: Vec::new(),
+        import_corpora: Vec::new(),
+        record: None,
+        inject: 0,
+        inject_rate: 0.1,
+        pairwise: false,
+        max_time: None,
+        max_execs: None,
+        stop_on_crash: false,
+        rate: None,
+        duty: None,
+        duty_period: Duration::from_secs(60),
+        jobs: 1,
+        workers: Vec::new(),
+        bind_cores: false,
+        processes: 1,
+        fuzz_args: Vec::new(),
+        dashboard: None,
+        broker: None,
+        broker_dir: PathBuf::from("broker"),
+        sync_to: None,
+        sync_interval: Duration::from_secs(30),
+        storage: None,
+        storage_endpoint: None,
+        storage_interval: Duration::from_secs(300),
+        net_addr: None,
+        net_timeout: None,
+        net_probe: None,
+        net_server: None,
+        net_session: None,
+        dlopen: None,
+        dlopen_symbol: String::from("fuzz_one"),
+        dlopen_fork: false,
+        target: Vec::new(),
+        timeout: Duration::from_millis(1000),
+        shm_input: None,
+        sandbox: false,
+        sanitizer: false,
+        positions: Vec::new(),
+        outputs: Vec::new(),
+        cmplog: None,
+        symcc: None,
+        filter: None,
+        histograms: false,
+        rule_stats: false,
+        feedbacks: Vec::new(),
+        oracles: Vec::new(),
+        #[cfg(unix)]
+        limits: Limits::default(),
+        #[cfg(windows)]
+        memory_limit: None,
+        qemu: false,
+        frida: false,
+        frida_persistent: None,
+        frida_persistent_cnt: None,
+        frida_persistent_hook: None,
+        intel_pt: false,
+        svg: false,
+        choices: None,
+        manifest: None,
+        update: false,
+        ignore_fingerprint: false,
+        format: Format::C,
+        count: 10,
+        inputs: Vec::new(),
+        report_dir: None,
+        png: false,
+        baseline: Vec::new(),
+        candidate: Vec::new(),
+        runs: replay::DEFAULT_RUNS,
+        json: false,
+        references: Vec::new(),
+        samples: 1000,
+        max_len: 0,
+        examples: 5,
+        from: None,
+        in_place: false,
+    };
+
+    let mut cli = std::env::args().skip(1).collect::<Vec<_>>();
+    opts.command = match cli.first().map(String::as_str) {
+        Some("graph") => Command::Graph,
+        Some("derive") => Command::Derive,
+        Some("validate") => Command::Validate,
+        Some("export") => Command::Export,
+        Some("cover") => Command::Cover,
+        Some("trim") => Command::Trim,
+        Some("selftest") => Command::Selftest,
+        Some("plot") => Command::Plot,
+        Some("compare-runs") => Command::CompareRuns,
+        Some("bench-compare") => Command::BenchCompare,
+        Some("repl") => Command::Repl,
+        Some("mutate-grammar") => Command::MutateGrammar,
+        Some("verify-determinism") => Command::VerifyDeterminism,
+        Some("golden") => Command::Golden,
+        Some("replay") => Command::Replay,
+        Some("findings") => Command::Findings,
+        Some("upgrade") => Command::Upgrade,
+        _ => Command::Fuzz,
+    };
+    if opts.command != Command::Fuzz {
+        cli.remove(0);
+    }
+
+    // the config file goes first so flags on the command line override it
+    let options_end = cli.iter().position(|x| x == "--").unwrap_or(cli.len());
+    let mut config_target = Vec::new();
+    let mut args = Vec::new();
+    if let Some(pos) = cli[..options_end].iter().position(|x| x == "--config") {
+        let path = cli.get(pos + 1).cloned().unwrap_or_else(|| usage());
+        cli.drain(pos..pos + 2);
+        let config = std::fs::read_to_string(&path).and_then(|x| config::to_args(&x));
+        (args, config_target) = config.unwrap_or_else(|e| {
+            error!("campaign", "{}: {}", path, e);
+            std::process::exit(1);
+        });
+    }
+    args.extend(cli);
+    opts.fuzz_args = args[..args.iter().position(|x| x == "--")
+        .unwrap_or(args.len())].to_vec();
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--max-nodes" => {
+                opts.max_nodes = value().parse().unwrap_or_else(|_| usage());
+            }
+            "--max-size" => {
+                opts.max_size = value().parse().ok().filter(|&x| x > 0)
+                    .unwrap_or_else(|| usage());
+            }
+            "--quiet" | "-q" => opts.verbosity = -1,
+            "--verbose" | "-v" => opts.verbosity = opts.verbosity.max(0) + 1,
+            "--log-json" => opts.log_json = true,
+            "--include" => opts.includes.push(value()),
+            "--template" => opts.templates.push(value().into()),
+            "--duplicates" => {
+                opts.duplicates = match value().as_str() {
+                    "merge" => DuplicatePolicy::Merge,
+                    "warn" => DuplicatePolicy::Warn,
+                    "error" => DuplicatePolicy::Error,
+                    _ => usage(),
+                };
+            }
+            "--strict-grammar" => opts.parsing = Parsing::Strict,
+            "--from" => {
+                opts.from = Some(value().parse().ok().filter(|&x| x > 0)
+                    .unwrap_or_else(|| usage()));
+            }
+            "--in-place" => opts.in_place = true,
+            "--strategy" => {
+                opts.strategy = match value().as_str() {
+                    "uniform" => Strategy::Uniform,
+                    "rare" => Strategy::RareBoost,
+                    "markov" => Strategy::Markov,
+                    _ => usage(),
+                };
+            }
+            "--corrupt" => {
+                opts.corrupt = value().parse().ok()
+                    .filter(|x| (0.0..=1.0).contains(x))
+                    .unwrap_or_else(|| usage());
+            }
+            "--cost-budget" => {
+                opts.cost_budget = Some(value().parse().unwrap_or_else(|_| usage()));
+            }
+            "--token-stream" => {
+                opts.token_stream = Some(Encoding::parse(&value())
+                    .unwrap_or_else(|| usage()));
+            }
+            "--seed" => {
+                opts.seed = Some(value().parse().unwrap_or_else(|_| usage()));
+            }
+            "--emit-stdout" => opts.emit = Some(SinkSpec::Stdout),
+            "--emit-dir" => opts.emit = Some(SinkSpec::Dir(value().into())),
+            "--emit-socket" => opts.emit = Some(SinkSpec::Socket(value())),
+            "--emit-shm" => opts.emit = Some(SinkSpec::Shm(
+                value().parse().ok().filter(|&x| x > 0).unwrap_or_else(|| usage()))),
+            "--profile-internals" => opts.profile_internals = true,
+            "--trace" => {
+                opts.trace = Some(value().parse().unwrap_or_else(|_| usage()));
+            }
+            "-o" => opts.out_dir = value().into(),
+            "-M" | "-S" => {
+                opts.main_node = arg == "-M";
+                opts.sync_instances = true;
+                opts.instance = value();
+            }
+            "--jobs" => {
+                opts.jobs = value().parse().unwrap_or_else(|_| usage());
+                if opts.jobs == 0 {
+                    usage();
+                }
+            }
+            "--worker" => {
+                let group = WorkerGroup::parse(&value()).unwrap_or_else(|e| {
+                    error!("campaign", "--worker {}", e);
+                    std::process::exit(1);
+                });
+                opts.workers.push(group);
+            }
+            "--bind-cores" => opts.bind_cores = true,
+            "--processes" => {
+                opts.processes = value().parse().ok()
+                    .filter(|&x| x > 0).unwrap_or_else(|| usage());
+            }
+            "--max-time" => {
+                opts.max_time = Some(Duration::from_secs(
+                    value().parse().unwrap_or_else(|_| usage())));
+            }
+            "--max-execs" => {
+                opts.max_execs = Some(value().parse().unwrap_or_else(|_| usage()));
+            }
+            "--stop-on-first-crash" => opts.stop_on_crash = true,
+            "--rate" => {
+                opts.rate = Some(Rate::parse(&value()).unwrap_or_else(|| usage()));
+            }
+            "--duty" => {
+                opts.duty = Some(value().parse().ok()
+                    .filter(|x| (0.0..=1.0).contains(x) && *x > 0.0)
+                    .unwrap_or_else(|| usage()));
+            }
+            "--duty-period" => {
+                opts.duty_period = Duration::from_secs(value().parse().ok()
+                    .filter(|&x| x > 0).unwrap_or_else(|| usage()));
+            }
+            "--havoc" => {
+                opts.havoc = value().parse().unwrap_or_else(|_| usage());
+                if !(0.0..=1.0).contains(&opts.havoc) {
+                    usage();
+                }
+            }
+            "--temperature" => opts.temperature = Some(value()),
+            "--anneal" => {
+                opts.anneal = Some(Duration::from_secs(value().parse().ok()
+                    .filter(|&x| x > 0).unwrap_or_else(|| usage())));
+            }
+            "--mutation-stack" => {
+                opts.mutation_stack = value().parse().ok()
+                    .filter(|&x| x > 0).unwrap_or_else(|| usage());
+            }
+            "--adaptive-mutators" => opts.adaptive_mutators = true,
+            "--adaptive-size" => opts.adaptive_size = true,
+            "--derivation-cache" => {
+                let value = value();
+                let (reuse, entries) = value.split_once(',').map_or((value.as_str(), None),
+                    |(reuse, entries)| (reuse, Some(entries)));
+                let reuse = reuse.parse().ok().filter(|x| (0.0..=1.0).contains(x));
+                let entries = entries.map_or(Some(derivation_cache::DEFAULT_ENTRIES),
+                    |x| x.parse().ok().filter(|&x| x > 0));
+                let (Some(reuse), Some(entries)) = (reuse, entries) else {
+                    usage();
+                };
+                opts.derivation_cache = Some(CachePolicy { reuse, entries });
+            }
+            "--utf8" => opts.utf8 = true,
+            "--no-dedup" => opts.no_dedup = true,
+            "--exec-cache" => {
+                opts.exec_cache.get_or_insert(exec_cache::DEFAULT_ENTRIES);
+            }
+            "--exec-cache-entries" => {
+                opts.exec_cache = Some(value().parse().ok().filter(|&x| x > 0)
+                    .unwrap_or_else(|| usage()));
+            }
+            "--cull" => {
+                opts.cull = Some(value().parse().ok().filter(|&x| x > 0)
+                    .unwrap_or_else(|| usage()));
+            }
+            "--compress" => opts.compress = true,
+            "--mmap-output" => opts.mmap_output = true,
+            "--prefix" | "--suffix" => {
+                let bytes = export::unescape(&value()).unwrap_or_else(|e| {
+                    error!("campaign", "{}: {}", arg, e);
+                    std::process::exit(1);
+                });
+                match arg.as_str() {
+                    "--prefix" => opts.prefix = bytes,
+                    _ => opts.suffix = bytes,
+                }
+            }
+            "--import-dir" => opts.import_dirs.push(value().into()),
+            "--import-corpus" => opts.import_corpora.push(value().into()),
+            "--record" => opts.record = Some((Mode::Record, value().into())),
+            "--replay-record" => opts.record = Some((Mode::Replay, value().into())),
+            "--inject" => {
+                opts.inject = value().parse().unwrap_or_else(|_| usage());
+            }
+            "--inject-rate" => {
+                opts.inject_rate = value().parse().unwrap_or_else(|_| usage());
+                if !(0.0..=1.0).contains(&opts.inject_rate) {
+                    usage();
                 }
-                // Allocate a new fragment for all the options
-                // List of Options - Vec<String>
-                expressions.push(
-                    ret.allocate_fragment(Fragment::Expression(options)));
             }
+            "--pairwise" => opts.pairwise = true,
+            "--dashboard" => opts.dashboard = Some(value()),
+            "--broker" => opts.broker = Some(value()),
+            "--broker-dir" => opts.broker_dir = value().into(),
+            "--sync-to" => opts.sync_to = Some(value()),
+            "--sync-interval" => {
+                opts.sync_interval = Duration::from_secs(
+                    value().parse().unwrap_or_else(|_| usage()));
+            }
+            "--storage" => opts.storage = Some(value()),
+            "--storage-endpoint" => opts.storage_endpoint = Some(value()),
+            "--storage-interval" => {
+                opts.storage_interval = Duration::from_secs(
+                    value().parse().unwrap_or_else(|_| usage()));
+            }
+            "--net" => opts.net_addr = Some(value()),
+            "--net-timeout" => {
+                opts.net_timeout = Some(Duration::from_millis(
+                    value().parse().unwrap_or_else(|_| usage())));
+            }
+            "--net-probe" => opts.net_probe = Some(value()),
+            "--net-server" => {
+                opts.net_server = Some(value().split_whitespace()
+                    .map(String::from).collect::<Vec<_>>());
+            }
+            "--net-session" => opts.net_session = Some(PathBuf::from(value())),
+            "--dlopen" => opts.dlopen = Some(PathBuf::from(value())),
+            "--dlopen-symbol" => opts.dlopen_symbol = value(),
+            "--dlopen-fork" => opts.dlopen_fork = true,
+            "--timeout" => {
+                opts.timeout = Duration::from_millis(
+                    value().parse().unwrap_or_else(|_| usage()));
+            }
+            "--shm-input" => {
+                opts.shm_input.get_or_insert(MAX_OUTPUT_SIZE);
+            }
+            "--shm-input-max" => {
+                opts.shm_input = Some(value().parse().ok().filter(|&x| x > 0)
+                    .unwrap_or_else(|| usage()));
+            }
+            "--sandbox" => opts.sandbox = true,
+            "--sanitizer" => opts.sanitizer = true,
+            "--position" => {
+                let value = value();
+                let (position, grammar) = value.split_once('=')
+                    .unwrap_or_else(|| usage());
+                let position = Position::parse(position).unwrap_or_else(|| usage());
+                opts.positions.push((position, grammar.into()));
+            }
+            "--output" => {
+                let name = value();
+                if !positions::is_file_name(&name) || opts.outputs.contains(&name) {
+                    usage();
+                }
+                opts.outputs.push(name);
+            }
+            "--cmplog" => opts.cmplog = Some(value()),
+            "--symcc" => opts.symcc = Some(value()),
+            "--reference" => {
+                let value = value();
+                let (name, argv) = value.split_once('=')
+                    .filter(|x| !x.0.is_empty() && !x.1.trim().is_empty())
+                    .unwrap_or_else(|| usage());
+                opts.references.push((name.to_string(), argv.split_whitespace()
+                    .map(String::from).collect()));
+            }
+            "--histograms" => opts.histograms = true,
+            "--rule-stats" => opts.rule_stats = true,
+            "--filter" => {
+                opts.filter = Some(value().split_whitespace()
+                    .map(String::from).collect::<Vec<_>>());
+            }
+            "--feedback" => {
+                let spec = FeedbackSpec::parse(&value()).unwrap_or_else(|e| {
+                    error!("campaign", "--feedback {}", e);
+                    std::process::exit(1);
+                });
+                opts.feedbacks.push(spec);
+            }
+            "--oracle" => {
+                let spec = OracleSpec::parse(&value()).unwrap_or_else(|e| {
+                    error!("campaign", "--oracle {}", e);
+                    std::process::exit(1);
+                });
+                opts.oracles.push(spec);
+            }
+            #[cfg(unix)]
+            "--limit-mem" => opts.limits.memory = Some(
+                value().parse::<u64>().unwrap_or_else(|_| usage()) << 20),
+            #[cfg(windows)]
+            "--limit-mem" => opts.memory_limit = Some(
+                value().parse::<usize>().unwrap_or_else(|_| usage()) << 20),
+            #[cfg(unix)]
+            "--limit-cpu" => opts.limits.cpu_time = Some(
+                value().parse().unwrap_or_else(|_| usage())),
+            #[cfg(unix)]
+            "--limit-fsize" => opts.limits.file_size = Some(
+                value().parse::<u64>().unwrap_or_else(|_| usage()) << 20),
+            #[cfg(unix)]
+            "--limit-nofile" => opts.limits.open_files = Some(
+                value().parse().unwrap_or_else(|_| usage())),
+            "--qemu" => opts.qemu = true,
+            "--frida" => opts.frida = true,
+            "--frida-persistent" => opts.frida_persistent = Some(value()),
+            "--frida-persistent-cnt" => {
+                opts.frida_persistent_cnt =
+                    Some(value().parse().unwrap_or_else(|_| usage()));
+            }
+            "--frida-persistent-hook" => {
+                opts.frida_persistent_hook = Some(value().into());
+            }
+            "--intel-pt" => opts.intel_pt = true,
+            "--svg" => opts.svg = true,
+            "--choices" => opts.choices = Some(value().into()),
+            "--manifest" => opts.manifest = Some(value().into()),
+            "--update" => opts.update = true,
+            "--ignore-fingerprint" => opts.ignore_fingerprint = true,
+            "--format" => {
+                opts.format = Format::parse(&value()).unwrap_or_else(|| usage());
+            }
+            "--count" => opts.count = value().parse().unwrap_or_else(|_| usage()),
+            "--examples" => opts.examples = value().parse().unwrap_or_else(|_| usage()),
+            "--input" => opts.inputs.push(value().into()),
+            "--report-dir" => opts.report_dir = Some(value().into()),
+            "--png" => opts.png = true,
+            "--json" => opts.json = true,
+            "--baseline" => opts.baseline.push(value().into()),
+            "--candidate" => opts.candidate.push(value().into()),
+            "--runs" => {
+                opts.runs = value().parse().ok().filter(|&x| x > 0)
+                    .unwrap_or_else(|| usage());
+            }
+            "--samples" => {
+                opts.samples = value().parse().unwrap_or_else(|_| usage());
+            }
+            "--max-len" => {
+                opts.max_len = value().parse().unwrap_or_else(|_| usage());
+            }
+            "--" => {
+                opts.target.extend(args.by_ref());
+                break;
+            }
+            x if x.starts_with('-') => usage(),
+            _ if opts.command == Command::Replay => opts.inputs.push(arg.into()),
+            _ => opts.grammar_path = arg,
+        }
+    }
+    if opts.target.is_empty() {
+        opts.target = config_target;
+    }
+    opts.jobs = opts.jobs.max(opts.workers.iter().map(|x| x.jobs).sum());
+    opts
+}
+
+// Whether there is something to fuzz: a network target, a library or a
+// target command line
+fn has_target(opts: &Options) -> bool {
+    opts.net_addr.is_some() || opts.dlopen.is_some() || !opts.target.is_empty()
+}
+
+// Bail out when a backend is missing on this machine
+fn unavailable(what: &str) -> ! {
+    error!("campaign", "{}", what);
+    std::process::exit(1);
+}
+
+// Positions the executors split inputs into, the named outputs last like
+// positions::combine() puts them
+fn fuzzed_positions(opts: &Options) -> Vec<Position> {
+    opts.positions.iter().map(|x| x.0.clone())
+        .chain(opts.outputs.iter().map(|x| Position::File(x.clone())))
+        .collect()
+}
+
+// Other builds of the target (comparison logging, concolic), run with the
+// same arguments
+fn target_builds(opts: &Options) -> io::Result<TargetBuilds> {
+    let mut builds = TargetBuilds::default();
+    if let Some(argv) = &opts.filter {
+        builds.filter = Some(Box::new(ProcessExecutor::new(argv.clone(), opts.timeout)));
+    }
+    if opts.cmplog.is_none() && opts.symcc.is_none() {
+        return Ok(builds);
+    }
+    if opts.target.is_empty() {
+        unavailable("--cmplog and --symcc need a target command line");
+    }
+    #[cfg(unix)]
+    {
+        let executor = |binary: &String, timeout| {
+            let argv = std::iter::once(binary.clone())
+                .chain(opts.target[1..].iter().cloned())
+                .collect();
+            let mut executor = ProcessExecutor::new(argv, timeout);
+            executor.positions(fuzzed_positions(opts));
+            executor
+        };
+        if let Some(binary) = &opts.cmplog {
+            builds.cmplog = Some(CmpLog::new(executor(binary, opts.timeout))?);
+        }
+        if let Some(binary) = &opts.symcc {
+            builds.symcc = Some(SymCc::new(executor(binary,
+                opts.timeout * symcc::TIMEOUT_FACTOR))?);
+        }
+        Ok(builds)
+    }
+    #[cfg(not(unix))]
+    unavailable("--cmplog and --symcc are not supported on this platform");
+}
 
-            // get access to the fragment we want to change
-            let fragment = ret.lookup_fragment_mut(fragment_id);
+// Oracles of a worker. Differential oracles run their golden build with the
+// same arguments
+fn build_oracles(opts: &Options) -> io::Result<Vec<Box<dyn Oracle>>> {
+    opts.oracles.iter().map(|spec| spec.build(|binary| {
+        if opts.target.is_empty() {
+            unavailable("--oracle differential: needs a target command line");
+        }
+        #[cfg(unix)]
+        {
+            let argv = std::iter::once(binary.to_string())
+                .chain(opts.target[1..].iter().cloned())
+                .collect();
+            let mut golden = ProcessExecutor::new(argv, opts.timeout);
+            golden.positions(fuzzed_positions(opts));
+            golden.capture_output();
+            Ok(Box::new(golden) as Box<dyn Executor>)
+        }
+        #[cfg(not(unix))]
+        unavailable(&format!("--oracle differential:{} is not supported on this platform",
+            binary));
+    })).collect()
+}
+
+// Set up the executor selected on the command line, None when there is no
+// target to run
+fn build_executor(opts: &Options) -> io::Result<Option<Box<dyn Executor>>> {
+    if let Some(addr) = &opts.net_addr {
+        if !opts.positions.is_empty() || !opts.outputs.is_empty() {
+            unavailable("--position and --output need a target command line, not --net");
+        }
+        let mut executor = NetworkExecutor::new(addr)?;
+        if let Some(timeout) = opts.net_timeout {
+            executor = executor.response_timeout(timeout);
+        }
+        if let Some(probe) = &opts.net_probe {
+            executor = executor.probe(probe);
+        }
+        if let Some(server) = opts.net_server.clone() {
+            if server.is_empty() {
+                usage();
+            }
+            executor = executor.server(server)?;
+        }
+        if let Some(path) = &opts.net_session {
+            executor = executor.session(Script::load(path)?);
+        }
+        return Ok(Some(Box::new(executor)));
+    }
 
-            // Overwrite the terminal definition
-            // expressions - Vec<Vec<String>>
-            *fragment = Fragment::NonTerminal(expressions);
+    if let Some(library) = &opts.dlopen {
+        if !opts.positions.is_empty() || !opts.outputs.is_empty() {
+            unavailable("--position and --output need a target command line, not --dlopen");
         }
+        #[cfg(unix)]
+        return Ok(Some(Box::new(DlopenExecutor::new(library, &opts.dlopen_symbol,
+            opts.dlopen_fork, opts.timeout)?)));
+        #[cfg(not(unix))]
+        unavailable("--dlopen is not supported on this platform");
+    }
+
+    if opts.target.is_empty() {
+        return Ok(None);
+    }
 
-        // Resolve the start node
-        ret.start = Some(ret.name_to_fragment["<start>"]);
+    if opts.intel_pt {
+        // the Intel PT runner hands the target the whole input as a file,
+        // none of the process runner's extras
+        #[cfg(unix)]
+        let limited = opts.limits.memory.is_some() || opts.limits.cpu_time.is_some()
+            || opts.limits.file_size.is_some() || opts.limits.open_files.is_some();
+        #[cfg(windows)]
+        let limited = opts.memory_limit.is_some();
+        let unsupported = [
+            (!opts.positions.is_empty() || !opts.outputs.is_empty(), "--position and --output"),
+            (opts.shm_input.is_some(), "--shm-input"),
+            (opts.sanitizer, "--sanitizer"),
+            (opts.sandbox, "--sandbox"),
+            (limited, "--limit-*"),
+            (opts.feedbacks.iter().any(FeedbackSpec::needs_output)
+                || opts.oracles.iter().any(OracleSpec::needs_output)
+                || opts.oracles.iter().any(OracleSpec::needs_stderr),
+                "feedbacks and oracles on the target's output"),
+        ];
+        if let Some((_, flags)) = unsupported.iter().find(|x| x.0) {
+            unavailable(&format!("--intel-pt does not support {}", flags));
+        }
+        #[cfg(target_os = "linux")]
+        return Ok(Some(Box::new(
+            IntelPtExecutor::new(opts.target.clone(), opts.timeout)?)));
+        #[cfg(not(target_os = "linux"))]
+        unavailable("--intel-pt is only supported on Linux");
+    }
 
-        // print!("{:#?}\n", ret);
-        ret
+    #[allow(unused_mut)]
+    let mut executor = ProcessExecutor::new(opts.target.clone(), opts.timeout);
+    if let Some(max_len) = opts.shm_input {
+        #[cfg(unix)]
+        executor.shm_input(max_len)?;
+        #[cfg(not(unix))]
+        unavailable("--shm-input is not supported on this platform");
     }
-    // Initialize the RNG
-    pub fn seed(&self, val: usize){
-        self.seed.set(val);
+    #[cfg(unix)]
+    executor.limits(opts.limits);
+    #[cfg(windows)]
+    if let Some(bytes) = opts.memory_limit {
+        executor.memory_limit(bytes);
+    }
+    if let Some((position, _)) = opts.positions.iter().find(|(x, _)|
+            matches!(x, Position::Arg(index) if *index >= opts.target.len())) {
+        unavailable(&format!("--position {}: the target command line is shorter",
+            position));
+    }
+    let positions = fuzzed_positions(opts);
+    if let Some(placeholder) = positions.iter().filter_map(Position::placeholder)
+            .find(|x| !opts.target.contains(x)) {
+        unavailable(&format!("the target command line has no {} for the file", placeholder));
+    }
+    if !positions.is_empty() {
+        #[cfg(unix)]
+        executor.positions(positions);
+        #[cfg(not(unix))]
+        unavailable("--position is not supported on this platform");
+    }
+    if opts.sanitizer {
+        #[cfg(unix)]
+        executor.capture_sanitizer();
+        #[cfg(not(unix))]
+        unavailable("--sanitizer is not supported on this platform");
+    }
+    if opts.feedbacks.iter().any(FeedbackSpec::needs_output)
+            || opts.oracles.iter().any(OracleSpec::needs_output) {
+        #[cfg(unix)]
+        executor.capture_output();
+        #[cfg(not(unix))]
+        unavailable("capturing target output is not supported on this platform");
+    }
+    if opts.oracles.iter().any(OracleSpec::needs_stderr) {
+        #[cfg(unix)]
+        executor.capture_stderr();
+        #[cfg(not(unix))]
+        unavailable("--oracle stderr: is not supported on this platform");
+    }
+    if opts.sandbox {
+        #[cfg(unix)]
+        executor.sandbox()?;
+        #[cfg(not(unix))]
+        unavailable("--sandbox is not supported on this platform");
     }
 
-    // get a random value
-    pub fn rand(&self) -> usize{
-        let mut seed = self.seed.get();
-        seed ^= seed << 13;
-        seed ^= seed >> 17;
-        seed ^= seed << 43;
+    if opts.qemu {
+        #[cfg(unix)]
+        {
+            let path = QemuExecutor::find_qemu().unwrap_or_else(||
+                unavailable("afl-qemu-trace not found in $AFL_PATH or $PATH"));
+            return Ok(Some(Box::new(QemuExecutor::new(executor, path)?)));
+        }
+        #[cfg(not(unix))]
+        unavailable("--qemu is not supported on this platform");
+    }
 
-        self.seed.set(seed);
-        seed
+    if opts.frida {
+        #[cfg(unix)]
+        {
+            let lib = FridaExecutor::find_frida().unwrap_or_else(||
+                unavailable("afl-frida-trace library not found in $AFL_PATH"));
+            let persistent = opts.frida_persistent.clone()
+                .map(|addr| FridaPersistent {
+                    addr,
+                    count: opts.frida_persistent_cnt,
+                    hook: opts.frida_persistent_hook.clone(),
+                });
+            return Ok(Some(Box::new(
+                FridaExecutor::new(executor, lib, persistent)?)));
+        }
+        #[cfg(not(unix))]
+        unavailable("--frida is not supported on this platform");
     }
 
-    pub fn allocate_fragment(&mut self, fragment: Fragment) -> FragmentId {
-        // get a unique fragment ID
-        let fragment_id = FragmentId(self.fragments.len());
+    Ok(Some(Box::new(executor)))
+}
 
-        // store the fragment
-        self.fragments.push(fragment);
+// Refuse to go on with a grammar other than the one something was made
+// with, unless told to. Choice sequences mean something else then and a
+// resumed corpus would no longer match its derivation trees
+fn check_fingerprint(what: &str, expected: Option<u64>, actual: u64,
+        ignore: bool) {
+    let Some(expected) = expected.filter(|&x| x != actual) else {
+        return;
+    };
+    if ignore {
+        warn!("grammar", "{} was made with grammar {:016x}, this one is {:016x}",
+            what, expected, actual);
+        return;
+    }
+    error!("grammar", "{} was made with grammar {:016x}, this one is {:016x} (--ignore-fingerprint to go on anyway)",
+        what, expected, actual);
+    std::process::exit(1);
+}
 
-        fragment_id
+// Replay the queue of every instance against a build of the target
+// instrumented for source coverage and write lcov and HTML reports, see
+// llvm_cov.rs
+fn cover(opts: &Options) -> io::Result<()> {
+    if opts.target.is_empty() {
+        usage();
+    }
+    let report_dir = opts.report_dir.clone()
+        .unwrap_or_else(|| opts.out_dir.join("coverage"));
+    let raw = report_dir.join("raw");
+    if raw.exists() {
+        std::fs::remove_dir_all(&raw)?;
     }
+    std::fs::create_dir_all(&raw)?;
 
-    #[inline]
-    pub fn lookup_fragment_mut(&mut self, id: FragmentId) -> &mut Fragment {
-        &mut self.fragments[id.0]
+    #[allow(unused_mut)]
+    let mut executor = ProcessExecutor::new(opts.target.clone(), opts.timeout);
+    executor.env("LLVM_PROFILE_FILE",
+        &llvm_cov::profile_pattern(&report_dir).to_string_lossy());
+    if !opts.positions.is_empty() || !opts.outputs.is_empty() {
+        #[cfg(unix)]
+        executor.positions(fuzzed_positions(opts));
+        #[cfg(not(unix))]
+        unavailable("--position is not supported on this platform");
+    }
+    let entries = output::queue_entries(&opts.out_dir)?;
+    info!("cover", "replaying {} queue entries", entries.len());
+    for path in &entries {
+        executor.run(&compress::read_entry(path)?)?;
     }
 
-    #[inline]
-    pub fn lookup_fragment(&self, id: FragmentId) -> &Fragment {
-        &self.fragments[id.0]
+    let summary = llvm_cov::report(&report_dir, Path::new(&opts.target[0]))?;
+    let percent = |hit, found: u64| hit as f64 * 100. / found.max(1) as f64;
+    info!("cover", "lines {}/{} ({:.1}%), functions {}/{} ({:.1}%)",
+        summary.lines_hit, summary.lines_found,
+        percent(summary.lines_hit, summary.lines_found),
+        summary.functions_hit, summary.functions_found,
+        percent(summary.functions_hit, summary.functions_found));
+    info!("cover", "report in {}", report_dir.join("html").join("index.html")
+        .display());
+    Ok(())
+}
+
+// Check that the target accepts what the grammar generates
+fn selftest(opts: &Options, gram: &GrammarRust, seed: usize) -> io::Result<()> {
+    if opts.target.is_empty() {
+        usage();
     }
+    let mut validator = ProcessExecutor::new(opts.target.clone(), opts.timeout);
+    #[cfg(unix)]
+    validator.capture_stderr();
+    let report = selftest::selftest(gram, seed, opts.samples, opts.examples,
+        &mut validator)?;
 
-    #[inline]
-    pub fn lookup_fragment_nonterm(&self, id: FragmentId) -> &[FragmentId] {
-        // Match control flow action (?)
-        if let Fragment::NonTerminal(x) = &self.fragments[id.0]{
-            x
-        }else{
-            panic!("Was not a non-terminal!");
+    for rejection in &report.examples {
+        let mut sample = String::from_utf8_lossy(&rejection.input).into_owned();
+        if sample.len() > 200 {
+            let end = (0..=200).rev().find(|&x| sample.is_char_boundary(x)).unwrap();
+            sample.truncate(end);
+            sample.push_str("...");
+        }
+        println!("sample {} rejected ({}): {:?}", rejection.sample,
+            rejection.reason(), sample);
+        for line in String::from_utf8_lossy(&rejection.stderr).lines().take(5) {
+            println!("    {}", line);
         }
     }
+    println!("{} of {} samples rejected ({:.1}%)", report.rejected,
+        report.samples, report.rate());
+    Ok(())
+}
 
-    pub fn generate(&self, stack: &mut Vec<FragmentId>, buf: &mut Vec<u8>) {
-        // get access to the start node
-        let start = self.start.unwrap();
+// Generate --count inputs and compare their hashes with the manifest, or
+// record it when there is none yet (or --update)
+fn verify_determinism(opts: &Options, grammar: &Grammar) -> io::Result<()> {
+    let path = opts.manifest.as_ref().unwrap_or_else(|| usage());
+    let expected: Option<Manifest> = match path.exists() && !opts.update {
+        true => Some(serde_json::from_slice(&std::fs::read(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e)))?),
+        false => None,
+    };
+    let seed = opts.seed.or(expected.as_ref().map(|x| x.seed))
+        .unwrap_or_else(|| usage());
+    let count = expected.as_ref().map_or(opts.count, |x| x.inputs.len());
+    let manifest = Manifest::generate(&compile(grammar, opts), seed, count);
 
-        // start off working on start
-        stack.clear();
-        stack.push(start);
+    // the rules again, in the order of another hash map
+    let reordered = Grammar(grammar.0.clone().into_iter().collect(),
+        grammar.1.clone().into_iter().collect());
+    let again = Manifest::generate(&compile(&reordered, opts), seed, count);
+    let mut differences = again.compare(&manifest).into_iter()
+        .map(|x| format!("compiled again: {}", x)).collect::<Vec<_>>();
 
-        while !stack.is_empty() {
-            // unwrap makes sure the option is not a None
-            let cur = stack.pop().unwrap();
+    let verify = expected.is_some();
+    match expected {
+        Some(expected) => differences.extend(manifest.compare(&expected)),
+        None => {
+            std::fs::write(path, serde_json::to_vec_pretty(&manifest)?)?;
+            info!("determinism", "recorded {} inputs of seed {} to {}", count, seed,
+                path.display());
+        }
+    }
+    for difference in &differences {
+        error!("determinism", "{}", difference);
+    }
+    if !differences.is_empty() {
+        std::process::exit(1);
+    }
+    if verify {
+        info!("determinism", "{} inputs of seed {} are the same", count, seed);
+    }
+    Ok(())
+}
 
-            match self.lookup_fragment(cur) {
-                Fragment ::NonTerminal(options) => {
-                    let sel = options[self.rand() % options.len()];
-                    stack.push(sel);
-                    // print!("Non-terminal: {:?}\n", sel);
+// Perturb the grammar --count times and report the mutants that made
+// validation or generation panic or hang, saved to <sync dir>/grammar_bugs
+fn mutate_grammar(opts: &Options, grammar: &Grammar, seed: usize) -> io::Result<()> {
+    let dir = opts.out_dir.join("grammar_bugs");
+    let mut state = GeneratorState::new(seed);
+    let (mut rejected, mut bugs) = (0, 0);
+    for ii in 0..opts.count {
+        let mut mutant = grammar.clone();
+        let edits = perturb::perturb(&mut mutant, &mut || state.rand());
+        let json = perturb::to_json(&mutant);
+        let outcome = perturb::check(mutant, opts.samples, seed, opts.timeout);
+        match &outcome {
+            Outcome::Ok => continue,
+            Outcome::Rejected(reason) => {
+                rejected += 1;
+                debug!("perturb", "mutant {} rejected: {}", ii, reason);
+                continue;
+            }
+            Outcome::Panicked(message) =>
+                error!("perturb", "mutant {} panicked: {}", ii, message),
+            Outcome::Hung => error!("perturb", "mutant {} still running after {:?}",
+                ii, opts.timeout),
+        }
+        bugs += 1;
+        std::fs::create_dir_all(&dir)?;
+        let path = dir.join(format!("mutant_{}.json", ii));
+        std::fs::write(&path, serde_json::to_vec_pretty(&json)?)?;
+        error!("perturb", "edits: {}, saved to {}", edits.join("; "), path.display());
+    }
+    info!("perturb", "{} mutants, {} rejected by validation, {} bugs", opts.count,
+        rejected, bugs);
+    if bugs > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn bench_compare(opts: &Options, gram: &GrammarRust, seed: usize) -> io::Result<()> {
+    let mut runs = vec![bench::own(gram, seed, opts.samples)];
+    #[cfg(feature = "batch")]
+    runs.push(bench::batched(gram, seed, opts.samples));
+    for (name, argv) in &opts.references {
+        let dir = std::env::temp_dir().join(format!("mff-bench-{}-{}",
+            std::process::id(), name));
+        match bench::reference(name, argv, opts.samples, &dir)? {
+            Some(run) => runs.push(run),
+            None => warn!("bench", "{}: {} is not installed, skipped", name, argv[0]),
+        }
+    }
+    if !opts.target.is_empty() {
+        let mut validator = ProcessExecutor::new(opts.target.clone(), opts.timeout);
+        for run in &mut runs {
+            bench::check(run, &mut validator)?;
+        }
+    }
+
+    let ours = runs[0].bytes_per_sec();
+    println!("{:<24} {:>8} {:>12} {:>12} {:>8} {:>8}", "generator", "samples",
+        "samples/s", "MB/s", "valid", "speed");
+    for run in &runs {
+        println!("{:<24} {:>8} {:>12.0} {:>12.2} {:>8} {:>7.2}x", run.name,
+            run.samples.len(), run.samples_per_sec(), run.bytes_per_sec() / 1e6,
+            run.validity().map_or("-".to_string(), |x| format!("{:.1}%", x)),
+            run.bytes_per_sec() / ours.max(f64::MIN_POSITIVE));
+    }
+    Ok(())
+}
+
+// Generated bytes between --prefix and --suffix
+fn framed(opts: &Options, data: &[u8]) -> Vec<u8> {
+    [&opts.prefix, data, &opts.suffix].concat()
+}
+
+// Shrink the queue entries of our instance that came from the grammar,
+// keeping the path they take through the target. Entries are rewritten in
+// place, together with their choice sequence
+fn trim(opts: &Options, gram: &GrammarRust) -> io::Result<()> {
+    let output = AflOutputDir::new(&opts.out_dir, &opts.instance, false)?;
+    check_fingerprint(&format!("{}", output.dir().display()),
+        output.grammar_fingerprint()?, gram.fingerprint(),
+        opts.ignore_fingerprint);
+    let mut executor = build_executor(opts)?.unwrap_or_else(|| usage());
+    let mut signature = |input: &[u8]| -> io::Result<_> {
+        let result = executor.run(input)?;
+        let map = executor.coverage().unwrap_or_else(||
+            unavailable("trim needs a coverage backend (--qemu, --frida, --intel-pt)"));
+        Ok((path_hash(map), result.exit))
+    };
+
+    let (mut trimmed, mut before, mut after, mut execs) = (0, 0, 0, 0);
+    let (mut stack, mut tree) = (TreeStack::default(), Tree::default());
+    for path in output::queue_entries(&opts.out_dir)? {
+        let choices_path = path.parent().unwrap().join(".choices")
+            .join(path.file_name().unwrap());
+        if !path.starts_with(output.dir()) || !choices_path.exists() {
+            continue;
+        }
+        let stored = std::fs::read(&path)?;
+        let data = match compress::is_compressed(&stored) {
+            true => compress::decompress(&stored)?,
+            false => stored.clone(),
+        };
+        gram.replay_full_choices(&std::fs::read(&choices_path)?, &mut stack,
+            &mut tree);
+        let expected = signature(&data)?;
+        let stats = trim::trim(gram, &mut tree,
+            |candidate| Ok(signature(&framed(opts, candidate))? == expected))?;
+        execs += stats.tries + 1;
+        before += data.len();
+        if stats.reductions == 0 {
+            after += data.len();
+            continue;
+        }
+
+        let mut bytes = Vec::new();
+        tree.serialize(gram, &mut bytes);
+        let bytes = framed(opts, &bytes);
+        let mut choices = Vec::new();
+        tree.choices(gram, &mut choices);
+        // keep the entry the way it was stored, a later index line wins
+        if compress::is_compressed(&stored) {
+            let packed = compress::compress(&bytes)?;
+            std::fs::write(&path, &packed)?;
+            compress::index(&path, bytes.len(), packed.len())?;
+        } else {
+            std::fs::write(&path, &bytes)?;
+        }
+        std::fs::write(&choices_path, &choices)?;
+        debug!("trim", "{}: {} -> {} bytes", path.display(), data.len(), bytes.len());
+        trimmed += 1;
+        after += bytes.len();
+    }
+    info!("trim", "{} entries trimmed, {} -> {} bytes in {} execs", trimmed,
+        before, after, execs);
+    Ok(())
+}
+
+// Compile the grammar for generation with the settings of the command line
+fn compile(grammar: &Grammar, opts: &Options) -> GrammarRust {
+    let mut gram = GrammarRust::new(grammar);
+    gram.set_node_budget(opts.max_nodes);
+    gram.set_max_output(opts.max_size);
+    gram.set_strategy(opts.strategy);
+    gram.set_corruption(opts.corrupt);
+    gram.set_cost_budget(opts.cost_budget);
+    gram
+}
+
+// Charts of the stats CSVs given with --input, or of the instance's
+fn plot(opts: &Options) -> io::Result<()> {
+    let paths = match opts.inputs.is_empty() {
+        true => vec![opts.out_dir.join(&opts.instance).join(plot::CSV_NAME)],
+        false => opts.inputs.clone(),
+    };
+    let campaigns = paths.iter().map(|path| {
+        // the instance directory tells campaigns apart better than the file
+        let name = match path.file_name().is_some_and(|x| x == plot::CSV_NAME) {
+            true => path.parent().and_then(|x| x.file_name()).unwrap_or_default(),
+            false => path.file_stem().unwrap_or_default(),
+        };
+        let samples = plot::read_csv(path).map_err(|e| io::Error::new(e.kind(),
+            format!("{}: {}", path.display(), e)))?;
+        Ok((name.to_string_lossy().into_owned(), samples))
+    }).collect::<io::Result<Vec<_>>>()?;
+
+    let dir = opts.report_dir.clone().unwrap_or_else(|| PathBuf::from("."));
+    std::fs::create_dir_all(&dir)?;
+    for (name, svg) in plot::charts(&campaigns) {
+        let path = dir.join(name).with_extension(if opts.png { "png" } else { "svg" });
+        if opts.png {
+            plot::to_png(&svg, &path)?;
+        } else {
+            std::fs::write(&path, svg)?;
+        }
+        info!("plot", "wrote {}", path.display());
+    }
+    Ok(())
+}
+
+// Migrate the grammar file to the current version of the format, printing
+// it or rewriting the file
+fn upgrade(opts: &Options) -> io::Result<()> {
+    let path = &opts.grammar_path;
+    let invalid = |what: String| io::Error::new(io::ErrorKind::InvalidData,
+        format!("{}: {}", path, what));
+    let mut definitions = loader::parse(path, &std::fs::read(path)?)?;
+    definitions.1 = definitions.1.or(opts.from);
+    match definitions.1 {
+        Some(0) => return Err(invalid("version 0, versions start at 1".to_string())),
+        Some(x) if x > schema::CURRENT => return Err(invalid(format!(
+            "version {} is newer than this fuzzer reads ({})", x, schema::CURRENT))),
+        _ => {}
+    }
+    // what is not understood cannot be migrated
+    if let Some((name, rule)) = definitions.0.iter().find(|x| !x.1.unknown.is_empty()) {
+        return Err(invalid(format!("{}: unknown annotation {:?}", name, rule.unknown[0])));
+    }
+    let from = definitions.1.unwrap_or(schema::CURRENT);
+    for note in schema::upgrade(&mut definitions) {
+        info!("grammar", "{}", note);
+    }
+    let json = schema::to_json(&definitions);
+    if !opts.in_place {
+        return io::stdout().lock().write_all(json.as_bytes());
+    }
+    std::fs::write(path, json)?;
+    info!("grammar", "{} upgraded from version {} to {}", path, from, schema::CURRENT);
+    Ok(())
+}
+
+fn compare_runs(opts: &Options) -> io::Result<()> {
+    if opts.baseline.is_empty() || opts.candidate.is_empty() {
+        usage();
+    }
+    let load = |paths: &[PathBuf]| paths.iter().map(|x| compare::load_runs(x))
+        .collect::<io::Result<Vec<_>>>()
+        .map(|x| x.into_iter().flatten().collect::<Vec<_>>());
+    let (baseline, candidate) = (load(&opts.baseline)?, load(&opts.candidate)?);
+    let rows = compare::compare(&baseline, &candidate);
+    compare::write_table(&baseline, &candidate, &rows, &mut io::stdout().lock())
+}
+
+// Exits with 1 if an input did not reproduce in any run
+fn replay(opts: &Options) -> io::Result<()> {
+    if opts.inputs.is_empty() {
+        usage();
+    }
+    let mut executor = build_executor(opts)?.unwrap_or_else(|| usage());
+    let (mut total, mut solid, mut flaky, mut gone) = (0, 0, 0, 0);
+    for path in &opts.inputs {
+        for entry in replay::entries(path)? {
+            let result = replay::replay(executor.as_mut(), &entry, opts.runs)?;
+            let buckets = result.buckets.iter().map(|(bucket, runs)|
+                format!("{} x{}", bucket, runs)).collect::<Vec<_>>();
+            info!("replay", "{}: reproduced {:.0}% of {} runs{}{}", entry.display(),
+                result.reproduced * 100., result.runs,
+                if result.flaky { ", flaky" } else { "" },
+                if buckets.is_empty() { String::new() }
+                else { format!(" ({})", buckets.join(", ")) });
+            replay::update_sidecar(&entry, &result)?;
+            total += 1;
+            match (result.reproduced > 0., result.flaky) {
+                (false, _) => gone += 1,
+                (true, true) => flaky += 1,
+                (true, false) => solid += 1,
+            }
+        }
+    }
+    info!("replay", "{} inputs: {} reproduce every time, {} flaky, {} not at all",
+        total, solid, flaky, gone);
+    if gone > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+// Print the grammar as dot, or as svg rendered by graphviz
+fn graph(gram: &GrammarRust, svg: bool) -> io::Result<()> {
+    if !svg {
+        return dot::write_dot(gram, &mut io::stdout().lock());
+    }
+
+    let mut child = std::process::Command::new("dot").arg("-Tsvg")
+        .stdin(std::process::Stdio::piped())
+        .spawn()
+        .map_err(|e| io::Error::new(e.kind(),
+            format!("running graphviz dot failed: {}", e)))?;
+    dot::write_dot(gram, &mut child.stdin.take().unwrap())?;
+    if !child.wait()?.success() {
+        return Err(io::Error::other("graphviz dot failed"));
+    }
+    Ok(())
+}
+
+fn main() -> io::Result<()> {
+    let opts = parse_args();
+    log::set_max_level(match opts.verbosity {
+        ..=-1 => Level::Warn,
+        0 => Level::Info,
+        1 => Level::Debug,
+        _ => Level::Trace,
+    });
+    log::set_json(opts.log_json);
+
+    if let Some(addr) = &opts.broker {
+        return broker::run_broker(addr, opts.broker_dir.clone());
+    }
+
+    if opts.command == Command::Cover {
+        return cover(&opts);
+    }
+    if opts.command == Command::Plot {
+        return plot(&opts);
+    }
+    if opts.command == Command::CompareRuns {
+        return compare_runs(&opts);
+    }
+    if opts.command == Command::Replay {
+        return replay(&opts);
+    }
+    if opts.command == Command::Upgrade {
+        return upgrade(&opts);
+    }
+    if opts.command == Command::Findings {
+        let instance = opts.out_dir.join(&opts.instance);
+        let found = findings::collect(&instance)?;
+        let value = match opts.json {
+            true => serde_json::to_value(&found)?,
+            false => findings::to_sarif(&found, &instance),
+        };
+        let mut out = io::stdout().lock();
+        serde_json::to_writer_pretty(&mut out, &value)?;
+        return writeln!(out);
+    }
+
+    // serialize grammar input
+    let paths = std::iter::once(&opts.grammar_path).chain(&opts.includes)
+        .collect::<Vec<_>>();
+    let loaded = loader::load(&paths, opts.duplicates, opts.parsing);
+    let mut grammar = loaded.unwrap_or_else(|e| {
+        error!("grammar", "{}", e);
+        std::process::exit(1);
+    });
+    if !opts.templates.is_empty() {
+        let templates = opts.templates.iter().map(|path| std::fs::read(path)
+            .map(|data| (path.display().to_string(), data))
+            .map_err(|e| format!("{}: {}", path.display(), e)))
+            .collect::<Result<Vec<_>, _>>();
+        if let Err(e) = templates.and_then(|x| template::apply(&mut grammar, &x)) {
+            error!("grammar", "--template: {}", e);
+            std::process::exit(1);
+        }
+    }
+    let grammar = if opts.positions.is_empty() && opts.outputs.is_empty() {
+        grammar
+    } else {
+        let positions = opts.positions.iter().map(|(position, path)| {
+            loader::load(&[path], opts.duplicates, opts.parsing)
+                .map(|x| (position.clone(), x))
+        }).collect::<io::Result<Vec<_>>>().unwrap_or_else(|e| {
+            error!("grammar", "{}", e);
+            std::process::exit(1);
+        });
+        positions::combine(grammar, positions, &opts.outputs).unwrap_or_else(|e| {
+            error!("grammar", "--output: {}", e);
+            std::process::exit(1);
+        })
+    };
+    let grammar = match opts.token_stream {
+        Some(encoding) => tokens::token_stream(&grammar, encoding).unwrap_or_else(|e| {
+            error!("grammar", "--token-stream: {}", e);
+            std::process::exit(1);
+        }),
+        None => grammar,
+    };
+
+    if opts.command == Command::Validate {
+        let report = validate::validate(&grammar, &ValidateOptions {
+            samples: opts.samples,
+            max_nodes: opts.max_nodes,
+            max_len: opts.max_len,
+            seed: opts.seed.map_or(1, |x| SplitSeed::new(x).stream(0)),
+            cost_budget: opts.cost_budget,
+        });
+        for finding in &report.findings {
+            println!("{}", finding);
+        }
+        for rule in &report.costs {
+            println!("cost: {} {} per expansion, {} expansions per test case expected",
+                rule.name, rule.cost, rule.expansions.map_or("unbounded".to_string(),
+                    |x| format!("{:.2}", x)));
+        }
+        if report.samples > 0 {
+            println!("{} samples, length min {} avg {:.1} max {}", report.samples,
+                report.min_len, report.avg_len, report.max_len);
+        }
+        if !report.costs.is_empty() {
+            println!("cost per test case: expected {}, samples avg {:.1}{}",
+                report.expected_cost.map_or("unbounded".to_string(), |x| format!("{:.1}", x)),
+                report.avg_cost, opts.cost_budget.map_or(String::new(),
+                    |x| format!(" (budget {})", x)));
+        }
+        std::process::exit(if report.has_errors() { 1 } else { 0 });
+    }
+
+    if opts.command == Command::Graph {
+        return graph(&GrammarRust::new(&grammar), opts.svg);
+    }
+
+    if opts.command == Command::Trim {
+        return trim(&opts, &compile(&grammar, &opts));
+    }
+
+    if opts.command == Command::Derive {
+        let path = opts.choices.as_ref().unwrap_or_else(|| usage());
+        let choices = std::fs::read(path)?;
+        let gram = GrammarRust::new(&grammar);
+        check_fingerprint(&format!("{}", path.display()),
+            import::sidecar_fingerprint(path), gram.fingerprint(),
+            opts.ignore_fingerprint);
+        let mut tree = Tree::default();
+        gram.replay_full_choices(&choices, &mut TreeStack::default(), &mut tree);
+
+        let mut buf = opts.prefix.clone();
+        tree.serialize(&gram, &mut buf);
+        buf.extend_from_slice(&opts.suffix);
+        return io::stdout().write_all(&buf);
+    }
+
+    if opts.command == Command::VerifyDeterminism {
+        return verify_determinism(&opts, &grammar);
+    }
+
+    if opts.command == Command::Golden {
+        let seed = opts.seed.unwrap_or_else(|| usage());
+        let (inputs, manifest) = golden::select(&compile(&grammar, &opts), seed, opts.count);
+        golden::write(&opts.out_dir, &inputs, &manifest)?;
+        info!("golden", "wrote {} inputs of seed {} to {}, {} of {} alternatives",
+            inputs.len(), seed, opts.out_dir.display(), manifest.alternatives_covered,
+            manifest.alternatives);
+        return Ok(());
+    }
+
+    // print the seed so any run can be repeated
+    let seed = opts.seed.map(SplitSeed::new).unwrap_or_else(SplitSeed::random);
+    info!("campaign", "seed {}", seed.value());
+
+    if opts.command == Command::Selftest {
+        return selftest(&opts, &compile(&grammar, &opts), seed.stream(0));
+    }
+
+    if opts.command == Command::Repl {
+        let gram = compile(&grammar, &opts);
+        let mut repl = Repl::new(&gram, seed.stream(0));
+        return repl.run(io::stdin().lock(), &mut io::stdout().lock());
+    }
+
+    if opts.command == Command::MutateGrammar {
+        return mutate_grammar(&opts, &grammar, seed.stream(0));
+    }
+
+    if opts.command == Command::BenchCompare {
+        return bench_compare(&opts, &compile(&grammar, &opts), seed.stream(0));
+    }
+
+    if opts.command == Command::Export {
+        let mut out = io::stdout().lock();
+        for (ii, path) in opts.inputs.iter().enumerate() {
+            export::write_literal(opts.format, &format!("sample_{}", ii),
+                Some(&path.display().to_string()), &compress::read_entry(path)?,
+                &mut out)?;
+        }
+        if !opts.inputs.is_empty() {
+            return Ok(());
+        }
+
+        let gram = compile(&grammar, &opts);
+        let cases = gram.iter_testcases(seed.stream(0)).take(opts.count);
+        for (ii, case) in cases.enumerate() {
+            export::write_literal(opts.format, &format!("sample_{}", ii),
+                Some(&format!("seed {}, sample {}", seed.value(), ii)), &case,
+                &mut out)?;
+        }
+        return Ok(());
+    }
+
+    if opts.utf8 {
+        let checked = unicode::without_expansions(&grammar);
+        if let Some(value) = GrammarRust::new(&checked).non_utf8_terminal() {
+            error!("campaign", "--utf8: grammar has a terminal that is not valid UTF-8: {:?}",
+                String::from_utf8_lossy(value));
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(count) = opts.trace {
+        let gram = compile(&grammar, &opts);
+        let mut state = GeneratorState::new(seed.stream(0));
+        // a traced derivation shows the starting temperature
+        if let Some(spec) = &opts.temperature {
+            state.set_temperature(Schedule::parse(spec, Duration::ZERO)
+                .unwrap_or_else(|| usage()).start);
+        }
+
+        let mut stack = TreeStack::default();
+        let mut tree = Tree::default();
+        let mut out = io::stdout().lock();
+        for _ in 0..count {
+            gram.generate_full_tree(&mut state, &mut stack, &mut tree);
+            tree.write_trace(&gram, &mut out)?;
+            writeln!(out)?;
+        }
+        return Ok(());
+    }
+
+    if opts.profile_internals {
+        let profile = profile::run(&compile(&grammar, &opts), seed.stream(0));
+        return profile.write(&mut io::stdout().lock());
+    }
+
+    if let Some(spec) = &opts.emit {
+        let gram = compile(&grammar, &opts);
+
+        let mut sink = spec.open()?;
+        let mut cases = gram.iter_testcases(seed.stream(0));
+        if let Some(policy) = opts.derivation_cache {
+            cases = cases.cache(policy);
+        }
+        let delivered = cases.deliver(sink.as_mut(), opts.max_execs)?;
+        debug!("sink", "delivered {} test cases", delivered);
+        return Ok(());
+    }
+
+    if opts.processes > 1 {
+        if !has_target(&opts) {
+            unavailable("--processes needs a target");
+        }
+        return orchestrate(&opts, seed);
+    }
+
+    // without a target we only measure generation speed
+    if !has_target(&opts) {
+        let gram = compile(&grammar, &opts);
+        // print!("{:#?}\n", gram);
+
+        let mut cases = gram.iter_testcases(seed.stream(0));
+        if let Some(policy) = opts.derivation_cache {
+            cases = cases.cache(policy);
+        }
+        let mut generated = 0usize;
+        let it = Instant::now();
+
+        for iters in 1u64.. {
+            let buf = cases.next_ref();
+            generated += buf.len();
+
+            if (iters & 0xffff) == 0{
+                let elapsed = (Instant::now() - it).as_secs_f64();
+                let bytes_per_sec = generated as f64 / elapsed;
+                info!("generator", "Bytes per sec: {:12.0} | Example: {:#?}", bytes_per_sec, String::from_utf8_lossy(buf));
+            }
+        }
+        return Ok(());
+    }
+
+    // hand out one core per worker
+    let cores = if opts.bind_cores {
+        let cores = affinity::allowed_cores()?;
+        if cores.len() < opts.jobs {
+            error!("campaign", "--bind-cores: only {} cores available for {} jobs",
+                cores.len(), opts.jobs);
+            std::process::exit(1);
+        }
+        cores.into_iter().map(Some).collect()
+    } else {
+        vec![None; opts.jobs]
+    };
+
+    // ours until the campaign ends, temp files go in there from now on
+    let _workspace = Workspace::create(&opts.out_dir.join(&opts.instance))?;
+
+    // what earlier machines left behind goes first, our ids continue after it
+    let storage = opts.storage.as_deref()
+        .map(|url| storage::open(url, opts.storage_endpoint.as_deref()))
+        .transpose()?;
+    if let Some(storage) = &storage {
+        info!("storage", "pulling corpus from {}", storage.url());
+        storage.pull(&opts.out_dir)?;
+    }
+    let mut output = AflOutputDir::new(&opts.out_dir, &opts.instance,
+        opts.main_node)?;
+    output.set_compress(opts.compress);
+    let mut shared = Shared::new(output);
+    if !opts.no_dedup {
+        shared.dedup = Some(Dedup::default());
+    }
+    if let Some(entries) = opts.exec_cache {
+        shared.exec_cache = Some(ExecCache::new(entries));
+    }
+    if let Some(limit) = opts.cull {
+        shared.corpus.get_mut().unwrap().set_cull(limit);
+    }
+    shared.feedbacks = opts.feedbacks.iter()
+        .map(|spec| Mutex::new(spec.build()))
+        .collect();
+    if opts.histograms {
+        shared.histograms = Some(Histograms::default());
+    }
+    shared.throttle = Throttle::new(opts.rate, opts.duty.map(|on| DutyCycle {
+        on,
+        period: opts.duty_period,
+    }));
+    // one compiled grammar for all workers, each brings its own RNG
+    let gram = compile(&grammar, &opts);
+    let fingerprint = gram.fingerprint();
+    check_fingerprint(&format!("{}", shared.output.dir().display()),
+        shared.output.grammar_fingerprint()?, fingerprint,
+        opts.ignore_fingerprint);
+    shared.output.set_grammar_fingerprint(fingerprint)?;
+    if opts.rule_stats {
+        shared.rule_stats = Some(RuleStats::new(&gram));
+    }
+    for dir in &opts.import_dirs {
+        let seeds = import::read_dir(dir, &gram, &opts.prefix, &opts.suffix)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", dir.display(), e)))?;
+        info!("import", "{} seeds in {}, {} with a derivation tree", seeds.len(),
+            dir.display(), seeds.iter().filter(|x| x.tree.is_some()).count());
+        shared.seeds.get_mut().unwrap().extend(seeds);
+    }
+    for dir in &opts.import_corpora {
+        let (seeds, recovery) = import::read_corpus(dir, &gram, &opts.prefix, &opts.suffix)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", dir.display(), e)))?;
+        info!("import", "{} entries in {}, {} parsed into derivation trees ({:.1}%), \
+            {} duplicates left out", recovery.entries, dir.display(), recovery.parsed,
+            recovery.rate(), recovery.duplicates);
+        if recovery.parsed < recovery.entries {
+            info!("import", "kept as bytes: {} without a parse, {} too large to parse, \
+                {} parsed to a different input", recovery.no_parse, recovery.too_large,
+                recovery.mismatch);
+        }
+        shared.seeds.get_mut().unwrap().extend(seeds);
+    }
+    // workers pop from the back
+    shared.seeds.get_mut().unwrap().reverse();
+    if let Some((mode, dir)) = &opts.record {
+        if opts.strategy == Strategy::Markov {
+            error!("campaign", "--strategy markov cannot be recorded or replayed");
+            std::process::exit(1);
+        }
+        if *mode == Mode::Record {
+            std::fs::create_dir_all(dir)?;
+        }
+        info!("campaign", "{} worker decisions in {}", match mode {
+            Mode::Record => "recording",
+            Mode::Replay => "replaying",
+        }, dir.display());
+    }
+    let temperature = opts.temperature.as_ref().map(|spec| {
+        let period = opts.anneal.or(opts.max_time)
+            .unwrap_or(Duration::from_secs(3600));
+        Schedule::parse(spec, period).unwrap_or_else(|| usage())
+    });
+    let mut mutators = Scheduler::standard(opts.mutation_stack, opts.havoc,
+        opts.inject, opts.inject_rate);
+    mutators.set_adaptive(opts.adaptive_mutators);
+    // worker groups bring their own mutators, and their own grammar if its
+    // limits differ
+    let groups = opts.workers.iter().map(|group| {
+        info!("campaign", "workers: {}", group);
+        let limits = (group.max_nodes.is_some() || group.max_size.is_some()).then(|| {
+            let mut gram = compile(&grammar, &opts);
+            gram.set_node_budget(group.max_nodes.unwrap_or(opts.max_nodes));
+            gram.set_max_output(group.max_size.unwrap_or(opts.max_size));
+            gram
+        });
+        let mut scheduler = group.scheduler(opts.mutation_stack, opts.havoc,
+            opts.inject, opts.inject_rate);
+        scheduler.set_adaptive(opts.adaptive_mutators);
+        (limits, scheduler)
+    }).collect::<Vec<_>>();
+    let assigned = roles::assign(&opts.workers, opts.jobs);
+    let dashboard = opts.dashboard.as_ref()
+        .map(|addr| Dashboard::new(addr.as_str(), shared.output.dir()))
+        .transpose()?;
+    let mut client = opts.sync_to.as_deref().map(BrokerClient::new);
+    let mut last_sync = Instant::now();
+    let it = Instant::now();
+    signals::install()?;
+
+    std::thread::scope(|s| -> io::Result<()> {
+        let workers = cores.iter().take(opts.jobs).enumerate().map(|(ii, &core)| {
+            let (opts, shared) = (&opts, &shared);
+            let (gram, mutators, role) = match assigned[ii] {
+                Some(idx) => (groups[idx].0.as_ref().unwrap_or(&gram), &groups[idx].1,
+                    opts.workers[idx].role),
+                None => (&gram, &mutators, Role::Mixed),
+            };
+            let config = WorkerConfig {
+                seed: seed.stream(ii),
+                core,
+                syncing: opts.sync_to.is_some(),
+                utf8: opts.utf8,
+                pairwise: opts.pairwise,
+                max_execs: opts.max_execs,
+                stop_on_crash: opts.stop_on_crash,
+                temperature,
+                record: opts.record.as_ref()
+                    .map(|(mode, dir)| (*mode, record::log_path(dir, ii))),
+                mmap: opts.mmap_output,
+                prefix: opts.prefix.clone(),
+                suffix: opts.suffix.clone(),
+                adaptive_size: opts.adaptive_size,
+                derivation_cache: opts.derivation_cache,
+                role,
+            };
+            s.spawn(move || {
+                let ret = build_executor(opts).and_then(|executor| {
+                    let executor = executor
+                        .expect("workers are only started with a target");
+                    fuzzer::run_worker(gram, mutators, &config, executor,
+                        target_builds(opts)?, build_oracles(opts)?, shared)
+                });
+                // one worker failing takes the campaign down
+                shared.stop.store(true, Ordering::Relaxed);
+                ret
+            })
+        }).collect::<Vec<_>>();
+        let Shared { feedback, pairs, bugs, stats, stop, throttle, .. } = &shared;
+        let server = dashboard.as_ref().map(|x| s.spawn(|| x.serve(stop)));
+        let uploader = storage.as_ref().map(|storage| s.spawn(|| {
+            let mut last_push = Instant::now();
+            while !stop.load(Ordering::Relaxed) {
+                std::thread::sleep(Duration::from_millis(100));
+                if last_push.elapsed() < opts.storage_interval {
+                    continue;
+                }
+                if let Err(e) = storage.push(shared.output.dir(), &opts.instance) {
+                    warn!("storage", "pushing corpus to {} failed: {}", storage.url(), e);
+                }
+                last_push = Instant::now();
+            }
+        }));
+        let write_stats = || {
+            let execs = stats.execs.load(Ordering::Relaxed);
+            shared.output.write_stats(&StatsSnapshot {
+                execs_done: execs,
+                execs_per_sec: execs as f64 / it.elapsed().as_secs_f64(),
+                corpus_imported: stats.imported.load(Ordering::Relaxed),
+                edges_found: feedback.lock().unwrap().edges(),
+                total_edges: MAP_SIZE,
+            })
+        };
+
+        let mut reason = None;
+        let ret = (|| -> io::Result<()> { while !stop.load(Ordering::Relaxed) {
+            // a second between reports, but react to ctrl-c, the time limit
+            // and workers stopping right away
+            for _ in 0..10 {
+                if signals::shutdown_requested() {
+                    info!("campaign", "shutting down");
+                    reason = Some(StopReason::Interrupted);
+                    stop.store(true, Ordering::Relaxed);
+                }
+                if opts.max_time.is_some_and(|x| it.elapsed() >= x) {
+                    reason = Some(StopReason::MaxTime);
+                    stop.store(true, Ordering::Relaxed);
                 }
-                Fragment::Expression(expr) => {
-                    // we must process all of these in sequence
-                    // take expr slice and append all elements to stack vec
-                    expr.iter().rev().for_each(|x| stack.push(*x));
+                if stop.load(Ordering::Relaxed) {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(100));
+            }
+
+            // trade findings with the other fuzzers
+            if last_sync.elapsed() >= opts.sync_interval {
+                last_sync = Instant::now();
+                if opts.sync_instances {
+                    let new = shared.output.sync_foreign()?;
+                    shared.inbox.lock().unwrap().extend(new);
                 }
-                Fragment::Terminal(value) => {
-                    buf.extend_from_slice(value);
-                    // print!("TERM\n");
-                    if buf.len() > 1024*1024 {
-                        break;
+                if let Some(client) = &mut client {
+                    for (kind, data) in shared.outbox.lock().unwrap().drain(..) {
+                        client.push(kind, &data);
                     }
+                    match client.sync() {
+                        Ok(new) => shared.inbox.lock().unwrap().extend(new),
+                        Err(e) => warn!("sync", "sync with broker failed: {}", e),
+                    }
+                }
+            }
+
+            write_stats()?;
+            let execs = stats.execs.load(Ordering::Relaxed);
+            let elapsed = (Instant::now() - it).as_secs_f64();
+            let sample = Sample {
+                time: elapsed,
+                execs,
+                execs_per_sec: execs as f64 / elapsed,
+                edges: feedback.lock().unwrap().edges(),
+                corpus: shared.output.queue_len(),
+                crashes: stats.crashes.load(Ordering::Relaxed),
+                timeouts: stats.timeouts.load(Ordering::Relaxed),
+            };
+            plot::append_csv(&shared.output.dir().join(plot::CSV_NAME), &sample)?;
+            if let Some(dashboard) = &dashboard {
+                dashboard.record(sample);
+            }
+            info!("stats", "Execs: {:10} | Execs per sec: {:8.0} | Crashes: {:6} | Timeouts: {:6} | Edges: {:6}{}",
+                execs, execs as f64 / elapsed,
+                stats.crashes.load(Ordering::Relaxed),
+                stats.timeouts.load(Ordering::Relaxed),
+                feedback.lock().unwrap().edges(),
+                if opts.pairwise {
+                    format!(" | Pairs: {:8}", pairs.lock().unwrap().len())
+                } else {
+                    String::new()
+                } + &if opts.sanitizer {
+                    format!(" | Bugs: {:4}", bugs.lock().unwrap().values()
+                        .map(|x| x.len()).sum::<usize>())
+                } else {
+                    String::new()
+                } + &match &shared.dedup {
+                    Some(dedup) => format!(" | Dups: {:5.1}%", dedup.rate()),
+                    None => String::new(),
+                } + &match &shared.exec_cache {
+                    Some(cache) => format!(" | Cached: {:5.1}%", cache.rate()),
+                    None => String::new(),
+                } + &if throttle.is_active() {
+                    format!(" | Throttled: {:3.0}%", throttled(throttle,
+                        elapsed, opts.jobs))
+                } else {
+                    String::new()
+                } + &if opts.filter.is_some() {
+                    format!(" | Filtered: {:5.1}%", stats.filter_rate())
+                } else {
+                    String::new()
+                } + &match &shared.histograms {
+                    Some(histograms) => format!(" | Depth p50: {:3} | Size p50: {:7}",
+                        histograms.depth.summary().p50, histograms.size.summary().p50),
+                    None => String::new(),
+                });
+        } Ok(()) })();
+
+        // stop the workers if we bailed out
+        stop.store(true, Ordering::Relaxed);
+        for worker in workers {
+            worker.join().expect("worker panicked")?;
+        }
+        if let Some(server) = server {
+            server.join().expect("dashboard panicked")?;
+        }
+        if let Some(uploader) = uploader {
+            uploader.join().expect("uploader panicked");
+        }
+
+        // workers stop on their own for the exec limit and crashes
+        let execs = stats.execs.load(Ordering::Relaxed);
+        let reason = reason.unwrap_or(if ret.is_err() {
+            StopReason::Error
+        } else if opts.max_execs.is_some_and(|x| execs >= x) {
+            StopReason::MaxExecs
+        } else if opts.stop_on_crash && stats.crashes.load(Ordering::Relaxed) > 0 {
+            StopReason::Crash
+        } else if opts.record.as_ref().is_some_and(|x| x.0 == Mode::Replay) {
+            StopReason::ReplayEnd
+        } else {
+            StopReason::Error
+        });
+
+        // last word on disk and on the console
+        write_stats()?;
+        let report = CampaignReport::new(&shared, &gram,
+            it.elapsed().as_secs_f64(), opts.jobs, reason);
+        report.log();
+        if opts.strategy == Strategy::Markov {
+            info!("campaign", "markov model learned from {} derivations",
+                shared.markov.lock().unwrap().learned());
+        }
+        if opts.adaptive_mutators {
+            for (name, uses, finds) in mutators.stats() {
+                info!("campaign", "mutator {}: {} finds in {} recent uses",
+                    name, finds, uses);
+            }
+            for (group, (_, scheduler)) in opts.workers.iter().zip(&groups) {
+                for (name, uses, finds) in scheduler.stats() {
+                    info!("campaign", "{} workers, mutator {}: {} finds in {} recent uses",
+                        group.role, name, finds, uses);
                 }
             }
-            // let _ = stack.pop();
         }
+        report.save(&shared)?;
+        if let Some(storage) = &storage {
+            info!("storage", "pushing corpus to {}", storage.url());
+            if let Err(e) = storage.push(shared.output.dir(), &opts.instance) {
+                error!("storage", "pushing corpus to {} failed: {}", storage.url(), e);
+            }
+        }
+        ret?;
 
-    }
+        // bounded campaigns gate CI, finding a crash fails them
+        let bounded = opts.max_time.is_some() || opts.max_execs.is_some()
+            || opts.stop_on_crash;
+        if bounded && report.saved_crashes > 0 {
+            std::process::exit(1);
+        }
+        Ok(())
+    })
 }
 
-fn main() -> std::io::Result<()> {
-    // serialize grammar input
-    let grammar: Grammar = serde_json::from_slice(&std::fs::read("test.json")?)?;
-    let gram = GrammarRust::new(&grammar);
-    let mut rng = rand::thread_rng();
-    gram.seed(rng.gen::<i32>() as usize);
-    // print!("{:#?}\n", gram);
-
-    let mut buf = Vec::new();
-    let mut stack = Vec::new();
-    let mut generated = 0usize;
-    let mut it = Instant::now();
-
-    for iters in 1u64.. {
-        buf.clear();
-        gram.generate(&mut stack, &mut buf);
-        generated += buf.len();
-
-        if (iters & 0xffff) == 0{
-            let elapsed = (Instant::now() - it).as_secs_f64();
-            let bytes_per_sec = generated as f64 / elapsed;
-            print!("Bytes per sec: {:12.0} | Example: {:#?}\n", bytes_per_sec, String::from_utf8_lossy(&buf));
+// Run the campaign as opts.processes supervised fuzzer processes sharing
+// the sync dir
+fn orchestrate(opts: &Options, seed: SplitSeed) -> io::Result<()> {
+    let mut orchestrator = Orchestrator::new(std::env::current_exe()?,
+        &opts.out_dir, opts.fuzz_args.clone(), opts.target.clone(),
+        opts.processes, |ii| seed.stream(ii) as u64);
+    let bounded = opts.max_time.is_some() || opts.max_execs.is_some()
+        || opts.stop_on_crash;
+    orchestrator.set_bounded(bounded);
+    let it = Instant::now();
+    signals::install()?;
+
+    let mut last_report = Instant::now();
+    while orchestrator.poll()? {
+        if signals::shutdown_requested() {
+            info!("campaign", "shutting down");
+            break;
+        }
+        if opts.max_time.is_some_and(|x| it.elapsed() >= x) {
+            break;
         }
+        if last_report.elapsed() >= Duration::from_secs(1) {
+            last_report = Instant::now();
+            let stats = orchestrator.stats();
+            info!("stats", "Instances: {}/{} | Restarts: {:4} | Execs: {:10} | Execs per sec: {:8.0} | Corpus: {:6} | Crashes: {:6} | Hangs: {:6}",
+                stats.alive, stats.instances, stats.restarts, stats.execs_done,
+                stats.execs_per_sec, stats.corpus_count, stats.saved_crashes,
+                stats.saved_hangs);
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+    orchestrator.shutdown()?;
+
+    let stats = orchestrator.stats();
+    info!("campaign", "{} execs in {} instances, {} crashes, {} hangs, {} restarts",
+        stats.execs_done, stats.instances, stats.saved_crashes,
+        stats.saved_hangs, stats.restarts);
+    if bounded && stats.saved_crashes > 0 {
+        std::process::exit(1);
     }
     Ok(())
 }
+
+// Share of the worker time spent held back by the throttle, in percent
+fn throttled(throttle: &Throttle, elapsed: f64, jobs: usize) -> f64 {
+    throttle.waited().as_secs_f64() * 100. / (elapsed * jobs as f64).max(1e-9)
+}
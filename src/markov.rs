@@ -0,0 +1,70 @@
+// Alternative weights learned from interesting inputs
+//
+// Flat weights treat every alternative of a rule the same no matter where
+// the rule is used. The Markov strategy counts, over the derivations that
+// made it into the corpus, which alternative a rule took given the
+// alternative it was referenced from (its context, one level up), and
+// generation then picks alternatives in the same context with weight
+// 1 + count. Contexts the corpus has not seen yet stay uniform, so
+// generation keeps exploring while it follows what worked.
+//
+// The campaign learns into one shared model, workers pick up a snapshot
+// of it every now and then (see fuzzer.rs).
+
+use std::collections::HashMap;
+
+use crate::grammar::{Fragment, FragmentId, GrammarRust};
+use crate::tree::Tree;
+
+// Context of the start rule, which has no alternative above it
+const NO_CONTEXT: u32 = u32::MAX;
+
+#[derive(Clone, Debug, Default)]
+pub struct Markov {
+    // times an alternative was taken, by (context, alternative)
+    counts: HashMap<(u32, u32), u32>,
+    // derivations learned from
+    learned: u64,
+}
+
+impl Markov {
+    // Count the choices of a derivation
+    pub fn learn(&mut self, gram: &GrammarRust, tree: &Tree) {
+        // end of the subtree of every open node, with the context its
+        // children are in
+        let mut open: Vec<(usize, Option<FragmentId>)> = Vec::new();
+        for (ii, node) in tree.nodes.iter().enumerate() {
+            while open.last().is_some_and(|&(end, _)| end <= ii) {
+                open.pop();
+            }
+            let context = open.last().and_then(|x| x.1);
+            let end = ii + node.size as usize;
+            match gram.lookup_fragment(node.fragment) {
+                Fragment::NonTerminal(options) => {
+                    if let Some(next) = tree.nodes.get(ii + 1).filter(|_| options.len() > 1) {
+                        *self.counts.entry(key(context, next.fragment)).or_insert(0) += 1;
+                    }
+                    open.push((end, context));
+                }
+                Fragment::Expression(_) => open.push((end, Some(node.fragment))),
+                Fragment::Terminal(_) => {}
+            }
+        }
+        self.learned += 1;
+    }
+
+    // Weight of taking option in context
+    #[inline]
+    pub fn weight(&self, context: Option<FragmentId>, option: FragmentId) -> usize {
+        1 + self.counts.get(&key(context, option)).copied().unwrap_or(0) as usize
+    }
+
+    pub fn learned(&self) -> u64 {
+        self.learned
+    }
+}
+
+#[inline]
+fn key(context: Option<FragmentId>, option: FragmentId) -> (u32, u32) {
+    (context.map_or(NO_CONTEXT, |x| x.0), option.0)
+}
@@ -0,0 +1,283 @@
+// Generating straight into a memory mapped file
+//
+// Targets that parse huge inputs (hundreds of MB) are slow to fuzz the
+// plain way: the worker keeps the executed input in a heap buffer and the
+// executor writes a second copy into the file the target reads (@@).
+// With --mmap-output the worker's output buffer is that file, mapped
+// shared: derivations serialize right into it, the target reads the very
+// same pages, and saving an entry writes them out from the mapping.
+//
+// Everything that produces output (GrammarRust::generate(), serialization
+// of trees) appends to a Buffer, a Vec<u8> or a MappedFile. The file grows
+// in pages as needed and gets truncated to the bytes written before the
+// target sees it, see MappedFile::finish().
+
+#[cfg(unix)]
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::ops::Deref;
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+#[cfg(unix)]
+use std::path::{Path, PathBuf};
+
+// Where generated bytes go
+pub trait Buffer {
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn extend_from_slice(&mut self, data: &[u8]);
+
+    // Bytes written so far, to patch them up
+    fn as_mut_slice(&mut self) -> &mut [u8];
+}
+
+impl Buffer for Vec<u8> {
+    #[inline]
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+
+    #[inline]
+    fn extend_from_slice(&mut self, data: &[u8]) {
+        Vec::extend_from_slice(self, data)
+    }
+
+    #[inline]
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        Vec::as_mut_slice(self)
+    }
+}
+
+// Growable buffer backed by a shared mapping of a file. The file is as
+// long as the mapping while writing, finish() cuts it to the bytes written
+#[cfg(unix)]
+pub struct MappedFile {
+    file: File,
+    path: PathBuf,
+    ptr: *mut u8,
+    len: usize,
+    capacity: usize,
+    // current length of the file
+    file_len: usize,
+}
+
+// SAFETY: the mapping is owned like a heap allocation, only &mut self
+// writes to it
+#[cfg(unix)]
+unsafe impl Send for MappedFile {}
+
+#[cfg(unix)]
+impl MappedFile {
+    // Map a new (or truncated) file at path, nothing is mapped until the
+    // first write
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).create(true)
+            .truncate(true).open(path)
+            .map_err(|e| io::Error::new(e.kind(),
+                format!("{}: {}", path.display(), e)))?;
+        Ok(MappedFile {
+            file,
+            path: path.to_path_buf(),
+            ptr: std::ptr::null_mut(),
+            len: 0,
+            capacity: 0,
+            file_len: 0,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    // Cut the file to the bytes written, for whoever reads it next. Later
+    // writes grow it again
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.file_len != self.len {
+            self.file.set_len(self.len as u64)?;
+            self.file_len = self.len;
+        }
+        Ok(())
+    }
+
+    // Make room for at least need bytes: the file first, a mapping beyond
+    // its end faults on access
+    fn reserve(&mut self, need: usize) -> io::Result<()> {
+        if need > self.capacity {
+            // SAFETY: sysconf has no preconditions
+            let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(4096) as usize;
+            let capacity = need.max(self.capacity * 2).max(1 << 20)
+                .next_multiple_of(page);
+            self.file.set_len(capacity as u64)?;
+            self.file_len = capacity;
+
+            // the bytes live in the file, a new mapping sees them all
+            // SAFETY: mapping a file we own read/write, the old mapping is
+            // ours and nothing borrows it across &mut self
+            unsafe {
+                let ptr = libc::mmap(std::ptr::null_mut(), capacity,
+                    libc::PROT_READ | libc::PROT_WRITE, libc::MAP_SHARED,
+                    self.file.as_raw_fd(), 0);
+                if ptr == libc::MAP_FAILED {
+                    return Err(io::Error::last_os_error());
+                }
+                self.unmap();
+                self.ptr = ptr as *mut u8;
+            }
+            self.capacity = capacity;
+        } else if need > self.file_len {
+            self.file.set_len(self.capacity as u64)?;
+            self.file_len = self.capacity;
+        }
+        Ok(())
+    }
+
+    fn unmap(&mut self) {
+        if !self.ptr.is_null() {
+            // SAFETY: ptr is a mapping of capacity bytes we made
+            unsafe { libc::munmap(self.ptr as *mut libc::c_void, self.capacity) };
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Buffer for MappedFile {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn extend_from_slice(&mut self, data: &[u8]) {
+        let need = self.len + data.len();
+        if need > self.file_len {
+            // like a failed allocation, there is no going on without room
+            self.reserve(need).unwrap_or_else(|e| panic!("growing {} to {} bytes failed: {}",
+                self.path.display(), need, e));
+        }
+        // SAFETY: reserve() made the mapping and the file at least need
+        // bytes long, data cannot point into the mapping past len
+        unsafe {
+            std::ptr::copy_nonoverlapping(data.as_ptr(), self.ptr.add(self.len),
+                data.len());
+        }
+        self.len = need;
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        if self.len == 0 {
+            return &mut [];
+        }
+        // SAFETY: the first len bytes of the mapping are written
+        unsafe { std::slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+#[cfg(unix)]
+impl Deref for MappedFile {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+        // SAFETY: see as_mut_slice
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for MappedFile {
+    fn drop(&mut self) {
+        self.unmap();
+    }
+}
+
+// Output buffer of a worker, on the heap or in the target's input file
+pub enum Output {
+    Heap(Vec<u8>),
+    #[cfg(unix)]
+    Mapped(MappedFile),
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Output::Heap(Vec::new())
+    }
+}
+
+impl Output {
+    // Output mapped from the file at path, where not supported the error
+    // says so
+    #[cfg(unix)]
+    pub fn mapped(path: &Path) -> io::Result<Self> {
+        MappedFile::create(path).map(Output::Mapped)
+    }
+
+    #[cfg(not(unix))]
+    pub fn mapped(_path: &std::path::Path) -> io::Result<Self> {
+        Err(io::Error::new(io::ErrorKind::Unsupported,
+            "memory mapped output is only supported on unix"))
+    }
+
+    pub fn is_mapped(&self) -> bool {
+        !matches!(self, Output::Heap(_))
+    }
+
+    pub fn clear(&mut self) {
+        match self {
+            Output::Heap(buf) => buf.clear(),
+            #[cfg(unix)]
+            Output::Mapped(file) => file.clear(),
+        }
+    }
+
+    // Bring the mapped file in step with the bytes, see MappedFile::finish()
+    pub fn finish(&mut self) -> io::Result<()> {
+        match self {
+            Output::Heap(_) => Ok(()),
+            #[cfg(unix)]
+            Output::Mapped(file) => file.finish(),
+        }
+    }
+}
+
+impl Buffer for Output {
+    fn len(&self) -> usize {
+        self.deref().len()
+    }
+
+    #[inline]
+    fn extend_from_slice(&mut self, data: &[u8]) {
+        match self {
+            Output::Heap(buf) => buf.extend_from_slice(data),
+            #[cfg(unix)]
+            Output::Mapped(file) => Buffer::extend_from_slice(file, data),
+        }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        match self {
+            Output::Heap(buf) => buf.as_mut_slice(),
+            #[cfg(unix)]
+            Output::Mapped(file) => Buffer::as_mut_slice(file),
+        }
+    }
+}
+
+impl Deref for Output {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Output::Heap(buf) => buf,
+            #[cfg(unix)]
+            Output::Mapped(file) => file,
+        }
+    }
+}
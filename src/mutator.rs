@@ -0,0 +1,513 @@
+// Mutation pipeline
+//
+// Every way of deriving a new input from an old one is a Mutator, the
+// Scheduler decides which of them run on an input. It stacks a few
+// weighted picks per iteration and then gives the off grammar mutators
+// (havoc, error injection) their chance. Tree mutators keep the derivation
+// in step with the bytes, once a byte level one ran the input is off
+// grammar and only byte level mutators apply to it. Downstream crates add
+// their own mutators by implementing the trait and pushing them onto a
+// Scheduler handed to fuzzer::run_worker().
+
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::grammar::{GeneratorState, GrammarRust};
+use crate::havoc::havoc;
+use crate::inject;
+use crate::record::Session;
+use crate::tree::{self, Node, Tree, TreeStack};
+use crate::debug;
+
+// Picks tried per stack slot before giving up on it
+const RETRIES: usize = 4;
+
+// The input being mutated: a derivation and its bytes, serialized lazily.
+// Along with the bytes it keeps the byte range of every node, so replacing
+// a subtree of a big input splices the new bytes in instead of serializing
+// everything again
+#[derive(Clone, Debug, Default)]
+pub struct TestCase {
+    tree: Tree,
+    bytes: Vec<u8>,
+
+    // bytes lag behind the tree
+    dirty: bool,
+
+    // the tree does not derive the bytes any more
+    off_grammar: bool,
+
+    // byte range of every node (see Tree::serialize_spans()), if in step
+    // with the bytes
+    spans: Vec<(usize, usize)>,
+    spans_valid: bool,
+
+    // serialization of a replacement subtree
+    splice: Vec<u8>,
+}
+
+impl TestCase {
+    // Start over from a derivation, to be filled in by the caller
+    pub fn reset_tree(&mut self) -> &mut Tree {
+        self.dirty = true;
+        self.off_grammar = false;
+        self.spans_valid = false;
+        &mut self.tree
+    }
+
+    // Start over from another test case, its bytes and spans come along
+    pub fn reset_from(&mut self, other: &TestCase) {
+        self.clone_from(other);
+    }
+
+    // Start over from raw bytes, to be filled in by the caller
+    pub fn reset_bytes(&mut self) -> &mut Vec<u8> {
+        self.bytes.clear();
+        self.dirty = false;
+        self.off_grammar = true;
+        self.spans_valid = false;
+        &mut self.bytes
+    }
+
+    // Derivation of the input, None once it went off grammar
+    pub fn tree(&self) -> Option<&Tree> {
+        (!self.off_grammar).then_some(&self.tree)
+    }
+
+    // Derivation to mutate, the bytes follow on the next bytes() call
+    pub fn tree_mut(&mut self) -> Option<&mut Tree> {
+        if self.off_grammar {
+            return None;
+        }
+        self.dirty = true;
+        self.spans_valid = false;
+        Some(&mut self.tree)
+    }
+
+    // Replace the subtree rooted at idx, splicing its bytes if they are
+    // serialized already. False if the input is off grammar
+    pub fn replace_subtree(&mut self, gram: &GrammarRust, idx: usize,
+            nodes: &[Node]) -> bool {
+        if self.off_grammar {
+            return false;
+        }
+        if self.dirty || !self.spans_valid {
+            self.tree.replace_subtree(idx, nodes);
+            self.dirty = true;
+            self.spans_valid = false;
+            return true;
+        }
+
+        let old = self.tree.nodes[idx].size as usize;
+        let (start, end) = self.spans[idx];
+        self.splice.clear();
+        let mut spans = Vec::with_capacity(nodes.len());
+        tree::serialize_spans(gram, nodes, &mut self.splice, &mut spans);
+        let delta = self.splice.len() as isize - (end - start) as isize;
+        let shift = |x: usize| x.wrapping_add_signed(delta);
+
+        // ancestors end later (or earlier), everything behind moves
+        for ii in 0..idx {
+            if ii + self.tree.nodes[ii].size as usize > idx {
+                self.spans[ii].1 = shift(self.spans[ii].1);
+            }
+        }
+        for span in &mut self.spans[idx + old..] {
+            *span = (shift(span.0), shift(span.1));
+        }
+        self.spans.splice(idx..idx + old, spans.into_iter()
+            .map(|(from, to)| (from + start, to + start)));
+        self.bytes.splice(start..end, self.splice.iter().copied());
+        self.tree.replace_subtree(idx, nodes);
+        true
+    }
+
+    // The bytes are in step with the tree, bytes() costs nothing
+    pub fn is_serialized(&self) -> bool {
+        !self.dirty
+    }
+
+    pub fn bytes(&mut self, gram: &GrammarRust) -> &[u8] {
+        self.serialize(gram);
+        &self.bytes
+    }
+
+    // Bytes to mutate, this takes the input off grammar
+    pub fn bytes_mut(&mut self, gram: &GrammarRust) -> &mut Vec<u8> {
+        self.serialize(gram);
+        self.off_grammar = true;
+        &mut self.bytes
+    }
+
+    // Bring the bytes in step with the tree, and the spans with them
+    pub fn serialize(&mut self, gram: &GrammarRust) {
+        if self.dirty {
+            self.bytes.clear();
+            self.tree.serialize_spans(gram, &mut self.bytes, &mut self.spans);
+            self.dirty = false;
+            self.spans_valid = true;
+        }
+    }
+}
+
+// Buffers mutators may use, reused across iterations
+#[derive(Default)]
+pub struct Scratch {
+    pub stack: TreeStack,
+    pub tree: Tree,
+    pub choices: Vec<u8>,
+}
+
+// What a mutator gets to work with besides the input
+pub struct MutationContext<'a> {
+    pub gram: &'a GrammarRust,
+    pub state: &'a mut GeneratorState,
+    pub scratch: &'a mut Scratch,
+
+    // the previous input, to splice bytes from
+    pub splice: &'a [u8],
+
+    // detail a mutator wants in the op label after its name
+    pub note: String,
+
+    // campaign recording or replay, see record.rs
+    pub session: Option<&'a mut Session>,
+}
+
+pub trait Mutator: Send + Sync {
+    // Label of the mutation in op of saved entries
+    fn name(&self) -> &str;
+
+    // Mutate input in place, false if the mutator does not apply to it
+    fn mutate(&self, ctx: &mut MutationContext, input: &mut TestCase) -> bool;
+}
+
+// Swap a keyword or digit for a sibling alternative
+pub struct TerminalSwap;
+
+impl Mutator for TerminalSwap {
+    fn name(&self) -> &str {
+        "swap"
+    }
+
+    fn mutate(&self, ctx: &mut MutationContext, input: &mut TestCase) -> bool {
+        input.tree().and_then(|tree| ctx.gram.terminal_swap(ctx.state, tree))
+            .is_some_and(|(idx, nodes)| input.replace_subtree(ctx.gram, idx, &nodes))
+    }
+}
+
+// Unroll a recursive rule once more
+pub struct Recursion;
+
+impl Mutator for Recursion {
+    fn name(&self) -> &str {
+        "recursion"
+    }
+
+    fn mutate(&self, ctx: &mut MutationContext, input: &mut TestCase) -> bool {
+        let scratch = &mut ctx.scratch.tree;
+        input.tree().and_then(|tree| ctx.gram.unroll_recursion(ctx.state, tree, scratch))
+            .is_some_and(|idx| input.replace_subtree(ctx.gram, idx, &scratch.nodes))
+    }
+}
+
+// Edit the choice sequence of the derivation and replay it
+pub struct Choices;
+
+impl Mutator for Choices {
+    fn name(&self) -> &str {
+        "choices"
+    }
+
+    fn mutate(&self, ctx: &mut MutationContext, input: &mut TestCase) -> bool {
+        let Some(tree) = input.tree_mut() else {
+            return false;
+        };
+        let choices = &mut ctx.scratch.choices;
+        choices.clear();
+        tree.choices(ctx.gram, choices);
+        ctx.gram.mutate_choices(ctx.state, choices);
+        ctx.gram.replay_full_choices(choices, &mut ctx.scratch.stack, tree);
+        true
+    }
+}
+
+// Regenerate a random subtree
+pub struct Subtree;
+
+impl Mutator for Subtree {
+    fn name(&self) -> &str {
+        "subtree"
+    }
+
+    fn mutate(&self, ctx: &mut MutationContext, input: &mut TestCase) -> bool {
+        let Some(tree) = input.tree() else {
+            return false;
+        };
+        let scratch = &mut ctx.scratch;
+        // a tree without non-terminals was left as it is before
+        let Some(idx) = ctx.gram.derive_subtree(ctx.state, tree, &mut scratch.stack,
+                &mut scratch.tree) else {
+            return true;
+        };
+        input.replace_subtree(ctx.gram, idx, &scratch.tree.nodes)
+    }
+}
+
+// Byte level havoc, see havoc.rs
+pub struct Havoc;
+
+impl Mutator for Havoc {
+    fn name(&self) -> &str {
+        "havoc"
+    }
+
+    fn mutate(&self, ctx: &mut MutationContext, input: &mut TestCase) -> bool {
+        let state = &mut *ctx.state;
+        havoc(input.bytes_mut(ctx.gram), ctx.splice, &mut || state.rand());
+        true
+    }
+}
+
+// Break this many things in a valid input, see inject.rs
+pub struct Inject(pub usize);
+
+impl Mutator for Inject {
+    fn name(&self) -> &str {
+        "inject"
+    }
+
+    fn mutate(&self, ctx: &mut MutationContext, input: &mut TestCase) -> bool {
+        if input.off_grammar {
+            return false;
+        }
+        let violations = ctx.gram.inject_errors(ctx.state, &input.tree, self.0,
+            &mut input.bytes);
+        // whatever happened, the bytes are the plain serialization now
+        input.dirty = false;
+        input.spans_valid = false;
+        if violations.is_empty() {
+            return false;
+        }
+        for violation in &violations {
+            debug!("generator", "injected {}", violation);
+        }
+        input.off_grammar = true;
+        ctx.note = format!("viol:{}", inject::tag(&violations));
+        true
+    }
+}
+
+// Decides which mutators run on an input
+//
+// Stacked mutators are picked by their static weight, or adaptively
+// (MOpt style): then every pick is weighted by how often the mutator led
+// to something new recently, (finds + 1) / (uses + 2) times its static
+// weight, with a share of EXPLORE spread evenly so that no mutator starves
+// for good. Counters are shared by all workers and halved once a mutator
+// has been used WINDOW times, so the mix follows the campaign as it goes.
+pub struct Scheduler {
+    // picked by weight, up to max_stack per iteration
+    stacked: Vec<Stacked>,
+    total: usize,
+    max_stack: usize,
+    adaptive: bool,
+
+    // tried after the stack in order, each with its probability, at most
+    // one of them applies
+    finishers: Vec<(Box<dyn Mutator>, f64)>,
+}
+
+struct Stacked {
+    mutator: Box<dyn Mutator>,
+    weight: usize,
+
+    // rounds the mutator took part in, and those that found something
+    uses: AtomicU64,
+    finds: AtomicU64,
+}
+
+// Share of the picks spread evenly in adaptive mode
+const EXPLORE: f64 = 0.1;
+
+// Uses after which a mutator's counters are halved
+const WINDOW: u64 = 10_000;
+
+// The tree mutators Scheduler::standard() stacks, with their weights
+pub fn grammar_mutators() -> Vec<(Box<dyn Mutator>, usize)> {
+    vec![
+        (Box::new(TerminalSwap), 2),
+        (Box::new(Recursion), 1),
+        (Box::new(Choices), 1),
+        (Box::new(Subtree), 4),
+    ]
+}
+
+impl Scheduler {
+    pub fn new(max_stack: usize) -> Self {
+        Scheduler {
+            stacked: Vec::new(),
+            total: 0,
+            max_stack: max_stack.max(1),
+            adaptive: false,
+            finishers: Vec::new(),
+        }
+    }
+
+    // The grammar mutators and, if enabled, havoc with probability havoc
+    // and error injection of inject violations with probability inject_rate
+    pub fn standard(max_stack: usize, havoc: f64, inject: usize,
+            inject_rate: f64) -> Self {
+        let mut scheduler = Scheduler::new(max_stack);
+        for (mutator, weight) in grammar_mutators() {
+            scheduler.push(mutator, weight);
+        }
+        if havoc > 0.0 {
+            scheduler.push_finisher(Box::new(Havoc), havoc);
+        }
+        if inject > 0 && inject_rate > 0.0 {
+            scheduler.push_finisher(Box::new(Inject(inject)), inject_rate);
+        }
+        scheduler
+    }
+
+    // Weight stacked picks by recent success, see above
+    pub fn set_adaptive(&mut self, adaptive: bool) {
+        self.adaptive = adaptive;
+    }
+
+    pub fn push(&mut self, mutator: Box<dyn Mutator>, weight: usize) {
+        self.total += weight;
+        self.stacked.push(Stacked {
+            mutator,
+            weight,
+            uses: AtomicU64::new(0),
+            finds: AtomicU64::new(0),
+        });
+    }
+
+    pub fn push_finisher(&mut self, mutator: Box<dyn Mutator>, probability: f64) {
+        self.finishers.push((mutator, probability));
+    }
+
+    // Mutate a corpus input: a stack of weighted picks, then the finishers.
+    // What was applied goes to op, joined by '+', the stacked mutators that
+    // applied to applied for report(). False if nothing applied, errors
+    // only come from the session
+    pub fn mutate(&self, ctx: &mut MutationContext, input: &mut TestCase,
+            op: &mut String, applied: &mut Vec<usize>) -> io::Result<bool> {
+        op.clear();
+        applied.clear();
+        if self.total > 0 {
+            let stack = 1 + ctx.state.rand() % self.max_stack;
+            for _ in 0..stack {
+                for _ in 0..RETRIES {
+                    let idx = self.pick(ctx.state, ctx.session.as_deref_mut())?;
+                    if apply(self.stacked[idx].mutator.as_ref(), ctx, input, op) {
+                        applied.push(idx);
+                        break;
+                    }
+                }
+            }
+        }
+        self.finish(ctx, input, op);
+        Ok(!op.is_empty())
+    }
+
+    // Give the finishers their chance on an input, fresh generations only
+    // get this. Appends to op like mutate()
+    pub fn finish(&self, ctx: &mut MutationContext, input: &mut TestCase,
+            op: &mut String) -> bool {
+        for (mutator, probability) in &self.finishers {
+            if chance(ctx.state, *probability)
+                    && apply(mutator.as_ref(), ctx, input, op) {
+                return true;
+            }
+        }
+        false
+    }
+
+    // Book the outcome of a round of mutate(): found is whether the input
+    // turned out new (coverage, crash, ...)
+    pub fn report(&self, applied: &[usize], found: bool) {
+        for &idx in applied {
+            let stacked = &self.stacked[idx];
+            let uses = stacked.uses.fetch_add(1, Ordering::Relaxed) + 1;
+            if found {
+                stacked.finds.fetch_add(1, Ordering::Relaxed);
+            }
+            // workers crossing the window together halve it once, the
+            // one that got to uses first. Finds of other workers may come
+            // in between, the counters only need to be roughly right
+            if uses >= WINDOW && stacked.uses.fetch_update(Ordering::Relaxed,
+                    Ordering::Relaxed, |x| (x >= WINDOW).then_some(x / 2)).is_ok() {
+                let _ = stacked.finds.fetch_update(Ordering::Relaxed, Ordering::Relaxed,
+                    |x| Some(x / 2));
+            }
+        }
+    }
+
+    // Name, uses and finds of every stacked mutator, within the current
+    // window
+    pub fn stats(&self) -> impl Iterator<Item = (&str, u64, u64)> {
+        self.stacked.iter().map(|x| (x.mutator.name(),
+            x.uses.load(Ordering::Relaxed), x.finds.load(Ordering::Relaxed)))
+    }
+
+    // The adaptive weights are shared, a session records or replays what
+    // they made of the draw
+    fn pick(&self, state: &mut GeneratorState, session: Option<&mut Session>)
+            -> io::Result<usize> {
+        if !self.adaptive {
+            let mut pick = state.rand() % self.total;
+            return Ok(self.stacked.iter()
+                .position(|x| match pick.checked_sub(x.weight) {
+                    Some(rest) => {
+                        pick = rest;
+                        false
+                    }
+                    None => true,
+                })
+                .expect("pick is below the total weight"));
+        }
+
+        let mass = |x: &Stacked| x.weight as f64
+            * (x.finds.load(Ordering::Relaxed) + 1) as f64
+            / (x.uses.load(Ordering::Relaxed) + 2) as f64;
+        let total = self.stacked.iter().map(mass).sum::<f64>();
+        let even = EXPLORE / self.stacked.len() as f64;
+        let mut pick = (state.rand() % 1_000_000) as f64 / 1_000_000.0;
+        let idx = self.stacked.iter()
+            .position(|stacked| {
+                pick -= even + (1.0 - EXPLORE) * mass(stacked) / total;
+                pick < 0.0
+            })
+            .unwrap_or(self.stacked.len() - 1);
+        match session {
+            Some(session) => session.index(b'P', idx),
+            None => Ok(idx),
+        }
+    }
+}
+
+fn apply(mutator: &dyn Mutator, ctx: &mut MutationContext, input: &mut TestCase,
+        op: &mut String) -> bool {
+    ctx.note.clear();
+    if !mutator.mutate(ctx, input) {
+        return false;
+    }
+    if !op.is_empty() {
+        op.push('+');
+    }
+    op.push_str(mutator.name());
+    if !ctx.note.is_empty() {
+        op.push(',');
+        op.push_str(&ctx.note);
+    }
+    true
+}
+
+// True with probability p
+fn chance(state: &mut GeneratorState, p: f64) -> bool {
+    p > 0.0 && (state.rand() % 1_000_000) < (p * 1_000_000.0) as usize
+}
@@ -0,0 +1,303 @@
+// Terminals for numbers in a range
+//
+// Numeric parsers fail at the edges: 0, -1, the limits of the type, powers
+// of two and their neighbours, NaN. Picking numbers uniformly almost never
+// hits them. A symbol of the form <kind:lo..hi> stands for a number of
+// the range, written as text:
+//
+//   "<int:-128..127>"            decimal
+//   "<hex:0..0xffff>"            hexadecimal digits, no prefix
+//   "<float:-1.5..1e6>"          decimal with a fraction, or a special value
+//   "<int:0..65535,bias=0.8>"    share of special values, 0.5 without
+//
+// The special values are the bounds and their neighbours, 0, 1, -1, powers
+// of two and their neighbours, all of them as long as they are in range,
+// and for floats NaN, infinities, -0.0, the smallest and largest doubles.
+// The rest of the time the number is uniform over the patterns of digits
+// the range splits into (0..255 is [0-9], [1-9][0-9], 1[0-9][0-9],
+// 2[0-4][0-9], 25[0-5]), not over the numbers.
+//
+// expand() adds a rule named like the symbol when loading the grammar,
+// with sub rules for the special values, the patterns and the digits. A
+// rule of the grammar with the same name takes precedence.
+
+use std::collections::HashMap;
+
+use crate::grammar::Grammar;
+
+// Alternatives the choice between special and uniform is spread over
+const BIAS_SLOTS: usize = 20;
+
+const DEFAULT_BIAS: f64 = 0.5;
+
+// Floats always worth a try, whatever the range
+const FLOAT_ALWAYS: &[&str] = &["NaN", "nan", "inf", "-inf", "Infinity", "-Infinity"];
+
+// Floats tried when in range
+const FLOAT_SPECIAL: &[&str] = &[
+    "0.0", "-0.0", "1.0", "-1.0", "0.1", "0.5", "1e-7", "2.220446049250313e-16",
+    "5e-324", "2.2250738585072014e-308", "1.7976931348623157e308",
+    "-1.7976931348623157e308", "9007199254740993", "1e16", "1e22", "1e23",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Int { lo: i128, hi: i128, base: u32 },
+    Float { lo: f64, hi: f64 },
+}
+
+fn parse_int(text: &str) -> Option<i128> {
+    let (negative, digits) = match text.trim().strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.trim()),
+    };
+    let value = match digits.strip_prefix("0x") {
+        Some(hex) => i128::from_str_radix(hex, 16).ok()?,
+        None => digits.parse::<i128>().ok()?,
+    };
+    Some(if negative { -value } else { value })
+}
+
+// Kind and bias of a symbol: None if it is not one, an error if it looks
+// like one but is malformed
+fn parse(symbol: &str) -> Option<Result<(Kind, f64), String>> {
+    let inner = symbol.strip_prefix('<')?.strip_suffix('>')?;
+    let (kind, spec) = inner.split_once(':')?;
+    if !matches!(kind, "int" | "hex" | "float") {
+        return None;
+    }
+    let error = |what: &str| format!("{}: {}", symbol, what);
+    let (range, bias) = match spec.split_once(',') {
+        Some((range, option)) => match option.trim().strip_prefix("bias=")
+                .and_then(|x| x.parse::<f64>().ok())
+                .filter(|x| (0.0..=1.0).contains(x)) {
+            Some(bias) => (range, bias),
+            None => return Some(Err(error("the option is bias=<0 to 1>"))),
+        },
+        None => (spec, DEFAULT_BIAS),
+    };
+    let Some((lo, hi)) = range.split_once("..") else {
+        return Some(Err(error("the range is lo..hi")));
+    };
+    let kind = match kind {
+        "float" => match (lo.trim().parse::<f64>(), hi.trim().parse::<f64>()) {
+            (Ok(lo), Ok(hi)) if lo <= hi && lo.is_finite() && hi.is_finite() =>
+                Kind::Float { lo, hi },
+            _ => return Some(Err(error("bounds are finite numbers, lo <= hi"))),
+        },
+        _ => match (parse_int(lo), parse_int(hi)) {
+            (Some(lo), Some(hi)) if lo <= hi && lo >= i64::MIN as i128
+                    && hi <= u64::MAX as i128 =>
+                Kind::Int { lo, hi, base: if kind == "hex" { 16 } else { 10 } },
+            _ => return Some(Err(error("bounds are 64 bit integers, lo <= hi"))),
+        },
+    };
+    Some(Ok((kind, bias)))
+}
+
+fn digits(mut value: u128, base: u32) -> Vec<u32> {
+    let mut ret = Vec::new();
+    loop {
+        ret.push((value % base as u128) as u32);
+        value /= base as u128;
+        if value == 0 {
+            break;
+        }
+    }
+    ret.reverse();
+    ret
+}
+
+fn render(value: i128, base: u32) -> String {
+    let text = digits(value.unsigned_abs(), base).iter()
+        .map(|&x| char::from_digit(x, base).unwrap()).collect::<String>();
+    if value < 0 { format!("-{}", text) } else { text }
+}
+
+// Digit ranges, one per position, of the numbers lo..=hi with as many
+// digits as hi has and a first digit that is not 0 (unless the number is)
+fn same_length(lo: &[u32], hi: &[u32], base: u32, prefix: &mut Vec<(u32, u32)>,
+        out: &mut Vec<Vec<(u32, u32)>>) {
+    let last = base - 1;
+    if lo.len() == 1 {
+        prefix.push((lo[0], hi[0]));
+        out.push(prefix.clone());
+        prefix.pop();
+        return;
+    }
+    if lo[0] == hi[0] {
+        prefix.push((lo[0], lo[0]));
+        same_length(&lo[1..], &hi[1..], base, prefix, out);
+        prefix.pop();
+        return;
+    }
+    let (mut first, mut end) = (lo[0], hi[0]);
+    if lo[1..].iter().any(|&x| x != 0) {
+        prefix.push((lo[0], lo[0]));
+        same_length(&lo[1..], &vec![last; lo.len() - 1], base, prefix, out);
+        prefix.pop();
+        first += 1;
+    }
+    let trailing = hi[1..].iter().any(|&x| x != last);
+    if trailing {
+        end -= 1;
+    }
+    if first <= end {
+        let mut run = prefix.clone();
+        run.push((first, end));
+        run.extend(std::iter::repeat_n((0, last), lo.len() - 1));
+        out.push(run);
+    }
+    if trailing {
+        prefix.push((hi[0], hi[0]));
+        same_length(&vec![0; hi.len() - 1], &hi[1..], base, prefix, out);
+        prefix.pop();
+    }
+}
+
+// Digit patterns of lo..=hi, non-negative
+fn patterns(lo: u128, hi: u128, base: u32) -> Vec<Vec<(u32, u32)>> {
+    let mut out = Vec::new();
+    let mut from = lo;
+    while from <= hi {
+        // up to the largest number with as many digits as from
+        let len = digits(from, base).len() as u32;
+        let to = (base as u128).checked_pow(len).map_or(hi, |x| (x - 1).min(hi));
+        same_length(&digits(from, base), &digits(to, base), base, &mut Vec::new(),
+            &mut out);
+        from = to + 1;
+    }
+    out
+}
+
+struct Rules<'a> {
+    name: &'a str,
+    added: &'a mut HashMap<String, Vec<Vec<String>>>,
+}
+
+impl Rules<'_> {
+    // Symbol of a digit range
+    fn digit(&mut self, (lo, hi): (u32, u32), base: u32) -> String {
+        let char = |x| char::from_digit(x, base).unwrap().to_string();
+        if lo == hi {
+            return char(lo);
+        }
+        let name = format!("<numeric digit {}-{}>", char(lo), char(hi));
+        self.added.entry(name.clone())
+            .or_insert_with(|| (lo..=hi).map(|x| vec![char(x)]).collect());
+        name
+    }
+
+    // Alternatives writing lo..=hi uniformly over digit patterns
+    fn uniform_int(&mut self, lo: i128, hi: i128, base: u32) -> Vec<Vec<String>> {
+        let mut ret = Vec::new();
+        if lo < 0 {
+            let magnitudes = (hi.min(-1).unsigned_abs(), lo.unsigned_abs());
+            for pattern in patterns(magnitudes.0, magnitudes.1, base) {
+                ret.push(std::iter::once("-".to_string())
+                    .chain(pattern.into_iter().map(|x| self.digit(x, base))).collect());
+            }
+        }
+        if hi >= 0 {
+            for pattern in patterns(lo.max(0) as u128, hi as u128, base) {
+                ret.push(pattern.into_iter().map(|x| self.digit(x, base)).collect());
+            }
+        }
+        ret
+    }
+
+    // Add a sub rule of the symbol's rule, returning its name
+    fn sub_rule(&mut self, what: &str, alternatives: Vec<Vec<String>>) -> String {
+        let name = format!("{} {}>", &self.name[..self.name.len() - 1], what);
+        self.added.insert(name.clone(), alternatives);
+        name
+    }
+}
+
+// Special values of an integer range, in order
+fn special_ints(lo: i128, hi: i128) -> Vec<i128> {
+    let mut ret = vec![lo, lo + 1, hi - 1, hi, -1, 0, 1];
+    for bits in 1..=64 {
+        let power = 1i128 << bits;
+        ret.extend([power - 1, power, power + 1, -power, -power + 1, -power - 1]);
+    }
+    ret.retain(|x| (lo..=hi).contains(x));
+    ret.sort_unstable();
+    ret.dedup();
+    ret
+}
+
+// Alternatives of the rule of a symbol
+fn alternatives(name: &str, kind: Kind, bias: f64,
+        added: &mut HashMap<String, Vec<Vec<String>>>) -> Vec<Vec<String>> {
+    let mut rules = Rules { name, added };
+    let (special, uniform) = match kind {
+        Kind::Int { lo, hi, base } => (
+            special_ints(lo, hi).into_iter().map(|x| vec![render(x, base)]).collect(),
+            rules.uniform_int(lo, hi, base),
+        ),
+        Kind::Float { lo, hi } => {
+            let mut special = FLOAT_ALWAYS.iter().map(|x| x.to_string())
+                .chain(FLOAT_SPECIAL.iter().filter(|x| x.parse::<f64>()
+                    .is_ok_and(|x| (lo..=hi).contains(&x))).map(|x| x.to_string()))
+                .chain([lo.to_string(), hi.to_string()])
+                .collect::<Vec<_>>();
+            special.dedup();
+
+            // k.fraction stays between k and its neighbour away from 0,
+            // which has to be in range as well
+            let (first, last) = (lo.ceil() as i128, hi.floor() as i128);
+            let mut uniform = rules.uniform_int(first, last, 10);
+            if first < last - 1 {
+                let digit = rules.digit((0, 9), 10);
+                let fraction = rules.sub_rule("fraction", (1..=3)
+                    .map(|len| vec![digit.clone(); len]).collect());
+                for mut alternative in rules.uniform_int(first + 1, last - 1, 10) {
+                    alternative.extend([".".to_string(), fraction.clone()]);
+                    uniform.push(alternative);
+                }
+            }
+            (special.into_iter().map(|x| vec![x]).collect(), uniform)
+        }
+    };
+    // the share of special values, in slots of equally likely alternatives
+    let slots = ((bias * BIAS_SLOTS as f64).round() as usize)
+        .clamp(usize::from(bias > 0.0), BIAS_SLOTS - usize::from(bias < 1.0));
+    if uniform.is_empty() || slots == BIAS_SLOTS {
+        return special;
+    }
+    if slots == 0 {
+        return uniform;
+    }
+    let special = rules.sub_rule("special", special);
+    let uniform = rules.sub_rule("uniform", uniform);
+    (0..BIAS_SLOTS).map(|ii| vec![if ii < slots { special.clone() } else { uniform.clone() }])
+        .collect()
+}
+
+// Whether a symbol is of the form <kind:lo..hi>, well formed or not
+pub fn is_symbol(symbol: &str) -> bool {
+    parse(symbol).is_some()
+}
+
+// Add the rules for the numeric symbols of the grammar, see the top of the
+// file. Errors name malformed ones
+pub fn expand(grammar: &mut Grammar) -> Result<(), String> {
+    let mut symbols = grammar.0.values().flatten().flatten()
+        .filter(|x| !grammar.0.contains_key(*x))
+        .filter_map(|x| parse(x).map(|spec| (x.clone(), spec)))
+        .collect::<Vec<_>>();
+    symbols.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    symbols.dedup_by(|a, b| a.0 == b.0);
+
+    let mut added = HashMap::new();
+    for (symbol, spec) in symbols {
+        let (kind, bias) = spec?;
+        let alternatives = alternatives(&symbol, kind, bias, &mut added);
+        added.insert(symbol, alternatives);
+    }
+    for (name, alternatives) in added {
+        grammar.0.entry(name).or_insert(alternatives);
+    }
+    Ok(())
+}
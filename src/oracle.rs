@@ -0,0 +1,156 @@
+// Bugs that do not crash the target
+//
+// Signals and sanitizer reports only catch memory corruption. An Oracle
+// looks at an execution that ended normally and says whether the target
+// misbehaved anyway: printed something it never should, exited with an
+// error code, panicked in a way it handled, or answered differently than a
+// golden (reference) build of it. Such executions are findings like
+// crashes, saved in crashes/ (sig:00) and bucketed by oracle and what it
+// saw, like sanitizer reports are by bug type and function.
+//
+// Oracles belong to one worker, the differential oracle runs the golden
+// build with an executor of its own. Oracles see the stdout and stderr of
+// process targets only when those are captured, see OracleSpec.
+
+use std::collections::BTreeSet;
+use std::io;
+
+use crate::executor::Executor;
+use crate::feedback::{Observation, Pattern};
+
+pub trait Oracle {
+    // Bug type of its findings
+    fn name(&self) -> &str;
+
+    // What went wrong in an execution, None if nothing did
+    fn check(&mut self, obs: &Observation) -> io::Result<Option<String>>;
+}
+
+// Output (stdout, network response) matching a pattern
+pub struct OutputContains(pub Pattern);
+
+impl Oracle for OutputContains {
+    fn name(&self) -> &str {
+        "output"
+    }
+
+    fn check(&mut self, obs: &Observation) -> io::Result<Option<String>> {
+        Ok(self.0.find(&obs.result.output)
+            .map(|text| String::from_utf8_lossy(text).into_owned()))
+    }
+}
+
+// stderr matching a pattern, "panicked at" for Rust targets catching
+// their panics
+pub struct StderrContains(pub Pattern);
+
+impl Oracle for StderrContains {
+    fn name(&self) -> &str {
+        "stderr"
+    }
+
+    fn check(&mut self, obs: &Observation) -> io::Result<Option<String>> {
+        Ok(self.0.find(&obs.result.stderr)
+            .map(|text| String::from_utf8_lossy(text).into_owned()))
+    }
+}
+
+// Exit code in a set
+pub struct ExitCodeIn(pub BTreeSet<i32>);
+
+impl Oracle for ExitCodeIn {
+    fn name(&self) -> &str {
+        "exit-code"
+    }
+
+    fn check(&mut self, obs: &Observation) -> io::Result<Option<String>> {
+        Ok(obs.result.code.filter(|x| self.0.contains(x))
+            .map(|code| format!("exit code {}", code)))
+    }
+}
+
+// Golden build answering the same input differently: other output or other
+// exit code. Inputs the golden build fails on itself are not judged
+pub struct Differential {
+    golden: Box<dyn Executor>,
+}
+
+impl Differential {
+    // golden has to capture output like the fuzzed target does
+    pub fn new(golden: Box<dyn Executor>) -> Self {
+        Differential { golden }
+    }
+}
+
+impl Oracle for Differential {
+    fn name(&self) -> &str {
+        "differential"
+    }
+
+    fn check(&mut self, obs: &Observation) -> io::Result<Option<String>> {
+        let golden = self.golden.run(obs.input)?;
+        if golden.exit != obs.result.exit {
+            return Ok(None);
+        }
+        Ok(if golden.code != obs.result.code {
+            Some(format!("exit code {:?} instead of {:?}", obs.result.code,
+                golden.code))
+        } else if golden.output != obs.result.output {
+            Some("output differs".to_string())
+        } else {
+            None
+        })
+    }
+}
+
+// Built-in oracles as given on the command line: output:<pattern>,
+// stderr:<pattern>, exit-code:<code>[,<code>...] or
+// differential:<golden build>
+#[derive(Clone, Debug)]
+pub enum OracleSpec {
+    Output(Pattern),
+    Stderr(Pattern),
+    ExitCode(BTreeSet<i32>),
+    Differential(String),
+}
+
+impl OracleSpec {
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (kind, arg) = spec.split_once(':').unwrap_or((spec, ""));
+        match kind {
+            "output" | "stderr" | "exit-code" | "differential" if arg.is_empty() =>
+                Err(format!("{}: expected {}:<argument>", spec, kind)),
+            "output" => Pattern::parse(arg).map(OracleSpec::Output),
+            "stderr" => Pattern::parse(arg).map(OracleSpec::Stderr),
+            "exit-code" => arg.split(',').map(|x| x.trim().parse().ok())
+                .collect::<Option<_>>().map(OracleSpec::ExitCode)
+                .ok_or_else(|| format!("{}: expected exit-code:<code>[,<code>...]",
+                    spec)),
+            "differential" => Ok(OracleSpec::Differential(arg.to_string())),
+            _ => Err(format!("{}: unknown oracle", spec)),
+        }
+    }
+
+    // golden sets up the executor of a golden build, for the differential
+    // oracle
+    pub fn build(&self, golden: impl FnOnce(&str)
+            -> io::Result<Box<dyn Executor>>) -> io::Result<Box<dyn Oracle>> {
+        Ok(match self {
+            OracleSpec::Output(pattern) => Box::new(OutputContains(pattern.clone())),
+            OracleSpec::Stderr(pattern) => Box::new(StderrContains(pattern.clone())),
+            OracleSpec::ExitCode(codes) => Box::new(ExitCodeIn(codes.clone())),
+            OracleSpec::Differential(binary) =>
+                Box::new(Differential::new(golden(binary)?)),
+        })
+    }
+
+    // Whether the target's output has to be captured for it
+    pub fn needs_output(&self) -> bool {
+        matches!(self, OracleSpec::Output(_) | OracleSpec::Differential(_))
+    }
+
+    // Whether the target's stderr has to be captured for it
+    pub fn needs_stderr(&self) -> bool {
+        matches!(self, OracleSpec::Stderr(_))
+    }
+}
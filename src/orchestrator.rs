@@ -0,0 +1,273 @@
+// Several fuzzer processes on one machine, without tmux scripts
+//
+// The orchestrator starts one -M main instance running the campaign as
+// configured and secondaries (-S sec1, sec2, ...) that each try a
+// different strategy, all in one sync dir so they share their findings.
+// Every instance gets its own stream of the campaign seed. Instances that
+// die (a signal, an error) are started again, with a growing delay when
+// they keep dying. The numbers the instances publish in their
+// fuzzer_stats are summed up into one status line.
+//
+// Instance output goes to <sync dir>/<name>/fuzzer_log.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+
+use crate::{info, warn};
+
+// Flags of the secondaries, taken in turn. The main instance runs the
+// campaign unchanged
+const VARIANTS: &[&[&str]] = &[
+    &["--strategy", "rare"],
+    &["--havoc", "0.1"],
+    &["--pairwise"],
+    &["--adaptive-mutators", "--mutation-stack", "4"],
+];
+
+// Restart delays double from MIN_BACKOFF up to MAX_BACKOFF, an instance
+// that ran for STABLE_RUN starts over with the short delay
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+const STABLE_RUN: Duration = Duration::from_secs(60);
+
+// How long instances get to write their last stats before being killed
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
+
+struct Instance {
+    name: String,
+    // arguments besides the shared ones
+    args: Vec<String>,
+    child: Option<Child>,
+    started: Instant,
+    // consecutive quick deaths, and when to try again after the last one
+    failures: u32,
+    restart_at: Option<Instant>,
+    // ran to its end, not to be started again
+    done: bool,
+}
+
+// Sums of the stats of all instances
+#[derive(Clone, Debug, Default)]
+pub struct AggregateStats {
+    pub alive: usize,
+    pub instances: usize,
+    pub restarts: u64,
+    pub execs_done: u64,
+    pub execs_per_sec: f64,
+    pub corpus_count: u64,
+    pub saved_crashes: u64,
+    pub saved_hangs: u64,
+}
+
+pub struct Orchestrator {
+    exe: PathBuf,
+    sync_dir: PathBuf,
+    // fuzzer flags of every instance, and the target command line
+    args: Vec<String>,
+    target: Vec<String>,
+    instances: Vec<Instance>,
+    restarts: u64,
+    // an instance exiting with status 1 found a crash in a bounded
+    // campaign, it did not die
+    bounded: bool,
+}
+
+impl Orchestrator {
+    // processes instances of exe in sync_dir, every one gets args, its
+    // instance flags and the seed of its stream, then "--" and target.
+    // dashboard flags only go to the main instance
+    pub fn new(exe: PathBuf, sync_dir: &Path, args: Vec<String>,
+            target: Vec<String>, processes: usize,
+            seed: impl Fn(usize) -> u64) -> Self {
+        let instances = (0..processes).map(|ii| {
+            let (name, mut args) = if ii == 0 {
+                ("main".to_string(), vec!["-M".to_string(), "main".to_string()])
+            } else {
+                let name = format!("sec{}", ii);
+                let mut args = vec!["-S".to_string(), name.clone()];
+                args.extend(VARIANTS[(ii - 1) % VARIANTS.len()].iter()
+                    .map(|x| x.to_string()));
+                (name, args)
+            };
+            args.extend(["--seed".to_string(), seed(ii).to_string()]);
+            Instance {
+                name,
+                args,
+                child: None,
+                started: Instant::now(),
+                failures: 0,
+                restart_at: Some(Instant::now()),
+                done: false,
+            }
+        }).collect();
+
+        Orchestrator {
+            exe,
+            sync_dir: sync_dir.to_path_buf(),
+            args,
+            target,
+            instances,
+            restarts: 0,
+            bounded: false,
+        }
+    }
+
+    pub fn set_bounded(&mut self, bounded: bool) {
+        self.bounded = bounded;
+    }
+
+    fn spawn(&self, instance: &Instance) -> io::Result<Child> {
+        let dir = self.sync_dir.join(&instance.name);
+        fs::create_dir_all(&dir)?;
+        let log = fs::File::create(dir.join("fuzzer_log"))?;
+
+        let shared = self.args.iter().enumerate().filter(|&(ii, arg)| {
+            let value_of = |flag: &str| ii > 0 && self.args[ii - 1] == flag;
+            instance.name == "main" || (arg != "--dashboard" && !value_of("--dashboard"))
+        }).map(|(_, arg)| arg);
+        Command::new(&self.exe)
+            .args(shared)
+            .args(["--processes", "1"])
+            .args(&instance.args)
+            .arg("--")
+            .args(&self.target)
+            .stdin(Stdio::null())
+            .stdout(log.try_clone()?)
+            .stderr(log)
+            .spawn()
+    }
+
+    // Start instances due for a (re)start and reap the ones that ended.
+    // Returns whether any instance is still running or will be
+    pub fn poll(&mut self) -> io::Result<bool> {
+        for ii in 0..self.instances.len() {
+            let instance = &self.instances[ii];
+            if instance.restart_at.is_some_and(|x| Instant::now() >= x) {
+                let child = self.spawn(instance)?;
+                info!("orchestrator", "started {} (pid {})", instance.name,
+                    child.id());
+                let instance = &mut self.instances[ii];
+                instance.child = Some(child);
+                instance.started = Instant::now();
+                instance.restart_at = None;
+            }
+
+            let instance = &mut self.instances[ii];
+            let Some(status) = instance.child.as_mut()
+                    .map(|x| x.try_wait()).transpose()?.flatten() else {
+                continue;
+            };
+            instance.child = None;
+            if finished(status, self.bounded) {
+                info!("orchestrator", "{} finished ({})", instance.name, status);
+                instance.done = true;
+                continue;
+            }
+
+            if instance.started.elapsed() >= STABLE_RUN {
+                instance.failures = 0;
+            }
+            let delay = (MIN_BACKOFF * 2u32.pow(instance.failures.min(6)))
+                .min(MAX_BACKOFF);
+            instance.failures += 1;
+            instance.restart_at = Some(Instant::now() + delay);
+            self.restarts += 1;
+            warn!("orchestrator", "{} died ({}), restarting in {}s",
+                instance.name, status, delay.as_secs());
+        }
+        Ok(self.instances.iter().any(|x| !x.done))
+    }
+
+    // Ask every instance to stop, the way ctrl-c would, and wait for them.
+    // Instances not done after SHUTDOWN_GRACE are killed
+    pub fn shutdown(&mut self) -> io::Result<()> {
+        for instance in &mut self.instances {
+            instance.restart_at = None;
+            if let Some(child) = &mut instance.child {
+                interrupt(child)?;
+            }
+        }
+
+        let deadline = Instant::now() + SHUTDOWN_GRACE;
+        for instance in &mut self.instances {
+            let Some(mut child) = instance.child.take() else {
+                continue;
+            };
+            while child.try_wait()?.is_none() {
+                if Instant::now() >= deadline {
+                    warn!("orchestrator", "{} did not stop, killing it",
+                        instance.name);
+                    child.kill()?;
+                    child.wait()?;
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            instance.done = true;
+        }
+        Ok(())
+    }
+
+    // Sum up what the instances last wrote to their fuzzer_stats
+    pub fn stats(&self) -> AggregateStats {
+        let mut total = AggregateStats {
+            alive: self.instances.iter().filter(|x| x.child.is_some()).count(),
+            instances: self.instances.len(),
+            restarts: self.restarts,
+            ..Default::default()
+        };
+        for instance in &self.instances {
+            let Ok(stats) = read_stats(&self.sync_dir.join(&instance.name)) else {
+                continue;
+            };
+            let int = |key: &str| stats.get(key)
+                .and_then(|x| x.parse::<u64>().ok()).unwrap_or(0);
+            total.execs_done += int("execs_done");
+            total.corpus_count += int("corpus_count");
+            total.saved_crashes += int("saved_crashes");
+            total.saved_hangs += int("saved_hangs");
+            // a dead instance does not execute anything
+            if instance.child.is_some() {
+                total.execs_per_sec += stats.get("execs_per_sec")
+                    .and_then(|x| x.parse::<f64>().ok()).unwrap_or(0.);
+            }
+        }
+        total
+    }
+}
+
+// Whether an instance that exited ended its campaign rather than died
+fn finished(status: ExitStatus, bounded: bool) -> bool {
+    match status.code() {
+        Some(0) => true,
+        Some(1) => bounded,
+        _ => false,
+    }
+}
+
+#[cfg(unix)]
+fn interrupt(child: &mut Child) -> io::Result<()> {
+    // SAFETY: plain syscall on the pid of a child we have not reaped
+    if unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGINT) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// No signals to ask nicely with
+#[cfg(not(unix))]
+fn interrupt(child: &mut Child) -> io::Result<()> {
+    child.kill()
+}
+
+// Keys and values of an instance's fuzzer_stats
+pub fn read_stats(dir: &Path) -> io::Result<HashMap<String, String>> {
+    Ok(fs::read_to_string(dir.join("fuzzer_stats"))?.lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect())
+}
@@ -0,0 +1,395 @@
+// Campaign output in the afl-fuzz sync directory layout
+//
+//   <sync dir>/<instance>/queue/id:000000,...
+//                        /crashes/id:000000,sig:11,...
+//                        /hangs/id:000000,...
+//                        /fuzzer_stats
+//                        /.synced/<other instance>
+//
+// Entries derived from the grammar also get their choice sequence (see
+// choices.rs) under the same name in a .choices directory next to them, and
+// every entry gets a json sidecar in .meta saying where it came from.
+// afl-fuzz ignores dot directories, so they do not bother other instances.
+//
+// With compression on (see compress.rs) entries are stored as <name>.zst
+// and listed in <dir>/.index. AFL++ instances cannot read those, leave it
+// off when sharing a sync dir with them.
+//
+// Instances named with -M/-S share one sync dir, AFL++ instances included.
+// Everybody picks up the queue entries of the others, .synced remembers per
+// foreign instance the next id to import (u32, native endian, like afl-fuzz).
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::compress;
+
+// Name and version in fuzzer_stats and metadata sidecars
+pub const GENERATOR: &str = concat!("maybe_fastest_fuzzer-",
+    env!("CARGO_PKG_VERSION"));
+
+// Seconds since the epoch
+pub fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs()).unwrap_or(0)
+}
+
+// Parse the id out of an afl style file name ("id:000042,...")
+fn parse_id(name: &str) -> Option<u32> {
+    name.strip_prefix("id:")?.split(',').next()?.parse().ok()
+}
+
+// Queue entries of every instance in a sync dir, by instance and id
+pub fn queue_entries(sync_dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut entries = Vec::new();
+    for instance in fs::read_dir(sync_dir)? {
+        let instance = instance?;
+        let queue = instance.path().join("queue");
+        if instance.file_name().to_string_lossy().starts_with('.') || !queue.is_dir() {
+            continue;
+        }
+        for entry in fs::read_dir(queue)? {
+            let entry = entry?;
+            if let Some(id) = parse_id(&entry.file_name().to_string_lossy()) {
+                entries.push((instance.file_name(), id, entry.path()));
+            }
+        }
+    }
+    entries.sort();
+    Ok(entries.into_iter().map(|(_, _, path)| path).collect())
+}
+
+// Provenance of a saved entry, enough to trace it back and regenerate it
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Metadata {
+    pub generator: &'static str,
+    // GrammarRust::fingerprint() as hex
+    pub grammar_hash: String,
+    // RNG seed of the worker that found it
+    pub seed: u64,
+    pub op: String,
+    // choice sequence as hex, None for inputs not derived from the grammar
+    pub choices: Option<String>,
+    // queue entry the input was mutated from
+    pub parent: Option<String>,
+    pub execs: u64,
+    #[serde(flatten)]
+    pub finding: Finding,
+}
+
+// What a crash or hang was saved for, empty for queue entries
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Finding {
+    // signal that killed the target
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signal: Option<i32>,
+    // the bug the crash was bucketed as ("heap-buffer-overflow in parse"),
+    // "signal 11" when only the signal tells crashes apart
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bucket: Option<String>,
+    // sanitizer report in one line, see sanitizer::Report::summary()
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sanitizer: Option<String>,
+    // oracle that flagged the input (see oracle.rs)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oracle: Option<String>,
+}
+
+// Metadata plus when it was written
+#[derive(Serialize)]
+struct Sidecar<'a> {
+    #[serde(flatten)]
+    meta: &'a Metadata,
+    time: u64,
+    runtime_ms: u64,
+}
+
+// Campaign numbers published in fuzzer_stats
+#[derive(Clone, Debug, Default)]
+pub struct StatsSnapshot {
+    pub execs_done: u64,
+    pub execs_per_sec: f64,
+    pub corpus_imported: u64,
+    pub edges_found: usize,
+    pub total_edges: usize,
+}
+
+pub struct AflOutputDir {
+    // shared sync dir all instances live in
+    sync_dir: PathBuf,
+
+    // our instance name and directory
+    name: String,
+    dir: PathBuf,
+
+    start_time: u64,
+    command_line: String,
+
+    // next free id per directory, afl ids are per directory
+    queue_id: AtomicU64,
+    crash_id: AtomicU64,
+    hang_id: AtomicU64,
+
+    // unix time of the latest find of each kind, 0 for never
+    last_find: AtomicU64,
+    last_crash: AtomicU64,
+    last_hang: AtomicU64,
+
+    // store entries zstd compressed
+    compress: bool,
+}
+
+impl AflOutputDir {
+    // Create (or reopen) the instance directory, main marks the -M instance
+    pub fn new(sync_dir: &Path, name: &str, main: bool) -> io::Result<Self> {
+        let dir = sync_dir.join(name);
+        for sub in ["queue", "crashes", "hangs", ".synced", "queue/.choices",
+                "crashes/.choices", "hangs/.choices", "queue/.meta",
+                "crashes/.meta", "hangs/.meta"] {
+            fs::create_dir_all(dir.join(sub))?;
+        }
+        if main {
+            fs::write(dir.join("is_main_node"), b"")?;
+        }
+
+        // continue numbering after entries of an earlier run
+        let next_id = |sub: &str| -> io::Result<u64> {
+            Ok(fs::read_dir(dir.join(sub))?
+                .filter_map(|x| x.ok())
+                .filter_map(|x| parse_id(&x.file_name().to_string_lossy()))
+                .map(|x| x as u64 + 1).max().unwrap_or(0))
+        };
+
+        Ok(AflOutputDir {
+            sync_dir: sync_dir.to_path_buf(),
+            name: name.to_string(),
+            queue_id: AtomicU64::new(next_id("queue")?),
+            crash_id: AtomicU64::new(next_id("crashes")?),
+            hang_id: AtomicU64::new(next_id("hangs")?),
+            dir,
+            start_time: unix_time(),
+            command_line: std::env::args().collect::<Vec<_>>().join(" "),
+            last_find: AtomicU64::new(0),
+            last_crash: AtomicU64::new(0),
+            last_hang: AtomicU64::new(0),
+            compress: false,
+        })
+    }
+
+    // Compress entries saved from now on
+    pub fn set_compress(&mut self, compress: bool) {
+        self.compress = compress;
+    }
+
+    // Directory of our instance
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // Number of queue entries, as corpus_count in fuzzer_stats
+    pub fn queue_len(&self) -> u64 {
+        self.queue_id.load(Ordering::Relaxed)
+    }
+
+    // Number of saved crashes and hangs
+    pub fn crashes_len(&self) -> u64 {
+        self.crash_id.load(Ordering::Relaxed)
+    }
+
+    pub fn hangs_len(&self) -> u64 {
+        self.hang_id.load(Ordering::Relaxed)
+    }
+
+    // Milliseconds since the campaign started, used in file names
+    fn runtime_ms(&self) -> u64 {
+        SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|x| x.as_millis() as u64).unwrap_or(0)
+            .saturating_sub(self.start_time * 1000)
+    }
+
+    // Save an input that found new coverage, op says where it came from
+    pub fn save_queue(&self, data: &[u8], execs: u64, op: &str)
+            -> io::Result<PathBuf> {
+        let id = self.queue_id.fetch_add(1, Ordering::Relaxed);
+        self.last_find.store(unix_time(), Ordering::Relaxed);
+        let path = self.dir.join("queue").join(format!(
+            "id:{:06},time:{},execs:{},op:{},+cov", id, self.runtime_ms(),
+            execs, op));
+        self.write_entry(path, data)
+    }
+
+    // Save an input that crashed the target
+    pub fn save_crash(&self, data: &[u8], signal: Option<i32>, execs: u64,
+            op: &str) -> io::Result<PathBuf> {
+        let id = self.crash_id.fetch_add(1, Ordering::Relaxed);
+        self.last_crash.store(unix_time(), Ordering::Relaxed);
+        let path = self.dir.join("crashes").join(format!(
+            "id:{:06},sig:{:02},time:{},execs:{},op:{}", id,
+            signal.unwrap_or(0), self.runtime_ms(), execs, op));
+        self.write_entry(path, data)
+    }
+
+    // Save an input that made the target time out
+    pub fn save_hang(&self, data: &[u8], execs: u64, op: &str)
+            -> io::Result<PathBuf> {
+        let id = self.hang_id.fetch_add(1, Ordering::Relaxed);
+        self.last_hang.store(unix_time(), Ordering::Relaxed);
+        let path = self.dir.join("hangs").join(format!(
+            "id:{:06},time:{},execs:{},op:{}", id, self.runtime_ms(),
+            execs, op));
+        self.write_entry(path, data)
+    }
+
+    // Write an entry, compressed and indexed if asked to
+    fn write_entry(&self, mut path: PathBuf, data: &[u8]) -> io::Result<PathBuf> {
+        if !self.compress {
+            fs::write(&path, data)?;
+            return Ok(path);
+        }
+        let stored = compress::compress(data)?;
+        path.as_mut_os_string().push(compress::SUFFIX);
+        fs::write(&path, &stored)?;
+        compress::index(&path, data.len(), stored.len())?;
+        Ok(path)
+    }
+
+    // Store the choice sequence of a saved entry (the path save_queue(),
+    // save_crash() or save_hang() returned)
+    pub fn save_choices(&self, entry: &Path, choices: &[u8]) -> io::Result<()> {
+        let (Some(dir), Some(name)) = (entry.parent(), entry.file_name()) else {
+            return Err(io::Error::from(io::ErrorKind::InvalidInput));
+        };
+        fs::write(dir.join(".choices").join(name), choices)
+    }
+
+    // Write the metadata sidecar of a saved entry, <dir>/.meta/<name>.json
+    pub fn save_meta(&self, entry: &Path, meta: &Metadata) -> io::Result<()> {
+        let (Some(dir), Some(name)) = (entry.parent(), entry.file_name()) else {
+            return Err(io::Error::from(io::ErrorKind::InvalidInput));
+        };
+        let mut name = name.to_os_string();
+        name.push(".json");
+        let sidecar = Sidecar {
+            meta,
+            time: unix_time(),
+            runtime_ms: self.runtime_ms(),
+        };
+        fs::write(dir.join(".meta").join(name), serde_json::to_vec_pretty(&sidecar)?)
+    }
+
+    // Fingerprint of the grammar an earlier run in this directory used
+    pub fn grammar_fingerprint(&self) -> io::Result<Option<u64>> {
+        match fs::read_to_string(self.dir.join("grammar_fingerprint")) {
+            Ok(text) => u64::from_str_radix(text.trim(), 16).map(Some)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Remember the grammar of this run, for resuming
+    pub fn set_grammar_fingerprint(&self, fingerprint: u64) -> io::Result<()> {
+        fs::write(self.dir.join("grammar_fingerprint"),
+            format!("{:016x}\n", fingerprint))
+    }
+
+    // Rewrite fuzzer_stats, keys follow afl-fuzz so afl-whatsup and
+    // friends can read it
+    pub fn write_stats(&self, stats: &StatsSnapshot) -> io::Result<()> {
+        let now = unix_time();
+        let queue = self.queue_id.load(Ordering::Relaxed);
+        let fields: Vec<(&str, String)> = vec![
+            ("start_time", self.start_time.to_string()),
+            ("last_update", now.to_string()),
+            ("run_time", (now - self.start_time).to_string()),
+            ("fuzzer_pid", std::process::id().to_string()),
+            ("cycles_done", "0".into()),
+            ("execs_done", stats.execs_done.to_string()),
+            ("execs_per_sec", format!("{:.2}", stats.execs_per_sec)),
+            ("corpus_count", queue.to_string()),
+            ("corpus_found", (queue.saturating_sub(stats.corpus_imported))
+                .to_string()),
+            ("corpus_imported", stats.corpus_imported.to_string()),
+            ("pending_favs", "0".into()),
+            ("pending_total", "0".into()),
+            ("bitmap_cvg", format!("{:.2}%", stats.edges_found as f64 * 100. /
+                stats.total_edges.max(1) as f64)),
+            ("saved_crashes", self.crash_id.load(Ordering::Relaxed).to_string()),
+            ("saved_hangs", self.hang_id.load(Ordering::Relaxed).to_string()),
+            ("last_find", self.last_find.load(Ordering::Relaxed).to_string()),
+            ("last_crash", self.last_crash.load(Ordering::Relaxed).to_string()),
+            ("last_hang", self.last_hang.load(Ordering::Relaxed).to_string()),
+            ("edges_found", stats.edges_found.to_string()),
+            ("total_edges", stats.total_edges.to_string()),
+            ("afl_banner", self.name.clone()),
+            ("afl_version", GENERATOR.into()),
+            ("target_mode", "default".into()),
+            ("command_line", self.command_line.clone()),
+        ];
+
+        // write then rename so readers never see half a file
+        let tmp = self.dir.join(".fuzzer_stats_tmp");
+        let mut file = io::BufWriter::new(fs::File::create(&tmp)?);
+        for (key, value) in fields {
+            writeln!(file, "{:<18}: {}", key, value)?;
+        }
+        file.into_inner().map_err(|x| x.into_error())?.sync_all()?;
+        fs::rename(tmp, self.dir.join("fuzzer_stats"))
+    }
+
+    // Collect queue entries other instances added since the last call
+    pub fn sync_foreign(&self) -> io::Result<Vec<Vec<u8>>> {
+        let mut found = Vec::new();
+
+        for instance in fs::read_dir(&self.sync_dir)? {
+            let instance = instance?;
+            let name = instance.file_name().to_string_lossy().into_owned();
+            let queue = instance.path().join("queue");
+            if name == self.name || name.starts_with('.') || !queue.is_dir() {
+                continue;
+            }
+
+            let synced = self.dir.join(".synced").join(&name);
+            let next = fs::read(&synced).ok()
+                .and_then(|x| x.get(..4).map(|x| x.try_into().unwrap()))
+                .map(u32::from_ne_bytes).unwrap_or(0);
+
+            let mut entries = fs::read_dir(&queue)?.filter_map(|x| x.ok())
+                .filter_map(|x| {
+                    let file = x.file_name().to_string_lossy().into_owned();
+                    parse_id(&file).map(|id| (id, file, x.path()))
+                })
+                .filter(|(id, _, _)| *id >= next)
+                .collect::<Vec<_>>();
+            entries.sort();
+
+            let mut new_next = next;
+            for (id, file, path) in entries {
+                new_next = id + 1;
+
+                // the instance got it from someone else, no need to bounce
+                // it around again
+                if file.contains(",sync:") || file.contains(",op:sync") {
+                    continue;
+                }
+                match compress::read_entry(&path) {
+                    Ok(data) => found.push(data),
+                    // vanished under us, retry from here next round
+                    Err(_) => {
+                        new_next = id;
+                        break;
+                    }
+                }
+            }
+            if new_next != next {
+                fs::write(synced, new_next.to_ne_bytes())?;
+            }
+        }
+        Ok(found)
+    }
+}
@@ -0,0 +1,64 @@
+// Pairwise production coverage
+//
+// Grammar level feedback in the spirit of combinatorial testing: which
+// pairs of alternatives (productions) have appeared together in one input.
+// Many parser bugs need two features to interact, single rule coverage
+// says nothing about that. A pair of an alternative with itself stands for
+// the alternative alone, so plain production coverage comes for free.
+
+use std::collections::HashSet;
+
+use crate::grammar::{Fragment, GrammarRust};
+use crate::tree::Tree;
+
+#[derive(Debug, Default)]
+pub struct PairCoverage {
+    // (smaller fragment id << 32) | larger one
+    seen: HashSet<u64>,
+}
+
+// Alternatives a derivation picked at rules with more than one, sorted
+fn alternatives(grammar: &GrammarRust, tree: &Tree, out: &mut Vec<u32>) {
+    out.clear();
+    // the option taken always directly follows the non-terminal
+    for pair in tree.nodes.windows(2) {
+        if let Fragment::NonTerminal(options) = grammar.lookup_fragment(pair[0].fragment) {
+            if options.len() > 1 {
+                out.push(pair[1].fragment.0);
+            }
+        }
+    }
+    out.sort_unstable();
+    out.dedup();
+}
+
+// Every pair of a sorted list, including each element with itself
+fn pairs(alternatives: &[u32]) -> impl Iterator<Item = u64> + '_ {
+    alternatives.iter().enumerate().flat_map(move |(ii, &a)|
+        alternatives[ii..].iter().map(move |&b| (a as u64) << 32 | b as u64))
+}
+
+impl PairCoverage {
+    // Number of pairs seen so far
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+
+    // Pairs of the derivation nobody has seen yet, scratch is reused
+    pub fn count_new(&self, grammar: &GrammarRust, tree: &Tree,
+            scratch: &mut Vec<u32>) -> usize {
+        alternatives(grammar, tree, scratch);
+        pairs(scratch).filter(|x| !self.seen.contains(x)).count()
+    }
+
+    // Record the pairs of a derivation, returns how many were new
+    pub fn add(&mut self, grammar: &GrammarRust, tree: &Tree,
+            scratch: &mut Vec<u32>) -> usize {
+        alternatives(grammar, tree, scratch);
+        pairs(scratch).filter(|&x| self.seen.insert(x)).count()
+    }
+}
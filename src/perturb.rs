@@ -0,0 +1,316 @@
+// Fuzzing the fuzzer with broken grammars
+//
+// Grammars people write are full of mistakes: rules that lost their last
+// alternative, misspelled references, cycles that never produce anything.
+// Whatever a grammar looks like, validation has to report it and generation
+// has to either work or be refused, never panic or hang. The mutate-grammar
+// command checks that: it perturbs a grammar at random, a few edits at a
+// time, and puts every mutant through loading, validation and generation
+// (see exercise()) in a thread of its own, so a panic or a hang is caught
+// and the mutant kept to reproduce it.
+//
+// The same checks run on grammars made from scratch and on grammar files
+// that are not even json (random_grammar(), mangle() and check_bytes()):
+// tests/frontend.rs puts a few hundred of them through the whole front end
+// with every cargo test, and the cargo-fuzz targets under fuzz/ do it with
+// the inputs libFuzzer comes up with.
+
+use std::collections::HashMap;
+use std::sync::mpsc;
+use std::time::Duration;
+
+use crate::binary;
+use crate::grammar::{Annotation, GeneratorState, Grammar, GrammarRust, DEFAULT_NODE_BUDGET};
+use crate::loader::{self, DuplicatePolicy};
+use crate::numeric;
+use crate::schema::{self, Parsing};
+use crate::tree::{Tree, TreeStack};
+use crate::unicode;
+use crate::validate::{self, ValidateOptions};
+
+// Largest stack of edits applied to one mutant
+const MAX_EDITS: usize = 4;
+
+// Most rules of a grammar from scratch, alternatives per rule and symbols
+// per alternative
+const MAX_RULES: usize = 8;
+const MAX_ALTERNATIVES: usize = 4;
+const MAX_SYMBOLS: usize = 5;
+
+// What grammars from scratch are made of besides references to their own
+// rules: text, things that look like rules, and the symbols the loader
+// expands (see unicode.rs, numeric.rs, binary.rs). A malformed one makes
+// the loader refuse the whole grammar, they are one in BROKEN_ODDS symbols
+const SYMBOLS: &[&str] = &["a", "", " ", "\u{0}", "\u{e9}", "<", ">", "<>", "<start",
+    "<undefined>", "<unicode:letter>", "<unicode:any>", "<unicode:U+0041-U+005A>",
+    "<int:-128..127>", "<hex:0..0xffff>", "<float:-1.5..1e6>", "<int:0..65535,bias=0.8>",
+    "<u8:*>", "<u16le:0x7f45>", "<u32be:1|2|0x10>", "<i64be:*>", "<bits:4=4,4=*,8=0>"];
+const BROKEN: &[&str] = &["<unicode:U+D800>", "<unicode:U+0041-U+0040>",
+    "<unicode:nothing>", "<int:5..1>", "<float:nan..1>", "<int:0..1,bias=2>",
+    "<int:0..99999999999999999999>", "<u8:256>", "<u64le:|>", "<bits:3=1>", "<bits:0=0>"];
+const BROKEN_ODDS: usize = 100;
+
+// Json fragments mangle() inserts
+const JSON: &[&[u8]] = &[b"\"", b"{", b"}", b"[", b"]", b",", b":", b"null", b"[[]]", b"{}",
+    b"1e999", b"-0", b"\"<start>\"", b"\"\\u0000\"", b"\"\\ud800\"", b"\"alternatives\""];
+
+// Apply a random stack of edits to grammar, rand the caller's random
+// source. What was done, for the report
+pub fn perturb(grammar: &mut Grammar, rand: &mut impl FnMut() -> usize) -> Vec<String> {
+    let edits = 1 + rand() % MAX_EDITS;
+    (0..edits).filter_map(|_| edit(grammar, rand)).collect()
+}
+
+// One random edit, None when the grammar had nothing to apply it to
+fn edit(grammar: &mut Grammar, rand: &mut impl FnMut() -> usize) -> Option<String> {
+    // rules in a fixed order, hash map order would make seeds meaningless
+    let mut names = grammar.0.keys().cloned().collect::<Vec<_>>();
+    names.sort_unstable();
+    let name = names.get(rand() % names.len().max(1))?.clone();
+    let other = names[rand() % names.len()].clone();
+    let rule = grammar.0.get_mut(&name).unwrap();
+    let alt = rand() % rule.len().max(1);
+
+    Some(match rand() % 8 {
+        // drop an alternative, possibly the last one
+        0 => {
+            rule.get(alt)?;
+            rule.remove(alt);
+            format!("dropped alternative {} of {}", alt, name)
+        }
+        // drop a symbol of an alternative
+        1 => {
+            let symbols = rule.get_mut(alt).filter(|x| !x.is_empty())?;
+            let removed = symbols.remove(rand() % symbols.len());
+            format!("dropped {:?} from alternative {} of {}", removed, alt, name)
+        }
+        // point a symbol somewhere else: another rule, or nowhere
+        2 => {
+            let symbols = rule.get_mut(alt).filter(|x| !x.is_empty())?;
+            let pos = rand() % symbols.len();
+            let target = match rand() % 2 {
+                0 => other,
+                _ => format!("<undefined_{}>", rand() % 100),
+            };
+            let old = std::mem::replace(&mut symbols[pos], target.clone());
+            format!("renamed {:?} to {:?} in {}", old, target, name)
+        }
+        // a cycle through another rule that produces nothing on the way
+        3 => {
+            rule.push(vec![other.clone()]);
+            grammar.0.get_mut(&other).unwrap().push(vec![name.clone()]);
+            format!("cycle {} -> {} -> {}", name, other, name)
+        }
+        // direct left recursion
+        4 => {
+            let mut symbols = rule.get(alt).cloned().unwrap_or_default();
+            symbols.insert(0, name.clone());
+            rule.push(symbols);
+            format!("left recursion in {}", name)
+        }
+        // epsilon, both spellings
+        5 => {
+            rule.push(match rand() % 2 {
+                0 => Vec::new(),
+                _ => vec![String::new()],
+            });
+            format!("epsilon alternative in {}", name)
+        }
+        // the whole rule, <start> included
+        6 => {
+            grammar.0.remove(&name);
+            grammar.1.remove(&name);
+            format!("removed {}", name)
+        }
+        // the tightest expansion limit there is
+        _ => {
+            grammar.1.entry(name.clone()).or_default().max_expansions = Some(1);
+            format!("max_expansions 1 on {}", name)
+        }
+    })
+}
+
+// A grammar from scratch: <start> and up to MAX_RULES - 1 more rules,
+// alternatives of references (to rules that exist or not) and SYMBOLS,
+// and annotations of every kind on some rules, rand the caller's random
+// source
+pub fn random_grammar(rand: &mut impl FnMut() -> usize) -> Grammar {
+    let count = 1 + rand() % MAX_RULES;
+    let names = std::iter::once("<start>".to_string())
+        .chain((1..count).map(|ii| format!("<r{}>", ii)))
+        .collect::<Vec<_>>();
+    let mut grammar = Grammar::default();
+    for name in &names {
+        // no alternatives at all is one of the broken cases
+        let count = match rand().is_multiple_of(BROKEN_ODDS) {
+            true => 0,
+            false => 1 + rand() % MAX_ALTERNATIVES,
+        };
+        let alternatives = (0..count).map(|_|
+            (0..rand() % (MAX_SYMBOLS + 1)).map(|_| match rand() % 3 {
+                0 => names[rand() % names.len()].clone(),
+                _ if rand().is_multiple_of(BROKEN_ODDS) => BROKEN[rand() % BROKEN.len()].into(),
+                _ => SYMBOLS[rand() % SYMBOLS.len()].to_string(),
+            }).collect::<Vec<_>>()).collect::<Vec<_>>();
+        if rand().is_multiple_of(3) {
+            grammar.1.insert(name.clone(), random_annotation(&alternatives, rand));
+        }
+        grammar.0.insert(name.clone(), alternatives);
+    }
+    grammar
+}
+
+// Random settings for a rule with these alternatives, out of range one in
+// BROKEN_ODDS times
+fn random_annotation(alternatives: &[Vec<String>], rand: &mut impl FnMut() -> usize)
+        -> Annotation {
+    let some = |rand: &mut dyn FnMut() -> usize| rand().is_multiple_of(3);
+    let listed = |rand: &mut dyn FnMut() -> usize| alternatives.iter()
+        .filter(|_| rand().is_multiple_of(3)).cloned().collect::<Vec<_>>();
+    let broken = |rand: &mut dyn FnMut() -> usize| rand().is_multiple_of(BROKEN_ODDS) as u32;
+    Annotation {
+        corrupt: some(rand).then(|| (rand() % 5) as f64 / 4.0 + broken(rand) as f64),
+        max_expansions: some(rand).then(|| (1 + rand() % 3) as u32 - broken(rand)),
+        cost: some(rand).then(|| (1 + rand() % 3) as u32 * 50 - 50 * broken(rand)),
+        token: some(rand).then(|| (rand() % 4) as u32),
+        mutation_only: if some(rand) { listed(rand) } else { Vec::new() },
+        generation_only: if some(rand) { listed(rand) } else { Vec::new() },
+        shared: some(rand),
+    }
+}
+
+// Break a grammar file a few random edits at a time: flip bytes, drop or
+// repeat runs of them, put in bits of json
+pub fn mangle(data: &mut Vec<u8>, rand: &mut impl FnMut() -> usize) {
+    for _ in 0..1 + rand() % MAX_EDITS {
+        let pos = rand() % (data.len() + 1);
+        let len = (1 + rand() % 16).min(data.len() - pos);
+        match rand() % 4 {
+            0 if pos < data.len() => data[pos] ^= 1 << (rand() % 8),
+            1 => {
+                data.drain(pos..pos + len);
+            }
+            2 => {
+                let run = data[pos..pos + len].to_vec();
+                data.splice(pos..pos, run);
+            }
+            _ => {
+                data.splice(pos..pos, JSON[rand() % JSON.len()].iter().copied());
+            }
+        }
+    }
+}
+
+// Put a grammar through everything a campaign does with one: binary
+// terminals, validation, compiling, generating samples and trees and
+// mutating them. Err is the reason validation refused it, which is fine
+pub fn exercise(mut grammar: Grammar, samples: usize, seed: usize) -> Result<(), String> {
+    unicode::expand(&mut grammar)?;
+    numeric::expand(&mut grammar)?;
+    binary::expand(&mut grammar)?;
+    let report = validate::validate(&grammar, &ValidateOptions {
+        samples,
+        max_nodes: DEFAULT_NODE_BUDGET,
+        max_len: 0,
+        seed,
+        cost_budget: None,
+    });
+    if let Some(error) = report.findings.iter().find(|x| x.severity == validate::Severity::Error) {
+        return Err(error.message.clone());
+    }
+
+    let gram = GrammarRust::new(&grammar);
+    gram.fingerprint();
+    let mut cases = gram.iter_testcases(seed);
+    for _ in 0..samples {
+        cases.next_ref();
+    }
+
+    let mut state = GeneratorState::new(seed);
+    let (mut stack, mut tree, mut scratch) = (TreeStack::default(), Tree::default(),
+        Tree::default());
+    let mut buf = Vec::new();
+    for _ in 0..samples {
+        gram.generate_full_tree(&mut state, &mut stack, &mut tree);
+        gram.mutate_subtree(&mut state, &mut tree, &mut stack, &mut scratch);
+        gram.mutate_terminal_swap(&mut state, &mut tree);
+        gram.mutate_recursion(&mut state, &mut tree, &mut scratch);
+        buf.clear();
+        tree.serialize(&gram, &mut buf);
+    }
+    Ok(())
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    // worked, or validation refused the grammar with this error
+    Ok,
+    Rejected(String),
+    // the panic message
+    Panicked(String),
+    // still running after the timeout, the thread is left behind
+    Hung,
+}
+
+impl Outcome {
+    pub fn is_bug(&self) -> bool {
+        matches!(self, Outcome::Panicked(_) | Outcome::Hung)
+    }
+}
+
+// exercise() in a thread of its own
+pub fn check(grammar: Grammar, samples: usize, seed: usize, timeout: Duration) -> Outcome {
+    watch(timeout, move || exercise(grammar, samples, seed))
+}
+
+// check() for the contents of a grammar file: loading it, which may
+// refuse it like validation may, and exercise()
+pub fn check_bytes(data: Vec<u8>, samples: usize, seed: usize, timeout: Duration)
+        -> Outcome {
+    watch(timeout, move || {
+        let mut definitions = loader::parse("grammar", &data).map_err(|e| e.to_string())?;
+        schema::conform(&mut definitions, Parsing::Lenient)?;
+        let grammar = loader::combine([("grammar".to_string(), definitions)],
+            DuplicatePolicy::Merge).map_err(|e| e.to_string())?;
+        exercise(grammar, samples, seed)
+    })
+}
+
+// Run work in a thread of its own, telling a panic or running past the
+// timeout from returning
+fn watch(timeout: Duration, work: impl FnOnce() -> Result<(), String> + Send + 'static)
+        -> Outcome {
+    let (send, recv) = mpsc::channel();
+    let thread = std::thread::spawn(move || {
+        let result = work();
+        let _ = send.send(());
+        result
+    });
+    match recv.recv_timeout(timeout) {
+        Err(mpsc::RecvTimeoutError::Timeout) => return Outcome::Hung,
+        Ok(()) | Err(mpsc::RecvTimeoutError::Disconnected) => {}
+    }
+    match thread.join() {
+        Ok(Ok(())) => Outcome::Ok,
+        Ok(Err(reason)) => Outcome::Rejected(reason),
+        Err(panic) => Outcome::Panicked(panic.downcast_ref::<String>().cloned()
+            .or_else(|| panic.downcast_ref::<&str>().map(|x| x.to_string()))
+            .unwrap_or_default()),
+    }
+}
+
+// The grammar in the json format loader::load() reads
+pub fn to_json(grammar: &Grammar) -> serde_json::Value {
+    let rules = grammar.0.iter().map(|(name, alternatives)| {
+        let value = match grammar.1.get(name) {
+            Some(annotation) => {
+                let mut object = serde_json::to_value(annotation).unwrap();
+                object["alternatives"] = serde_json::to_value(alternatives).unwrap();
+                object
+            }
+            None => serde_json::to_value(alternatives).unwrap(),
+        };
+        (name.clone(), value)
+    }).collect::<HashMap<_, _>>();
+    serde_json::to_value(rules).unwrap()
+}
@@ -0,0 +1,201 @@
+// Campaign stats over time, as CSV and as charts
+//
+// The stats loop appends a row to <instance>/stats.csv every time it
+// reports, the same numbers the dashboard draws (see dashboard::Sample).
+// The plot command reads one or more of these files and draws coverage and
+// execution speed over time as SVG line charts, one line per file, so
+// campaigns can be compared side by side. SVG is written by hand, PNG goes
+// through rsvg-convert ($RSVG_CONVERT, or in $PATH).
+
+use std::fmt::Write as _;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::dashboard::Sample;
+
+// Name of the CSV in an instance directory
+pub const CSV_NAME: &str = "stats.csv";
+
+const HEADER: &str = "time,execs,execs_per_sec,edges,corpus,crashes,timeouts";
+
+// Line colors, one per campaign
+const COLORS: [&str; 6] = ["#1f77b4", "#d62728", "#2ca02c", "#ff7f0e", "#9467bd", "#8c564b"];
+
+const WIDTH: f64 = 800.;
+const HEIGHT: f64 = 400.;
+// room for the axes labels, left right top bottom
+const MARGIN: (f64, f64, f64, f64) = (70., 20., 40., 50.);
+
+// Append a sample to the CSV at path, with a header if the file is new
+pub fn append_csv(path: &Path, sample: &Sample) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    if file.metadata()?.len() == 0 {
+        writeln!(file, "{}", HEADER)?;
+    }
+    writeln!(file, "{:.1},{},{:.1},{},{},{},{}", sample.time, sample.execs,
+        sample.execs_per_sec, sample.edges, sample.corpus, sample.crashes,
+        sample.timeouts)
+}
+
+// Samples of a CSV written by append_csv(). Columns go by the header. A
+// campaign resumed in the same directory starts its rows over at time 0,
+// they are moved behind the rows of the run before
+pub fn read_csv(path: &Path) -> io::Result<Vec<Sample>> {
+    let text = fs::read_to_string(path)?;
+    let mut lines = text.lines();
+    let header = lines.next().unwrap_or("").split(',').collect::<Vec<_>>();
+    let invalid = |line: usize| io::Error::new(io::ErrorKind::InvalidData,
+        format!("{}:{}: not a stats row", path.display(), line));
+
+    let mut samples: Vec<Sample> = Vec::new();
+    let mut offset = 0.;
+    for (ii, line) in lines.enumerate() {
+        let mut sample = Sample::default();
+        for (name, value) in header.iter().zip(line.split(',')) {
+            let ok = match *name {
+                "time" => value.parse().map(|x| sample.time = x).is_ok(),
+                "execs" => value.parse().map(|x| sample.execs = x).is_ok(),
+                "execs_per_sec" => value.parse().map(|x| sample.execs_per_sec = x).is_ok(),
+                "edges" => value.parse().map(|x| sample.edges = x).is_ok(),
+                "corpus" => value.parse().map(|x| sample.corpus = x).is_ok(),
+                "crashes" => value.parse().map(|x| sample.crashes = x).is_ok(),
+                "timeouts" => value.parse().map(|x| sample.timeouts = x).is_ok(),
+                _ => true,
+            };
+            if !ok {
+                return Err(invalid(ii + 2));
+            }
+        }
+        if let Some(last) = samples.last().filter(|x| x.time > sample.time + offset) {
+            offset = last.time;
+        }
+        sample.time += offset;
+        samples.push(sample);
+    }
+    Ok(samples)
+}
+
+// Round step for about count ticks over range: 1, 2 or 5 times a power of 10
+fn tick_step(range: f64, count: f64) -> f64 {
+    let raw = (range / count).max(f64::MIN_POSITIVE);
+    let magnitude = 10f64.powf(raw.log10().floor());
+    [1., 2., 5., 10.].iter().map(|x| x * magnitude)
+        .find(|&x| x >= raw).unwrap_or(10. * magnitude)
+}
+
+// Tick label, short for big numbers
+fn label(value: f64) -> String {
+    match value.abs() {
+        x if x >= 1e9 => format!("{}G", value / 1e9),
+        x if x >= 1e6 => format!("{}M", value / 1e6),
+        x if x >= 1e4 => format!("{}k", value / 1e3),
+        _ => format!("{}", (value * 1000.).round() / 1000.),
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+// Line chart of every named series of (x, y) points, x in seconds
+pub fn line_chart(title: &str, y_label: &str, series: &[(String, Vec<(f64, f64)>)])
+        -> String {
+    let points = || series.iter().flat_map(|x| x.1.iter());
+    let max_x = points().map(|x| x.0).fold(0., f64::max);
+    let max_y = points().map(|x| x.1).fold(0., f64::max);
+
+    // time in the unit that keeps the numbers small
+    let (unit, scale) = match max_x {
+        x if x >= 2. * 3600. => ("h", 3600.),
+        x if x >= 2. * 60. => ("min", 60.),
+        _ => ("s", 1.),
+    };
+    let (x_step, y_step) = (tick_step(max_x / scale, 8.), tick_step(max_y, 5.));
+    let x_end = ((max_x / scale / x_step).ceil() * x_step).max(x_step);
+    let y_end = ((max_y / y_step).ceil() * y_step).max(y_step);
+
+    let (left, right, top, bottom) = MARGIN;
+    let (plot_w, plot_h) = (WIDTH - left - right, HEIGHT - top - bottom);
+    let px = |x: f64| left + x / scale / x_end * plot_w;
+    let py = |y: f64| top + plot_h - y / y_end * plot_h;
+
+    let mut svg = String::new();
+    let _ = writeln!(svg, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" \
+        height=\"{}\" font-family=\"sans-serif\" font-size=\"12\">", WIDTH, HEIGHT);
+    let _ = writeln!(svg, "<rect width=\"100%\" height=\"100%\" fill=\"white\"/>");
+    let _ = writeln!(svg, "<text x=\"{}\" y=\"24\" text-anchor=\"middle\" \
+        font-size=\"16\">{}</text>", WIDTH / 2., escape(title));
+
+    // grid and ticks
+    let mut x = 0.;
+    while x <= x_end + x_step / 2. {
+        let at = left + x / x_end * plot_w;
+        let _ = writeln!(svg, "<line x1=\"{at:.1}\" y1=\"{top}\" x2=\"{at:.1}\" \
+            y2=\"{}\" stroke=\"#eee\"/>", top + plot_h);
+        let _ = writeln!(svg, "<text x=\"{at:.1}\" y=\"{}\" text-anchor=\"middle\">{}</text>",
+            top + plot_h + 16., label(x));
+        x += x_step;
+    }
+    let mut y = 0.;
+    while y <= y_end + y_step / 2. {
+        let at = py(y);
+        let _ = writeln!(svg, "<line x1=\"{left}\" y1=\"{at:.1}\" x2=\"{}\" \
+            y2=\"{at:.1}\" stroke=\"#eee\"/>", left + plot_w);
+        let _ = writeln!(svg, "<text x=\"{}\" y=\"{:.1}\" text-anchor=\"end\">{}</text>",
+            left - 6., at + 4., label(y));
+        y += y_step;
+    }
+    let _ = writeln!(svg, "<rect x=\"{left}\" y=\"{top}\" width=\"{plot_w}\" \
+        height=\"{plot_h}\" fill=\"none\" stroke=\"#333\"/>");
+    let _ = writeln!(svg, "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">time ({})</text>",
+        left + plot_w / 2., HEIGHT - 10., unit);
+    let _ = writeln!(svg, "<text transform=\"translate(16 {}) rotate(-90)\" \
+        text-anchor=\"middle\">{}</text>", top + plot_h / 2., escape(y_label));
+
+    // the lines, and a legend when there is more than one
+    for (ii, (name, points)) in series.iter().enumerate() {
+        let color = COLORS[ii % COLORS.len()];
+        let path = points.iter().map(|&(x, y)| format!("{:.1},{:.1}", px(x), py(y)))
+            .collect::<Vec<_>>().join(" ");
+        let _ = writeln!(svg, "<polyline points=\"{}\" fill=\"none\" stroke=\"{}\" \
+            stroke-width=\"1.5\"/>", path, color);
+        if series.len() > 1 {
+            let at = top + 14. + ii as f64 * 16.;
+            let _ = writeln!(svg, "<line x1=\"{}\" y1=\"{at}\" x2=\"{}\" y2=\"{at}\" \
+                stroke=\"{}\" stroke-width=\"2\"/>", left + 10., left + 30., color);
+            let _ = writeln!(svg, "<text x=\"{}\" y=\"{}\">{}</text>", left + 36.,
+                at + 4., escape(name));
+        }
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+// Coverage and execution speed charts of named campaigns, (file name, svg)
+pub fn charts(campaigns: &[(String, Vec<Sample>)]) -> Vec<(&'static str, String)> {
+    let series = |y: fn(&Sample) -> f64| campaigns.iter()
+        .map(|(name, samples)| (name.clone(),
+            samples.iter().map(|x| (x.time, y(x))).collect()))
+        .collect::<Vec<_>>();
+    vec![
+        ("coverage", line_chart("Coverage", "edges", &series(|x| x.edges as f64))),
+        ("speed", line_chart("Execution speed", "execs/s", &series(|x| x.execs_per_sec))),
+    ]
+}
+
+// Render an SVG to a PNG file
+pub fn to_png(svg: &str, path: &Path) -> io::Result<()> {
+    let tool = std::env::var_os("RSVG_CONVERT").unwrap_or_else(|| "rsvg-convert".into());
+    let mut child = Command::new(tool).args(["-f", "png", "-o"]).arg(path)
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| io::Error::new(e.kind(),
+            format!("running rsvg-convert failed: {}", e)))?;
+    child.stdin.take().unwrap().write_all(svg.as_bytes())?;
+    if !child.wait()?.success() {
+        return Err(io::Error::other("rsvg-convert failed"));
+    }
+    Ok(())
+}
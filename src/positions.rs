@@ -0,0 +1,161 @@
+// Fuzzing argv, environment variables and sets of files
+//
+// Besides stdin/file, parts of the input can go into command line
+// arguments or environment variables of the target, each generated from a
+// grammar of its own. The grammars are combined into one whose start rule
+// produces the value of every position followed by a NUL byte, then the
+// regular input. Corpus, mutation and saved entries all work on that
+// combined input unchanged, the executor splits it up again right before
+// starting the target. argv and environment values cannot hold a NUL, so
+// the split is unambiguous; a part whose separator went missing (havoc) is
+// just empty.
+//
+// Targets taking several related files (a schema and a document, a
+// certificate and its key) get them as named outputs: the grammar has a
+// start rule <start:name> per output, every iteration derives all of them
+// together and the executor writes each into a file called name, passed
+// where the command line says @@name. The outputs are derived from the
+// same rules, so shared rules (see Annotation::shared) bind values across
+// them: a <namespace> the schema declares is the one the document uses.
+// Files can hold a NUL, an output with one gets cut short there.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::grammar::Grammar;
+
+// Separates the parts of a combined input
+pub const SEPARATOR: u8 = 0;
+
+// Name of the start rule of the regular input in a combined grammar
+const INPUT_START: &str = "<start:input>";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Position {
+    // index into the target command line, 0 being the program
+    Arg(usize),
+    // environment variable
+    Env(String),
+    // file name of a named output
+    File(String),
+}
+
+impl Position {
+    // arg:<index>, env:<name> or file:<name>
+    pub fn parse(spec: &str) -> Option<Self> {
+        match spec.split_once(':')? {
+            ("arg", index) => index.parse().ok().filter(|&x| x > 0)
+                .map(Position::Arg),
+            ("env", name) if !name.is_empty() && !name.contains('=') =>
+                Some(Position::Env(name.to_string())),
+            ("file", name) if is_file_name(name) => Some(Position::File(name.to_string())),
+            _ => None,
+        }
+    }
+
+    // Placeholder of a named output on the target command line
+    pub fn placeholder(&self) -> Option<String> {
+        match self {
+            Position::File(name) => Some(format!("@@{}", name)),
+            _ => None,
+        }
+    }
+}
+
+// Whether a named output can be a file of that name in a directory
+pub fn is_file_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\\', '\0'])
+}
+
+// Start rule of a named output
+pub fn output_start(name: &str) -> String {
+    format!("<start:{}>", name)
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Position::Arg(index) => write!(f, "arg:{}", index),
+            Position::Env(name) => write!(f, "env:{}", name),
+            Position::File(name) => write!(f, "file:{}", name),
+        }
+    }
+}
+
+// Combine the grammar of the regular input with one grammar per position
+// and the named outputs of the grammar (positions file:<name> after the
+// others). Rules of position grammars get the position as prefix
+// (<arg:1><start>), the start rule of the regular input becomes
+// <start:input>. A grammar made of named outputs alone has an empty
+// regular input. Errors name outputs without a start rule
+pub fn combine(mut input: Grammar, positions: Vec<(Position, Grammar)>, outputs: &[String])
+        -> Result<Grammar, String> {
+    if let Some(name) = outputs.iter().find(|x| !input.0.contains_key(&output_start(x))) {
+        return Err(format!("output {} has no rule {}", name, output_start(name)));
+    }
+    let regular = input.0.contains_key("<start>");
+    rename(&mut input, |name| match name {
+        "<start>" => INPUT_START.to_string(),
+        _ => name.to_string(),
+    });
+
+    let separator = String::from(SEPARATOR as char);
+    let mut start = Vec::new();
+    for (position, mut grammar) in positions {
+        let prefix = format!("<{}>", position);
+        rename(&mut grammar, |name| format!("{}{}", prefix, name));
+        start.push(format!("{}<start>", prefix));
+        start.push(separator.clone());
+        input.0.extend(grammar.0);
+        input.1.extend(grammar.1);
+    }
+    for name in outputs {
+        start.push(output_start(name));
+        start.push(separator.clone());
+    }
+    if regular {
+        start.push(INPUT_START.to_string());
+    }
+    input.0.insert("<start>".to_string(), vec![start]);
+    Ok(input)
+}
+
+// Rename every rule of a grammar and every reference to it
+pub fn rename(grammar: &mut Grammar, new_name: impl Fn(&str) -> String) {
+    let rules = std::mem::take(&mut grammar.0);
+    let names = rules.keys().cloned().collect::<HashSet<_>>();
+    let alternative = |alternative: Vec<String>| alternative.into_iter()
+        .map(|symbol| if names.contains(&symbol) { new_name(&symbol) } else { symbol })
+        .collect::<Vec<_>>();
+    grammar.0 = rules.into_iter().map(|(name, alternatives)| (new_name(&name),
+        alternatives.into_iter().map(alternative).collect())).collect();
+    // annotations name alternatives by their symbols
+    grammar.1 = std::mem::take(&mut grammar.1).into_iter()
+        .map(|(name, mut annotation)| {
+            for list in [&mut annotation.mutation_only, &mut annotation.generation_only] {
+                *list = std::mem::take(list).into_iter().map(alternative).collect();
+            }
+            (new_name(&name), annotation)
+        })
+        .collect();
+}
+
+// Split a combined input into the values of count positions and the
+// regular input
+pub fn split(input: &[u8], count: usize) -> (Vec<&[u8]>, &[u8]) {
+    let mut values = Vec::with_capacity(count);
+    let mut rest = input;
+    for _ in 0..count {
+        match rest.iter().position(|&x| x == SEPARATOR) {
+            Some(end) => {
+                values.push(&rest[..end]);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                values.push(rest);
+                rest = &[];
+            }
+        }
+    }
+    (values, rest)
+}
@@ -0,0 +1,242 @@
+// Where generation spends its time, for work on the generator itself
+//
+// --profile-internals (not in the usage, it is for us) derives test cases
+// from the grammar for about PROFILE_TIME in chunks of CHUNK derivations,
+// running every chunk several times from the same state with parts of the
+// work switched off, and attributes the differences:
+//
+//   choice sampling    picking alternatives with choose() vs. replaying
+//                      the picks it made
+//   stack operations   the pushes and pops of the derivations alone
+//   tree walk          the rest of a derivation without sampling or copying:
+//                      fragment lookups, the loop
+//   terminal copying   appending terminals to the output vs. counting them
+//   buffer growth      a new output buffer per derivation vs. one kept
+//
+// Times are per million derivations. Corruption is left out, and timing
+// differences of a few percent are noise.
+
+use std::hint::black_box;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use crate::context::GenerationContext;
+use crate::grammar::{Fragment, FragmentId, GeneratorState, GrammarRust};
+
+// Derivations per chunk, every pass over a chunk starts from the same state
+const CHUNK: usize = 1000;
+
+// Derivations stop after this much time with everything switched on, or
+// after a million
+const PROFILE_TIME: Duration = Duration::from_secs(2);
+const MAX_DERIVATIONS: usize = 1_000_000;
+
+// Pick of a derivation that ran out of budget
+const STOPPED: FragmentId = FragmentId(u32::MAX);
+
+#[derive(Clone, Debug, Default)]
+pub struct Profile {
+    pub derivations: usize,
+    pub nodes: u64,
+    pub bytes: u64,
+    // seconds over all derivations
+    pub generate: f64,
+    pub sampling: f64,
+    pub stack: f64,
+    pub walk: f64,
+    pub copying: f64,
+    pub growth: f64,
+}
+
+// What a derivation leaves for the passes replaying it
+#[derive(Default)]
+struct Recording {
+    picks: Vec<FragmentId>,
+    // fragments pushed at every node, and where every derivation ends
+    shape: Vec<u32>,
+    ends: Vec<usize>,
+}
+
+impl Recording {
+    fn clear(&mut self) {
+        self.picks.clear();
+        self.shape.clear();
+        self.ends.clear();
+    }
+}
+
+// One derivation like GrammarRust::generate(). Picks alternatives with
+// choose() and records them, or with REPLAY takes them from the recording
+// starting at next. Copies terminals into buf or with !COPY only counts
+// them. Returns the nodes expanded and the output size
+#[inline(always)]
+fn derive<const COPY: bool, const REPLAY: bool>(gram: &GrammarRust,
+        state: &mut GeneratorState, stack: &mut Vec<FragmentId>, buf: &mut Vec<u8>,
+        rec: &mut Recording, next: &mut usize) -> (usize, usize) {
+    let max_output = gram.output_limit(state);
+    stack.clear();
+    stack.push(gram.start());
+    if !REPLAY {
+        gram.start_derivation(state);
+    }
+    let (mut nodes, mut bytes) = (0, 0);
+    while let Some(cur) = stack.pop() {
+        nodes += 1;
+        match gram.lookup_fragment(cur) {
+            Fragment::NonTerminal(options) => {
+                let sel = match REPLAY {
+                    true => {
+                        *next += 1;
+                        rec.picks[*next - 1]
+                    }
+                    false => {
+                        let sel = gram.choose(state, cur, options, nodes, None)
+                            .unwrap_or(STOPPED);
+                        rec.picks.push(sel);
+                        rec.shape.push(1);
+                        sel
+                    }
+                };
+                if sel == STOPPED {
+                    break;
+                }
+                stack.push(sel);
+            }
+            Fragment::Expression(expr) => {
+                expr.iter().rev().for_each(|x| stack.push(*x));
+                if !REPLAY {
+                    rec.shape.push(expr.len() as u32);
+                }
+            }
+            Fragment::Terminal(value) => {
+                if COPY {
+                    buf.extend_from_slice(value);
+                }
+                bytes += value.len();
+                if !REPLAY {
+                    rec.shape.push(0);
+                }
+                if bytes > max_output {
+                    break;
+                }
+            }
+        }
+    }
+    if !REPLAY {
+        rec.ends.push(rec.shape.len());
+    }
+    (nodes, bytes)
+}
+
+// Time a pass over a chunk
+fn timed(run: impl FnOnce()) -> f64 {
+    let start = Instant::now();
+    run();
+    start.elapsed().as_secs_f64()
+}
+
+pub fn run(gram: &GrammarRust, seed: usize) -> Profile {
+    let mut profile = Profile::default();
+    let mut state = GeneratorState::new(seed);
+    let mut stack = Vec::new();
+    let mut buf = Vec::new();
+    let mut rec = Recording::default();
+    let mut ctx = GenerationContext::new(seed);
+    let mut spent = 0.;
+
+    while spent < PROFILE_TIME.as_secs_f64() && profile.derivations < MAX_DERIVATIONS {
+        let start = state.clone();
+
+        // everything on, one buffer
+        rec.clear();
+        let mut after = start.clone();
+        let copy = timed(|| for _ in 0..CHUNK {
+            buf.clear();
+            let (nodes, bytes) = derive::<true, false>(gram, &mut after, &mut stack, &mut buf,
+                &mut rec, &mut 0);
+            profile.nodes += nodes as u64;
+            profile.bytes += bytes as u64;
+            black_box(&buf);
+        });
+
+        // a new buffer every time
+        rec.clear();
+        let mut s = start.clone();
+        let grow = timed(|| for _ in 0..CHUNK {
+            let mut buf = Vec::new();
+            derive::<true, false>(gram, &mut s, &mut stack, &mut buf, &mut rec, &mut 0);
+            black_box(&buf);
+        });
+
+        // no copying, and the recording the next passes replay
+        rec.clear();
+        let mut s = start.clone();
+        let sample = timed(|| for _ in 0..CHUNK {
+            black_box(derive::<false, false>(gram, &mut s, &mut stack, &mut buf, &mut rec,
+                &mut 0));
+        });
+
+        // the same picks without choose()
+        let mut s = start.clone();
+        let mut next = 0;
+        let replay = timed(|| for _ in 0..CHUNK {
+            black_box(derive::<false, true>(gram, &mut s, &mut stack, &mut buf, &mut rec,
+                &mut next));
+        });
+
+        // the pushes and pops alone, summing what is popped so they
+        // cannot be optimized out
+        let stack_only = timed(|| {
+            let (mut from, mut sum) = (0, 0u32);
+            for &end in &rec.ends {
+                stack.clear();
+                stack.push(gram.start());
+                for &pushed in &rec.shape[from..end] {
+                    let top = stack.pop().unwrap_or(FragmentId(0));
+                    sum = sum.wrapping_add(top.0);
+                    for ii in 0..pushed {
+                        stack.push(FragmentId(top.0 ^ ii));
+                    }
+                }
+                from = end;
+            }
+            black_box(sum);
+        });
+
+        // the real thing
+        ctx.state = start;
+        let generate = timed(|| for _ in 0..CHUNK {
+            black_box(ctx.generate_bytes(gram));
+        });
+
+        profile.derivations += CHUNK;
+        profile.generate += generate;
+        profile.sampling += sample - replay;
+        profile.stack += stack_only;
+        profile.walk += replay - stack_only;
+        profile.copying += copy - sample;
+        profile.growth += grow - copy;
+        spent += copy;
+        state = after;
+    }
+    profile
+}
+
+impl Profile {
+    pub fn write(&self, out: &mut impl Write) -> io::Result<()> {
+        let n = self.derivations.max(1) as f64;
+        writeln!(out, "{} derivations, {:.1} nodes and {:.1} bytes on average, per million:",
+            self.derivations, self.nodes as f64 / n, self.bytes as f64 / n)?;
+        let ms = |x: f64| x.max(0.) * 1e3 * 1e6 / n;
+        writeln!(out, "  {:<18} {:>10.1} ms", "generate()", ms(self.generate))?;
+        let parts = [("choice sampling", self.sampling), ("stack operations", self.stack),
+            ("tree walk", self.walk), ("terminal copying", self.copying),
+            ("buffer growth", self.growth)];
+        let total = parts.iter().map(|x| x.1.max(0.)).sum::<f64>().max(f64::MIN_POSITIVE);
+        for (name, time) in parts {
+            writeln!(out, "  {:<18} {:>10.1} ms {:>5.1}%", name, ms(time),
+                time.max(0.) * 100. / total)?;
+        }
+        Ok(())
+    }
+}
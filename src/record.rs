@@ -0,0 +1,275 @@
+// Recording a campaign to replay it exactly
+//
+// Worker RNGs are seeded from the campaign seed, but what a worker does
+// still depends on the others: which corpus entry the shared scheduler
+// hands out, what arrives from other fuzzers, the adaptive mutator
+// weights. A misbehaving scheduler or mutator rarely shows up twice.
+//
+// With --record every worker logs every decision it took from shared
+// state, and after it its RNG state. --replay-record feeds the log back
+// instead of asking the shared state, so the worker draws the same numbers
+// and derives, mutates and runs the same inputs, round for round, under a
+// debugger if need be. Replay ends with the log.
+//
+// One log per worker, <dir>/worker<n>.log. Every round starts with what
+// it works on, then the worker state, then the picks it made:
+//
+//   'F'                            fresh generation
+//   'S' len bytes                  input synced from another fuzzer
+//   'C' energy first:u8 len parent id [len (fragment size)*]
+//                                  corpus entry to mutate, trees are
+//                                  numbered in order of appearance and
+//                                  only logged the first time
+//   'R' rng:u64 temperature:f64    RNG state and temperature
+//   'P' idx                        adaptive mutator pick
+//   'B' idx                        pairwise candidate kept
+//
+// Integers are LEB128 unless sized, sized ones little endian. The markov
+// strategy learns into shared state this log does not cover, it cannot be
+// recorded.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::grammar::{FragmentId, GeneratorState};
+use crate::hash::hash64;
+use crate::tree::{Node, Tree};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Record,
+    Replay,
+}
+
+// Log of worker n in dir
+pub fn log_path(dir: &Path, worker: usize) -> PathBuf {
+    dir.join(format!("worker{}.log", worker))
+}
+
+// A corpus pick as the scheduler made it
+#[derive(Clone, Debug, Default)]
+pub struct Pick {
+    pub tree: Tree,
+    pub parent: Option<String>,
+    pub energy: u64,
+    pub first: bool,
+}
+
+// What a worker took from shared state in a round
+#[derive(Clone, Debug)]
+pub enum Decision {
+    Fresh,
+    Sync(Vec<u8>),
+    Corpus(Pick),
+}
+
+pub enum Session {
+    // ids of the trees logged so far, by hash, and the trees themselves
+    Record(BufWriter<File>, HashMap<u64, u64>),
+    Replay(BufReader<File>, Vec<Tree>),
+}
+
+impl Session {
+    pub fn open(mode: Mode, path: &Path) -> io::Result<Self> {
+        let error = |e: io::Error| io::Error::new(e.kind(),
+            format!("{}: {}", path.display(), e));
+        Ok(match mode {
+            Mode::Record => Session::Record(BufWriter::new(File::create(path)
+                .map_err(error)?), HashMap::new()),
+            Mode::Replay => Session::Replay(BufReader::new(File::open(path)
+                .map_err(error)?), Vec::new()),
+        })
+    }
+
+    pub fn replaying(&self) -> bool {
+        matches!(self, Session::Replay(..))
+    }
+
+    // What a round works on: live() asks the shared state when recording,
+    // replay takes it from the log. None once the log is over
+    pub fn decide(&mut self, live: impl FnOnce() -> Decision)
+            -> io::Result<Option<Decision>> {
+        match self {
+            Session::Record(out, logged) => {
+                let decision = live();
+                match &decision {
+                    Decision::Fresh => out.write_all(b"F")?,
+                    Decision::Sync(data) => {
+                        out.write_all(b"S")?;
+                        write_bytes(out, data)?;
+                    }
+                    Decision::Corpus(pick) => {
+                        out.write_all(b"C")?;
+                        write_varint(out, pick.energy)?;
+                        out.write_all(&[pick.first as u8])?;
+                        write_bytes(out, pick.parent.as_deref().unwrap_or("").as_bytes())?;
+                        let next = logged.len() as u64;
+                        let id = *logged.entry(tree_hash(&pick.tree)).or_insert(next);
+                        write_varint(out, id)?;
+                        if id != next {
+                            return Ok(Some(decision));
+                        }
+                        write_varint(out, pick.tree.nodes.len() as u64)?;
+                        for node in &pick.tree.nodes {
+                            write_varint(out, node.fragment.0 as u64)?;
+                            write_varint(out, node.size as u64)?;
+                        }
+                    }
+                }
+                Ok(Some(decision))
+            }
+            Session::Replay(input, trees) => {
+                let mut tag = [0u8];
+                if input.read(&mut tag)? == 0 {
+                    return Ok(None);
+                }
+                read_decision(tag[0], input, trees).map(Some)
+            }
+        }
+    }
+
+    // The worker state after the decision: to the log, or from it, what
+    // live() drew does not matter then
+    pub fn state(&mut self, state: &mut GeneratorState) -> io::Result<()> {
+        match self {
+            Session::Record(out, _) => {
+                out.write_all(b"R")?;
+                out.write_all(&(state.rng_state() as u64).to_le_bytes())?;
+                out.write_all(&state.temperature().to_le_bytes())
+            }
+            Session::Replay(input, _) => {
+                expect(read_tag(input)?, b'R')?;
+                state.seed(read_u64(input)? as usize);
+                state.set_temperature(f64::from_bits(read_u64(input)?));
+                Ok(())
+            }
+        }
+    }
+
+    // An index picked from shared state (tag 'P' or 'B'): live when
+    // recording, the logged one when replaying
+    pub fn index(&mut self, tag: u8, live: usize) -> io::Result<usize> {
+        match self {
+            Session::Record(out, _) => {
+                out.write_all(&[tag])?;
+                write_varint(out, live as u64)?;
+                Ok(live)
+            }
+            Session::Replay(input, _) => {
+                expect(read_tag(input)?, tag)?;
+                Ok(read_varint(input)? as usize)
+            }
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Session::Record(out, _) => out.flush(),
+            Session::Replay(..) => Ok(()),
+        }
+    }
+}
+
+fn read_decision(tag: u8, input: &mut impl Read, trees: &mut Vec<Tree>)
+        -> io::Result<Decision> {
+    match tag {
+        b'F' => Ok(Decision::Fresh),
+        b'S' => Ok(Decision::Sync(read_bytes(input)?)),
+        b'C' => {
+            let energy = read_varint(input)?;
+            let mut first = [0u8];
+            input.read_exact(&mut first)?;
+            let parent = String::from_utf8(read_bytes(input)?)
+                .map_err(|_| invalid("parent name is not UTF-8".into()))?;
+            let id = read_varint(input)? as usize;
+            if id == trees.len() {
+                let mut tree = Tree::default();
+                for _ in 0..read_varint(input)? {
+                    let fragment = FragmentId(read_varint(input)? as u32);
+                    let size = read_varint(input)? as u32;
+                    tree.nodes.push(Node { fragment, size });
+                }
+                trees.push(tree);
+            }
+            let tree = trees.get(id).cloned()
+                .ok_or_else(|| invalid(format!("tree {} was never logged", id)))?;
+            Ok(Decision::Corpus(Pick {
+                tree,
+                parent: (!parent.is_empty()).then_some(parent),
+                energy,
+                first: first[0] != 0,
+            }))
+        }
+        tag => Err(invalid(format!("unexpected event {:?}",
+            tag as char))),
+    }
+}
+
+fn tree_hash(tree: &Tree) -> u64 {
+    let bytes = tree.nodes.iter()
+        .flat_map(|x| [x.fragment.0, x.size])
+        .flat_map(u32::to_le_bytes)
+        .collect::<Vec<_>>();
+    hash64(&bytes)
+}
+
+fn invalid(what: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("replay diverged: {}", what))
+}
+
+fn expect(tag: u8, want: u8) -> io::Result<()> {
+    if tag != want {
+        return Err(invalid(format!("expected event {:?}, log has {:?}",
+            want as char, tag as char)));
+    }
+    Ok(())
+}
+
+fn read_tag(input: &mut impl Read) -> io::Result<u8> {
+    let mut tag = [0u8];
+    input.read_exact(&mut tag)?;
+    Ok(tag[0])
+}
+
+fn read_u64(input: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    input.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn write_varint(out: &mut impl Write, mut value: u64) -> io::Result<()> {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            return out.write_all(&[byte]);
+        }
+        out.write_all(&[byte | 0x80])?;
+    }
+}
+
+fn read_varint(input: &mut impl Read) -> io::Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = read_tag(input)?;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid("varint too long".into()))
+}
+
+fn write_bytes(out: &mut impl Write, data: &[u8]) -> io::Result<()> {
+    write_varint(out, data.len() as u64)?;
+    out.write_all(data)
+}
+
+fn read_bytes(input: &mut impl Read) -> io::Result<Vec<u8>> {
+    let len = read_varint(input)? as usize;
+    let mut data = vec![0; len];
+    input.read_exact(&mut data)?;
+    Ok(data)
+}
@@ -0,0 +1,261 @@
+// Exploring a grammar one expansion at a time
+//
+// The repl command keeps a partial derivation: the bytes produced so far
+// and the rules not expanded yet, shown as numbered holes like [0:<expr>].
+// Holes get expanded one at a time, with a random alternative or a chosen
+// one, or finished off like generation would, to see how a grammar ends up
+// producing the shapes it does.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+use crate::grammar::{Fragment, FragmentId, GeneratorState, GrammarRust};
+use crate::tree::{Tree, TreeStack};
+
+const HELP: &str = "\
+commands, n is a hole, the first one by default:
+  show                 the partial output
+  alts [n]             alternatives of the rule of hole n
+  expand [n]           expand hole n with a random alternative
+  pick <alt> [n]       expand hole n with alternative alt
+  finish [n]           derive hole n, or every hole, to the end
+  seed <n>             reseed the random choices
+  start [<rule>]       start over from <start> or the given rule
+  undo                 take back the last step
+  quit";
+
+#[derive(Clone, Debug)]
+enum Item {
+    Bytes(Vec<u8>),
+    // a rule still to expand
+    Hole(FragmentId),
+}
+
+// What a command prints
+enum Reply {
+    // the partial output, it changed
+    Show,
+    Text(String),
+    Nothing,
+}
+
+pub struct Repl<'a> {
+    gram: &'a GrammarRust,
+    names: HashMap<FragmentId, &'a str>,
+    state: GeneratorState,
+    items: Vec<Item>,
+    history: Vec<Vec<Item>>,
+}
+
+impl<'a> Repl<'a> {
+    pub fn new(gram: &'a GrammarRust, seed: usize) -> Self {
+        let mut repl = Repl {
+            gram,
+            names: gram.rules().map(|(name, id)| (id, name)).collect(),
+            state: GeneratorState::new(seed),
+            items: Vec::new(),
+            history: Vec::new(),
+        };
+        repl.restart(gram.start());
+        repl
+    }
+
+    fn restart(&mut self, rule: FragmentId) {
+        self.gram.start_derivation(&mut self.state);
+        self.items = vec![Item::Hole(rule)];
+        self.history.clear();
+    }
+
+    // Index in items of the nth hole
+    fn hole(&self, nth: usize) -> Option<usize> {
+        self.items.iter().enumerate().filter(|x| matches!(x.1, Item::Hole(_)))
+            .nth(nth).map(|x| x.0)
+    }
+
+    fn name(&self, id: FragmentId) -> &str {
+        self.names.get(&id).copied().unwrap_or("<?>")
+    }
+
+    // A reference to a rule is a non-terminal with the rule as its only
+    // option, follow it to the rule
+    fn resolve(&self, mut id: FragmentId) -> FragmentId {
+        while !self.names.contains_key(&id) {
+            match self.gram.lookup_fragment(id) {
+                Fragment::NonTerminal(options) if options.len() == 1 => id = options[0],
+                _ => break,
+            }
+        }
+        id
+    }
+
+    // What an alternative consists of, terminals quoted
+    fn describe(&self, alternative: FragmentId) -> String {
+        let parts = match self.gram.lookup_fragment(alternative) {
+            Fragment::Expression(expr) => expr.to_vec(),
+            _ => vec![alternative],
+        };
+        if parts.is_empty() {
+            return "\"\"".to_string();
+        }
+        parts.iter().map(|&x| match self.gram.lookup_fragment(x) {
+            Fragment::Terminal(value) => format!("{:?}", String::from_utf8_lossy(value)),
+            _ => self.name(self.resolve(x)).to_string(),
+        }).collect::<Vec<_>>().join(" ")
+    }
+
+    // Replace the hole at items[at] by an alternative of its rule
+    fn expand(&mut self, at: usize, alternative: FragmentId) {
+        let parts = match self.gram.lookup_fragment(alternative) {
+            Fragment::Expression(expr) => expr.to_vec(),
+            _ => vec![alternative],
+        };
+        let new = parts.iter().map(|&x| match self.gram.lookup_fragment(x) {
+            Fragment::Terminal(value) => Item::Bytes(value.to_vec()),
+            _ => Item::Hole(self.resolve(x)),
+        }).collect::<Vec<_>>();
+        self.items.splice(at..=at, new);
+    }
+
+    // Derive the hole at items[at] to the end, the way generate() does
+    fn finish(&mut self, at: usize) {
+        let Item::Hole(rule) = self.items[at] else {
+            return;
+        };
+        let mut tree = Tree::default();
+        let (gram, state) = (self.gram, &mut self.state);
+        gram.derive_tree(rule, &mut TreeStack::default(), &mut tree,
+            |cur, options, nodes, context| gram.choose(state, cur, options, nodes, context));
+        let mut bytes = Vec::new();
+        tree.serialize(gram, &mut bytes);
+        self.items[at] = Item::Bytes(bytes);
+    }
+
+    fn show(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut text = String::new();
+        let mut holes = 0;
+        for item in &self.items {
+            match item {
+                Item::Bytes(bytes) => text.push_str(&String::from_utf8_lossy(bytes)),
+                &Item::Hole(rule) => {
+                    text.push_str(&format!("[{}:{}]", holes, self.name(rule)));
+                    holes += 1;
+                }
+            }
+        }
+        writeln!(out, "{}", text)?;
+        if holes == 0 {
+            writeln!(out, "(complete)")?;
+        }
+        Ok(())
+    }
+
+    // Carry out the command of words, what to print afterwards
+    fn step(&mut self, words: &[&str]) -> Result<Reply, String> {
+        let number = |idx: usize| -> Result<usize, String> {
+            words.get(idx).map_or(Ok(0), |x| x.parse()
+                .map_err(|_| format!("{:?} is not a number", x)))
+        };
+        // the hole numbered by words[idx], as an index in items, and its rule
+        let hole = |repl: &Self, idx: usize| -> Result<(usize, FragmentId), String> {
+            let nth = number(idx)?;
+            let at = repl.hole(nth).ok_or_else(|| format!("there is no hole {}", nth))?;
+            match repl.items[at] {
+                Item::Hole(rule) => Ok((at, rule)),
+                Item::Bytes(_) => unreachable!("hole() only finds holes"),
+            }
+        };
+
+        match words[0] {
+            "help" | "?" => Ok(Reply::Text(HELP.to_string())),
+            "show" => Ok(Reply::Show),
+            "alts" => {
+                let (_, rule) = hole(self, 1)?;
+                Ok(Reply::Text(self.gram.lookup_fragment_nonterm(rule).iter().enumerate()
+                    .map(|(ii, &x)| format!("  {}: {}", ii, self.describe(x)))
+                    .collect::<Vec<_>>().join("\n")))
+            }
+            "expand" | "pick" => {
+                let ((at, rule), alt) = match words[0] {
+                    "pick" if words.len() < 2 => return Err("pick <alt> [n]".to_string()),
+                    "pick" => (hole(self, 2)?, Some(number(1)?)),
+                    _ => (hole(self, 1)?, None),
+                };
+                let options = self.gram.lookup_fragment_nonterm(rule);
+                let alternative = match alt {
+                    Some(alt) => *options.get(alt).ok_or_else(||
+                        format!("{} has {} alternatives", self.name(rule), options.len()))?,
+                    None => self.gram.choose(&mut self.state, rule, options, 0, None)
+                        .ok_or_else(|| format!("{} cannot terminate", self.name(rule)))?,
+                };
+                self.history.push(self.items.clone());
+                self.expand(at, alternative);
+                Ok(Reply::Show)
+            }
+            "finish" => {
+                let only = match words.len() {
+                    1 => None,
+                    _ => Some(hole(self, 1)?.0),
+                };
+                self.history.push(self.items.clone());
+                for at in 0..self.items.len() {
+                    if only.is_none_or(|x| x == at) {
+                        self.finish(at);
+                    }
+                }
+                Ok(Reply::Show)
+            }
+            "seed" => {
+                let seed = words.get(1).and_then(|x| x.parse().ok())
+                    .ok_or_else(|| "seed <n>".to_string())?;
+                self.state.seed(seed);
+                Ok(Reply::Nothing)
+            }
+            "start" => {
+                let rule = match words.get(1) {
+                    Some(name) => self.gram.rules().find(|x| x.0 == *name).map(|x| x.1)
+                        .ok_or_else(|| format!("there is no rule {}", name))?,
+                    None => self.gram.start(),
+                };
+                self.restart(rule);
+                Ok(Reply::Show)
+            }
+            "undo" => {
+                self.items = self.history.pop().ok_or_else(|| "nothing to undo".to_string())?;
+                Ok(Reply::Show)
+            }
+            command => Err(format!("unknown command {:?}, see help", command)),
+        }
+    }
+
+    // Run one command line, false once asked to quit
+    pub fn command(&mut self, line: &str, out: &mut impl Write) -> io::Result<bool> {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        match words.first() {
+            None => return Ok(true),
+            Some(&"quit" | &"exit") => return Ok(false),
+            Some(_) => {}
+        }
+        match self.step(&words) {
+            Ok(Reply::Show) => self.show(out)?,
+            Ok(Reply::Text(text)) => writeln!(out, "{}", text)?,
+            Ok(Reply::Nothing) => {}
+            Err(e) => writeln!(out, "{}", e)?,
+        }
+        Ok(true)
+    }
+
+    // Read commands until quit or the end of input
+    pub fn run(&mut self, input: impl BufRead, out: &mut impl Write) -> io::Result<()> {
+        self.show(out)?;
+        write!(out, "> ")?;
+        out.flush()?;
+        for line in input.lines() {
+            if !self.command(&line?, out)? {
+                return Ok(());
+            }
+            write!(out, "> ")?;
+            out.flush()?;
+        }
+        writeln!(out)
+    }
+}
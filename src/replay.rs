@@ -0,0 +1,120 @@
+// Replaying saved crashes
+//
+// Crashes of grammar fuzzing often depend on timing or on state the target
+// kept from inputs before, so one crash in the campaign does not mean the
+// input crashes the target. The replay command runs saved crashes (a file,
+// or every entry of a crashes or hangs directory) a number of times against
+// the target and counts how often they crash again, and how: the sanitizer
+// bucket (see sanitizer.rs) or the signal of every run that crashed. The
+// result goes into the metadata sidecar of entries that have a .meta
+// directory next to them, under "replay", so triage sees which crashes are
+// solid and which are flaky.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::compress;
+use crate::executor::{ExitKind, Executor};
+use crate::output::unix_time;
+
+// Runs per input unless asked for more
+pub const DEFAULT_RUNS: u32 = 10;
+
+// What replaying one input found
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Replay {
+    pub runs: u32,
+    pub crashes: u32,
+    pub timeouts: u32,
+    // share of the runs that did what the input was saved for, crashing
+    // or for entries of a hangs directory timing out
+    pub reproduced: f64,
+    // reproduced in some runs but not in all
+    pub flaky: bool,
+    // runs that crashed by bucket, "signal 11" without a sanitizer report
+    pub buckets: BTreeMap<String, u32>,
+    // when it was replayed, unix time
+    pub time: u64,
+}
+
+// The inputs a path stands for, in name order: a file, or every file of a
+// directory but hidden ones and the README AFL++ puts in crashes
+pub fn entries(path: &Path) -> io::Result<Vec<PathBuf>> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut entries = fs::read_dir(path)?.filter_map(|x| x.ok()).map(|x| x.path())
+        .filter(|x| x.is_file() && x.file_name().is_some_and(|name| {
+            let name = name.to_string_lossy();
+            !name.starts_with('.') && name != "README.txt"
+        })).collect::<Vec<_>>();
+    entries.sort();
+    Ok(entries)
+}
+
+// Whether an entry was saved for timing out rather than crashing
+fn is_hang(entry: &Path) -> bool {
+    entry.parent().and_then(|x| x.file_name()).is_some_and(|x| x == "hangs")
+}
+
+// Run an entry runs times
+pub fn replay(executor: &mut dyn Executor, entry: &Path, runs: u32) -> io::Result<Replay> {
+    let data = compress::read_entry(entry)?;
+    let mut replay = Replay { runs, time: unix_time(), ..Replay::default() };
+    for _ in 0..runs {
+        let result = executor.run(&data)?;
+        match result.exit {
+            ExitKind::Crash => {
+                replay.crashes += 1;
+                let bucket = match (&result.sanitizer, result.signal) {
+                    (Some(report), _) => report.key(),
+                    (None, Some(signal)) => format!("signal {}", signal),
+                    (None, None) => "crash".to_string(),
+                };
+                *replay.buckets.entry(bucket).or_insert(0) += 1;
+            }
+            ExitKind::Timeout => replay.timeouts += 1,
+            ExitKind::Ok | ExitKind::Limit(_) => {}
+        }
+    }
+    let reproduced = match is_hang(entry) {
+        true => replay.timeouts,
+        false => replay.crashes,
+    };
+    replay.reproduced = reproduced as f64 / runs.max(1) as f64;
+    replay.flaky = reproduced > 0 && reproduced < runs;
+    Ok(replay)
+}
+
+// Put the replay into the metadata sidecar of entry, created if there is
+// a .meta directory but no sidecar. False for entries outside an output
+// directory
+pub fn update_sidecar(entry: &Path, replay: &Replay) -> io::Result<bool> {
+    let (Some(dir), Some(name)) = (entry.parent(), entry.file_name()) else {
+        return Ok(false);
+    };
+    let meta = dir.join(".meta");
+    if !meta.is_dir() {
+        return Ok(false);
+    }
+    let mut name = name.to_os_string();
+    name.push(".json");
+    let path = meta.join(name);
+    let mut sidecar = match fs::read(&path) {
+        Ok(bytes) => serde_json::from_slice(&bytes).map_err(|e|
+            io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => serde_json::json!({}),
+        Err(e) => return Err(e),
+    };
+    let Some(object) = sidecar.as_object_mut() else {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+            format!("{}: not a json object", path.display())));
+    };
+    object.insert("replay".to_string(), serde_json::to_value(replay)?);
+    fs::write(&path, serde_json::to_vec_pretty(&sidecar)?)?;
+    Ok(true)
+}
@@ -0,0 +1,310 @@
+// End of campaign report
+//
+// Everything a CI job wants to gate on: how long it ran, what it found
+// (crashes bucketed by sanitizer report), and how much of the target and
+// of the grammar it covered. Logged for humans and written as json to
+// campaign_report.json in the instance directory for machines.
+
+use std::collections::{BTreeMap, HashSet};
+use std::io;
+use std::sync::atomic::Ordering;
+
+use serde::Serialize;
+
+use crate::coverage::MAP_SIZE;
+use crate::executor::Resource;
+use crate::fuzzer::Shared;
+use crate::grammar::{Fragment, GrammarRust};
+use crate::histogram::HistogramReport;
+use crate::info;
+use crate::output::GENERATOR;
+use crate::rule_stats::RuleStatsReport;
+
+// Why the campaign ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StopReason {
+    MaxTime,
+    MaxExecs,
+    Crash,
+    Interrupted,
+    // the logs of --replay-record are over
+    ReplayEnd,
+    Error,
+}
+
+impl StopReason {
+    pub fn name(self) -> &'static str {
+        match self {
+            StopReason::MaxTime => "max-time",
+            StopReason::MaxExecs => "max-execs",
+            StopReason::Crash => "crash",
+            StopReason::Interrupted => "interrupted",
+            StopReason::ReplayEnd => "replay-end",
+            StopReason::Error => "error",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Bug {
+    pub bug_type: String,
+    pub function: String,
+    pub crashes: u64,
+}
+
+// Which parts of the grammar the corpus exercises
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct GrammarCoverage {
+    pub rules: usize,
+    pub rules_used: usize,
+    pub alternatives: usize,
+    pub alternatives_used: usize,
+
+    // corpus entries using each rule, most used first
+    pub rule_usage: Vec<(String, usize)>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CampaignReport {
+    pub generator: &'static str,
+    pub grammar_fingerprint: String,
+    pub stop_reason: StopReason,
+
+    pub run_time: f64,
+    pub execs: u64,
+    pub execs_per_sec: f64,
+    // share of worker time spent held back by --rate/--duty, percent
+    pub throttled: Option<f64>,
+
+    // inputs skipped for having run before, see dedup.rs
+    pub duplicates: u64,
+    pub duplicate_rate: f64,
+
+    // inputs that got a cached result instead of running, see
+    // exec_cache.rs
+    pub cache_hits: u64,
+    pub cache_hit_rate: f64,
+
+    // inputs the validity filter saw and rejected, see selftest.rs
+    pub filter_checked: u64,
+    pub filter_rejected: u64,
+
+    pub crashes: u64,
+    pub timeouts: u64,
+    pub saved_crashes: u64,
+    pub saved_hangs: u64,
+    // executions that hit each resource limit
+    pub limits: BTreeMap<&'static str, u64>,
+    pub bugs: Vec<Bug>,
+
+    pub edges: usize,
+    pub total_edges: usize,
+    pub queue_entries: u64,
+    pub derivation_trees: usize,
+    // corpus entries cut for redundancy, see corpus.rs
+    pub culled: u64,
+    pub production_pairs: usize,
+    pub grammar: GrammarCoverage,
+
+    // shape of the executed inputs, with --histograms
+    pub histograms: Option<HistogramReport>,
+    // what every rule does for the campaign, with --rule-stats
+    pub rule_stats: Option<RuleStatsReport>,
+}
+
+impl CampaignReport {
+    pub fn new(shared: &Shared, gram: &GrammarRust, elapsed: f64, jobs: usize,
+            stop_reason: StopReason) -> Self {
+        let Shared { feedback, stats, corpus, pairs, bugs, throttle, output,
+            dedup, exec_cache, histograms, rule_stats, .. } = shared;
+        let execs = stats.execs.load(Ordering::Relaxed);
+        let corpus = corpus.lock().unwrap();
+
+        // how many corpus entries use each rule and which alternatives
+        // show up at all
+        let rules = gram.rules().collect::<BTreeMap<_, _>>();
+        let mut usage = rules.keys().map(|&name| (name, 0usize))
+            .collect::<BTreeMap<_, _>>();
+        let alternatives = rules.values()
+            .flat_map(|&id| gram.lookup_fragment_nonterm(id).iter().copied())
+            .collect::<HashSet<_>>();
+        let mut alternatives_used = HashSet::new();
+        for idx in 0..corpus.len() {
+            let fragments = corpus.get(idx).tree.nodes.iter()
+                .map(|x| x.fragment).collect::<HashSet<_>>();
+            for (name, id) in &rules {
+                if fragments.contains(id) {
+                    *usage.get_mut(name).unwrap() += 1;
+                }
+            }
+            alternatives_used.extend(fragments.into_iter().filter(|x|
+                matches!(gram.lookup_fragment(*x), Fragment::Expression(_))
+                    && alternatives.contains(x)));
+        }
+        let mut rule_usage = usage.into_iter()
+            .map(|(name, count)| (name.to_string(), count)).collect::<Vec<_>>();
+        rule_usage.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        let bugs = bugs.lock().unwrap().iter().flat_map(|(bug_type, functions)|
+            functions.iter().map(|(function, &crashes)| Bug {
+                bug_type: bug_type.clone(),
+                function: function.clone(),
+                crashes,
+            })).collect();
+
+        CampaignReport {
+            generator: GENERATOR,
+            grammar_fingerprint: format!("{:016x}", gram.fingerprint()),
+            stop_reason,
+            run_time: elapsed,
+            execs,
+            execs_per_sec: execs as f64 / elapsed.max(1e-9),
+            throttled: throttle.is_active().then(|| throttle.waited().as_secs_f64()
+                * 100. / (elapsed * jobs as f64).max(1e-9)),
+            duplicates: dedup.as_ref()
+                .map_or(0, |x| x.duplicates.load(Ordering::Relaxed)),
+            duplicate_rate: dedup.as_ref().map_or(0., |x| x.rate()),
+            cache_hits: exec_cache.as_ref().map_or(0, |x| x.hits.load(Ordering::Relaxed)),
+            cache_hit_rate: exec_cache.as_ref().map_or(0., |x| x.rate()),
+            filter_checked: stats.filter_checked.load(Ordering::Relaxed),
+            filter_rejected: stats.filter_rejected.load(Ordering::Relaxed),
+            crashes: stats.crashes.load(Ordering::Relaxed),
+            timeouts: stats.timeouts.load(Ordering::Relaxed),
+            saved_crashes: output.crashes_len(),
+            saved_hangs: output.hangs_len(),
+            limits: Resource::ALL.iter().map(|&x| (x.name(),
+                stats.limits[x as usize].load(Ordering::Relaxed))).collect(),
+            bugs,
+            edges: feedback.lock().unwrap().edges(),
+            total_edges: MAP_SIZE,
+            queue_entries: output.queue_len(),
+            derivation_trees: corpus.len(),
+            culled: corpus.culled(),
+            production_pairs: pairs.lock().unwrap().len(),
+            grammar: GrammarCoverage {
+                rules: rules.len(),
+                rules_used: rule_usage.iter().filter(|x| x.1 > 0).count(),
+                alternatives: alternatives.len(),
+                alternatives_used: alternatives_used.len(),
+                rule_usage,
+            },
+            histograms: histograms.as_ref().map(|x| x.report()),
+            rule_stats: rule_stats.as_ref().map(|x| x.report()),
+        }
+    }
+
+    // Write the json report into the instance directory
+    pub fn save(&self, shared: &Shared) -> io::Result<()> {
+        std::fs::write(shared.output.dir().join("campaign_report.json"),
+            serde_json::to_vec_pretty(self)?)
+    }
+
+    // Human readable summary
+    pub fn log(&self) {
+        info!("campaign", "stopped ({}) after {:.0}s, {} execs ({:.0}/s), {} crashes, {} timeouts",
+            self.stop_reason.name(), self.run_time, self.execs, self.execs_per_sec,
+            self.crashes, self.timeouts);
+        if self.duplicates > 0 {
+            info!("campaign", "{} duplicate inputs skipped ({:.1}% of all)",
+                self.duplicates, self.duplicate_rate);
+        }
+        if self.cache_hits > 0 {
+            info!("campaign", "{} inputs got a cached result ({:.1}% of lookups)",
+                self.cache_hits, self.cache_hit_rate);
+        }
+        if self.filter_checked > 0 {
+            info!("campaign", "{} of {} inputs rejected by the validity filter ({:.1}%)",
+                self.filter_rejected, self.filter_checked,
+                self.filter_rejected as f64 * 100. / self.filter_checked as f64);
+        }
+        if let Some(throttled) = self.throttled {
+            info!("campaign", "workers were throttled {:.0}% of the time", throttled);
+        }
+        for (resource, &count) in &self.limits {
+            if count > 0 {
+                info!("campaign", "{} execs hit the {} limit", count, resource);
+            }
+        }
+        let mut by_type = BTreeMap::<&str, Vec<&Bug>>::new();
+        for bug in &self.bugs {
+            by_type.entry(&bug.bug_type).or_default().push(bug);
+        }
+        for (bug_type, bugs) in by_type {
+            info!("campaign", "{}: {} crashes in {}", bug_type,
+                bugs.iter().map(|x| x.crashes).sum::<u64>(),
+                bugs.iter().map(|x| x.function.as_str()).collect::<Vec<_>>()
+                    .join(", "));
+        }
+
+        if let Some(histograms) = &self.histograms {
+            for (what, summary) in [("depth", &histograms.depth),
+                    ("nodes", &histograms.nodes), ("size", &histograms.size)] {
+                if summary.count == 0 {
+                    continue;
+                }
+                info!("campaign", "input {}: mean {:.1}, p50 {}, p90 {}, p99 {}, max {}",
+                    what, summary.mean, summary.p50, summary.p90, summary.p99,
+                    summary.max);
+            }
+        }
+        info!("campaign", "{} edges, {} queue entries, {} derivation trees",
+            self.edges, self.queue_entries, self.derivation_trees);
+        if self.culled > 0 {
+            info!("campaign", "{} corpus entries culled as redundant", self.culled);
+        }
+        if self.production_pairs > 0 {
+            info!("campaign", "{} production pairs generated", self.production_pairs);
+        }
+        if self.derivation_trees == 0 {
+            return;
+        }
+
+        // rules nobody uses point at parts of the grammar the target never
+        // cared about (or never got to)
+        let grammar = &self.grammar;
+        info!("campaign", "grammar coverage: {}/{} rules, {}/{} alternatives",
+            grammar.rules_used, grammar.rules, grammar.alternatives_used,
+            grammar.alternatives);
+        let usage = &grammar.rule_usage;
+        let list = |rules: &[(String, usize)]| rules.iter()
+            .map(|(name, count)| format!("{} ({})", name, count))
+            .collect::<Vec<_>>().join(", ");
+        info!("campaign", "most used rules: {}", list(&usage[..usage.len().min(5)]));
+        let unused = usage.iter().filter(|x| x.1 == 0).map(|x| x.0.as_str())
+            .collect::<Vec<_>>();
+        if !unused.is_empty() {
+            info!("campaign", "rules no corpus entry uses: {}", unused.join(", "));
+        } else {
+            let tail = usage.len().saturating_sub(5);
+            info!("campaign", "least used rules: {}", list(&usage[tail..]));
+        }
+        if let Some(rule_stats) = &self.rule_stats {
+            log_rule_stats(rule_stats);
+        }
+    }
+}
+
+// The per rule table, then the rules that do nothing for the campaign
+fn log_rule_stats(report: &RuleStatsReport) {
+    info!("campaign", "rule stats over {} inputs, {} interesting, {} bytes",
+        report.inputs, report.interesting, report.bytes);
+    info!("campaign", "{:<24} {:>10} {:>7} {:>7} {:>11} {:>6}", "rule", "expansions",
+        "inputs", "bytes", "interesting", "lift");
+    for rule in &report.rules {
+        info!("campaign", "{:<24} {:>10} {:>6.1}% {:>6.1}% {:>11} {:>6}", rule.rule,
+            rule.expansions, rule.input_share, rule.byte_share, rule.interesting,
+            rule.lift.map_or("-".to_string(), |x| format!("{:.2}", x)));
+    }
+    let dead = report.rules.iter().filter(|x| x.inputs == 0).map(|x| x.rule.as_str())
+        .collect::<Vec<_>>();
+    if !dead.is_empty() {
+        info!("campaign", "rules never derived: {}", dead.join(", "));
+    }
+    let idle = report.rules.iter()
+        .filter(|x| x.inputs > 0 && x.interesting == 0 && report.interesting > 0)
+        .map(|x| x.rule.as_str()).collect::<Vec<_>>();
+    if !idle.is_empty() {
+        info!("campaign", "rules in no interesting input: {}", idle.join(", "));
+    }
+}
@@ -0,0 +1,41 @@
+// Splittable seeding for parallel runs
+//
+// A campaign has a single seed. Every worker derives its own xorshift seed
+// from it with splitmix64, so the stream of worker n only depends on the
+// campaign seed and n, and "--seed X --jobs N" generates the same inputs
+// every time no matter how the threads get scheduled.
+
+// splitmix64 increment (golden ratio)
+const GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+// splitmix64 finalizer, turns neighbouring seeds into unrelated ones
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SplitSeed(u64);
+
+impl SplitSeed {
+    pub fn new(seed: u64) -> Self {
+        SplitSeed(seed)
+    }
+
+    // Fresh campaign seed when the user did not pick one
+    pub fn random() -> Self {
+        use rand::Rng;
+        SplitSeed(rand::thread_rng().gen())
+    }
+
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+
+    // Seed of stream n, usable as xorshift state (never 0)
+    pub fn stream(&self, n: usize) -> usize {
+        let seed = mix(self.0.wrapping_add(GAMMA.wrapping_mul(n as u64 + 1)));
+        (seed as usize).max(1)
+    }
+}
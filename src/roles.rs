@@ -0,0 +1,180 @@
+// Workers with roles of their own
+//
+// Every worker of a campaign does the same by default: mostly mutating
+// corpus entries, now and then deriving a fresh input. --worker sets a
+// group of workers apart with a role and settings of their own, while all
+// of them keep feeding and drawing from the one corpus and coverage map:
+//
+//   generate   fresh derivations only, havoc on them still applies
+//   tree       corpus entries through the tree mutators, no havoc or
+//              error injection
+//   havoc      corpus entries through havoc only
+//   mixed      what workers without a group do
+//
+// A group is a comma separated list of key=value settings, a bare word
+// being the role:
+//
+//   --worker havoc,jobs=2,havoc=0.5
+//   --worker role=tree,max-nodes=200,subtree=8,swap=0
+//
+//   jobs             workers in the group, 1 by default
+//   max-nodes        node budget of their derivations (depth, in effect)
+//   max-size         output size limit
+//   havoc            havoc probability, 1 for the havoc role by default
+//   mutation-stack   most tree mutators stacked on an input
+//   swap, recursion, choices, subtree
+//                    weight of a tree mutator, 0 leaves it out
+//
+// Settings a group leaves alone come from the campaign flags. In a config
+// file every [[worker]] table is a group. The campaign runs the workers
+// of all groups, and mixed workers beyond them up to --jobs.
+
+use std::fmt;
+
+use crate::mutator::{self, Havoc, Inject, Scheduler};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Role {
+    #[default]
+    Mixed,
+    Generate,
+    Tree,
+    Havoc,
+}
+
+impl Role {
+    fn parse(name: &str) -> Option<Role> {
+        match name {
+            "mixed" => Some(Role::Mixed),
+            "generate" => Some(Role::Generate),
+            "tree" => Some(Role::Tree),
+            "havoc" => Some(Role::Havoc),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Role::Mixed => "mixed",
+            Role::Generate => "generate",
+            Role::Tree => "tree",
+            Role::Havoc => "havoc",
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct WorkerGroup {
+    pub role: Role,
+    pub jobs: usize,
+    pub max_nodes: Option<usize>,
+    pub max_size: Option<usize>,
+    pub havoc: Option<f64>,
+    pub mutation_stack: Option<usize>,
+    // tree mutator weights by mutator name
+    pub weights: Vec<(String, usize)>,
+}
+
+impl WorkerGroup {
+    // Parse a group, see the top of the file
+    pub fn parse(spec: &str) -> Result<WorkerGroup, String> {
+        let mut group = WorkerGroup {
+            role: Role::Mixed,
+            jobs: 1,
+            max_nodes: None,
+            max_size: None,
+            havoc: None,
+            mutation_stack: None,
+            weights: Vec::new(),
+        };
+        for setting in spec.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+            let (key, value) = setting.split_once('=').unwrap_or(("role", setting));
+            let (key, value) = (key.trim(), value.trim());
+            let number = || value.parse::<usize>()
+                .map_err(|_| format!("{}: {:?} is not a number", key, value));
+            match key {
+                "role" => group.role = Role::parse(value)
+                    .ok_or_else(|| format!("unknown role {:?}", value))?,
+                "jobs" => group.jobs = number()?,
+                "max-nodes" => group.max_nodes = Some(number()?),
+                "max-size" => group.max_size = Some(number()?),
+                "mutation-stack" => group.mutation_stack = Some(number()?),
+                "havoc" => group.havoc = Some(value.parse().ok()
+                    .filter(|x| (0.0..=1.0).contains(x))
+                    .ok_or_else(|| format!("havoc: {:?} is not a probability", value))?),
+                _ if mutator::grammar_mutators().iter().any(|x| x.0.name() == key) =>
+                    group.weights.push((key.to_string(), number()?)),
+                _ => return Err(format!("unknown worker setting {:?}", key)),
+            }
+        }
+        if group.jobs == 0 {
+            return Err("a worker group needs jobs".to_string());
+        }
+        Ok(group)
+    }
+
+    // The mutators of the group's workers, havoc, inject and inject_rate
+    // as in Scheduler::standard() unless the group says otherwise
+    pub fn scheduler(&self, max_stack: usize, havoc: f64, inject: usize,
+            inject_rate: f64) -> Scheduler {
+        let havoc = self.havoc.unwrap_or(match self.role {
+            Role::Havoc => 1.0,
+            _ => havoc,
+        });
+        let mut scheduler = Scheduler::new(self.mutation_stack.unwrap_or(max_stack));
+        // only corpus entries get stacked mutators
+        if matches!(self.role, Role::Mixed | Role::Tree) {
+            for (mutator, weight) in mutator::grammar_mutators() {
+                let weight = self.weights.iter().rfind(|x| x.0 == mutator.name())
+                    .map_or(weight, |x| x.1);
+                if weight > 0 {
+                    scheduler.push(mutator, weight);
+                }
+            }
+        }
+        if self.role == Role::Tree {
+            return scheduler;
+        }
+        if havoc > 0.0 {
+            scheduler.push_finisher(Box::new(Havoc), havoc);
+        }
+        if inject > 0 && inject_rate > 0.0 {
+            scheduler.push_finisher(Box::new(Inject(inject)), inject_rate);
+        }
+        scheduler
+    }
+}
+
+impl fmt::Display for WorkerGroup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} x{}", self.role, self.jobs)?;
+        if let Some(x) = self.max_nodes {
+            write!(f, ", max-nodes {}", x)?;
+        }
+        if let Some(x) = self.max_size {
+            write!(f, ", max-size {}", x)?;
+        }
+        if let Some(x) = self.havoc {
+            write!(f, ", havoc {}", x)?;
+        }
+        if let Some(x) = self.mutation_stack {
+            write!(f, ", mutation-stack {}", x)?;
+        }
+        for (name, weight) in &self.weights {
+            write!(f, ", {} {}", name, weight)?;
+        }
+        Ok(())
+    }
+}
+
+// The group of every one of jobs workers, in group order, None for the
+// mixed workers beyond the groups
+pub fn assign(groups: &[WorkerGroup], jobs: usize) -> Vec<Option<usize>> {
+    let mut ret = groups.iter().enumerate()
+        .flat_map(|(idx, group)| std::iter::repeat_n(Some(idx), group.jobs))
+        .collect::<Vec<_>>();
+    ret.resize(ret.len().max(jobs), None);
+    ret
+}
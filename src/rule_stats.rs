@@ -0,0 +1,166 @@
+// What every rule of the grammar does for a campaign
+//
+// Grammar coverage in the report says which rules the corpus uses, not
+// whether they pull their weight. With --rule-stats every executed input
+// that has a derivation tree adds, for every rule in it, how often the rule
+// was expanded and how many bytes its outermost expansions derived, and
+// whether the input was interesting: new coverage, a feedback keeping it or
+// a new crash or hang. The report has a row per rule with the share of
+// inputs and bytes it takes and its lift, how much likelier an input with
+// the rule is interesting than inputs are in general. A rule in much of
+// the inputs and bytes with a lift below 1 is dead freight, one never
+// derived is dead.
+//
+// Bytes are those of the terminals under an expansion, as derived: what
+// corruption changes and what repeated shared rules copy is not counted.
+// Counters are relaxed atomics, all workers share one RuleStats.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::Serialize;
+
+use crate::grammar::{Fragment, GrammarRust};
+use crate::tree::Tree;
+
+#[derive(Debug, Default)]
+struct Counters {
+    expansions: AtomicU64,
+    inputs: AtomicU64,
+    interesting: AtomicU64,
+    bytes: AtomicU64,
+}
+
+#[derive(Debug)]
+pub struct RuleStats {
+    // by fragment index, None for fragments that are not rules
+    rules: Vec<Option<(String, Counters)>>,
+    inputs: AtomicU64,
+    interesting: AtomicU64,
+    bytes: AtomicU64,
+}
+
+// Per input counts of a rule: expansions, bytes, and whether an outermost
+// expansion of it is open
+#[derive(Default)]
+struct Seen {
+    expansions: u64,
+    bytes: u64,
+    open: bool,
+}
+
+impl RuleStats {
+    pub fn new(gram: &GrammarRust) -> Self {
+        let mut rules = Vec::new();
+        for (name, id) in gram.rules() {
+            if rules.len() <= id.index() {
+                rules.resize_with(id.index() + 1, || None);
+            }
+            rules[id.index()] = Some((name.to_string(), Counters::default()));
+        }
+        RuleStats {
+            rules,
+            inputs: AtomicU64::new(0),
+            interesting: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+        }
+    }
+
+    // Record the derivation tree of an executed input
+    pub fn record(&self, gram: &GrammarRust, tree: &Tree, interesting: bool) {
+        let is_rule = |idx: usize| self.rules.get(idx).is_some_and(Option::is_some);
+        let mut seen = HashMap::<usize, Seen>::new();
+
+        // outermost expansions not over yet: end node, rule and the bytes
+        // derived before it. Expansions nest, the innermost ends first
+        let mut open: Vec<(usize, usize, u64)> = Vec::new();
+        let mut derived = 0u64;
+        for (ii, node) in tree.nodes.iter().enumerate() {
+            while let Some((_, rule, from)) = open.pop_if(|x| x.0 <= ii) {
+                let rule = seen.get_mut(&rule).expect("open rules are seen");
+                rule.bytes += derived - from;
+                rule.open = false;
+            }
+            match gram.lookup_fragment(node.fragment) {
+                Fragment::NonTerminal(_) if is_rule(node.fragment.index()) => {
+                    let rule = seen.entry(node.fragment.index()).or_default();
+                    rule.expansions += 1;
+                    if !rule.open {
+                        rule.open = true;
+                        open.push((ii + node.size as usize, node.fragment.index(), derived));
+                    }
+                }
+                Fragment::Terminal(value) => derived += value.len() as u64,
+                _ => {}
+            }
+        }
+        for (_, rule, from) in open {
+            seen.get_mut(&rule).expect("open rules are seen").bytes += derived - from;
+        }
+
+        self.inputs.fetch_add(1, Ordering::Relaxed);
+        self.interesting.fetch_add(interesting as u64, Ordering::Relaxed);
+        self.bytes.fetch_add(derived, Ordering::Relaxed);
+        for (idx, rule) in seen {
+            let Some((_, counters)) = &self.rules[idx] else {
+                continue;
+            };
+            counters.expansions.fetch_add(rule.expansions, Ordering::Relaxed);
+            counters.inputs.fetch_add(1, Ordering::Relaxed);
+            counters.interesting.fetch_add(interesting as u64, Ordering::Relaxed);
+            counters.bytes.fetch_add(rule.bytes, Ordering::Relaxed);
+        }
+    }
+
+    pub fn report(&self) -> RuleStatsReport {
+        let inputs = self.inputs.load(Ordering::Relaxed);
+        let interesting = self.interesting.load(Ordering::Relaxed);
+        let bytes = self.bytes.load(Ordering::Relaxed);
+        let share = |x: u64, of: u64| x as f64 * 100. / of.max(1) as f64;
+        let mut rules = self.rules.iter().flatten().map(|(name, counters)| {
+            let rule_inputs = counters.inputs.load(Ordering::Relaxed);
+            let rule_interesting = counters.interesting.load(Ordering::Relaxed);
+            let rule_bytes = counters.bytes.load(Ordering::Relaxed);
+            RuleReport {
+                rule: name.clone(),
+                expansions: counters.expansions.load(Ordering::Relaxed),
+                inputs: rule_inputs,
+                input_share: share(rule_inputs, inputs),
+                bytes: rule_bytes,
+                byte_share: share(rule_bytes, bytes),
+                interesting: rule_interesting,
+                lift: (rule_inputs > 0 && interesting > 0).then(||
+                    share(rule_interesting, rule_inputs) / share(interesting, inputs)),
+            }
+        }).collect::<Vec<_>>();
+        rules.sort_by(|a, b| b.bytes.cmp(&a.bytes).then(b.inputs.cmp(&a.inputs))
+            .then(a.rule.cmp(&b.rule)));
+        RuleStatsReport { inputs, interesting, bytes, rules }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct RuleReport {
+    pub rule: String,
+    pub expansions: u64,
+    // inputs the rule was expanded in, and their share of all, percent
+    pub inputs: u64,
+    pub input_share: f64,
+    // bytes its outermost expansions derived, and their share of all
+    pub bytes: u64,
+    pub byte_share: f64,
+    // interesting inputs the rule was expanded in
+    pub interesting: u64,
+    // interesting rate of inputs with the rule over that of all inputs,
+    // None without inputs with the rule or interesting ones
+    pub lift: Option<f64>,
+}
+
+// Rules by bytes derived, most first
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct RuleStatsReport {
+    pub inputs: u64,
+    pub interesting: u64,
+    pub bytes: u64,
+    pub rules: Vec<RuleReport>,
+}
@@ -0,0 +1,123 @@
+// Sanitizer report parsing
+//
+// Targets built with -fsanitize=address/undefined print a report to stderr
+// when they find a bug. We pull out what kind of bug it is, the function it
+// happened in and where the memory involved was allocated, so crashes can
+// be bucketed by bug instead of by coverage. The target needs symbolized
+// reports (symbolize=1, the default) for function names, and
+// halt_on_error=1 for UBSAN to stop at the first finding.
+
+// What a report says about one bug
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    // "AddressSanitizer", "UndefinedBehaviorSanitizer", ...
+    pub sanitizer: String,
+
+    // "heap-buffer-overflow", "signed integer overflow", ...
+    pub bug_type: String,
+
+    // first frame of the crash stack outside the sanitizer runtime
+    pub function: Option<String>,
+
+    // stack of the allocation the bad access touched (ASAN only)
+    pub allocation: Vec<String>,
+}
+
+impl Report {
+    // Identifies the bug for bucketing, bug type and faulting function
+    pub fn key(&self) -> String {
+        format!("{} in {}", self.bug_type,
+            self.function.as_deref().unwrap_or("?"))
+    }
+
+    // The report in one line, for metadata and findings
+    pub fn summary(&self) -> String {
+        let mut summary = format!("{}: {}", self.sanitizer, self.key());
+        if !self.allocation.is_empty() {
+            summary.push_str(&format!(", allocated in {}", self.allocation.join(" < ")));
+        }
+        summary
+    }
+}
+
+// Function of a stack frame line ("#3 0x4f5b3c in parse_value /src/a.c:12")
+fn frame_function(line: &str) -> Option<&str> {
+    let line = line.trim_start();
+    if !line.starts_with('#') {
+        return None;
+    }
+    let (_, rest) = line.split_once(" in ")?;
+    rest.split_whitespace().next()
+}
+
+// Frames of the sanitizer runtime itself (and the allocator it wraps),
+// never the interesting one
+fn is_runtime(function: &str) -> bool {
+    ["__asan", "__ubsan", "__sanitizer", "__interceptor_", "__lsan",
+        "__msan", "operator"].iter().any(|x| function.starts_with(x))
+        || ["malloc", "calloc", "realloc", "free"].contains(&function)
+}
+
+// Function names of the stack trace starting at lines[0], runtime frames
+// skipped
+fn stack<'a>(lines: &[&'a str]) -> Vec<&'a str> {
+    lines.iter().skip_while(|x| frame_function(x).is_none())
+        .map_while(|x| frame_function(x))
+        .filter(|x| !is_runtime(x))
+        .collect()
+}
+
+// Parse the first sanitizer report in the stderr of a target
+pub fn parse(stderr: &[u8]) -> Option<Report> {
+    let text = String::from_utf8_lossy(stderr);
+    let lines = text.lines().collect::<Vec<_>>();
+
+    for (ii, line) in lines.iter().enumerate() {
+        // ==1234==ERROR: AddressSanitizer: heap-buffer-overflow on address ...
+        if let Some((_, rest)) = line.split_once("ERROR: ") {
+            let Some((sanitizer, rest)) = rest.split_once(": ") else {
+                continue;
+            };
+            if !sanitizer.ends_with("Sanitizer") {
+                continue;
+            }
+            let bug_type = match rest.split_whitespace().next() {
+                // "SEGV on unknown address", "detected memory leaks"
+                Some("detected") => rest.trim(),
+                Some(x) => x,
+                None => continue,
+            };
+
+            let allocation = lines.iter().position(|x|
+                    x.contains("allocated by thread"))
+                .map(|start| stack(&lines[start + 1..]).iter()
+                    .map(|x| x.to_string()).collect())
+                .unwrap_or_default();
+            return Some(Report {
+                sanitizer: sanitizer.to_string(),
+                bug_type: bug_type.to_string(),
+                function: stack(&lines[ii + 1..]).first().map(|x| x.to_string()),
+                allocation,
+            });
+        }
+
+        // /src/a.c:12:5: runtime error: signed integer overflow: 1 + ...
+        if let Some((_, rest)) = line.split_once("runtime error: ") {
+            // the message goes on with values and types that differ per
+            // input, numbers in the part we keep become N
+            let bug_type = rest.split([':', '\'']).next().unwrap_or(rest)
+                .trim().trim_end_matches(" of type").trim_end_matches(" for type")
+                .split_whitespace()
+                .map(|x| if x.starts_with(|c: char| c.is_ascii_digit()
+                    || c == '-') { "N" } else { x })
+                .collect::<Vec<_>>().join(" ");
+            return Some(Report {
+                sanitizer: "UndefinedBehaviorSanitizer".to_string(),
+                bug_type,
+                function: stack(&lines[ii + 1..]).first().map(|x| x.to_string()),
+                allocation: Vec::new(),
+            });
+        }
+    }
+    None
+}
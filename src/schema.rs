@@ -0,0 +1,136 @@
+// Versions of the grammar file format
+//
+// The json format grew piece by piece: rule objects with annotations next
+// to the plain lists of alternatives, symbols of the form <kind:...> that
+// stand for terminals of their own. A grammar file states the version it
+// is written in with a top level key next to the rules:
+//
+//   {"version": 2, "<start>": [["<expr>"]], ...}
+//
+//   1   rules are lists of alternatives, every symbol that is not a rule
+//       is a literal terminal
+//   2   rule objects with annotations (see loader::Rule), and <u16le:...>,
+//       <unicode:...>, <int:...> and the like are binary, unicode and
+//       numeric terminals (binary.rs, unicode.rs, numeric.rs)
+//
+// Files without a version are read as the current one, as they always
+// were. Parsing is lenient by default: an older version is migrated as
+// the file is loaded, a newer one read as far as this version understands
+// it and unknown annotations left out, with a warning for each. Strict
+// parsing (--strict-grammar) refuses all of that, files without a version
+// too. The upgrade subcommand migrates a file to the current version for
+// good, stating the version.
+//
+// Migrating 1 to 2 splits the literal terminals that version 2 reads as
+// special ones into "<" and the rest, which derive the same bytes. Only
+// the rules of the same file count as rules there.
+
+use std::collections::HashSet;
+
+use crate::binary;
+use crate::loader::Definitions;
+use crate::numeric;
+use crate::unicode;
+
+// The version this fuzzer writes
+pub const CURRENT: u32 = 2;
+
+// The top level key of the version
+pub const KEY: &str = "version";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Parsing {
+    Lenient,
+    Strict,
+}
+
+// Version 1 to 2: literal terminals that became special ones stay literal
+fn literal_terminals(definitions: &mut Definitions, notes: &mut Vec<String>) {
+    let rules = definitions.0.iter().map(|x| x.0.clone()).collect::<HashSet<_>>();
+    let special = |symbol: &String| !rules.contains(symbol) && (binary::is_symbol(symbol)
+        || unicode::is_symbol(symbol) || numeric::is_symbol(symbol));
+    let split = |alternative: &mut Vec<String>| {
+        *alternative = alternative.drain(..).flat_map(|x| match special(&x) {
+            true => vec!["<".to_string(), x[1..].to_string()],
+            false => vec![x],
+        }).collect();
+    };
+    for (name, rule) in &mut definitions.0 {
+        for symbol in rule.alternatives.iter().flatten().filter(|x| special(x)) {
+            notes.push(format!("{}: {} split to stay a literal terminal", name, symbol));
+        }
+        // the phase annotations name alternatives, which change along
+        let annotation = &mut rule.annotation;
+        rule.alternatives.iter_mut().chain(&mut annotation.mutation_only)
+            .chain(&mut annotation.generation_only).for_each(split);
+    }
+}
+
+// Migrate definitions of an older version to the current one, what
+// changed on the way. Definitions without a version are current already
+pub fn upgrade(definitions: &mut Definitions) -> Vec<String> {
+    let mut notes = Vec::new();
+    let from = definitions.1.unwrap_or(CURRENT);
+    if from < 2 {
+        literal_terminals(definitions, &mut notes);
+    }
+    definitions.1 = Some(from.max(CURRENT));
+    notes
+}
+
+// Bring freshly parsed definitions to the current version as the parsing
+// mode allows: an error for what it refuses, warnings for what it let
+// through. Unknown annotations are gone afterwards
+pub fn conform(definitions: &mut Definitions, parsing: Parsing)
+        -> Result<Vec<String>, String> {
+    let strict = parsing == Parsing::Strict;
+    let mut warnings = Vec::new();
+    match definitions.1 {
+        Some(0) => return Err("version 0, versions start at 1".to_string()),
+        None if strict => return Err(format!(
+            "no \"{}\", the current one is {}", KEY, CURRENT)),
+        None => definitions.1 = Some(CURRENT),
+        Some(x) if x < CURRENT && strict => return Err(format!(
+            "version {}, the upgrade subcommand migrates it to {}", x, CURRENT)),
+        Some(x) if x < CURRENT => {
+            warnings.extend(upgrade(definitions));
+            warnings.push(format!(
+                "version {} read as {}, the upgrade subcommand migrates the file", x,
+                CURRENT));
+        }
+        Some(x) if x > CURRENT && strict => return Err(format!(
+            "version {} is newer than this fuzzer reads ({})", x, CURRENT)),
+        Some(x) if x > CURRENT => warnings.push(format!(
+            "version {} is newer than this fuzzer reads ({}), reading what it can", x,
+            CURRENT)),
+        Some(_) => {}
+    }
+    for (name, rule) in &mut definitions.0 {
+        for key in rule.unknown.drain(..) {
+            if strict {
+                return Err(format!("{}: unknown annotation {:?}", name, key));
+            }
+            warnings.push(format!("{}: unknown annotation {:?} left out", name, key));
+        }
+    }
+    Ok(warnings)
+}
+
+// The grammar file of definitions: the version first, then one rule per
+// line in their order
+pub fn to_json(definitions: &Definitions) -> String {
+    let mut out = format!("{{\n  {:?}: {}", KEY, definitions.1.unwrap_or(CURRENT));
+    for (name, rule) in &definitions.0 {
+        let alternatives = serde_json::to_value(&rule.alternatives)
+            .expect("alternatives are strings");
+        let value = match serde_json::to_value(&rule.annotation) {
+            Ok(serde_json::Value::Object(mut object)) if !object.is_empty() => {
+                object.insert("alternatives".to_string(), alternatives);
+                serde_json::Value::Object(object)
+            }
+            _ => alternatives,
+        };
+        out += &format!(",\n  {}: {}", serde_json::Value::from(name.as_str()), value);
+    }
+    out + "\n}\n"
+}
@@ -0,0 +1,88 @@
+// Measuring how valid a grammar's output is
+//
+// A grammar written from a spec rarely matches the real format exactly.
+// The selftest command generates samples and hands every one to an
+// external validator (jq, a compiler, the reference parser), which gets
+// it on stdin or as the file named by @@. Anything but exit code 0 is a
+// rejection. The report has the share rejected and the first rejected
+// samples with what the validator printed, to see where the grammar goes
+// wrong. Campaigns can use a validator as a filter as well (--filter),
+// then only inputs it accepts reach the target.
+
+use std::io;
+
+use crate::executor::{ExecResult, Executor, ExitKind};
+use crate::grammar::GrammarRust;
+
+// Whether the validator took an input: it exited by itself, with code 0
+pub fn accepted(result: &ExecResult) -> bool {
+    result.exit == ExitKind::Ok && result.code == Some(0)
+}
+
+// A sample the validator did not accept
+#[derive(Clone, Debug)]
+pub struct Rejection {
+    // index of the sample in the run
+    pub sample: usize,
+    pub input: Vec<u8>,
+    pub exit: ExitKind,
+    pub code: Option<i32>,
+    pub signal: Option<i32>,
+    pub stderr: Vec<u8>,
+}
+
+impl Rejection {
+    // How the validator said no
+    pub fn reason(&self) -> String {
+        match (self.exit, self.signal, self.code) {
+            (ExitKind::Timeout, _, _) => "timed out".to_string(),
+            (ExitKind::Limit(resource), _, _) =>
+                format!("hit the {} limit", resource.name()),
+            (_, Some(signal), _) => format!("killed by signal {}", signal),
+            (_, None, Some(code)) => format!("exit code {}", code),
+            (_, None, None) => "failed".to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SelftestReport {
+    pub samples: usize,
+    pub rejected: usize,
+    // the first rejections, up to the number asked for
+    pub examples: Vec<Rejection>,
+}
+
+impl SelftestReport {
+    // Share of samples rejected, percent
+    pub fn rate(&self) -> f64 {
+        self.rejected as f64 * 100. / self.samples.max(1) as f64
+    }
+}
+
+// Run samples generated from seed through validator, keeping up to
+// examples rejected ones
+pub fn selftest(gram: &GrammarRust, seed: usize, samples: usize, examples: usize,
+        validator: &mut dyn Executor) -> io::Result<SelftestReport> {
+    let mut report = SelftestReport { samples, ..Default::default() };
+    let mut cases = gram.iter_testcases(seed);
+    for sample in 0..samples {
+        let input = cases.next_ref();
+        let result = validator.run(input)?;
+        if accepted(&result) {
+            continue;
+        }
+        report.rejected += 1;
+        if report.examples.len() < examples {
+            report.examples.push(Rejection {
+                sample,
+                input: input.to_vec(),
+                exit: result.exit,
+                code: result.code,
+                signal: result.signal,
+                stderr: result.stderr,
+            });
+        }
+    }
+    Ok(report)
+}
@@ -0,0 +1,47 @@
+// Ctrl-C and friends
+//
+// SIGINT and SIGTERM only set a flag the campaign loop polls, so workers
+// finish the input at hand, executors clean up their targets and the
+// final stats get written. The handlers reset themselves, a second signal
+// kills the process the usual way.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn on_signal(_: libc::c_int) {
+    // only async signal safe things in here
+    SHUTDOWN.store(true, Ordering::Relaxed);
+}
+
+// Catch SIGINT and SIGTERM
+#[cfg(unix)]
+pub fn install() -> std::io::Result<()> {
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        // SAFETY: sigaction is plain data, zeroed is a valid empty mask
+        let ret = unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = on_signal as extern "C" fn(libc::c_int)
+                as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESETHAND;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(signal, &action, std::ptr::null_mut())
+        };
+        if ret != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+// Ctrl-C keeps killing the process right away where we have no handler
+#[cfg(not(unix))]
+pub fn install() -> std::io::Result<()> {
+    Ok(())
+}
+
+// Did somebody ask us to stop?
+pub fn shutdown_requested() -> bool {
+    SHUTDOWN.load(Ordering::Relaxed)
+}
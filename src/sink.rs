@@ -0,0 +1,178 @@
+// Where generated test cases go when there is no target to fuzz
+//
+// Generation only mode hands every test case to an OutputSink. The ones
+// built in, picked with the --emit-* flags:
+//
+//   stdout   frames of a u32 little endian length and the payload
+//   dir      one file per test case, numbered from 000000
+//   socket   the same frames as stdout over a TCP connection
+//   shm      a SysV shared memory segment (unix), see ShmSink
+//
+// Library users implement OutputSink for anything else, or wrap a closure
+// in a CallbackSink, and feed it with TestCases::deliver().
+
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::net::TcpStream;
+use std::path::PathBuf;
+#[cfg(unix)]
+use std::sync::atomic::{AtomicU32, Ordering};
+
+#[cfg(unix)]
+use crate::coverage::ShmCoverageMap;
+#[cfg(unix)]
+use crate::info;
+
+pub trait OutputSink {
+    // Take one test case. An error ends generation, a closed pipe or
+    // connection (BrokenPipe) as a normal end
+    fn deliver(&mut self, data: &[u8]) -> io::Result<()>;
+
+    // Called once generation ends
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Length prefixed frames on a stream, so harnesses in any language can
+// read them
+pub struct FramedSink<W: Write> {
+    out: W,
+}
+
+impl<W: Write> FramedSink<W> {
+    pub fn new(out: W) -> Self {
+        FramedSink { out }
+    }
+}
+
+impl<W: Write> OutputSink for FramedSink<W> {
+    fn deliver(&mut self, data: &[u8]) -> io::Result<()> {
+        self.out.write_all(&(data.len() as u32).to_le_bytes())?;
+        self.out.write_all(data)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+// One file per test case
+pub struct DirSink {
+    dir: PathBuf,
+    next: u64,
+}
+
+impl DirSink {
+    pub fn create(dir: PathBuf) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        Ok(DirSink { dir, next: 0 })
+    }
+}
+
+impl OutputSink for DirSink {
+    fn deliver(&mut self, data: &[u8]) -> io::Result<()> {
+        fs::write(self.dir.join(format!("{:06}", self.next)), data)?;
+        self.next += 1;
+        Ok(())
+    }
+}
+
+// Any function taking the test cases, for embedding
+pub struct CallbackSink<F: FnMut(&[u8]) -> io::Result<()>> {
+    callback: F,
+}
+
+impl<F: FnMut(&[u8]) -> io::Result<()>> CallbackSink<F> {
+    pub fn new(callback: F) -> Self {
+        CallbackSink { callback }
+    }
+}
+
+impl<F: FnMut(&[u8]) -> io::Result<()>> OutputSink for CallbackSink<F> {
+    fn deliver(&mut self, data: &[u8]) -> io::Result<()> {
+        (self.callback)(data)
+    }
+}
+
+// Header of the shared memory segment: sequence number of the last test
+// case written, sequence number the consumer is done with, length
+#[cfg(unix)]
+const SHM_HEADER: usize = 12;
+
+// Test cases through a SysV shared memory segment, one at a time. The
+// segment starts with three u32: the sequence number of the test case in
+// it (written last), the sequence number the consumer is done with (the
+// consumer writes it) and the length, then the bytes. A test case waits
+// for the consumer to be done with the one before. Test cases longer than
+// the segment are cut like --shm-input does
+#[cfg(unix)]
+pub struct ShmSink {
+    shm: ShmCoverageMap,
+    sequence: u32,
+}
+
+#[cfg(unix)]
+impl ShmSink {
+    pub fn new(max_len: usize) -> io::Result<Self> {
+        Ok(ShmSink { shm: ShmCoverageMap::new(SHM_HEADER + max_len)?, sequence: 0 })
+    }
+
+    // Identifier consumers attach to
+    pub fn id(&self) -> i32 {
+        self.shm.id()
+    }
+
+    fn word(&self, index: usize) -> &AtomicU32 {
+        // SAFETY: the segment is at least SHM_HEADER bytes and page
+        // aligned, the header words are only accessed atomically
+        unsafe { &*(self.shm.as_slice().as_ptr().add(index * 4) as *const AtomicU32) }
+    }
+}
+
+#[cfg(unix)]
+impl OutputSink for ShmSink {
+    fn deliver(&mut self, data: &[u8]) -> io::Result<()> {
+        while self.word(1).load(Ordering::Acquire) != self.sequence {
+            std::thread::yield_now();
+        }
+        let region = self.shm.as_mut_slice();
+        let len = data.len().min(region.len() - SHM_HEADER);
+        region[8..SHM_HEADER].copy_from_slice(&(len as u32).to_le_bytes());
+        region[SHM_HEADER..SHM_HEADER + len].copy_from_slice(&data[..len]);
+        self.sequence = self.sequence.wrapping_add(1);
+        self.word(0).store(self.sequence, Ordering::Release);
+        Ok(())
+    }
+}
+
+// A sink picked on the command line
+#[derive(Clone, Debug, PartialEq)]
+pub enum SinkSpec {
+    Stdout,
+    Dir(PathBuf),
+    Socket(String),
+    // largest test case
+    Shm(usize),
+}
+
+impl SinkSpec {
+    pub fn open(&self) -> io::Result<Box<dyn OutputSink>> {
+        Ok(match self {
+            SinkSpec::Stdout => Box::new(FramedSink::new(
+                BufWriter::with_capacity(1 << 16, io::stdout().lock()))),
+            SinkSpec::Dir(dir) => Box::new(DirSink::create(dir.clone())?),
+            SinkSpec::Socket(addr) => Box::new(FramedSink::new(
+                BufWriter::with_capacity(1 << 16, TcpStream::connect(addr.as_str())?))),
+            #[cfg(unix)]
+            SinkSpec::Shm(max_len) => {
+                let sink = ShmSink::new(*max_len)?;
+                info!("sink", "test cases in shared memory segment {}", sink.id());
+                Box::new(sink)
+            }
+            #[cfg(not(unix))]
+            SinkSpec::Shm(_) => return Err(io::Error::new(io::ErrorKind::Unsupported,
+                "shared memory sinks need a unix system")),
+        })
+    }
+}
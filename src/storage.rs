@@ -0,0 +1,130 @@
+// Remote corpus storage
+//
+// Cloud campaigns run on machines that come and go. With a storage URL the
+// campaign pulls everything under it into the sync dir on startup, so an
+// instance picks up where its last machine left off (ids continue, see
+// AflOutputDir::new()) and imports the entries of every other instance
+// that ever pushed there. While fuzzing, a background thread pushes our
+// instance directory every so often, and once more at the end:
+//
+//   s3://<bucket>/<prefix>/<instance>/queue/...
+//                                    /crashes/...
+//
+// Transfers only copy what is missing or changed on the other side and
+// never delete, pulling is a merge. The lock and temp files of a
+// workspace (see workspace.rs) stay where they are, a pulled lock would
+// block the instance it came from. Transfers run the vendor tools, aws
+// (S3 and compatible stores, with --endpoint-url) and gsutil (GCS), from
+// $AWS and $GSUTIL or $PATH, which also take care of credentials.
+
+use std::ffi::OsString;
+use std::io;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+pub trait Storage: Send + Sync {
+    // Where the corpus is stored, for messages
+    fn url(&self) -> &str;
+
+    // Merge everything stored into sync_dir
+    fn pull(&self, sync_dir: &Path) -> io::Result<()>;
+
+    // Store the directory of instance
+    fn push(&self, dir: &Path, instance: &str) -> io::Result<()>;
+}
+
+// Storage for an s3:// or gs:// URL, endpoint is for S3 compatible stores
+pub fn open(url: &str, endpoint: Option<&str>) -> io::Result<Box<dyn Storage>> {
+    let url = url.trim_end_matches('/').to_string();
+    if url.strip_prefix("s3://").is_some_and(|x| !x.is_empty()) {
+        Ok(Box::new(S3 { url, endpoint: endpoint.map(|x| x.to_string()) }))
+    } else if endpoint.is_some() {
+        Err(io::Error::new(io::ErrorKind::InvalidInput,
+            "a storage endpoint only goes with s3:// URLs"))
+    } else if url.strip_prefix("gs://").is_some_and(|x| !x.is_empty()) {
+        Ok(Box::new(Gcs { url }))
+    } else {
+        Err(io::Error::new(io::ErrorKind::InvalidInput,
+            format!("unsupported storage URL {:?}, expected s3:// or gs://", url)))
+    }
+}
+
+// Workspace files, relative to a sync dir or an instance directory, as
+// aws --exclude patterns and one gsutil -x regex
+const EXCLUDED: [&str; 4] = [".lock", "*/.lock", ".tmp/*", "*/.tmp/*"];
+const EXCLUDED_REGEX: &str = r"(^|.*/)(\.lock$|\.tmp/)";
+
+fn tool(var: &str, name: &str) -> OsString {
+    std::env::var_os(var).unwrap_or_else(|| name.into())
+}
+
+// Run a transfer, its output goes to our stderr
+fn run(cmd: &mut Command) -> io::Result<()> {
+    let program = cmd.get_program().to_string_lossy().into_owned();
+    let status = cmd.stdin(Stdio::null()).stdout(Stdio::null()).status()
+        .map_err(|e| io::Error::new(e.kind(),
+            format!("running {} failed: {}", program, e)))?;
+    if !status.success() {
+        return Err(io::Error::other(format!("{} failed ({})", program, status)));
+    }
+    Ok(())
+}
+
+pub struct S3 {
+    url: String,
+    endpoint: Option<String>,
+}
+
+impl S3 {
+    fn sync(&self, from: impl Into<OsString>, to: impl Into<OsString>) -> io::Result<()> {
+        let mut cmd = Command::new(tool("AWS", "aws"));
+        cmd.args(["s3", "sync", "--only-show-errors"]);
+        for pattern in EXCLUDED {
+            cmd.args(["--exclude", pattern]);
+        }
+        if let Some(endpoint) = &self.endpoint {
+            cmd.arg("--endpoint-url").arg(endpoint);
+        }
+        run(cmd.arg(from.into()).arg(to.into()))
+    }
+}
+
+impl Storage for S3 {
+    fn url(&self) -> &str {
+        &self.url
+    }
+
+    fn pull(&self, sync_dir: &Path) -> io::Result<()> {
+        self.sync(&self.url, sync_dir)
+    }
+
+    fn push(&self, dir: &Path, instance: &str) -> io::Result<()> {
+        self.sync(dir, format!("{}/{}", self.url, instance))
+    }
+}
+
+pub struct Gcs {
+    url: String,
+}
+
+impl Gcs {
+    fn rsync(&self, from: impl Into<OsString>, to: impl Into<OsString>) -> io::Result<()> {
+        let mut cmd = Command::new(tool("GSUTIL", "gsutil"));
+        cmd.args(["-m", "-q", "rsync", "-r", "-x", EXCLUDED_REGEX]);
+        run(cmd.arg(from.into()).arg(to.into()))
+    }
+}
+
+impl Storage for Gcs {
+    fn url(&self) -> &str {
+        &self.url
+    }
+
+    fn pull(&self, sync_dir: &Path) -> io::Result<()> {
+        self.rsync(&self.url, sync_dir)
+    }
+
+    fn push(&self, dir: &Path, instance: &str) -> io::Result<()> {
+        self.rsync(dir, format!("{}/{}", self.url, instance))
+    }
+}
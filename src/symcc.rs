@@ -0,0 +1,98 @@
+// Hybrid fuzzing with a SymCC build of the target
+//
+// The concolic build runs an input like the target would, follows the
+// path it takes symbolically and asks the solver for inputs that take the
+// branches it did not. Those end up as files in SYMCC_OUTPUT_DIR, and are
+// handed back to the fuzz loop to run against the real target. Solved
+// inputs are byte level edits without a derivation, they join the queue
+// as raw seeds when they find something.
+//
+// SymCC reads the symbolic input from stdin, or from the file named by
+// SYMCC_INPUT_FILE when the command line takes it from a file (@@). Values
+// of fuzzed argv/environment positions stay concrete, solved inputs keep
+// those of the input they were derived from.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[cfg(unix)]
+use crate::executor::{input_file_path, Executor, ProcessExecutor};
+
+// Solved inputs taken from one run at most
+pub const MAX_SOLVED: usize = 256;
+
+// How much longer than the target a concolic run should be given
+pub const TIMEOUT_FACTOR: u32 = 10;
+
+#[cfg(unix)]
+pub struct SymCc {
+    inner: ProcessExecutor,
+    dir: PathBuf,
+}
+
+#[cfg(unix)]
+impl SymCc {
+    // inner runs the SymCC build, set up like the fuzzed target but with
+    // TIMEOUT_FACTOR times the timeout
+    pub fn new(mut inner: ProcessExecutor) -> io::Result<Self> {
+        let dir = input_file_path().with_extension("symcc");
+        fs::create_dir_all(&dir)?;
+        inner.env("SYMCC_OUTPUT_DIR", &dir.to_string_lossy());
+        if let Some(path) = inner.input_file() {
+            let path = path.to_string_lossy().into_owned();
+            inner.env("SYMCC_INPUT_FILE", &path);
+        }
+        Ok(SymCc { inner, dir })
+    }
+
+    // Inputs the solver found for the branches input did not take
+    pub fn solve(&mut self, input: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+        self.inner.run(input)?;
+        let mut solved = take_solved(&self.dir)?;
+        let (_, rest) = self.inner.split(input);
+        let positions = &input[..input.len() - rest.len()];
+        if !positions.is_empty() {
+            for input in &mut solved {
+                input.splice(0..0, positions.iter().copied());
+            }
+        }
+        Ok(solved)
+    }
+}
+
+#[cfg(unix)]
+impl Drop for SymCc {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+// Concolic execution needs a SymCC build, those are unix only
+#[cfg(not(unix))]
+pub enum SymCc {}
+
+#[cfg(not(unix))]
+impl SymCc {
+    pub fn solve(&mut self, _input: &[u8]) -> io::Result<Vec<Vec<u8>>> {
+        match *self {}
+    }
+}
+
+// Read and remove what SymCC wrote into dir, in file name order (the
+// order it solved them in)
+fn take_solved(dir: &Path) -> io::Result<Vec<Vec<u8>>> {
+    let mut paths = fs::read_dir(dir)?
+        .map(|entry| entry.map(|x| x.path()))
+        .collect::<io::Result<Vec<PathBuf>>>()?;
+    paths.sort();
+
+    let mut solved = Vec::new();
+    for path in paths {
+        if solved.len() < MAX_SOLVED {
+            solved.push(fs::read(&path)?);
+        }
+        fs::remove_file(&path)?;
+    }
+    Ok(solved)
+}
@@ -0,0 +1,42 @@
+// Generation temperature
+//
+// Temperature skews how alternatives are picked by how many nodes they
+// take at least to expand (GrammarRust::min_cost()). At 1 nothing changes,
+// above 1 the expensive alternatives (recursion, long sequences) win more
+// often and derivations get deep and wild, below 1 the cheap ones do and
+// derivations stay short and conservative. An alternative of cost c has
+// weight c^(temperature - 1).
+//
+// A campaign can anneal: the temperature moves from a start to an end
+// value over a period of time, geometrically, and stays at the end value
+// afterwards. Early exploration of odd structure and later focus on
+// small inputs (or the other way round) need no restart.
+
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Schedule {
+    pub start: f64,
+    pub end: f64,
+    pub period: Duration,
+}
+
+impl Schedule {
+    // <temperature> or <start>:<end>, annealed over period
+    pub fn parse(spec: &str, period: Duration) -> Option<Self> {
+        let (start, end) = spec.split_once(':').unwrap_or((spec, spec));
+        let valid = |x: f64| x.is_finite() && x > 0.0;
+        let (start, end) = (start.parse().ok().filter(|&x| valid(x))?,
+            end.parse().ok().filter(|&x| valid(x))?);
+        Some(Schedule { start, end, period })
+    }
+
+    // Temperature after elapsed
+    pub fn at(&self, elapsed: Duration) -> f64 {
+        if self.start == self.end || elapsed >= self.period {
+            return self.end;
+        }
+        let progress = elapsed.as_secs_f64() / self.period.as_secs_f64();
+        self.start * (self.end / self.start).powf(progress)
+    }
+}
@@ -0,0 +1,112 @@
+// Template inputs
+//
+// Containers, archives and other formats where most of a file has to be
+// byte exact are hard to write a grammar for, and most of the grammar
+// would only reproduce one real file anyway. A template is such a real
+// file with holes marked {{<rule>}}: every hole is derived from the rule
+// of the grammar, everything else stays as it is.
+//
+// apply() turns the templates into the alternatives of <start>, the fixed
+// bytes as terminals and the holes as references, so trees, mutation, the
+// corpus and choice sequences work on templates like on any derivation.
+// Structural mutations only touch the holes, havoc and corruption can
+// still flip fixed bytes. The start rule of the grammar itself becomes
+// <start:grammar>, a hole {{<start>}} derives it. Holes can name binary
+// symbols ({{<u32le:*>}}, see binary.rs) as well as rules.
+
+use crate::binary;
+use crate::grammar::Grammar;
+use crate::positions;
+
+// Name of the start rule of the grammar under the templates
+const GRAMMAR_START: &str = "<start:grammar>";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Piece {
+    Bytes(Vec<u8>),
+    // rule name, brackets included
+    Hole(String),
+}
+
+// The pieces of a template. {{ not followed by a <name>}} is just bytes
+pub fn parse(data: &[u8]) -> Vec<Piece> {
+    let mut pieces = Vec::new();
+    let mut bytes = Vec::new();
+    let mut ii = 0;
+    while ii < data.len() {
+        let hole = data[ii..].strip_prefix(b"{{<").and_then(|rest| {
+            let end = rest.windows(3).position(|x| x == b">}}")?;
+            let name = std::str::from_utf8(&rest[..end]).ok()
+                .filter(|x| !x.is_empty() && !x.contains(['<', '>', '{', '}']))?;
+            Some(format!("<{}>", name))
+        });
+        match hole {
+            Some(name) => {
+                if !bytes.is_empty() {
+                    pieces.push(Piece::Bytes(std::mem::take(&mut bytes)));
+                }
+                ii += name.len() + 4;
+                pieces.push(Piece::Hole(name));
+            }
+            None => {
+                bytes.push(data[ii]);
+                ii += 1;
+            }
+        }
+    }
+    if !bytes.is_empty() {
+        pieces.push(Piece::Bytes(bytes));
+    }
+    pieces
+}
+
+// Grammar symbols for fixed bytes. Text goes as is, bytes that are not
+// UTF-8 and a leading < that could make it read as a symbol as <u8:n>
+fn literal(mut bytes: &[u8], symbols: &mut Vec<String>) {
+    while !bytes.is_empty() {
+        let text = match std::str::from_utf8(bytes) {
+            Ok(text) => text,
+            Err(e) => std::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap(),
+        };
+        if text.starts_with('<') || text.is_empty() {
+            symbols.push(format!("<u8:{}>", bytes[0]));
+            bytes = &bytes[1..];
+            continue;
+        }
+        // a later < could start a symbol only at the front of a terminal
+        let end = text.find('<').unwrap_or(text.len());
+        symbols.push(text[..end].to_string());
+        bytes = &bytes[end..];
+    }
+}
+
+// Make the templates (named for errors) the start of the grammar, see the
+// top of the file. Errors name holes without a rule
+pub fn apply(grammar: &mut Grammar, templates: &[(String, Vec<u8>)]) -> Result<(), String> {
+    positions::rename(grammar, |name| match name {
+        "<start>" => GRAMMAR_START.to_string(),
+        _ => name.to_string(),
+    });
+    let mut start = Vec::new();
+    for (name, data) in templates {
+        let mut symbols = Vec::new();
+        for piece in parse(data) {
+            match piece {
+                Piece::Bytes(bytes) => literal(&bytes, &mut symbols),
+                Piece::Hole(hole) if hole == "<start>" => symbols.push(GRAMMAR_START.into()),
+                Piece::Hole(hole) => {
+                    if !grammar.0.contains_key(&hole) && !binary::is_symbol(&hole) {
+                        return Err(format!("{}: hole {} has no rule", name, hole));
+                    }
+                    symbols.push(hole);
+                }
+            }
+        }
+        if !start.contains(&symbols) {
+            start.push(symbols);
+        }
+    }
+    grammar.0.insert("<start>".to_string(), start);
+    // rules for the binary symbols of holes
+    binary::expand(grammar)
+}
@@ -0,0 +1,87 @@
+// Pull based access to generated test cases
+//
+// Wraps the generation context that generate() needs so consumers can just
+// iterate over inputs with the normal iterator combinators
+
+use std::io;
+#[cfg(feature = "stream")]
+use std::pin::Pin;
+#[cfg(feature = "stream")]
+use std::task::{Context, Poll};
+
+use crate::context::GenerationContext;
+use crate::derivation_cache::{CachePolicy, DerivationCache};
+use crate::grammar::GrammarRust;
+use crate::sink::OutputSink;
+
+pub struct TestCases<'a> {
+    grammar: &'a GrammarRust,
+
+    // reused between test cases so steady state generation does not allocate
+    ctx: GenerationContext,
+}
+
+impl<'a> TestCases<'a> {
+    pub fn new(grammar: &'a GrammarRust, seed: usize) -> Self {
+        TestCases {
+            grammar,
+            ctx: GenerationContext::new(seed),
+        }
+    }
+
+    // Copy cached expansions of small rules, see derivation_cache.rs
+    pub fn cache(mut self, policy: CachePolicy) -> Self {
+        self.ctx.cache = Some(DerivationCache::new(self.grammar, policy));
+        self
+    }
+
+    // Generate the next test case into the internal buffer and borrow it
+    // Cheaper than next() as nothing gets copied, the slice is only valid
+    // until the following call
+    pub fn next_ref(&mut self) -> &[u8] {
+        self.ctx.generate_bytes(self.grammar)
+    }
+
+    // Hand test cases to a sink (see sink.rs) until limit of them went
+    // out, or forever. Runs until delivering fails, a closed pipe is a
+    // normal end. Returns the number delivered
+    pub fn deliver(&mut self, sink: &mut dyn OutputSink, limit: Option<u64>)
+            -> io::Result<u64> {
+        let mut delivered = 0;
+        while limit.is_none_or(|x| delivered < x) {
+            match sink.deliver(self.next_ref()) {
+                Ok(()) => delivered += 1,
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return Ok(delivered),
+                Err(e) => return Err(e),
+            }
+        }
+        match sink.flush() {
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(delivered),
+            ret => ret.map(|_| delivered),
+        }
+    }
+
+    // Same shape as futures::Stream::poll_next, generation never blocks so
+    // this is always ready. Wrap with `futures::stream::poll_fn` to get a
+    // real Stream without this crate depending on an async runtime
+    #[cfg(feature = "stream")]
+    pub fn poll_next(self: Pin<&mut Self>, _cx: &mut Context<'_>)
+            -> Poll<Option<Vec<u8>>> {
+        Poll::Ready(self.get_mut().next())
+    }
+}
+
+impl Iterator for TestCases<'_> {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Vec<u8>> {
+        Some(self.next_ref().to_vec())
+    }
+}
+
+impl GrammarRust {
+    // Seed the generator and get an endless iterator of test cases
+    pub fn iter_testcases(&self, seed: usize) -> TestCases<'_> {
+        TestCases::new(self, seed)
+    }
+}
@@ -0,0 +1,118 @@
+// Rate limiting and duty cycling of executions
+//
+// For running in the background on shared machines or against services
+// that rate limit. Executions of all workers are spaced out on one virtual
+// schedule: every execution books its cost (1 for an exec cap, the input
+// length for a byte cap) worth of time and waits until its slot comes up.
+// Idle time does not build up credit, so there are no bursts. The duty cycle
+// on top lets the campaign run for a share of every period and pauses for
+// the rest.
+
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rate {
+    ExecsPerSec(f64),
+    BytesPerSec(f64),
+}
+
+impl Rate {
+    // "100" execs per second, "64k" with a B suffix bytes per second
+    // ("64kB", "1MB")
+    pub fn parse(value: &str) -> Option<Rate> {
+        let (value, bytes) = match value.strip_suffix('B') {
+            Some(x) => (x, true),
+            None => (value, false),
+        };
+        let (value, scale) = match value.char_indices().last()? {
+            (ii, 'k') => (&value[..ii], 1e3),
+            (ii, 'M') => (&value[..ii], 1e6),
+            (ii, 'G') => (&value[..ii], 1e9),
+            _ => (value, 1.0),
+        };
+        let rate = value.parse::<f64>().ok().filter(|&x| x > 0.0)? * scale;
+        Some(if bytes { Rate::BytesPerSec(rate) } else { Rate::ExecsPerSec(rate) })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DutyCycle {
+    // share of every period spent fuzzing, (0, 1]
+    pub on: f64,
+    pub period: Duration,
+}
+
+#[derive(Default)]
+pub struct Throttle {
+    rate: Option<Rate>,
+    duty: Option<DutyCycle>,
+    start: Option<Instant>,
+
+    // when the next execution may start
+    next: Mutex<Option<Instant>>,
+
+    // time workers spent waiting, summed over all of them
+    waited_ns: AtomicU64,
+}
+
+impl Throttle {
+    pub fn new(rate: Option<Rate>, duty: Option<DutyCycle>) -> Self {
+        Throttle {
+            rate,
+            duty,
+            start: Some(Instant::now()),
+            ..Default::default()
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.rate.is_some() || self.duty.is_some()
+    }
+
+    // Total time workers were held back
+    pub fn waited(&self) -> Duration {
+        Duration::from_nanos(self.waited_ns.load(Ordering::Relaxed))
+    }
+
+    // Block until an input of len bytes may be run, or stop is set
+    pub fn wait(&self, len: usize, stop: &AtomicBool) {
+        if !self.is_active() {
+            return;
+        }
+        let mut until = Instant::now();
+
+        // off phase of the duty cycle: wait for the next period
+        if let (Some(duty), Some(start)) = (self.duty, self.start) {
+            let period = duty.period.as_secs_f64();
+            let phase = (until - start).as_secs_f64() % period;
+            if phase >= duty.on * period {
+                until += Duration::from_secs_f64(period - phase);
+            }
+        }
+
+        // book a slot on the schedule
+        if let Some(rate) = self.rate {
+            let cost = match rate {
+                Rate::ExecsPerSec(x) => 1.0 / x,
+                Rate::BytesPerSec(x) => len.max(1) as f64 / x,
+            };
+            let mut next = self.next.lock().unwrap();
+            let slot = next.map_or(until, |x| x.max(until));
+            *next = Some(slot + Duration::from_secs_f64(cost));
+            until = slot;
+        }
+
+        // in steps, to react to ctrl-c
+        let begin = Instant::now();
+        while let Some(left) = until.checked_duration_since(Instant::now()) {
+            if left.is_zero() || stop.load(Ordering::Relaxed) {
+                break;
+            }
+            std::thread::sleep(left.min(Duration::from_millis(100)));
+        }
+        self.waited_ns.fetch_add(begin.elapsed().as_nanos() as u64,
+            Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,80 @@
+// Token streams instead of text
+//
+// Compilers and query planners often have an entry point behind the lexer
+// that takes a sequence of token identifiers. Feeding it text only tests
+// the lexer again. Rules annotated with a token class stand for one token:
+//
+//   "<ident>": {"alternatives": [["<letter>", "<ident>"], ["<letter>"]], "token": 3}
+//
+// With --token-stream the grammar is rewritten before it is compiled: a
+// token rule has the encoded identifier as its only alternative, every
+// other rule loses its terminals (whitespace, punctuation that is not a
+// token class of its own). Generation, mutation and trimming then work on
+// token streams like on any other output.
+
+use crate::grammar::Grammar;
+
+// How an identifier is written
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    // fixed width integers, written as binary.rs constants
+    U8,
+    U16le,
+    U16be,
+    U32le,
+    U32be,
+    // decimal, one per line
+    Text,
+}
+
+impl Encoding {
+    pub fn parse(name: &str) -> Option<Encoding> {
+        match name {
+            "u8" => Some(Encoding::U8),
+            "u16le" => Some(Encoding::U16le),
+            "u16be" => Some(Encoding::U16be),
+            "u32le" => Some(Encoding::U32le),
+            "u32be" => Some(Encoding::U32be),
+            "text" => Some(Encoding::Text),
+            _ => None,
+        }
+    }
+
+    // The terminal of a token, None if the identifier does not fit
+    fn terminal(self, id: u32) -> Option<String> {
+        let (kind, max) = match self {
+            Encoding::U8 => ("u8", u8::MAX as u32),
+            Encoding::U16le => ("u16le", u16::MAX as u32),
+            Encoding::U16be => ("u16be", u16::MAX as u32),
+            Encoding::U32le => ("u32le", u32::MAX),
+            Encoding::U32be => ("u32be", u32::MAX),
+            Encoding::Text => return Some(format!("{}\n", id)),
+        };
+        (id <= max).then(|| format!("<{}:{}>", kind, id))
+    }
+}
+
+// The grammar of the token streams of a grammar, see the top of the file
+pub fn token_stream(grammar: &Grammar, encoding: Encoding) -> Result<Grammar, String> {
+    let mut tokens = grammar.1.iter()
+        .filter_map(|(name, x)| x.token.map(|id| (name, id)))
+        .collect::<Vec<_>>();
+    if tokens.is_empty() {
+        return Err("no rule has a token annotation".to_string());
+    }
+    tokens.sort();
+
+    let mut ret = grammar.clone();
+    for alternatives in ret.0.values_mut() {
+        for alternative in alternatives {
+            // a rule of the same name wins over a binary constant
+            alternative.retain(|x| grammar.0.contains_key(x));
+        }
+    }
+    for (name, id) in tokens {
+        let terminal = encoding.terminal(id).ok_or_else(|| format!(
+            "token {} of {} does not fit the encoding", id, name))?;
+        ret.0.insert(name.clone(), vec![vec![terminal]]);
+    }
+    Ok(ret)
+}
@@ -0,0 +1,173 @@
+// Probability traps
+//
+// With every alternative equally likely, a rule at the end of a long chain
+// of choices is practically never reached, and a rule whose alternatives
+// recurse more than once on average is expected to expand forever (only
+// the node budget ends its derivations). Both look fine in the grammar
+// file. check() reports them, with the weights that would fix them: the
+// grammar has no weights, repeating an alternative within its rule gives
+// it the weight of the repetitions.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use crate::grammar::{Fragment, FragmentId, GrammarRust};
+
+// Rules less likely than this to be expanded in a test case are reported
+pub const IMPROBABLE: f64 = 1e-6;
+
+// What the suggested fixes aim for
+const TARGET: f64 = 1e-3;
+
+// Expected sizes past this count as infinite
+const DIVERGENT: f64 = 1e12;
+
+// Rules an alternative refers to, in order
+fn references(gram: &GrammarRust, alternative: FragmentId) -> Vec<FragmentId> {
+    let Fragment::Expression(symbols) = gram.lookup_fragment(alternative) else {
+        return Vec::new();
+    };
+    symbols.iter().filter_map(|&x| match gram.lookup_fragment(x) {
+        Fragment::NonTerminal(target) => Some(target[0]),
+        _ => None,
+    }).collect()
+}
+
+// Chance of the likeliest way to reach every rule from <start>, with the
+// rule and alternative it is reached from
+fn reach(gram: &GrammarRust) -> HashMap<FragmentId, (f64, Option<(FragmentId, usize)>)> {
+    // Dijkstra over -ln(chance), non-negative floats order like their bits
+    let mut best = HashMap::from([(gram.start(), (1.0, None))]);
+    let mut done = HashSet::new();
+    let mut queue = BinaryHeap::from([(Reverse(0f64.to_bits()), gram.start().0)]);
+    while let Some((Reverse(distance), rule)) = queue.pop() {
+        let rule = FragmentId(rule);
+        if !done.insert(rule) {
+            continue;
+        }
+        let alternatives = gram.lookup_fragment_nonterm(rule);
+        let distance = f64::from_bits(distance) + (alternatives.len() as f64).ln();
+        for (index, &alternative) in alternatives.iter().enumerate() {
+            for target in references(gram, alternative) {
+                let chance = (-distance).exp();
+                if best.get(&target).is_none_or(|x: &(f64, _)| chance > x.0) {
+                    best.insert(target, (chance, Some((rule, index))));
+                    queue.push((Reverse(distance.to_bits()), target.0));
+                }
+            }
+        }
+    }
+    best
+}
+
+// Expected nodes of an expansion of every rule, None where that is
+// infinite
+fn expected_sizes(gram: &GrammarRust) -> HashMap<FragmentId, Option<f64>> {
+    let rules = gram.rules().map(|(_, id)| id).collect::<Vec<_>>();
+    let mut sizes: HashMap<FragmentId, f64> = HashMap::new();
+    let mut growing = HashSet::new();
+    for _ in 0..10_000 {
+        let next = rules.iter().map(|&rule| {
+            let alternatives = gram.lookup_fragment_nonterm(rule);
+            let total = alternatives.iter().map(|&alternative| {
+                let Fragment::Expression(symbols) = gram.lookup_fragment(alternative) else {
+                    return 0.0;
+                };
+                symbols.iter().map(|&x| match gram.lookup_fragment(x) {
+                    Fragment::NonTerminal(target) => sizes.get(&target[0]).copied()
+                        .unwrap_or(0.0),
+                    _ => 1.0,
+                }).sum::<f64>()
+            }).sum::<f64>();
+            (rule, (1.0 + total / alternatives.len() as f64).min(DIVERGENT))
+        }).collect::<HashMap<_, _>>();
+        growing = next.iter().filter(|(id, &x)|
+                (x - sizes.get(id).copied().unwrap_or(0.0)).abs() > 1e-9 * x.max(1.0))
+            .map(|(&id, _)| id).collect();
+        sizes = next;
+        if growing.is_empty() {
+            break;
+        }
+    }
+    // whatever still grows at the end has no finite size either
+    sizes.into_iter().map(|(id, x)|
+        (id, (x < DIVERGENT && !growing.contains(&id)).then_some(x))).collect()
+}
+
+// Rules reachable from a rule in one step or more
+fn reachable_from(gram: &GrammarRust, rule: FragmentId) -> HashSet<FragmentId> {
+    let mut seen = HashSet::new();
+    let mut stack = vec![rule];
+    while let Some(cur) = stack.pop() {
+        for &alternative in gram.lookup_fragment_nonterm(cur) {
+            for target in references(gram, alternative) {
+                if seen.insert(target) {
+                    stack.push(target);
+                }
+            }
+        }
+    }
+    seen
+}
+
+// Warnings about the traps in the rules reachable from <start>, see the
+// top of the file
+pub fn check(gram: &GrammarRust) -> Vec<String> {
+    let names = gram.rules().map(|(name, id)| (id, name)).collect::<HashMap<_, _>>();
+    let mut warnings = Vec::new();
+
+    // the first improbable rule on a path, the ones behind it only follow
+    let reach = reach(gram);
+    let mut rules = reach.iter().collect::<Vec<_>>();
+    rules.sort_by_key(|x| names[x.0]);
+    for (&rule, &(chance, from)) in &rules {
+        let parent = from.map(|x| reach[&x.0].0);
+        if chance >= IMPROBABLE || parent.is_some_and(|x| x < IMPROBABLE) {
+            continue;
+        }
+        let mut path = vec![names[&rule]];
+        let mut cur = from;
+        while let Some((parent, _)) = cur {
+            path.push(names[&parent]);
+            cur = reach[&parent].1;
+        }
+        path.reverse();
+        warnings.push(format!("rule {} is expanded in 1 of {:.1e} test cases at best, \
+            via {}; the alternatives on the way need about {:.0} times their weight in \
+            total to make it 1 in {}", names[&rule], 1.0 / chance, path.join(" -> "),
+            TARGET / chance, 1.0 / TARGET));
+    }
+
+    // rules expected to expand forever, where the recursion is
+    let sizes = expected_sizes(gram);
+    for &(&rule, _) in &rules {
+        if sizes[&rule].is_some() {
+            continue;
+        }
+        // the recursion a max_expansions annotation on the cycle cuts short
+        // is fine
+        let cycle = reachable_from(gram, rule).into_iter()
+            .filter(|&x| reachable_from(gram, x).contains(&rule)).collect::<Vec<_>>();
+        if cycle.is_empty() || cycle.iter().any(|&x| gram.max_expansions(x).is_some()) {
+            continue;
+        }
+        // references back into the divergent rules per alternative
+        let alternatives = gram.lookup_fragment_nonterm(rule);
+        let recursive = alternatives.iter().map(|&x| references(gram, x).iter()
+            .filter(|x| sizes[x].is_none()).count()).collect::<Vec<_>>();
+        let (growing, ending) = recursive.iter().fold((0, 0), |(g, e), &x|
+            if x > 0 { (g + 1, e) } else { (g, e + 1) });
+        let refs = recursive.iter().sum::<usize>();
+        let fix = match ending {
+            // refs / (growing + ending * weight) at most one half
+            0 => "a max_expansions annotation".to_string(),
+            _ => format!("a weight of {} on every alternative without recursion, or a \
+                max_expansions annotation",
+                (2 * refs).saturating_sub(growing).div_ceil(ending).max(2)),
+        };
+        warnings.push(format!("rule {} is expected to expand forever, its \
+            alternatives recurse {:.2} times on average; try {}", names[&rule],
+            refs as f64 / alternatives.len() as f64, fix));
+    }
+    warnings
+}
@@ -0,0 +1,474 @@
+// Derivation trees
+//
+// Same walk as GrammarRust::generate(), but every visited fragment is
+// recorded so the test case can be mutated structurally later on. Nodes are
+// stored flat in preorder together with the size of their subtree, so a
+// subtree is always the contiguous range nodes[i..i + size] and replacing
+// it is a single splice.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use crate::grammar::{Fragment, FragmentId, GeneratorState, GrammarRust,
+    MAX_OUTPUT_SIZE};
+use crate::mmap::Buffer;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Node {
+    pub fragment: FragmentId,
+
+    // number of nodes in the subtree rooted here, including this one
+    pub size: u32,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Tree {
+    pub nodes: Vec<Node>,
+}
+
+// Scratch space of derive_tree(), kept by the caller so derivations in
+// steady state allocate nothing
+#[derive(Clone, Debug, Default)]
+pub struct TreeStack {
+    // fragments still to visit with the index of their parent node
+    pending: Vec<(FragmentId, u32)>,
+
+    // parent index of every node, to compute subtree sizes at the end,
+    // and the alternative every node is in
+    parents: Vec<u32>,
+    contexts: Vec<Option<FragmentId>>,
+}
+
+impl Tree {
+    // Append the terminals of the tree to buf. A shared rule (see
+    // Annotation::shared) expanded a second time repeats the bytes of its
+    // first expansion instead of its own subtree, so the binding holds
+    // whatever mutation did to either
+    pub fn serialize<B: Buffer + ?Sized>(&self, grammar: &GrammarRust, buf: &mut B) {
+        if grammar.has_shared() {
+            let mut bindings = Bindings::default();
+            let mut ii = 0;
+            while ii < self.nodes.len() {
+                ii = bindings.visit(grammar, &self.nodes, ii, buf);
+            }
+            return;
+        }
+        for node in &self.nodes {
+            if let Fragment::Terminal(value) = grammar.lookup_fragment(node.fragment) {
+                buf.extend_from_slice(value);
+            }
+        }
+    }
+
+    // Serialize like serialize() and record the byte range every node
+    // produced in spans, by node index
+    pub fn serialize_spans(&self, grammar: &GrammarRust, buf: &mut Vec<u8>,
+            spans: &mut Vec<(usize, usize)>) {
+        spans.clear();
+        serialize_spans(grammar, &self.nodes, buf, spans);
+    }
+
+    // Replace the subtree rooted at idx with the nodes of another tree
+    pub fn replace_subtree(&mut self, idx: usize, new: &[Node]) {
+        let old = self.nodes[idx].size as usize;
+        let delta = new.len() as i64 - old as i64;
+
+        // every node whose range covers idx is an ancestor
+        for node in self.nodes[..idx].iter_mut().enumerate()
+                .filter(|(ii, node)| ii + node.size as usize > idx)
+                .map(|(_, node)| node) {
+            node.size = (node.size as i64 + delta) as u32;
+        }
+        self.nodes.splice(idx..idx + old, new.iter().copied());
+    }
+
+    // Pretty print the derivation: every rule expansion with the index of
+    // the alternative taken and the bytes it produced, indented by depth
+    pub fn write_trace(&self, grammar: &GrammarRust, out: &mut impl Write)
+            -> io::Result<()> {
+        let names = grammar.rules().map(|(name, id)| (id, name))
+            .collect::<HashMap<_, _>>();
+
+        // end of the subtree of every rule we are currently inside
+        let mut open: Vec<usize> = Vec::new();
+        let mut bytes = Vec::new();
+        for (ii, node) in self.nodes.iter().enumerate() {
+            while open.last().is_some_and(|&end| end <= ii) {
+                open.pop();
+            }
+            let Some(name) = names.get(&node.fragment) else {
+                continue;
+            };
+
+            // a truncated derivation can end right at the rule
+            let alternative = self.nodes.get(ii + 1).and_then(|x|
+                grammar.lookup_fragment_nonterm(node.fragment).iter()
+                    .position(|&option| option == x.fragment));
+            let end = ii + node.size as usize;
+
+            bytes.clear();
+            for node in &self.nodes[ii..end] {
+                if let Fragment::Terminal(value) = grammar.lookup_fragment(node.fragment) {
+                    bytes.extend_from_slice(value);
+                }
+            }
+            let rule = format!("{:indent$}{} #{}", "", name,
+                alternative.map_or(String::from("?"), |x| x.to_string()),
+                indent = open.len() * 2);
+            writeln!(out, "{:<40} {:?}", rule, String::from_utf8_lossy(&bytes))?;
+            open.push(end);
+        }
+        Ok(())
+    }
+
+    // Deepest nesting of rule expansions, the most alternatives taken on
+    // the way from the root to any node
+    pub fn depth(&self, grammar: &GrammarRust) -> usize {
+        // end of the subtree and depth of every alternative we are inside
+        let mut open: Vec<usize> = Vec::new();
+        let mut depth = 0;
+        for (ii, node) in self.nodes.iter().enumerate() {
+            while open.last().is_some_and(|&end| end <= ii) {
+                open.pop();
+            }
+            if let Fragment::Expression(_) = grammar.lookup_fragment(node.fragment) {
+                open.push(ii + node.size as usize);
+                depth = depth.max(open.len());
+            }
+        }
+        depth
+    }
+
+    // Indices of all nodes that are non-terminals, the points a structural
+    // mutation can regenerate from
+    pub fn nonterminals<'a>(&'a self, grammar: &'a GrammarRust)
+            -> impl Iterator<Item = usize> + 'a {
+        self.nodes.iter().enumerate().filter(|(_, x)| matches!(
+            grammar.lookup_fragment(x.fragment), Fragment::NonTerminal(_)))
+            .map(|(ii, _)| ii)
+    }
+}
+
+// Tree::serialize_spans() of a subtree's nodes, appending to spans
+pub fn serialize_spans(grammar: &GrammarRust, nodes: &[Node], buf: &mut Vec<u8>,
+        spans: &mut Vec<(usize, usize)>) {
+    // bytes in front of every node, a subtree produces the bytes
+    // between its first node and the node after it. The nodes a repeated
+    // shared rule skips get empty spans behind the bytes it repeats
+    let first = spans.len();
+    let mut bindings = Bindings::default();
+    let mut ii = 0;
+    while ii < nodes.len() {
+        let start = buf.len();
+        let next = match grammar.has_shared() {
+            true => bindings.visit(grammar, nodes, ii, buf),
+            false => {
+                if let Fragment::Terminal(value) = grammar.lookup_fragment(nodes[ii].fragment) {
+                    buf.extend_from_slice(value);
+                }
+                ii + 1
+            }
+        };
+        spans.push((start, 0));
+        spans.extend((ii + 1..next).map(|_| (buf.len(), 0)));
+        ii = next;
+    }
+    let end = buf.len();
+    for ii in 0..nodes.len() {
+        let next = first + ii + nodes[ii].size as usize;
+        spans[first + ii].1 = spans.get(next).map_or(end, |x| x.0);
+    }
+}
+
+// Bytes the shared rules of a tree produced so far, for serializing
+#[derive(Default)]
+struct Bindings {
+    // output range of the first expansion of every shared rule
+    bound: HashMap<FragmentId, (usize, usize)>,
+    // first expansions being serialized: rule, end of the subtree, output
+    // start
+    open: Vec<(FragmentId, usize, usize)>,
+}
+
+impl Bindings {
+    // Serialize nodes[ii], or the whole subtree when it repeats a shared
+    // rule. Returns the index of the next node
+    fn visit<B: Buffer + ?Sized>(&mut self, grammar: &GrammarRust, nodes: &[Node],
+            ii: usize, buf: &mut B) -> usize {
+        while let Some(&(rule, _, start)) = self.open.last().filter(|x| x.1 <= ii) {
+            self.bound.insert(rule, (start, buf.len()));
+            self.open.pop();
+        }
+        let node = nodes[ii];
+        let end = (ii + node.size.max(1) as usize).min(nodes.len());
+        match grammar.lookup_fragment(node.fragment) {
+            Fragment::Terminal(value) => buf.extend_from_slice(value),
+            Fragment::NonTerminal(_) if grammar.is_shared(node.fragment) => {
+                if let Some(&(from, to)) = self.bound.get(&node.fragment) {
+                    let value = buf.as_mut_slice()[from..to].to_vec();
+                    buf.extend_from_slice(&value);
+                    return end;
+                }
+                // a recursive expansion inside the first one derives freely
+                if !self.open.iter().any(|x| x.0 == node.fragment) {
+                    self.open.push((node.fragment, end, buf.len()));
+                }
+            }
+            _ => {}
+        }
+        ii + 1
+    }
+}
+
+impl GrammarRust {
+    // Derive a tree rooted at from, stack is scratch space of the caller
+    pub fn generate_tree(&self, state: &mut GeneratorState, from: FragmentId,
+            stack: &mut TreeStack, tree: &mut Tree) {
+        self.start_derivation(state);
+        let max_output = self.output_limit(state);
+        self.derive_limited(from, max_output, stack, tree,
+            |cur, options, nodes, context| self.choose(state, cur, options, nodes, context));
+    }
+
+    // Derive a tree rooted at from, choose picks the option of every
+    // non-terminal (see GrammarRust::choose()). It also gets the
+    // alternative the non-terminal was referenced from, if any
+    pub fn derive_tree(&self, from: FragmentId,
+            stack: &mut TreeStack, tree: &mut Tree,
+            choose: impl FnMut(FragmentId, &[FragmentId], usize,
+                Option<FragmentId>) -> Option<FragmentId>) {
+        self.derive_limited(from, self.max_output(), stack, tree, choose);
+    }
+
+    // derive_tree() stopping at max_output bytes
+    fn derive_limited(&self, from: FragmentId, max_output: usize,
+            stack: &mut TreeStack, tree: &mut Tree,
+            mut choose: impl FnMut(FragmentId, &[FragmentId], usize,
+                Option<FragmentId>) -> Option<FragmentId>) {
+        tree.nodes.clear();
+        let TreeStack { pending: stack, parents, contexts } = stack;
+        stack.clear();
+        parents.clear();
+        contexts.clear();
+        let mut nodes = 0usize;
+        let mut bytes = 0usize;
+
+        stack.push((from, u32::MAX));
+        while let Some((cur, parent)) = stack.pop() {
+            nodes += 1;
+            let idx = tree.nodes.len() as u32;
+            tree.nodes.push(Node { fragment: cur, size: 1 });
+            parents.push(parent);
+            let context = match parent {
+                u32::MAX => None,
+                parent => match self.lookup_fragment(tree.nodes[parent as usize].fragment) {
+                    Fragment::Expression(_) => Some(tree.nodes[parent as usize].fragment),
+                    _ => contexts[parent as usize],
+                },
+            };
+            contexts.push(context);
+
+            match self.lookup_fragment(cur) {
+                Fragment::NonTerminal(options) => {
+                    let Some(sel) = choose(cur, options, nodes, context) else {
+                        break;
+                    };
+                    stack.push((sel, idx));
+                }
+                Fragment::Expression(expr) => {
+                    expr.iter().rev().for_each(|x| stack.push((*x, idx)));
+                }
+                Fragment::Terminal(value) => {
+                    bytes += value.len();
+                    if bytes > max_output {
+                        break;
+                    }
+                }
+            }
+        }
+
+        // children come after their parents in preorder
+        for ii in (1..tree.nodes.len()).rev() {
+            let size = tree.nodes[ii].size;
+            tree.nodes[parents[ii] as usize].size += size;
+        }
+    }
+
+    // Derive a tree for a complete test case
+    pub fn generate_full_tree(&self, state: &mut GeneratorState,
+            stack: &mut TreeStack, tree: &mut Tree) {
+        self.generate_tree(state, self.start(), stack, tree);
+    }
+}
+
+impl GrammarRust {
+    // Throw away a random non-terminal subtree and derive a fresh one in
+    // its place. Returns false if the tree has nothing to regenerate
+    pub fn mutate_subtree(&self, state: &mut GeneratorState, tree: &mut Tree,
+            stack: &mut TreeStack, scratch: &mut Tree) -> bool {
+        let Some(idx) = self.derive_subtree(state, tree, stack, scratch) else {
+            return false;
+        };
+        tree.replace_subtree(idx, &scratch.nodes);
+        true
+    }
+
+    // The first half of mutate_subtree(): the index of the subtree to
+    // replace and its replacement in scratch
+    pub fn derive_subtree(&self, state: &mut GeneratorState, tree: &Tree,
+            stack: &mut TreeStack, scratch: &mut Tree) -> Option<usize> {
+        let candidates = tree.nonterminals(self).count();
+        if candidates == 0 {
+            return None;
+        }
+
+        let pick = state.rand() % candidates;
+        let idx = tree.nonterminals(self).nth(pick).unwrap();
+
+        state.set_mutating(true);
+        self.generate_tree(state, tree.nodes[idx].fragment, stack, scratch);
+        state.set_mutating(false);
+        Some(idx)
+    }
+}
+
+impl GrammarRust {
+    // Alternatives of a non-terminal that consist of exactly one terminal,
+    // the expression fragments of things like keywords or digits
+    fn terminal_alternatives(&self, nonterm: FragmentId) -> Vec<FragmentId> {
+        if !matches!(self.lookup_fragment(nonterm), Fragment::NonTerminal(_)) {
+            return Vec::new();
+        }
+        // swapping is mutating, generation_only alternatives stay out
+        let options = self.phase_options(nonterm, true);
+        options.iter().copied().filter(|&x| matches!(self.lookup_fragment(x),
+            Fragment::Expression(expr) if expr.len() == 1 && matches!(
+                self.lookup_fragment(expr[0]), Fragment::Terminal(_))))
+            .collect()
+    }
+
+    // Replace one single terminal alternative (keyword, digit, ...) with a
+    // sibling alternative of the same non-terminal. Much cheaper and more
+    // targeted than regenerating a subtree. Returns false if the tree has
+    // no terminal with a sibling to swap to
+    pub fn mutate_terminal_swap(&self, state: &mut GeneratorState,
+            tree: &mut Tree) -> bool {
+        let Some((idx, nodes)) = self.terminal_swap(state, tree) else {
+            return false;
+        };
+        tree.nodes[idx..idx + 2].copy_from_slice(&nodes);
+        true
+    }
+
+    // Where mutate_terminal_swap() swaps, and the two nodes (alternative
+    // and terminal) going there
+    pub fn terminal_swap(&self, state: &mut GeneratorState, tree: &Tree)
+            -> Option<(usize, [Node; 2])> {
+        // expression nodes wrapping a single terminal, the child of a
+        // non-terminal always directly follows it in preorder
+        let candidates = (1..tree.nodes.len()).filter(|&ii| {
+            tree.nodes[ii].size == 2 && matches!(
+                self.lookup_fragment(tree.nodes[ii + 1].fragment),
+                Fragment::Terminal(_)) && matches!(
+                self.lookup_fragment(tree.nodes[ii - 1].fragment),
+                Fragment::NonTerminal(_))
+        }).collect::<Vec<_>>();
+
+        // try a few spots, many terminals have no siblings
+        for _ in 0..candidates.len().min(8) {
+            let idx = candidates[state.rand() % candidates.len()];
+            let alternatives = self.terminal_alternatives(
+                tree.nodes[idx - 1].fragment);
+            let current = tree.nodes[idx].fragment;
+            if alternatives.len() < 2 || !alternatives.contains(&current) {
+                continue;
+            }
+
+            // pick any alternative but the current one
+            let mut pick = alternatives[state.rand() % (alternatives.len() - 1)];
+            if pick == current {
+                pick = *alternatives.last().unwrap();
+            }
+
+            let Fragment::Expression(expr) = self.lookup_fragment(pick) else {
+                unreachable!();
+            };
+            return Some((idx, [Node { fragment: pick, size: 2 },
+                Node { fragment: expr[0], size: 1 }]));
+        }
+        None
+    }
+}
+
+impl GrammarRust {
+    // Nautilus style recursion unrolling: find a non-terminal with a
+    // descendant of the same non-terminal and repeat the derivation between
+    // the two a few times. Produces deep nesting (arrays in arrays, long
+    // expression chains) that random generation rarely gets to. Returns
+    // false if the tree has no recursion
+    pub fn mutate_recursion(&self, state: &mut GeneratorState, tree: &mut Tree,
+            scratch: &mut Tree) -> bool {
+        let Some(outer) = self.unroll_recursion(state, tree, scratch) else {
+            return false;
+        };
+        tree.replace_subtree(outer, &scratch.nodes);
+        true
+    }
+
+    // The first half of mutate_recursion(): the index of the subtree to
+    // replace and the unrolled derivation in scratch
+    pub fn unroll_recursion(&self, state: &mut GeneratorState, tree: &Tree,
+            scratch: &mut Tree) -> Option<usize> {
+        let candidates = tree.nonterminals(self).collect::<Vec<_>>();
+        if candidates.is_empty() {
+            return None;
+        }
+
+        // recursion is not everywhere, try a few spots
+        for _ in 0..candidates.len().min(8) {
+            let outer = candidates[state.rand() % candidates.len()];
+            let outer_size = tree.nodes[outer].size as usize;
+            let fragment = tree.nodes[outer].fragment;
+
+            let inners = (outer + 1..outer + outer_size)
+                .filter(|&ii| tree.nodes[ii].fragment == fragment)
+                .collect::<Vec<_>>();
+            if inners.is_empty() {
+                continue;
+            }
+            let inner = inners[state.rand() % inners.len()];
+            let inner_size = tree.nodes[inner].size as usize;
+
+            // every unrolling adds the nodes between outer and inner once
+            // more, keep the result within a sane size
+            let grow = outer_size - inner_size;
+            let mut count = 1 << (state.rand() % 5);
+            while count > 1 && tree.nodes.len() + count * grow > MAX_OUTPUT_SIZE {
+                count /= 2;
+            }
+
+            let prefix = &tree.nodes[outer..inner];
+            let suffix = &tree.nodes[inner + inner_size..outer + outer_size];
+            let depth = inner - outer;
+
+            // the original derivation plus count copies of it, nested. Nodes
+            // on the path from outer to inner grow by the copies below them
+            scratch.nodes.clear();
+            for copy in 0..=count {
+                let below = ((count - copy) * grow) as u32;
+                scratch.nodes.extend(prefix.iter().enumerate().map(|(ii, x)|
+                    if ii + x.size as usize > depth {
+                        Node { fragment: x.fragment, size: x.size + below }
+                    } else {
+                        *x
+                    }));
+            }
+            scratch.nodes.extend_from_slice(&tree.nodes[inner..inner + inner_size]);
+            for _ in 0..=count {
+                scratch.nodes.extend_from_slice(suffix);
+            }
+            return Some(outer);
+        }
+        None
+    }
+}
@@ -0,0 +1,92 @@
+// Grammar aware corpus trimming
+//
+// Like afl-tmin over the whole queue, but on derivation trees so a trimmed
+// entry is still in the grammar. Two reductions are tried on every
+// non-terminal, front to back, and kept when the target takes the same
+// path with the smaller input:
+//
+//   - the subtree is replaced by the smallest derivation of its rule
+//   - a nested derivation of the same rule replaces the subtree (unrolled
+//     recursion is rolled back up)
+//
+// What "the same path" means is up to the caller, the trim command
+// compares the coverage path hash and how the execution ended.
+
+use std::io;
+
+use crate::grammar::{Fragment, FragmentId, GrammarRust};
+use crate::tree::{Node, Tree, TreeStack};
+
+impl GrammarRust {
+    // Derive the smallest tree rooted at from
+    pub fn minimal_tree(&self, from: FragmentId, stack: &mut TreeStack,
+            tree: &mut Tree) {
+        self.derive_tree(from, stack, tree, |cur, _, _, _| self.cheapest(cur));
+    }
+}
+
+// Outcome of trimming one tree
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TrimStats {
+    // executions spent, and how many of the reductions held
+    pub tries: u64,
+    pub reductions: u64,
+}
+
+// Shrink tree as long as same still accepts its serialization
+pub fn trim(gram: &GrammarRust, tree: &mut Tree,
+        mut same: impl FnMut(&[u8]) -> io::Result<bool>) -> io::Result<TrimStats> {
+    let mut stats = TrimStats::default();
+    let (mut stack, mut minimal) = (TreeStack::default(), Tree::default());
+    let (mut candidate, mut bytes) = (Tree::default(), Vec::new());
+
+    // indices shift with every reduction, nodes in front of idx never move
+    let mut idx = 0;
+    while idx < tree.nodes.len() {
+        let fragment = tree.nodes[idx].fragment;
+        if !matches!(gram.lookup_fragment(fragment), Fragment::NonTerminal(_)) {
+            idx += 1;
+            continue;
+        }
+        let size = tree.nodes[idx].size as usize;
+        let current = subtree_len(gram, &tree.nodes[idx..idx + size]);
+
+        // smallest derivation first, then the nested derivations of the
+        // same rule, outermost first
+        gram.minimal_tree(fragment, &mut stack, &mut minimal);
+        let mut replacements = vec![minimal.nodes.clone()];
+        replacements.extend((idx + 1..idx + size)
+            .filter(|&ii| tree.nodes[ii].fragment == fragment)
+            .map(|ii| tree.nodes[ii..ii + tree.nodes[ii].size as usize].to_vec()));
+
+        // a reduced subtree gets another go, it may shrink further
+        let mut reduced = false;
+        for nodes in replacements {
+            if subtree_len(gram, &nodes) >= current {
+                continue;
+            }
+            candidate.clone_from(tree);
+            candidate.replace_subtree(idx, &nodes);
+            bytes.clear();
+            candidate.serialize(gram, &mut bytes);
+            stats.tries += 1;
+            if same(&bytes)? {
+                stats.reductions += 1;
+                std::mem::swap(tree, &mut candidate);
+                reduced = true;
+                break;
+            }
+        }
+        if !reduced {
+            idx += 1;
+        }
+    }
+    Ok(stats)
+}
+
+// Serialized length of a subtree
+fn subtree_len(gram: &GrammarRust, nodes: &[Node]) -> usize {
+    let mut buf = Vec::new();
+    Tree { nodes: nodes.to_vec() }.serialize(gram, &mut buf);
+    buf.len()
+}
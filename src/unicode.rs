@@ -0,0 +1,225 @@
+// Terminals for text beyond ASCII
+//
+// Text processing code breaks on what its authors never typed: letters of
+// other scripts, combining marks, code points next to the surrogates or at
+// the edges of the UTF-8 encoding lengths, sequences that change under
+// normalization. A symbol of the form <unicode:class> stands for one code
+// point (or sequence) of the class, UTF-8 encoded:
+//
+//   "<unicode:letter>"         letters of a number of scripts, CJK, Hangul
+//   "<unicode:mark>"           combining marks
+//   "<unicode:digit>"          decimal digits of several scripts
+//   "<unicode:space>"          white space and zero width characters
+//   "<unicode:edge>"           encoding boundaries, noncharacters, BOM, bidi
+//                              controls, the neighbours of the surrogates
+//   "<unicode:normalization>"  sequences NFC/NFD/NFKC change
+//   "<unicode:any>"            any scalar value
+//   "<unicode:U+0400-U+04FF|U+20AC>"  ranges and single code points
+//
+// expand() adds a rule named like the symbol when loading the grammar.
+// Ranges are split into runs of code points whose UTF-8 encodings differ
+// only in byte ranges, one alternative per run and one byte rule per range
+// of bytes, so even <unicode:any> is a few dozen rules. Runs are equally
+// likely, not code points. A rule of the grammar with the same name takes
+// precedence.
+
+use std::collections::HashMap;
+
+use crate::grammar::Grammar;
+
+const SURROGATES: (u32, u32) = (0xd800, 0xdfff);
+
+const LETTER: &[(u32, u32)] = &[
+    (0x41, 0x5a), (0x61, 0x7a), (0xc0, 0xd6), (0xd8, 0xf6), (0xf8, 0x24f),
+    (0x391, 0x3a1), (0x3a3, 0x3a9), (0x3b1, 0x3c9), (0x400, 0x481), (0x48a, 0x4ff),
+    (0x531, 0x556), (0x5d0, 0x5ea), (0x621, 0x64a), (0x905, 0x939), (0xe01, 0xe30),
+    (0x3041, 0x3096), (0x30a1, 0x30fa), (0x4e00, 0x9fff), (0xac00, 0xd7a3),
+    (0x10400, 0x1044f), (0x1d400, 0x1d454),
+];
+
+const MARK: &[(u32, u32)] = &[
+    (0x300, 0x36f), (0x483, 0x489), (0x64b, 0x65f), (0x900, 0x903), (0x1ab0, 0x1abe),
+    (0x1dc0, 0x1dff), (0x20d0, 0x20f0), (0xfe20, 0xfe2f),
+];
+
+const DIGIT: &[(u32, u32)] = &[
+    (0x30, 0x39), (0x660, 0x669), (0x6f0, 0x6f9), (0x966, 0x96f), (0xe50, 0xe59),
+    (0xff10, 0xff19), (0x1d7ce, 0x1d7ff),
+];
+
+const SPACE: &[(u32, u32)] = &[
+    (0x9, 0xd), (0x20, 0x20), (0x85, 0x85), (0xa0, 0xa0), (0x1680, 0x1680),
+    (0x2000, 0x200b), (0x2028, 0x2029), (0x202f, 0x202f), (0x205f, 0x2060),
+    (0x3000, 0x3000), (0xfeff, 0xfeff),
+];
+
+const EDGE: &[(u32, u32)] = &[
+    (0x0, 0x0), (0x7f, 0x80), (0x7ff, 0x800), (0xd7ff, 0xd7ff), (0xe000, 0xe000),
+    (0xfdd0, 0xfdef), (0xfeff, 0xfeff), (0xfffd, 0xffff), (0x10000, 0x10000),
+    (0x1fffe, 0x1ffff), (0x10fffe, 0x10ffff), (0x200b, 0x200f), (0x202a, 0x202e),
+    (0x2066, 0x2069),
+];
+
+const ANY: &[(u32, u32)] = &[(0x0, SURROGATES.0 - 1), (SURROGATES.1 + 1, 0x10ffff)];
+
+// Composed and decomposed forms, compatibility characters, reordered marks
+const NORMALIZATION: &[&str] = &[
+    "\u{e9}", "e\u{301}", "\u{c5}", "A\u{30a}", "\u{212b}", "\u{212a}", "\u{fb01}",
+    "\u{ac00}", "\u{1100}\u{1161}", "\u{1e0b}\u{323}", "d\u{323}\u{307}",
+    "d\u{307}\u{323}", "\u{ff21}", "\u{2460}", "\u{fdfa}", "\u{1e9b}\u{323}",
+    "\u{3d3}", "\u{3d2}\u{301}", "\u{2126}", "\u{390}", "\u{df}", "\u{130}",
+];
+
+// Where code point ranges encode in a different number of bytes
+const LENGTH_LIMITS: [u32; 3] = [0x7f, 0x7ff, 0xffff];
+
+fn encode(cp: u32) -> Vec<u8> {
+    let mut buf = [0; 4];
+    char::from_u32(cp).expect("scalar value").encode_utf8(&mut buf).as_bytes().to_vec()
+}
+
+// Split lo..=hi (scalar values only) into runs that encode as byte ranges,
+// see the top of the file
+fn runs(lo: u32, hi: u32, out: &mut Vec<Vec<(u8, u8)>>) {
+    if lo > hi {
+        return;
+    }
+    for limit in LENGTH_LIMITS {
+        if lo <= limit && limit < hi {
+            runs(lo, limit, out);
+            runs(limit + 1, hi, out);
+            return;
+        }
+    }
+    // every byte after the first carries 6 bits, split until lo and hi
+    // differ in whole blocks of them
+    for shift in [6, 12, 18] {
+        let mask = (1 << shift) - 1;
+        if lo & !mask != hi & !mask {
+            if lo & mask != 0 {
+                runs(lo, lo | mask, out);
+                runs((lo | mask) + 1, hi, out);
+                return;
+            }
+            if hi & mask != mask {
+                runs(lo, (hi & !mask) - 1, out);
+                runs(hi & !mask, hi, out);
+                return;
+            }
+        }
+    }
+    out.push(encode(lo).into_iter().zip(encode(hi)).collect());
+}
+
+fn parse_code_point(text: &str) -> Option<u32> {
+    let hex = text.trim().strip_prefix("U+").or_else(|| text.trim().strip_prefix("u+"))?;
+    u32::from_str_radix(hex, 16).ok().filter(|&x| x <= 0x10ffff)
+}
+
+// Code point ranges of a class, None if there is no such class
+fn ranges(class: &str) -> Option<Result<Vec<(u32, u32)>, String>> {
+    let fixed = match class {
+        "letter" => LETTER,
+        "mark" => MARK,
+        "digit" => DIGIT,
+        "space" => SPACE,
+        "edge" => EDGE,
+        "any" => ANY,
+        _ if class.starts_with("U+") || class.starts_with("u+") => {
+            return Some(class.split('|').map(|part| {
+                let (lo, hi) = part.split_once('-').unwrap_or((part, part));
+                match (parse_code_point(lo), parse_code_point(hi)) {
+                    (Some(lo), Some(hi)) if lo <= hi => Ok((lo, hi)),
+                    _ => Err(format!("{:?} is not a code point or range of them", part)),
+                }
+            }).collect());
+        }
+        _ => return None,
+    };
+    Some(Ok(fixed.to_vec()))
+}
+
+// Rule name of the bytes lo..=hi, a terminal for a single byte
+fn byte_symbol(lo: u8, hi: u8, rules: &mut HashMap<String, Vec<Vec<String>>>) -> String {
+    if lo == hi {
+        return match lo {
+            0..=0x7f => (lo as char).to_string(),
+            _ => format!("<u8:{:#x}>", lo),
+        };
+    }
+    let name = format!("<unicode:bytes {:02x}-{:02x}>", lo, hi);
+    rules.entry(name.clone()).or_insert_with(||
+        (lo..=hi).map(|x| vec![format!("<u8:{:#x}>", x)]).collect());
+    name
+}
+
+// Alternatives of the rule of a class
+fn alternatives(class: &str, rules: &mut HashMap<String, Vec<Vec<String>>>)
+        -> Option<Result<Vec<Vec<String>>, String>> {
+    if class == "normalization" {
+        return Some(Ok(NORMALIZATION.iter().map(|x| vec![x.to_string()]).collect()));
+    }
+    let ranges = match ranges(class)? {
+        Ok(ranges) => ranges,
+        Err(e) => return Some(Err(e)),
+    };
+    let mut sequences = Vec::new();
+    for (lo, hi) in ranges {
+        // the surrogates are not scalar values, they have no encoding
+        if lo < SURROGATES.0 || hi > SURROGATES.1 {
+            runs(lo, hi.min(SURROGATES.0 - 1), &mut sequences);
+            runs(lo.max(SURROGATES.1 + 1), hi, &mut sequences);
+        }
+    }
+    if sequences.is_empty() {
+        return Some(Err("only surrogates, which have no encoding".to_string()));
+    }
+    Some(Ok(sequences.into_iter().map(|sequence| sequence.into_iter()
+        .map(|(lo, hi)| byte_symbol(lo, hi, rules)).collect()).collect()))
+}
+
+// Whether a symbol is of the form <unicode:class>, well formed or not
+pub fn is_symbol(symbol: &str) -> bool {
+    symbol.starts_with("<unicode:") && symbol.ends_with('>')
+}
+
+// Add the rules for the <unicode:...> symbols of the grammar, see the top
+// of the file. Errors name malformed ones
+pub fn expand(grammar: &mut Grammar) -> Result<(), String> {
+    let mut symbols = grammar.0.values().flatten().flatten()
+        .filter(|x| is_symbol(x) && !grammar.0.contains_key(*x))
+        .cloned().collect::<Vec<_>>();
+    symbols.sort_unstable();
+    symbols.dedup();
+
+    let mut added = HashMap::new();
+    for symbol in symbols {
+        let class = &symbol["<unicode:".len()..symbol.len() - 1];
+        match alternatives(class, &mut added) {
+            Some(Ok(alternatives)) => {
+                added.insert(symbol, alternatives);
+            }
+            Some(Err(e)) => return Err(format!("{}: {}", symbol, e)),
+            None => return Err(format!("{}: unknown class {:?}", symbol, class)),
+        }
+    }
+    for (name, alternatives) in added {
+        grammar.0.entry(name).or_insert(alternatives);
+    }
+    Ok(())
+}
+
+// The grammar with the rules expand() added left out, their references
+// standing for U+FFFD. The bytes of those rules only ever come out as
+// whole code points, checks for terminals that are not valid UTF-8 should
+// look at this instead
+pub fn without_expansions(grammar: &Grammar) -> Grammar {
+    let mut ret = grammar.clone();
+    ret.0.retain(|name, _| !name.starts_with("<unicode:"));
+    for symbol in ret.0.values_mut().flatten().flatten() {
+        if symbol.starts_with("<unicode:") {
+            *symbol = "\u{fffd}".to_string();
+        }
+    }
+    ret
+}
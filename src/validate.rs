@@ -0,0 +1,308 @@
+// Grammar sanity checks
+//
+// Static checks on the compiled grammar plus a smoke test generating a
+// handful of samples under the usual limits. Meant to run before a
+// campaign, so a typo in a rule name does not cost a night of fuzzing.
+// Rules with a cost annotation get the cost they are expected to add to a
+// test case, see expected_expansions(). Uniform choice traps (rules
+// practically never reached, rules expected to expand forever) are
+// warnings, see traps.rs.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::grammar::{Fragment, FragmentId, GeneratorState, Grammar, GrammarRust,
+    MAX_OUTPUT_SIZE};
+use crate::binary;
+use crate::traps;
+use crate::tree::{Tree, TreeStack};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Clone, Debug)]
+pub struct Finding {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}: {}", severity, self.message)
+    }
+}
+
+// Limits of the smoke test
+#[derive(Clone, Debug)]
+pub struct ValidateOptions {
+    pub samples: usize,
+    pub max_nodes: usize,
+    // samples longer than this are reported, 0 for no limit
+    pub max_len: usize,
+    pub seed: usize,
+    // see GrammarRust::set_cost_budget()
+    pub cost_budget: Option<u64>,
+}
+
+// An expensive rule and how much of it a test case is expected to have
+#[derive(Clone, Debug)]
+pub struct RuleCost {
+    pub name: String,
+    pub cost: u32,
+    // None when unbounded
+    pub expansions: Option<f64>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    pub findings: Vec<Finding>,
+
+    // sizes of the generated samples
+    pub samples: usize,
+    pub min_len: usize,
+    pub max_len: usize,
+    pub avg_len: f64,
+
+    // the expensive rules, the cost they are expected to add to a test
+    // case together (None when unbounded), and what the samples cost
+    pub costs: Vec<RuleCost>,
+    pub expected_cost: Option<f64>,
+    pub avg_cost: f64,
+}
+
+impl Report {
+    fn error(&mut self, message: String) {
+        self.findings.push(Finding { severity: Severity::Error, message });
+    }
+
+    fn warning(&mut self, message: String) {
+        self.findings.push(Finding { severity: Severity::Warning, message });
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.findings.iter().any(|x| x.severity == Severity::Error)
+    }
+}
+
+// Does a terminal look like a reference to a rule (probably a typo)?
+fn looks_like_rule(value: &str) -> bool {
+    value.len() > 2 && value.starts_with('<') && value.ends_with('>')
+        && !value[1..value.len() - 1].contains(['<', '>', ' '])
+}
+
+pub fn validate(grammar: &Grammar, options: &ValidateOptions) -> Report {
+    let mut report = Report::default();
+
+    // checks that would make GrammarRust::new() or generation fall over
+    if !grammar.0.contains_key("<start>") {
+        report.error(String::from("no <start> rule"));
+        return report;
+    }
+    let mut empty = grammar.0.iter().filter(|(_, x)| x.is_empty())
+        .map(|(name, _)| name.as_str()).collect::<Vec<_>>();
+    empty.sort_unstable();
+    for name in &empty {
+        report.error(format!("rule {} has no alternatives", name));
+    }
+    if !empty.is_empty() {
+        return report;
+    }
+
+    let mut gram = GrammarRust::new(grammar);
+    gram.set_node_budget(options.max_nodes);
+    gram.set_cost_budget(options.cost_budget);
+
+    // "<foo>" terminals are almost always a misspelled rule, unless they
+    // are binary constants (see binary.rs)
+    let mut undefined = grammar.0.values().flatten().flatten()
+        .filter(|x| looks_like_rule(x) && !grammar.0.contains_key(*x)
+            && binary::constant(x).is_none())
+        .collect::<Vec<_>>();
+    undefined.sort_unstable();
+    undefined.dedup();
+    for name in undefined {
+        report.warning(format!("{} is used but never defined, it is taken \
+            as a terminal", name));
+    }
+
+    // rules the start symbol never gets to
+    let mut reachable = HashSet::new();
+    let mut stack = vec![gram.start()];
+    while let Some(rule) = stack.pop() {
+        if !reachable.insert(rule) {
+            continue;
+        }
+        for &alternative in gram.lookup_fragment_nonterm(rule) {
+            let Fragment::Expression(symbols) = gram.lookup_fragment(alternative) else {
+                continue;
+            };
+            stack.extend(symbols.iter().filter_map(|&x| match gram.lookup_fragment(x) {
+                Fragment::NonTerminal(target) => Some(target[0]),
+                _ => None,
+            }));
+        }
+    }
+    for (name, id) in gram.rules() {
+        if !reachable.contains(&id) {
+            report.warning(format!("rule {} is unreachable from <start>", name));
+        }
+    }
+
+    // rules that can never finish, and alternatives that never do in
+    // rules that otherwise can (generation only takes them while in budget)
+    for (name, id) in gram.rules() {
+        if !reachable.contains(&id) {
+            continue;
+        }
+        if gram.min_cost(id) == usize::MAX {
+            report.error(format!("rule {} can never terminate", name));
+            continue;
+        }
+        for (index, &alternative) in gram.lookup_fragment_nonterm(id).iter().enumerate() {
+            if gram.min_cost(alternative) == usize::MAX {
+                report.warning(format!("alternative #{} of {} can never \
+                    terminate", index, name));
+            }
+        }
+    }
+    if report.has_errors() {
+        return report;
+    }
+    for warning in traps::check(&gram) {
+        report.warning(warning);
+    }
+
+    // what the expensive rules add up to, budgets aside
+    let expensive = gram.rules().filter(|x| gram.cost(x.1) > 0).collect::<Vec<_>>();
+    let expansions = match expensive.is_empty() {
+        true => None,
+        false => expected_expansions(&gram),
+    };
+    let mut expected_cost = Some(0.0);
+    for (name, id) in expensive {
+        let expected = expansions.as_ref().map(|x| x.get(&id).copied().unwrap_or(0.0));
+        expected_cost = expected_cost.zip(expected).map(|(sum, x)| sum + x * gram.cost(id) as f64);
+        report.costs.push(RuleCost { name: name.to_string(), cost: gram.cost(id),
+            expansions: expected });
+    }
+    report.expected_cost = expected_cost;
+    if !report.costs.is_empty() && expansions.is_none() && options.cost_budget.is_none() {
+        report.warning(String::from("the expected cost of the expensive rules is \
+            unbounded, consider a cost budget"));
+    }
+
+    let names = gram.rules().filter(|(_, id)| reachable.contains(id))
+        .map(|(name, id)| (id, name)).collect::<HashMap<_, _>>();
+    smoke_test(&gram, &names, options, &mut report);
+    report
+}
+
+// Expected expansions of every rule per derivation when alternatives are
+// picked uniformly and nothing cuts the derivation short. None if that
+// grows without bound (rules that recurse more often than they end)
+pub fn expected_expansions(gram: &GrammarRust) -> Option<HashMap<FragmentId, f64>> {
+    // every rule with the rules its alternatives refer to, each weighted by
+    // the chance of taking the alternative
+    let rules = gram.rules().map(|(_, id)| {
+        let alternatives = gram.lookup_fragment_nonterm(id);
+        let weight = 1.0 / alternatives.len() as f64;
+        let refs = alternatives.iter().flat_map(|&x| match gram.lookup_fragment(x) {
+            Fragment::Expression(symbols) => symbols,
+            _ => &[],
+        }).filter_map(|&x| match gram.lookup_fragment(x) {
+            Fragment::NonTerminal(target) => Some((target[0], weight)),
+            _ => None,
+        }).collect::<Vec<_>>();
+        (id, refs)
+    }).collect::<Vec<_>>();
+
+    // expansions = start + expansions flowing in, to the fixpoint
+    let mut expansions = HashMap::new();
+    for _ in 0..10_000 {
+        let mut next = HashMap::from([(gram.start(), 1.0)]);
+        for (id, refs) in &rules {
+            let count = expansions.get(id).copied().unwrap_or(0.0);
+            for &(target, weight) in refs {
+                *next.entry(target).or_insert(0.0) += count * weight;
+            }
+        }
+        let settled = next.iter().all(|(id, &x)|
+            (x - expansions.get(id).copied().unwrap_or(0.0)).abs() <= 1e-9 * x.max(1.0));
+        if next.values().any(|&x| x > 1e12) {
+            return None;
+        }
+        expansions = next;
+        if settled {
+            return Some(expansions);
+        }
+    }
+    None
+}
+
+// Generate samples and look at what comes out, names are the reachable
+// rules
+fn smoke_test(gram: &GrammarRust, names: &HashMap<FragmentId, &str>,
+        options: &ValidateOptions, report: &mut Report) {
+    let mut state = GeneratorState::new(options.seed.max(1));
+    let mut stack = TreeStack::default();
+    let mut tree = Tree::default();
+    let mut buf = Vec::new();
+    let mut truncated = 0;
+    let mut too_long = 0;
+    let mut used = HashSet::new();
+    let mut total = 0;
+    let mut cost = 0;
+    report.min_len = usize::MAX;
+
+    for _ in 0..options.samples {
+        gram.generate_full_tree(&mut state, &mut stack, &mut tree);
+        buf.clear();
+        tree.serialize(gram, &mut buf);
+        used.extend(tree.nodes.iter().map(|x| x.fragment));
+        cost += tree.nodes.iter().map(|x| gram.cost(x.fragment) as u64).sum::<u64>();
+
+        total += buf.len();
+        report.min_len = report.min_len.min(buf.len());
+        report.max_len = report.max_len.max(buf.len());
+        if buf.len() > MAX_OUTPUT_SIZE {
+            truncated += 1;
+        }
+        if options.max_len != 0 && buf.len() > options.max_len {
+            too_long += 1;
+        }
+    }
+    report.samples = options.samples;
+    if options.samples == 0 {
+        report.min_len = 0;
+        return;
+    }
+    report.avg_len = total as f64 / options.samples as f64;
+    report.avg_cost = cost as f64 / options.samples as f64;
+
+    if truncated > 0 {
+        report.warning(format!("{} of {} samples hit the {} byte output limit \
+            and were cut short", truncated, options.samples, MAX_OUTPUT_SIZE));
+    }
+    if too_long == options.samples {
+        report.error(format!("every sample is longer than {} bytes",
+            options.max_len));
+    } else if too_long > 0 {
+        report.warning(format!("{} of {} samples are longer than {} bytes",
+            too_long, options.samples, options.max_len));
+    }
+
+    let mut unused = names.iter().filter(|(id, _)| !used.contains(*id))
+        .map(|(_, name)| *name).collect::<Vec<_>>();
+    unused.sort_unstable();
+    if !unused.is_empty() && options.samples >= 100 {
+        report.warning(format!("{} samples never used: {}", options.samples,
+            unused.join(", ")));
+    }
+}
@@ -0,0 +1,111 @@
+// One campaign per instance directory
+//
+// Two campaigns started with the same sync dir and instance name would
+// number their entries over each other, and the temp files of all
+// campaigns on a machine (inputs handed to targets, sandboxes, captured
+// output) used to share the system temp dir. A Workspace claims the
+// instance directory for the campaign:
+//
+//   <sync dir>/<instance>/.lock    pid of the campaign, created exclusively
+//                        /.tmp/    every temp file of the campaign
+//
+// A second campaign on the same instance fails to start, naming the pid,
+// unless that process is gone, then the stale lock and temp files are
+// taken over. Once a workspace exists, temp_dir() points into it, dropping
+// the workspace moves the temp dir out of the way in one rename, deletes
+// it and releases the lock.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+// temp dir of the workspace of this process, if there is one
+static TEMP: OnceLock<PathBuf> = OnceLock::new();
+
+// Where temp files go: the campaign's temp dir, or the system's outside of
+// a campaign
+pub fn temp_dir() -> PathBuf {
+    TEMP.get().cloned().unwrap_or_else(std::env::temp_dir)
+}
+
+pub struct Workspace {
+    lock: PathBuf,
+    temp: PathBuf,
+}
+
+// Whether a process with this pid is running
+fn alive(pid: u32) -> bool {
+    #[cfg(unix)]
+    {
+        // SAFETY: signal 0 only checks the process exists
+        let ret = unsafe { libc::kill(pid as libc::pid_t, 0) };
+        ret == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+    }
+    #[cfg(not(unix))]
+    {
+        // no cheap way to tell, the lock has to be removed by hand
+        let _ = pid;
+        true
+    }
+}
+
+impl Workspace {
+    // Claim the instance directory dir, see the top of the file
+    pub fn create(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let lock = dir.join(".lock");
+        let temp = dir.join(".tmp");
+        for attempt in 0.. {
+            match OpenOptions::new().write(true).create_new(true).open(&lock) {
+                Ok(mut file) => {
+                    write!(file, "{}", std::process::id())?;
+                    break;
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(io::Error::new(e.kind(),
+                    format!("{}: {}", lock.display(), e))),
+            }
+            // a lock being written reads empty for a moment, one that
+            // stays empty lost its writer
+            let owner = fs::read_to_string(&lock).unwrap_or_default();
+            match owner.trim().parse::<u32>() {
+                Ok(pid) if pid != std::process::id() && alive(pid) => return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} is in use by the campaign of pid {} (remove {} if \
+                        there is none)", dir.display(), pid, lock.display()))),
+                Err(_) if attempt < 100 => {
+                    std::thread::sleep(std::time::Duration::from_millis(10));
+                    continue;
+                }
+                _ => {}
+            }
+            match fs::remove_file(&lock) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+
+        // a campaign that died leaves its temp files behind
+        if temp.exists() {
+            fs::remove_dir_all(&temp)?;
+        }
+        fs::create_dir(&temp)?;
+        let _ = TEMP.set(temp.clone());
+        Ok(Workspace { lock, temp })
+    }
+
+    pub fn temp(&self) -> &Path {
+        &self.temp
+    }
+}
+
+impl Drop for Workspace {
+    fn drop(&mut self) {
+        let trash = self.temp.with_extension(format!("old{}", std::process::id()));
+        if fs::rename(&self.temp, &trash).is_ok() {
+            let _ = fs::remove_dir_all(&trash);
+        }
+        let _ = fs::remove_file(&self.lock);
+    }
+}
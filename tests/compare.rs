@@ -0,0 +1,45 @@
+// Statistics of compare-runs against values computed elsewhere (see
+// compare.rs)
+
+use maybe_fastest_fuzzer::compare::mann_whitney;
+
+fn assert_close(actual: f64, expected: f64, tolerance: f64) {
+    assert!((actual - expected).abs() <= tolerance, "{} instead of {}", actual, expected);
+}
+
+#[test]
+fn separated() {
+    // every candidate value above every baseline one: U = 25 of 25
+    let (a12, p) = mann_whitney(&[1., 2., 3., 4., 5.], &[6., 7., 8., 9., 10.]).unwrap();
+    assert_close(a12, 1., 1e-12);
+    // scipy.stats.mannwhitneyu(method="asymptotic")
+    assert_close(p, 0.012186, 1e-5);
+
+    let (a12, p) = mann_whitney(&[6., 7., 8., 9., 10.], &[1., 2., 3., 4., 5.]).unwrap();
+    assert_close(a12, 0., 1e-12);
+    assert_close(p, 0.012186, 1e-5);
+}
+
+#[test]
+// 3.14 is a measurement, not pi
+#[allow(clippy::approx_constant)]
+fn ties() {
+    // the example of R's wilcox.test(), which has ties: W = 58, p = 0.1329
+    let x = [1.83, 0.50, 1.62, 2.48, 1.68, 1.88, 1.55, 3.06, 1.30];
+    let y = [0.878, 0.647, 0.598, 2.05, 1.06, 1.29, 1.06, 3.14, 1.29];
+    let (a12, p) = mann_whitney(&y, &x).unwrap();
+    assert_close(a12, 58. / 81., 1e-12);
+    assert_close(p, 0.1329, 1e-4);
+}
+
+#[test]
+fn degenerate() {
+    assert!(mann_whitney(&[1.], &[1., 2.]).is_none());
+    let (a12, p) = mann_whitney(&[3., 3., 3.], &[3., 3.]).unwrap();
+    assert_close(a12, 0.5, 1e-12);
+    assert_close(p, 1., 1e-12);
+    // identical samples are as alike as can be
+    let (a12, p) = mann_whitney(&[1., 2., 3., 4.], &[1., 2., 3., 4.]).unwrap();
+    assert_close(a12, 0.5, 1e-12);
+    assert!(p > 0.99, "{}", p);
+}
@@ -0,0 +1,53 @@
+// Campaign config files: the TOML subset they are written in and the
+// command line flags they stand for (see config.rs)
+
+use maybe_fastest_fuzzer::config::{self, Value};
+
+fn args(text: &str) -> Vec<String> {
+    config::to_args(text).unwrap().0
+}
+
+#[test]
+fn values() {
+    let parsed = config::parse("a = 'x' # comment\nb = 3\nc = [1.5,\n  true]\n").unwrap();
+    assert_eq!(parsed, [
+        ("a".to_string(), Value::String("x".into())),
+        ("b".to_string(), Value::Integer(3)),
+        ("c".to_string(), Value::Array(vec![Value::Float(1.5), Value::Boolean(true)])),
+    ]);
+}
+
+#[test]
+fn duplicate_keys() {
+    let err = config::parse("jobs = 1\njobs = 2\n").unwrap_err();
+    assert!(err.to_string().contains("duplicate key jobs"), "{}", err);
+    // tables of an array each have keys of their own
+    assert!(config::parse("[[worker]]\njobs = 1\n[[worker]]\njobs = 2\n").is_ok());
+    let err = config::parse("[[worker]]\njobs = 1\njobs = 2\n").unwrap_err();
+    assert!(err.to_string().contains("duplicate key jobs"), "{}", err);
+}
+
+#[test]
+fn repeatable_flags() {
+    assert_eq!(args("include = ['a.json', 'b.json']\noracle = ['exit-code:3']\n"),
+        ["--include", "a.json", "--include", "b.json", "--oracle", "exit-code:3"]);
+    assert_eq!(args("position = ['arg:1=x.json', 'env:HOME=y.json']\n"),
+        ["--position", "arg:1=x.json", "--position", "env:HOME=y.json"]);
+    assert_eq!(args("outputs = ['a.bin', 'b.bin']\noutput = 'out'\n"),
+        ["--output", "a.bin", "--output", "b.bin", "-o", "out"]);
+}
+
+#[test]
+fn other_flags() {
+    // arrays of other flags are command lines
+    assert_eq!(args("filter = ['jq', '.']\n"), ["--filter", "jq ."]);
+    assert_eq!(args("[[worker]]\njobs = 2\nstrategy = 'markov'\n[[worker]]\njobs = 1\n"),
+        ["--worker", "jobs=2,strategy=markov", "--worker", "jobs=1"]);
+    assert_eq!(args("pairwise = true\nutf8 = false\nmain = 'm'\n"),
+        ["--pairwise", "-M", "m"]);
+
+    let (args, target) = config::to_args("grammar = 'g.json'\ntarget = ['t', '@@']\n")
+        .unwrap();
+    assert_eq!((args, target), (vec!["g.json".to_string()],
+        vec!["t".to_string(), "@@".to_string()]));
+}
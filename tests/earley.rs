@@ -0,0 +1,57 @@
+// Parsing inputs into derivation trees (see earley.rs)
+
+use maybe_fastest_fuzzer::earley::ParseError;
+use maybe_fastest_fuzzer::{grammar, GrammarRust};
+
+fn round_trip(gram: &GrammarRust, input: &[u8]) {
+    let tree = gram.parse(input).unwrap_or_else(|e|
+        panic!("{:?}: {}", String::from_utf8_lossy(input), e));
+    assert_eq!(tree.nodes[0].size as usize, tree.nodes.len());
+    let mut derived = Vec::new();
+    tree.serialize(gram, &mut derived);
+    assert_eq!(derived, input);
+}
+
+#[test]
+fn ambiguous() {
+    // 1+1+1 parses two ways, either one will do
+    let gram = grammar! {
+        "<start>" => ["<e>"];
+        "<e>" => ["<e>", "+", "<e>"] | ["<e>", "*", "<e>"] | ["(", "<e>", ")"] | ["<n>"];
+        "<n>" => ["1"] | ["1", "<n>"];
+    };
+    for input in ["1", "11", "1+1", "1+1+1", "1*(1+11)*1", "((1))+1*1+1"] {
+        round_trip(&gram, input.as_bytes());
+    }
+    assert_eq!(gram.parse(b"1+").unwrap_err(), ParseError::NoParse(2));
+    assert_eq!(gram.parse(b"1++1").unwrap_err(), ParseError::NoParse(2));
+    assert_eq!(gram.parse(b"(1").unwrap_err(), ParseError::NoParse(2));
+    assert!(matches!(gram.parse(b""), Err(ParseError::NoParse(0))));
+}
+
+#[test]
+fn nullable() {
+    let gram = grammar! {
+        "<start>" => ["<a>", "<a>", "x", "<b>"];
+        "<a>" => [] | ["a"];
+        "<b>" => [] | ["b", "<b>"];
+    };
+    for input in ["x", "ax", "aax", "xb", "axbbb"] {
+        round_trip(&gram, input.as_bytes());
+    }
+    assert!(gram.parse(b"aaax").is_err());
+}
+
+#[test]
+fn right_recursion() {
+    // long lists go through Leo's optimization
+    let gram = grammar! {
+        "<start>" => ["[", "<items>", "]"];
+        "<items>" => ["<item>"] | ["<item>", ",", "<items>"];
+        "<item>" => ["a"] | ["b"] | ["<start>"];
+    };
+    let long = format!("[{}a]", "a,b,".repeat(2000));
+    for input in ["[a]", "[a,b]", "[[a],b,[b,[a]]]", &long] {
+        round_trip(&gram, input.as_bytes());
+    }
+}
@@ -0,0 +1,44 @@
+// The execution cache replays results, not findings: a campaign whose only
+// input crashes the target finds one crash, however often the cache hands
+// the input's result back (see exec_cache.rs)
+#![cfg(unix)]
+
+use std::path::Path;
+use std::process::Command;
+
+use serde_json::Value;
+
+fn campaign(dir: &Path, args: &[&str]) -> Value {
+    let _ = std::fs::remove_dir_all(dir);
+    std::fs::create_dir_all(dir).unwrap();
+    let grammar = dir.join("grammar.json");
+    std::fs::write(&grammar, r#"{"<start>": [["a"]]}"#).unwrap();
+    let status = Command::new(env!("CARGO_BIN_EXE_maybe_fastest_fuzzer"))
+        .arg(&grammar)
+        .arg("-o").arg(dir.join("out"))
+        .args(["--max-time", "1", "--quiet"])
+        .args(args)
+        .args(["--", "sh", "-c", "kill -SEGV $$"])
+        .status().unwrap();
+    // a bounded campaign with a crash fails
+    assert_eq!(status.code(), Some(1), "{}", status);
+
+    let instance = std::fs::read_dir(dir.join("out")).unwrap()
+        .map(|x| x.unwrap().path())
+        .find(|x| x.join("campaign_report.json").exists())
+        .expect("a campaign report");
+    let report = std::fs::read(instance.join("campaign_report.json")).unwrap();
+    serde_json::from_slice(&report).unwrap()
+}
+
+#[test]
+fn cached_crash_counted_once() {
+    let dir = std::env::temp_dir().join(format!("mff-exec-cache-{}", std::process::id()));
+    let report = campaign(&dir, &["--exec-cache"]);
+    let _ = std::fs::remove_dir_all(&dir);
+
+    assert_eq!(report["execs"], 1, "{}", report);
+    assert!(report["cache_hits"].as_u64().unwrap() > 0, "{}", report);
+    assert_eq!(report["crashes"], 1, "{}", report);
+    assert_eq!(report["saved_crashes"], 1, "{}", report);
+}
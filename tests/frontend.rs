@@ -0,0 +1,54 @@
+// Property tests of the grammar front end: whatever a grammar file holds,
+// loading, validating, compiling and generating from it either works or
+// fails with an error, it never panics or hangs (see perturb.rs)
+
+use std::time::Duration;
+
+use maybe_fastest_fuzzer::grammar::GeneratorState;
+use maybe_fastest_fuzzer::perturb::{self, Outcome};
+
+// Grammars per property, samples generated from every one and how long a
+// grammar may take before it counts as hanging
+const CASES: usize = 200;
+const SAMPLES: usize = 8;
+const TIMEOUT: Duration = Duration::from_secs(20);
+
+fn assert_no_bug(seed: usize, data: &[u8], outcome: Outcome) {
+    assert!(!outcome.is_bug(), "seed {}: {:?}\n{}", seed, outcome,
+        String::from_utf8_lossy(data));
+}
+
+#[test]
+fn random_grammars() {
+    for seed in 1..=CASES {
+        let mut state = GeneratorState::new(seed);
+        let grammar = perturb::random_grammar(&mut || state.rand());
+        let data = serde_json::to_vec(&perturb::to_json(&grammar)).unwrap();
+        let outcome = perturb::check_bytes(data.clone(), SAMPLES, seed, TIMEOUT);
+        assert_no_bug(seed, &data, outcome);
+    }
+}
+
+#[test]
+fn mangled_random_grammars() {
+    for seed in 1..=CASES {
+        let mut state = GeneratorState::new(seed);
+        let grammar = perturb::random_grammar(&mut || state.rand());
+        let mut data = serde_json::to_vec_pretty(&perturb::to_json(&grammar)).unwrap();
+        perturb::mangle(&mut data, &mut || state.rand());
+        let outcome = perturb::check_bytes(data.clone(), SAMPLES, seed, TIMEOUT);
+        assert_no_bug(seed, &data, outcome);
+    }
+}
+
+#[test]
+fn mangled_json_grammar() {
+    let json = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/grammar.json")).unwrap();
+    for seed in 1..=CASES {
+        let mut state = GeneratorState::new(seed);
+        let mut data = json.clone();
+        perturb::mangle(&mut data, &mut || state.rand());
+        let outcome = perturb::check_bytes(data.clone(), SAMPLES, seed, TIMEOUT);
+        assert_no_bug(seed, &data, outcome);
+    }
+}
@@ -0,0 +1,73 @@
+// Numeric range terminals (see numeric.rs): ranges split into digit
+// patterns that cover every number of the range once
+
+use std::collections::HashMap;
+
+use maybe_fastest_fuzzer::numeric;
+use maybe_fastest_fuzzer::Grammar;
+
+// Rules of the grammar with just the symbol, after expand()
+fn expanded(symbol: &str) -> HashMap<String, Vec<Vec<String>>> {
+    let mut rules = HashMap::new();
+    rules.insert("<start>".to_string(), vec![vec![symbol.to_string()]]);
+    let mut grammar = Grammar(rules, HashMap::new());
+    numeric::expand(&mut grammar).unwrap();
+    grammar.0
+}
+
+// Every string an alternative of digits and digit rules derives
+fn strings(rules: &HashMap<String, Vec<Vec<String>>>, alternative: &[String]) -> Vec<String> {
+    let mut ret = vec![String::new()];
+    for symbol in alternative {
+        let options = match rules.get(symbol) {
+            Some(options) => options.iter().map(|x| x.concat()).collect(),
+            None => vec![symbol.clone()],
+        };
+        ret = ret.iter().flat_map(|x| options.iter().map(move |y| format!("{}{}", x, y)))
+            .collect();
+    }
+    ret
+}
+
+#[test]
+fn patterns_0_255() {
+    let rules = expanded("<int:0..255,bias=0>");
+    let digits = |lo: char, hi: char| format!("<numeric digit {}-{}>", lo, hi);
+    assert_eq!(rules["<int:0..255,bias=0>"], [
+        vec![digits('0', '9')],
+        vec![digits('1', '9'), digits('0', '9')],
+        vec!["1".to_string(), digits('0', '9'), digits('0', '9')],
+        vec!["2".to_string(), digits('0', '4'), digits('0', '9')],
+        vec!["2".to_string(), "5".to_string(), digits('0', '5')],
+    ]);
+}
+
+#[test]
+fn patterns_cover_range() {
+    for (symbol, lo, hi, base) in [
+        ("<int:0..255,bias=0>", 0, 255, 10),
+        ("<int:37..1234,bias=0>", 37, 1234, 10),
+        ("<int:-128..127,bias=0>", -128, 127, 10),
+        ("<int:-1000..-7,bias=0>", -1000, -7, 10),
+        ("<hex:0x1f..0x2a0,bias=0>", 0x1f, 0x2a0, 16),
+        ("<int:5..5,bias=0>", 5, 5, 10),
+    ] {
+        let rules = expanded(symbol);
+        let mut numbers = rules[symbol].iter().flat_map(|x| strings(&rules, x))
+            .map(|x| i64::from_str_radix(&x, base).unwrap())
+            .collect::<Vec<_>>();
+        numbers.sort_unstable();
+        assert_eq!(numbers, (lo..=hi).collect::<Vec<i64>>(), "{}", symbol);
+    }
+}
+
+#[test]
+fn special_values() {
+    let rules = expanded("<int:-3..300,bias=1>");
+    let mut specials = rules["<int:-3..300,bias=1>"].iter().map(|x| x.concat())
+        .collect::<Vec<_>>();
+    specials.sort_by_key(|x| x.parse::<i64>().unwrap());
+    assert_eq!(specials, ["-3", "-2", "-1", "0", "1", "2", "3", "4", "5", "7", "8", "9",
+        "15", "16", "17", "31", "32", "33", "63", "64", "65", "127", "128", "129", "255",
+        "256", "257", "299", "300"]);
+}
@@ -0,0 +1,33 @@
+// Feeding stdin to targets: all of the input reaches one that reads it, one
+// that does not still times out (see executor/process.rs)
+#![cfg(unix)]
+
+use std::time::{Duration, Instant};
+
+use maybe_fastest_fuzzer::executor::{Executor, ExitKind, ProcessExecutor};
+
+// Larger than any pipe buffer
+const INPUT: usize = 1 << 20;
+
+fn command(line: &str) -> Vec<String> {
+    ["sh", "-c", line].iter().map(|x| x.to_string()).collect()
+}
+
+#[test]
+fn stdin_delivered() {
+    let line = format!("test $(wc -c) -eq {}", INPUT);
+    let mut executor = ProcessExecutor::new(command(&line), Duration::from_secs(10));
+    let result = executor.run(&vec![b'a'; INPUT]).unwrap();
+    assert_eq!(result.exit, ExitKind::Ok);
+    assert_eq!(result.code, Some(0));
+}
+
+#[test]
+fn stdin_not_read() {
+    let mut executor = ProcessExecutor::new(command("sleep 5"),
+        Duration::from_millis(100));
+    let start = Instant::now();
+    let result = executor.run(&vec![b'a'; INPUT]).unwrap();
+    assert_eq!(result.exit, ExitKind::Timeout);
+    assert!(start.elapsed() < Duration::from_secs(2), "{:?}", start.elapsed());
+}
@@ -0,0 +1,29 @@
+// Adaptive mutator counters shared by workers stay in range however many
+// of them cross the window at once (see mutator.rs)
+
+use maybe_fastest_fuzzer::mutator::Scheduler;
+
+const WORKERS: usize = 16;
+const ROUNDS: usize = 200_000;
+
+#[test]
+fn concurrent_halving() {
+    let mut scheduler = Scheduler::standard(1, 0.0, 0, 0.0);
+    scheduler.set_adaptive(true);
+    std::thread::scope(|s| {
+        for worker in 0..WORKERS {
+            let scheduler = &scheduler;
+            s.spawn(move || {
+                for round in 0..ROUNDS {
+                    scheduler.report(&[0], (round + worker) % 3 == 0);
+                }
+            });
+        }
+    });
+
+    let (name, uses, finds) = scheduler.stats().next().unwrap();
+    // halved at 10000 uses, a few more come in from workers in between
+    assert!(uses <= 10_000 + WORKERS as u64, "{}: {} uses", name, uses);
+    assert!(uses > 0);
+    assert!(finds <= uses + WORKERS as u64, "{}: {} finds in {} uses", name, finds, uses);
+}
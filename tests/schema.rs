@@ -0,0 +1,28 @@
+// Grammar files with and without a version (see schema.rs): test.json has
+// none and loads as the current version unless parsing is strict
+
+use maybe_fastest_fuzzer::loader::{self, DuplicatePolicy};
+use maybe_fastest_fuzzer::schema::Parsing;
+use maybe_fastest_fuzzer::GrammarRust;
+
+const UNVERSIONED: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/test.json");
+const VERSIONED: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/grammar.json");
+
+#[test]
+fn unversioned_lenient() {
+    let grammar = loader::load(&[UNVERSIONED], DuplicatePolicy::Error, Parsing::Lenient)
+        .unwrap();
+    GrammarRust::new(&grammar);
+}
+
+#[test]
+fn unversioned_strict() {
+    let err = loader::load(&[UNVERSIONED], DuplicatePolicy::Error, Parsing::Strict)
+        .unwrap_err();
+    assert!(err.to_string().contains("no \"version\""), "{}", err);
+}
+
+#[test]
+fn versioned_strict() {
+    loader::load(&[VERSIONED], DuplicatePolicy::Error, Parsing::Strict).unwrap();
+}
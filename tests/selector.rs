@@ -0,0 +1,72 @@
+// Uniform picks among the alternatives of a rule (see Selector in
+// grammar.rs): a mask for power of two counts, Lemire's method otherwise,
+// nothing at all for a single alternative
+
+use std::collections::HashMap;
+
+use maybe_fastest_fuzzer::{grammar, GrammarRust};
+
+const SAMPLES: usize = 60_000;
+
+// How often every byte shows up at every position of the samples
+fn counts(gram: &GrammarRust, seed: usize) -> Vec<HashMap<u8, usize>> {
+    let mut cases = gram.iter_testcases(seed);
+    let mut counts = Vec::new();
+    for _ in 0..SAMPLES {
+        let sample = cases.next_ref();
+        counts.resize_with(counts.len().max(sample.len()), HashMap::new);
+        for (position, &byte) in sample.iter().enumerate() {
+            *counts[position].entry(byte).or_insert(0) += 1;
+        }
+    }
+    counts
+}
+
+// Every alternative within 4% of its share, far more than chance allows
+// over SAMPLES draws
+fn assert_uniform(counts: &HashMap<u8, usize>, alternatives: usize) {
+    assert_eq!(counts.len(), alternatives, "{:?}", counts);
+    let expected = SAMPLES as f64 / alternatives as f64;
+    for (&byte, &count) in counts {
+        assert!((count as f64 - expected).abs() < expected * 0.04,
+            "{}: {} of {} draws, {:.0} expected", byte as char, count, SAMPLES, expected);
+    }
+}
+
+#[test]
+fn uniform() {
+    let gram = grammar! {
+        "<start>" => ["<three>", "<four>", "<seven>", "<one>"];
+        "<three>" => ["a"] | ["b"] | ["c"];
+        "<four>" => ["a"] | ["b"] | ["c"] | ["d"];
+        "<seven>" => ["a"] | ["b"] | ["c"] | ["d"] | ["e"] | ["f"] | ["g"];
+        "<one>" => ["a"];
+    };
+    let counts = counts(&gram, 7);
+    assert_eq!(counts.len(), 4);
+    assert_uniform(&counts[0], 3);
+    assert_uniform(&counts[1], 4);
+    assert_uniform(&counts[2], 7);
+    assert_uniform(&counts[3], 1);
+}
+
+#[test]
+fn single_draws_nothing() {
+    // a rule with one alternative takes no random number, the picks of
+    // the others stay the same with or without it
+    let with = grammar! {
+        "<start>" => ["<one>", "<three>", "<one>", "<three>"];
+        "<three>" => ["a"] | ["b"] | ["c"];
+        "<one>" => ["x"];
+    };
+    let without = grammar! {
+        "<start>" => ["<three>", "<three>"];
+        "<three>" => ["a"] | ["b"] | ["c"];
+    };
+    let (mut with, mut without) = (with.iter_testcases(3), without.iter_testcases(3));
+    for _ in 0..1000 {
+        let (a, b) = (with.next_ref().to_vec(), without.next_ref());
+        assert_eq!([a[1], a[3]], b, "{:?} {:?}", a, b);
+        assert_eq!([a[0], a[2]], *b"xx");
+    }
+}
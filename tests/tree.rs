@@ -0,0 +1,81 @@
+// Derivation trees stay well formed through the structural mutations (see
+// tree.rs): nodes in preorder, every size the node plus the subtrees of its
+// children, every child one the grammar allows
+
+use maybe_fastest_fuzzer::grammar::GeneratorState;
+use maybe_fastest_fuzzer::tree::{Tree, TreeStack};
+use maybe_fastest_fuzzer::{grammar, Fragment, FragmentId, GrammarRust};
+
+const ROUNDS: usize = 2000;
+
+fn assert_well_formed(gram: &GrammarRust, tree: &Tree) {
+    // nodes whose subtree is not over yet: index and the fragments each
+    // child still to come may be, last child first
+    let mut open: Vec<(usize, Vec<Vec<FragmentId>>)> = Vec::new();
+    for (idx, node) in tree.nodes.iter().enumerate() {
+        if idx > 0 {
+            let (_, children) = open.last_mut().expect("a single root");
+            let allowed = children.pop().unwrap();
+            assert!(allowed.contains(&node.fragment), "node {}", idx);
+        }
+        let children = match gram.lookup_fragment(node.fragment) {
+            Fragment::NonTerminal(options) => vec![options.to_vec()],
+            Fragment::Expression(expr) => expr.iter().rev().map(|&x| vec![x]).collect(),
+            Fragment::Terminal(_) => Vec::new(),
+        };
+        open.push((idx, children));
+        while let Some((start, _)) = open.pop_if(|x| x.1.is_empty()) {
+            assert_eq!(tree.nodes[start].size as usize, idx + 1 - start,
+                "size of node {}", start);
+        }
+    }
+    assert!(open.is_empty(), "{} nodes missing children", open.len());
+}
+
+fn grammar() -> GrammarRust {
+    grammar! {
+        "<start>" => ["<list>"];
+        "<list>" => ["<item>"] | ["<item>", ",", "<list>"] | ["[", "<list>", "]"];
+        "<item>" => ["a"] | ["b"] | ["(", "<item>", ")"];
+    }
+}
+
+#[test]
+fn replace_subtree() {
+    let gram = grammar();
+    let mut state = GeneratorState::new(11);
+    let (mut stack, mut tree, mut scratch) = (TreeStack::default(), Tree::default(),
+        Tree::default());
+    gram.generate_full_tree(&mut state, &mut stack, &mut tree);
+    for _ in 0..ROUNDS {
+        // a fresh subtree for a random non-terminal, or the tree
+        // started over when it grew too much
+        if tree.nodes.len() > 2000 {
+            gram.generate_full_tree(&mut state, &mut stack, &mut tree);
+        }
+        assert!(gram.mutate_subtree(&mut state, &mut tree, &mut stack, &mut scratch));
+        assert_well_formed(&gram, &tree);
+    }
+}
+
+#[test]
+fn unroll_recursion() {
+    let gram = grammar();
+    let mut state = GeneratorState::new(5);
+    let (mut stack, mut tree, mut scratch) = (TreeStack::default(), Tree::default(),
+        Tree::default());
+    let mut unrolled = 0;
+    for _ in 0..ROUNDS {
+        // unrolled again and again, or a new tree when there is no
+        // recursion to unroll
+        let before = tree.nodes.len();
+        if before > 2000 || !gram.mutate_recursion(&mut state, &mut tree, &mut scratch) {
+            gram.generate_full_tree(&mut state, &mut stack, &mut tree);
+        } else {
+            unrolled += 1;
+            assert!(tree.nodes.len() > before);
+        }
+        assert_well_formed(&gram, &tree);
+    }
+    assert!(unrolled > ROUNDS / 4, "{} unrolled", unrolled);
+}